@@ -1,22 +1,32 @@
 //! Plugin type definitions.
 
 use std::future::Future;
+use std::net::SocketAddr;
 use std::pin::Pin;
 use std::process::Stdio;
 use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::{bail, format_err, Error};
+use foreign_types::ForeignTypeRef;
 use hyper::{Body, Request, Response};
+use openssl::error::ErrorStack;
+use openssl::ssl::{
+    self, AlpnError, ClientHelloResponse, Ssl, SslAcceptor, SslAlert, SslMethod, SslRef,
+};
 use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
 use tokio::process::Command;
+use tokio_openssl::SslStream;
 
 use proxmox_acme::async_client::AcmeClient;
+use proxmox_acme::util::TlsAlpnCertificate;
 use proxmox_acme::{Authorization, Challenge};
 use proxmox_rest_server::WorkerTask;
 
 use crate::plugin_config::PluginData;
-use crate::types::{AcmeDomain, DnsPlugin};
+use crate::rfc2136::Rfc2136Client;
+use crate::types::{AcmeDomain, DnsPlugin, Rfc2136Plugin};
 
 const PROXMOX_ACME_SH_PATH: &str = "/usr/share/proxmox-acme/proxmox-acme";
 
@@ -34,10 +44,18 @@ pub(crate) fn get_acme_plugin(
             let plugin: DnsPlugin = serde::Deserialize::deserialize(data)?;
             Box::new(plugin)
         }
+        "rfc2136" => {
+            let plugin: Rfc2136Plugin = serde::Deserialize::deserialize(data)?;
+            Box::new(plugin)
+        }
         "standalone" => {
             // this one has no config
             Box::<StandaloneServer>::default()
         }
+        "tls-alpn" => {
+            // neither has this one
+            Box::<TlsAlpnServer>::default()
+        }
         other => bail!("missing implementation for plugin type '{}'", other),
     }))
 }
@@ -214,6 +232,65 @@ impl AcmePlugin for DnsPlugin {
     }
 }
 
+impl Rfc2136Plugin {
+    /// Returns the challenge, the name of the TXT record and its value.
+    fn dns_01_record<'a>(
+        client: &mut AcmeClient,
+        authorization: &'a Authorization,
+        domain: &AcmeDomain,
+    ) -> Result<(&'a Challenge, String, String), Error> {
+        let challenge = extract_challenge(authorization, "dns-01")?;
+        let value = client.dns_01_txt_value(
+            challenge
+                .token()
+                .ok_or_else(|| format_err!("missing token in challenge"))?,
+        )?;
+
+        let domain = domain.alias.as_deref().unwrap_or(&domain.domain);
+        let domain = domain.strip_prefix("*.").unwrap_or(domain);
+
+        Ok((challenge, format!("_acme-challenge.{}", domain), value))
+    }
+}
+
+impl AcmePlugin for Rfc2136Plugin {
+    fn setup<'fut, 'a: 'fut, 'b: 'fut, 'c: 'fut, 'd: 'fut>(
+        &'a mut self,
+        client: &'b mut AcmeClient,
+        authorization: &'c Authorization,
+        domain: &'d AcmeDomain,
+        task: Arc<WorkerTask>,
+    ) -> Pin<Box<dyn Future<Output = Result<&'c str, Error>> + Send + 'fut>> {
+        Box::pin(async move {
+            let (challenge, name, value) = Self::dns_01_record(client, authorization, domain)?;
+            let dns = Rfc2136Client::new(self)?;
+
+            task.log_message(format!("Adding TXT record {} via {}", name, self.server));
+            dns.add_txt(&name, &value).await?;
+            dns.wait_for_propagation(&name, &value, |msg| task.log_message(msg))
+                .await?;
+
+            Ok(challenge.url.as_str())
+        })
+    }
+
+    fn teardown<'fut, 'a: 'fut, 'b: 'fut, 'c: 'fut, 'd: 'fut>(
+        &'a mut self,
+        client: &'b mut AcmeClient,
+        authorization: &'c Authorization,
+        domain: &'d AcmeDomain,
+        task: Arc<WorkerTask>,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'fut>> {
+        Box::pin(async move {
+            let (_challenge, name, value) = Self::dns_01_record(client, authorization, domain)?;
+            let dns = Rfc2136Client::new(self)?;
+
+            task.log_message(format!("Removing TXT record {} via {}", name, self.server));
+            dns.delete_txt(&name, &value).await
+        })
+    }
+}
+
 #[derive(Default)]
 struct StandaloneServer {
     abort_handle: Option<futures::future::AbortHandle>,
@@ -313,3 +390,236 @@ impl AcmePlugin for StandaloneServer {
         })
     }
 }
+
+#[derive(Default)]
+struct TlsAlpnServer {
+    abort_handle: Option<futures::future::AbortHandle>,
+}
+
+// Same as for the `StandaloneServer`, don't keep the listener around if we get dropped:
+impl Drop for TlsAlpnServer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl TlsAlpnServer {
+    fn stop(&mut self) {
+        if let Some(abort) = self.abort_handle.take() {
+            abort.abort();
+        }
+    }
+}
+
+/// [`ACME_TLS_ALPN_PROTOCOL`] in ALPN wire format.
+const ACME_TLS_ALPN_WIRE: &[u8] = b"\x0aacme-tls/1";
+
+const TLSEXT_TYPE_APPLICATION_LAYER_PROTOCOL_NEGOTIATION: libc::c_uint = 16;
+
+// C type:
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+type SSL = <SslRef as ForeignTypeRef>::CType;
+
+extern "C" {
+    fn SSL_client_hello_get0_ext(
+        s: *mut SSL,
+        ty: libc::c_uint,
+        out: *mut *const libc::c_uchar,
+        outlen: *mut libc::size_t,
+    ) -> libc::c_int;
+}
+
+/// Check whether the client offers the "acme-tls/1" protocol, only valid in the client hello
+/// callback.
+fn client_hello_offers_acme_tls(ssl: &SslRef) -> bool {
+    let mut ptr = std::ptr::null();
+    let mut len = 0;
+    let rc = unsafe {
+        SSL_client_hello_get0_ext(
+            ssl.as_ptr(),
+            TLSEXT_TYPE_APPLICATION_LAYER_PROTOCOL_NEGOTIATION,
+            &mut ptr,
+            &mut len,
+        )
+    };
+    if rc != 1 || len < 2 {
+        return false;
+    }
+
+    // the extension contains the 2 byte length prefixed protocol name list
+    let ext = unsafe { std::slice::from_raw_parts(ptr, len) };
+    ssl::select_next_proto(ACME_TLS_ALPN_WIRE, &ext[2..]).is_some()
+}
+
+fn select_acme_tls_alpn<'a>(_ssl: &mut SslRef, client: &'a [u8]) -> Result<&'a [u8], AlpnError> {
+    ssl::select_next_proto(ACME_TLS_ALPN_WIRE, client).ok_or(AlpnError::ALERT_FATAL)
+}
+
+fn tls_alpn_acceptor(certificate: TlsAlpnCertificate) -> Result<SslAcceptor, Error> {
+    let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server())?;
+
+    // The validation certificate must only be presented to clients asking for the "acme-tls/1"
+    // protocol. The certificate is chosen before the ALPN select callback runs (which does not
+    // happen at all for clients without ALPN), so set it up per connection when looking at the
+    // client hello and fail the handshake for everyone else.
+    acceptor.set_client_hello_callback(move |ssl, alert| {
+        if !client_hello_offers_acme_tls(ssl) {
+            *alert = SslAlert::ILLEGAL_PARAMETER;
+            return Err(ErrorStack::get());
+        }
+        ssl.set_certificate(&certificate.certificate)?;
+        ssl.set_private_key(&certificate.private_key)?;
+        Ok(ClientHelloResponse::SUCCESS)
+    });
+    acceptor.set_alpn_select_callback(select_acme_tls_alpn);
+
+    Ok(acceptor.build())
+}
+
+async fn tls_alpn_serve(listener: TcpListener, acceptor: Arc<SslAcceptor>) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _peer)) => stream,
+            Err(err) => {
+                log::error!("tls-alpn-01 listener failed to accept connection - {}", err);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+
+        let acceptor = Arc::clone(&acceptor);
+        tokio::spawn(async move {
+            let mut stream =
+                match Ssl::new(acceptor.context()).and_then(|ssl| SslStream::new(ssl, stream)) {
+                    Ok(stream) => stream,
+                    Err(_) => return,
+                };
+
+            // the validation server closes the connection after the handshake
+            let handshake = Pin::new(&mut stream).accept();
+            if let Ok(Ok(())) = tokio::time::timeout(Duration::from_secs(10), handshake).await {
+                let _ = stream.shutdown().await;
+            }
+        });
+    }
+}
+
+impl AcmePlugin for TlsAlpnServer {
+    fn setup<'fut, 'a: 'fut, 'b: 'fut, 'c: 'fut, 'd: 'fut>(
+        &'a mut self,
+        client: &'b mut AcmeClient,
+        authorization: &'c Authorization,
+        domain: &'d AcmeDomain,
+        _task: Arc<WorkerTask>,
+    ) -> Pin<Box<dyn Future<Output = Result<&'c str, Error>> + Send + 'fut>> {
+        Box::pin(async move {
+            self.stop();
+
+            let challenge = extract_challenge(authorization, "tls-alpn-01")?;
+            let token = challenge
+                .token()
+                .ok_or_else(|| format_err!("missing token in challenge"))?;
+            let certificate = client.tls_alpn_01_certificate(token, &domain.domain)?;
+            let acceptor = Arc::new(tls_alpn_acceptor(certificate)?);
+
+            // `[::]:443` first, then `*:443`
+            let listener = match TcpListener::bind(SocketAddr::from(([0u16; 8], 443))).await {
+                Ok(listener) => listener,
+                Err(_) => TcpListener::bind(SocketAddr::from(([0u8; 4], 443))).await?,
+            };
+
+            let (future, abort) = futures::future::abortable(tls_alpn_serve(listener, acceptor));
+            self.abort_handle = Some(abort);
+            tokio::spawn(future);
+
+            Ok(challenge.url.as_str())
+        })
+    }
+
+    fn teardown<'fut, 'a: 'fut, 'b: 'fut, 'c: 'fut, 'd: 'fut>(
+        &'a mut self,
+        _client: &'b mut AcmeClient,
+        _authorization: &'c Authorization,
+        _domain: &'d AcmeDomain,
+        _task: Arc<WorkerTask>,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'fut>> {
+        Box::pin(async move {
+            self.stop();
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use openssl::ssl::{SslConnector, SslVerifyMode, SslVersion};
+
+    use proxmox_acme::util::ACME_TLS_ALPN_PROTOCOL;
+
+    use super::*;
+
+    async fn connect(
+        addr: SocketAddr,
+        alpn: Option<&[u8]>,
+        max_version: Option<SslVersion>,
+    ) -> Result<SslStream<tokio::net::TcpStream>, Error> {
+        let mut connector = SslConnector::builder(SslMethod::tls_client())?;
+        connector.set_verify(SslVerifyMode::NONE);
+        connector.set_max_proto_version(max_version)?;
+        if let Some(alpn) = alpn {
+            connector.set_alpn_protos(alpn)?;
+        }
+        let ssl = connector.build().configure()?.into_ssl("example.com")?;
+
+        let stream = tokio::net::TcpStream::connect(addr).await?;
+        let mut stream = SslStream::new(ssl, stream)?;
+        Pin::new(&mut stream).connect().await?;
+        Ok(stream)
+    }
+
+    #[tokio::test]
+    async fn test_tls_alpn_server() {
+        let key_authorization = "token.thumbprint";
+        let certificate = TlsAlpnCertificate::generate("example.com", key_authorization).unwrap();
+        let acceptor = Arc::new(tls_alpn_acceptor(certificate).unwrap());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(tls_alpn_serve(listener, acceptor));
+
+        let stream = connect(addr, Some(ACME_TLS_ALPN_WIRE), Some(SslVersion::TLS1_2))
+            .await
+            .unwrap();
+        assert_eq!(stream.ssl().version2(), Some(SslVersion::TLS1_2));
+
+        let stream = connect(addr, Some(ACME_TLS_ALPN_WIRE), None).await.unwrap();
+        assert_eq!(
+            stream.ssl().selected_alpn_protocol(),
+            Some(ACME_TLS_ALPN_PROTOCOL)
+        );
+
+        let peer = stream.ssl().peer_certificate().unwrap();
+        let san = peer.subject_alt_names().unwrap();
+        assert_eq!(san.len(), 1);
+        assert_eq!(san.get(0).unwrap().dnsname(), Some("example.com"));
+
+        // id-pe-acmeIdentifier, critical, OCTET STRING { OCTET STRING { sha256 } }
+        let mut extension = vec![
+            0x06, 0x08, 0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x01, 0x1f, 0x01, 0x01, 0xff, 0x04,
+            0x22, 0x04, 0x20,
+        ];
+        extension.extend(openssl::sha::sha256(key_authorization.as_bytes()));
+        let der = peer.to_der().unwrap();
+        assert!(der
+            .windows(extension.len())
+            .any(|window| window == extension));
+
+        // the validation certificate must not be served to regular TLS clients
+        for max_version in [None, Some(SslVersion::TLS1_2)] {
+            assert!(connect(addr, None, max_version).await.is_err());
+            assert!(connect(addr, Some(b"\x08http/1.1"), max_version)
+                .await
+                .is_err());
+        }
+    }
+}
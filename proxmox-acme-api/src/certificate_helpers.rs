@@ -35,6 +35,16 @@ pub async fn order_certificate(
     worker: Arc<WorkerTask>,
     acme_config: &AcmeConfig,
     domains: &[AcmeDomain],
+) -> Result<Option<OrderedCertificate>, Error> {
+    order_certificate_replacing(worker, acme_config, domains, None).await
+}
+
+/// Order a certificate which replaces the certificate with the ARI identifier `replaces`.
+pub async fn order_certificate_replacing(
+    worker: Arc<WorkerTask>,
+    acme_config: &AcmeConfig,
+    domains: &[AcmeDomain],
+    replaces: Option<String>,
 ) -> Result<Option<OrderedCertificate>, Error> {
     use proxmox_acme::authorization::Status;
     use proxmox_acme::order::Identifier;
@@ -63,7 +73,10 @@ pub async fn order_certificate(
     task_log!(worker, "Placing ACME order");
 
     let order = acme
-        .new_order(domains.iter().map(|d| d.domain.to_ascii_lowercase()))
+        .new_order_replacing(
+            domains.iter().map(|d| d.domain.to_ascii_lowercase()),
+            replaces,
+        )
         .await?;
 
     task_log!(worker, "Order URL: {}", order.location);
@@ -314,6 +327,20 @@ impl CertificateInfo {
         let cert_pem = String::from_utf8(cert_pem.to_vec())
             .map_err(|_| format_err!("certificate in {:?} is not a valid PEM file", filename))?;
 
+        Self::from_x509_with_pem(filename, &x509, cert_pem)
+    }
+
+    /// Create the certificate information of an already parsed certificate.
+    pub fn from_x509(filename: &str, x509: &openssl::x509::X509Ref) -> Result<Self, Error> {
+        let cert_pem = String::from_utf8(x509.to_pem()?)?;
+        Self::from_x509_with_pem(filename, x509, cert_pem)
+    }
+
+    fn from_x509_with_pem(
+        filename: &str,
+        x509: &openssl::x509::X509Ref,
+        cert_pem: String,
+    ) -> Result<Self, Error> {
         let pubkey = x509.public_key()?;
 
         let subject = x509name_to_string(x509.subject_name())?;
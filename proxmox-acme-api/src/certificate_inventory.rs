@@ -0,0 +1,823 @@
+//! Certificate inventory and expiry monitoring.
+//!
+//! Scans configured certificate locations, parses the complete certificate chains and checks
+//! them for common problems: wrong chain order, chains not trusted by a CA store, private keys
+//! not matching the certificate and certificates which are expired or about to expire.
+
+use std::path::Path;
+
+use anyhow::{format_err, Error};
+use openssl::pkey::PKey;
+use openssl::stack::Stack;
+use openssl::x509::store::{X509Store, X509StoreBuilder, X509StoreRef};
+use openssl::x509::verify::X509VerifyFlags;
+use openssl::x509::{X509StoreContext, X509VerifyResult, X509};
+
+use crate::types::{
+    CertificateFileType, CertificateInfo, CertificateInventoryEntry, CertificateIssue,
+    CertificateIssueType, CertificateLocation,
+};
+
+/// Default thresholds in days before expiry at which certificates are reported.
+pub const DEFAULT_EXPIRY_THRESHOLDS: &[u64] = &[30, 14, 7, 1];
+
+const DAY: i64 = 24 * 3600;
+
+/// Create a builder for a CA store used to verify chains.
+///
+/// Validity periods are not checked during verification, OpenSSL would stop at the first expired
+/// certificate and skip the remaining checks of the chain. They are reported separately instead.
+fn ca_store_builder() -> Result<X509StoreBuilder, Error> {
+    let mut builder = X509StoreBuilder::new()?;
+    builder.set_flags(X509VerifyFlags::NO_CHECK_TIME)?;
+    Ok(builder)
+}
+
+/// Build a CA store from PEM files, optionally including the system's default CA certificates.
+pub fn load_ca_store<P: AsRef<Path>>(
+    ca_files: &[P],
+    default_paths: bool,
+) -> Result<X509Store, Error> {
+    let mut builder = ca_store_builder()?;
+
+    if default_paths {
+        builder.set_default_paths()?;
+    }
+
+    for file in ca_files {
+        let file = file.as_ref();
+        let data = std::fs::read(file)
+            .map_err(|err| format_err!("failed to read CA file {:?} - {}", file, err))?;
+        for cert in X509::stack_from_pem(&data)? {
+            builder.add_cert(cert)?;
+        }
+    }
+
+    Ok(builder.build())
+}
+
+/// Scan and check all certificate files of the given locations.
+///
+/// Chains are verified against `ca_store` if one is given, which should be created with
+/// [`load_ca_store`], so that expired certificates do not cut the verification short.
+/// Certificates expiring within the largest of the `thresholds` (in days) are reported as
+/// expiring.
+pub fn scan_certificates(
+    locations: &[CertificateLocation],
+    ca_store: Option<&X509StoreRef>,
+    thresholds: &[u64],
+    now: i64,
+) -> Vec<CertificateInventoryEntry> {
+    let mut entries = Vec::new();
+
+    for location in locations {
+        let path = Path::new(&location.path);
+
+        let files = if path.is_dir() {
+            match list_certificate_files(path) {
+                Ok(files) => files,
+                Err(err) => {
+                    entries.push(unreadable_entry(location, &location.path, err));
+                    continue;
+                }
+            }
+        } else {
+            vec![location.path.clone()]
+        };
+
+        // the key can only belong to a single certificate file
+        let key_file = location.key.as_deref().filter(|_| !path.is_dir());
+
+        for filename in files {
+            let data = match std::fs::read(&filename) {
+                Ok(data) => data,
+                Err(err) => {
+                    entries.push(unreadable_entry(location, &filename, err.into()));
+                    continue;
+                }
+            };
+
+            let key = key_file.map(|key_file| {
+                std::fs::read(key_file)
+                    .map_err(|err| format_err!("failed to read key {:?} - {}", key_file, err))
+            });
+
+            entries.push(inspect_certificates(
+                location, &filename, &data, key, ca_store, thresholds, now,
+            ));
+        }
+    }
+
+    entries
+}
+
+fn list_certificate_files(dir: &Path) -> Result<Vec<String>, Error> {
+    let mut files = Vec::new();
+
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_certificate = matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("pem" | "crt")
+        );
+        if is_certificate && path.is_file() {
+            files.push(path.to_string_lossy().into_owned());
+        }
+    }
+
+    files.sort();
+
+    Ok(files)
+}
+
+fn unreadable_entry(
+    location: &CertificateLocation,
+    filename: &str,
+    err: Error,
+) -> CertificateInventoryEntry {
+    CertificateInventoryEntry {
+        name: location.name.clone(),
+        filename: filename.to_string(),
+        ty: location.ty.unwrap_or_default(),
+        certificates: Vec::new(),
+        issues: vec![issue(
+            CertificateIssueType::Unreadable,
+            None,
+            err.to_string(),
+        )],
+    }
+}
+
+fn issue(ty: CertificateIssueType, index: Option<usize>, message: String) -> CertificateIssue {
+    CertificateIssue {
+        ty,
+        index: index.map(|index| index as u32),
+        message,
+    }
+}
+
+/// Check the PEM encoded certificates of a single file.
+fn inspect_certificates(
+    location: &CertificateLocation,
+    filename: &str,
+    data: &[u8],
+    key: Option<Result<Vec<u8>, Error>>,
+    ca_store: Option<&X509StoreRef>,
+    thresholds: &[u64],
+    now: i64,
+) -> CertificateInventoryEntry {
+    let ty = location.ty.unwrap_or_default();
+
+    let chain = match X509::stack_from_pem(data) {
+        Ok(chain) if chain.is_empty() => {
+            let err = format_err!("no certificates found");
+            return unreadable_entry(location, filename, err);
+        }
+        Ok(chain) => chain,
+        Err(err) => return unreadable_entry(location, filename, err.into()),
+    };
+
+    let mut certificates = Vec::with_capacity(chain.len());
+    let mut issues = Vec::new();
+
+    for (index, cert) in chain.iter().enumerate() {
+        match CertificateInfo::from_x509(filename, cert) {
+            Ok(info) => {
+                check_validity(&info, index, thresholds, now, &mut issues);
+                certificates.push(info);
+            }
+            Err(err) => issues.push(issue(
+                CertificateIssueType::Unreadable,
+                Some(index),
+                err.to_string(),
+            )),
+        }
+    }
+
+    if ty == CertificateFileType::Chain {
+        check_chain_order(&chain, &mut issues);
+
+        if let Some(store) = ca_store {
+            if let Err(err) = verify_chain(&chain, store) {
+                issues.push(issue(CertificateIssueType::Untrusted, Some(0), err));
+            }
+        }
+
+        if let Some(key) = key {
+            if let Err(err) = check_private_key(&chain[0], key) {
+                issues.push(issue(CertificateIssueType::KeyMismatch, Some(0), err));
+            }
+        }
+    }
+
+    CertificateInventoryEntry {
+        name: location.name.clone(),
+        filename: filename.to_string(),
+        ty,
+        certificates,
+        issues,
+    }
+}
+
+fn check_validity(
+    info: &CertificateInfo,
+    index: usize,
+    thresholds: &[u64],
+    now: i64,
+    issues: &mut Vec<CertificateIssue>,
+) {
+    if let Some(notbefore) = info.notbefore.filter(|notbefore| *notbefore > now) {
+        let message = match proxmox_time::epoch_to_rfc3339_utc(notbefore) {
+            Ok(time) => format!("certificate is not valid before {time}"),
+            Err(_) => "certificate is not valid yet".to_string(),
+        };
+        issues.push(issue(
+            CertificateIssueType::NotYetValid,
+            Some(index),
+            message,
+        ));
+    }
+
+    let notafter = match info.notafter {
+        Some(notafter) => notafter,
+        None => return,
+    };
+
+    if notafter < now {
+        let days = (now - notafter) / DAY;
+        let message = format!("certificate expired {days} days ago");
+        issues.push(issue(CertificateIssueType::Expired, Some(index), message));
+        return;
+    }
+
+    let max_threshold = thresholds.iter().copied().max().unwrap_or(0) as i64;
+    if notafter - now <= max_threshold * DAY {
+        let days = (notafter - now) / DAY;
+        let message = format!("certificate expires in {days} days");
+        issues.push(issue(CertificateIssueType::Expiring, Some(index), message));
+    }
+}
+
+/// Every certificate needs to be followed by its issuer.
+fn check_chain_order(chain: &[X509], issues: &mut Vec<CertificateIssue>) {
+    for (index, pair) in chain.windows(2).enumerate() {
+        let (cert, issuer) = (&pair[0], &pair[1]);
+
+        let signed = issuer.issued(cert) == X509VerifyResult::OK
+            && issuer
+                .public_key()
+                .and_then(|key| cert.verify(&key))
+                .unwrap_or(false);
+
+        if !signed {
+            issues.push(issue(
+                CertificateIssueType::ChainOrder,
+                Some(index + 1),
+                format!(
+                    "certificate {} is not the issuer of certificate {}",
+                    index + 1,
+                    index
+                ),
+            ));
+        }
+    }
+}
+
+fn verify_chain(chain: &[X509], store: &X509StoreRef) -> Result<(), String> {
+    let verify = || -> Result<Option<X509VerifyResult>, Error> {
+        let mut untrusted = Stack::new()?;
+        for cert in &chain[1..] {
+            untrusted.push(cert.clone())?;
+        }
+
+        let mut context = X509StoreContext::new()?;
+        Ok(context.init(store, &chain[0], &untrusted, |context| {
+            Ok(match context.verify_cert()? {
+                true => None,
+                false => Some(context.error()),
+            })
+        })?)
+    };
+
+    match verify() {
+        Ok(None) => Ok(()),
+        Ok(Some(result)) => Err(format!(
+            "failed to verify certificate chain - {}",
+            result.error_string()
+        )),
+        Err(err) => Err(format!("failed to verify certificate chain - {err}")),
+    }
+}
+
+fn check_private_key(cert: &X509, key: Result<Vec<u8>, Error>) -> Result<(), String> {
+    let matches = || -> Result<bool, Error> {
+        let key = PKey::private_key_from_pem(&key?)
+            .map_err(|err| format_err!("failed to parse private key - {}", err))?;
+        Ok(cert.public_key()?.public_eq(&key))
+    };
+
+    match matches() {
+        Ok(true) => Ok(()),
+        Ok(false) => Err("private key does not match the certificate".to_string()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+#[cfg(feature = "notify")]
+mod notify {
+    use std::collections::HashMap;
+
+    use anyhow::Error;
+    use serde_json::json;
+
+    use proxmox_notify::{Bus, Notification, Severity};
+    use proxmox_product_config::{open_api_lockfile, replace_config};
+
+    use super::DAY;
+    use crate::types::CertificateInventoryEntry;
+
+    /// Name of the notification template used for expiry notifications.
+    pub const CERTIFICATE_EXPIRY_TEMPLATE: &str = "certificate-expiry";
+
+    /// Create the notifications for certificates which crossed an expiry threshold.
+    ///
+    /// `notified` maps certificate fingerprints to the last threshold a notification was created
+    /// for, with `0` meaning the certificate is expired. It is updated so that each threshold is
+    /// only reported once per certificate.
+    pub fn expiry_notifications(
+        entries: &[CertificateInventoryEntry],
+        thresholds: &[u64],
+        notified: &mut HashMap<String, u64>,
+        now: i64,
+    ) -> Vec<Notification> {
+        let mut notifications = Vec::new();
+        let mut seen = HashMap::new();
+
+        for entry in entries {
+            for cert in &entry.certificates {
+                let (fingerprint, notafter) = match (&cert.fingerprint, cert.notafter) {
+                    (Some(fingerprint), Some(notafter)) => (fingerprint, notafter),
+                    _ => continue,
+                };
+
+                let level = if notafter < now {
+                    0
+                } else {
+                    match thresholds
+                        .iter()
+                        .copied()
+                        .filter(|days| notafter - now <= *days as i64 * DAY)
+                        .min()
+                    {
+                        Some(level) => level,
+                        None => continue,
+                    }
+                };
+
+                seen.insert(fingerprint.clone(), level);
+                if notified.get(fingerprint).is_some_and(|prev| *prev <= level) {
+                    continue;
+                }
+
+                let severity = match level {
+                    0 => Severity::Error,
+                    1..=7 => Severity::Warning,
+                    _ => Severity::Notice,
+                };
+
+                let data = json!({
+                    "name": entry.name,
+                    "filename": entry.filename,
+                    "subject": cert.subject,
+                    "issuer": cert.issuer,
+                    "fingerprint": fingerprint,
+                    "notafter": notafter,
+                    "days-left": (notafter - now).max(0) / DAY,
+                    "expired": level == 0,
+                });
+
+                let fields = HashMap::from([
+                    ("type".to_string(), CERTIFICATE_EXPIRY_TEMPLATE.to_string()),
+                    ("hostname".to_string(), proxmox_sys::nodename().to_string()),
+                    ("certificate-location".to_string(), entry.name.clone()),
+                ]);
+
+                notifications.push(Notification::from_template(
+                    severity,
+                    CERTIFICATE_EXPIRY_TEMPLATE,
+                    data,
+                    fields,
+                ));
+            }
+        }
+
+        // forget certificates which were replaced or are not expiring anymore
+        *notified = seen;
+
+        notifications
+    }
+
+    /// Send notifications for certificates which crossed an expiry threshold.
+    ///
+    /// The product needs to provide the `certificate-expiry` template. The thresholds already
+    /// notified about are kept in the ACME configuration directory.
+    pub fn notify_expiring_certificates(
+        bus: &Bus,
+        entries: &[CertificateInventoryEntry],
+        thresholds: &[u64],
+        now: i64,
+    ) -> Result<(), Error> {
+        let _lock = open_api_lockfile(crate::certificate_expiry_state_lockfile(), None, true)?;
+
+        let filename = crate::certificate_expiry_state_filename();
+        let mut notified: HashMap<String, u64> =
+            match proxmox_sys::fs::file_read_optional_string(&filename)? {
+                Some(content) => serde_json::from_str(&content)?,
+                None => HashMap::new(),
+            };
+
+        for notification in expiry_notifications(entries, thresholds, &mut notified, now) {
+            bus.send(&notification);
+        }
+
+        let raw = serde_json::to_string_pretty(&notified)?;
+        replace_config(filename, raw.as_bytes())
+    }
+}
+
+#[cfg(feature = "notify")]
+pub use notify::{expiry_notifications, notify_expiring_certificates, CERTIFICATE_EXPIRY_TEMPLATE};
+
+#[cfg(test)]
+mod test {
+    use openssl::asn1::{Asn1Integer, Asn1Time};
+    use openssl::bn::BigNum;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::pkey::Private;
+    use openssl::x509::extension::BasicConstraints;
+    use openssl::x509::{X509Builder, X509NameBuilder};
+
+    use super::*;
+
+    fn generate_key() -> PKey<Private> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap()
+    }
+
+    /// Create a certificate valid until `notafter`, signed by `issuer` or self-signed.
+    fn generate_cert(
+        common_name: &str,
+        key: &PKey<Private>,
+        issuer: Option<(&X509, &PKey<Private>)>,
+        ca: bool,
+        notafter: i64,
+    ) -> X509 {
+        let mut subject = X509NameBuilder::new().unwrap();
+        subject.append_entry_by_text("CN", common_name).unwrap();
+        let subject = subject.build();
+
+        let mut builder = X509Builder::new().unwrap();
+        builder.set_version(2).unwrap();
+        let serial = Asn1Integer::from_bn(&BigNum::from_u32(1).unwrap()).unwrap();
+        builder.set_serial_number(&serial).unwrap();
+        builder.set_subject_name(&subject).unwrap();
+        builder.set_pubkey(key).unwrap();
+        let notbefore = Asn1Time::from_unix(proxmox_time::epoch_i64() - DAY).unwrap();
+        builder.set_not_before(&notbefore).unwrap();
+        let notafter = Asn1Time::from_unix(notafter).unwrap();
+        builder.set_not_after(&notafter).unwrap();
+
+        let (issuer_name, signing_key) = match issuer {
+            Some((issuer, issuer_key)) => (issuer.subject_name(), issuer_key),
+            None => (subject.as_ref(), key),
+        };
+        builder.set_issuer_name(issuer_name).unwrap();
+        if ca {
+            let constraints = BasicConstraints::new().critical().ca().build().unwrap();
+            builder.append_extension(constraints).unwrap();
+        }
+
+        builder.sign(signing_key, MessageDigest::sha256()).unwrap();
+        builder.build()
+    }
+
+    fn to_pem(chain: &[&X509]) -> Vec<u8> {
+        chain
+            .iter()
+            .flat_map(|cert| cert.to_pem().unwrap())
+            .collect()
+    }
+
+    fn location(ty: CertificateFileType) -> CertificateLocation {
+        CertificateLocation {
+            name: "node".to_string(),
+            path: "/etc/test/node.pem".to_string(),
+            key: None,
+            ty: Some(ty),
+        }
+    }
+
+    fn issue_types(entry: &CertificateInventoryEntry) -> Vec<(CertificateIssueType, Option<u32>)> {
+        entry
+            .issues
+            .iter()
+            .map(|issue| (issue.ty, issue.index))
+            .collect()
+    }
+
+    #[test]
+    fn test_inspect_chain() {
+        let now = proxmox_time::epoch_i64();
+
+        let root_key = generate_key();
+        let root = generate_cert("Root CA", &root_key, None, true, now + 3650 * DAY);
+        let intermediate_key = generate_key();
+        let intermediate = generate_cert(
+            "Intermediate CA",
+            &intermediate_key,
+            Some((&root, &root_key)),
+            true,
+            now + 365 * DAY,
+        );
+        let leaf_key = generate_key();
+        let leaf = generate_cert(
+            "node.example.com",
+            &leaf_key,
+            Some((&intermediate, &intermediate_key)),
+            false,
+            now + 90 * DAY,
+        );
+
+        let mut store = ca_store_builder().unwrap();
+        store.add_cert(root.clone()).unwrap();
+        let store = store.build();
+
+        let location = location(CertificateFileType::Chain);
+        let key = || Some(Ok(leaf_key.private_key_to_pem_pkcs8().unwrap()));
+
+        let chain = to_pem(&[&leaf, &intermediate]);
+        let entry = inspect_certificates(
+            &location,
+            "node.pem",
+            &chain,
+            key(),
+            Some(&store),
+            DEFAULT_EXPIRY_THRESHOLDS,
+            now,
+        );
+        assert_eq!(entry.certificates.len(), 2);
+        assert_eq!(entry.certificates[1].subject, "CN = Intermediate CA");
+        assert!(entry.issues.is_empty());
+
+        let reversed = to_pem(&[&intermediate, &leaf]);
+        let entry = inspect_certificates(
+            &location,
+            "node.pem",
+            &reversed,
+            None,
+            None,
+            DEFAULT_EXPIRY_THRESHOLDS,
+            now,
+        );
+        assert_eq!(
+            issue_types(&entry),
+            [(CertificateIssueType::ChainOrder, Some(1))]
+        );
+
+        let mut empty_store = ca_store_builder().unwrap().build();
+        let entry = inspect_certificates(
+            &location,
+            "node.pem",
+            &chain,
+            None,
+            Some(&empty_store),
+            DEFAULT_EXPIRY_THRESHOLDS,
+            now,
+        );
+        assert_eq!(
+            issue_types(&entry),
+            [(CertificateIssueType::Untrusted, Some(0))]
+        );
+
+        // the intermediate alone is not enough to verify the chain
+        empty_store = {
+            let mut store = ca_store_builder().unwrap();
+            store.add_cert(intermediate.clone()).unwrap();
+            store.build()
+        };
+        let leaf_only = to_pem(&[&leaf]);
+        let entry = inspect_certificates(
+            &location,
+            "node.pem",
+            &leaf_only,
+            None,
+            Some(&empty_store),
+            DEFAULT_EXPIRY_THRESHOLDS,
+            now,
+        );
+        assert_eq!(
+            issue_types(&entry),
+            [(CertificateIssueType::Untrusted, Some(0))]
+        );
+
+        let wrong_key = Some(Ok(generate_key().private_key_to_pem_pkcs8().unwrap()));
+        let entry = inspect_certificates(
+            &location,
+            "node.pem",
+            &chain,
+            wrong_key,
+            Some(&store),
+            DEFAULT_EXPIRY_THRESHOLDS,
+            now,
+        );
+        assert_eq!(
+            issue_types(&entry),
+            [(CertificateIssueType::KeyMismatch, Some(0))]
+        );
+
+        let entry = inspect_certificates(
+            &location,
+            "node.pem",
+            b"garbage",
+            None,
+            None,
+            DEFAULT_EXPIRY_THRESHOLDS,
+            now,
+        );
+        assert_eq!(
+            issue_types(&entry),
+            [(CertificateIssueType::Unreadable, None)]
+        );
+    }
+
+    #[test]
+    fn test_inspect_expiry() {
+        let now = proxmox_time::epoch_i64();
+
+        let root_key = generate_key();
+        let root = generate_cert("Root CA", &root_key, None, true, now + 3650 * DAY);
+        let expiring = generate_cert("Expiring CA", &generate_key(), None, true, now + 5 * DAY);
+        let expired = generate_cert("Expired CA", &generate_key(), None, true, now - 2 * DAY);
+
+        // bundles are not checked for chain order
+        let bundle = to_pem(&[&root, &expiring, &expired]);
+        let entry = inspect_certificates(
+            &location(CertificateFileType::Bundle),
+            "ca-bundle.pem",
+            &bundle,
+            None,
+            None,
+            DEFAULT_EXPIRY_THRESHOLDS,
+            now,
+        );
+        assert_eq!(
+            issue_types(&entry),
+            [
+                (CertificateIssueType::Expiring, Some(1)),
+                (CertificateIssueType::Expired, Some(2)),
+            ]
+        );
+        assert_eq!(entry.issues[0].message, "certificate expires in 5 days");
+        assert_eq!(entry.issues[1].message, "certificate expired 2 days ago");
+
+        // an expired certificate is not reported as untrusted as well
+        let leaf_key = generate_key();
+        let leaf = generate_cert(
+            "expired",
+            &leaf_key,
+            Some((&root, &root_key)),
+            false,
+            now - DAY,
+        );
+        let mut store = ca_store_builder().unwrap();
+        store.add_cert(root).unwrap();
+        let store = store.build();
+        let entry = inspect_certificates(
+            &location(CertificateFileType::Chain),
+            "node.pem",
+            &to_pem(&[&leaf]),
+            None,
+            Some(&store),
+            &[],
+            now,
+        );
+        assert_eq!(
+            issue_types(&entry),
+            [(CertificateIssueType::Expired, Some(0))]
+        );
+    }
+
+    #[test]
+    fn test_verify_expired_chain() {
+        let now = proxmox_time::epoch_i64();
+
+        let root_key = generate_key();
+        let root = generate_cert("Root CA", &root_key, None, true, now + 3650 * DAY);
+        let intermediate_key = generate_key();
+        let intermediate = generate_cert(
+            "Intermediate CA",
+            &intermediate_key,
+            Some((&root, &root_key)),
+            true,
+            now - DAY,
+        );
+
+        let mut store = ca_store_builder().unwrap();
+        store.add_cert(root).unwrap();
+        let store = store.build();
+
+        // the expired intermediate must not stop the verification of the leaf's signature
+        let leaf = generate_cert(
+            "node.example.com",
+            &generate_key(),
+            Some((&intermediate, &generate_key())),
+            false,
+            now + 90 * DAY,
+        );
+        let err = verify_chain(&[leaf, intermediate.clone()], &store).unwrap_err();
+        assert!(err.contains("signature"), "{err}");
+
+        // with a valid signature only the expiry is reported
+        let leaf = generate_cert(
+            "node.example.com",
+            &generate_key(),
+            Some((&intermediate, &intermediate_key)),
+            false,
+            now + 90 * DAY,
+        );
+        let entry = inspect_certificates(
+            &location(CertificateFileType::Chain),
+            "node.pem",
+            &to_pem(&[&leaf, &intermediate]),
+            None,
+            Some(&store),
+            &[],
+            now,
+        );
+        assert_eq!(
+            issue_types(&entry),
+            [(CertificateIssueType::Expired, Some(1))]
+        );
+    }
+
+    #[cfg(feature = "notify")]
+    #[test]
+    fn test_expiry_notifications() {
+        use std::collections::HashMap;
+
+        let now = proxmox_time::epoch_i64();
+        let key = generate_key();
+        let cert = generate_cert("node.example.com", &key, None, false, now + 20 * DAY);
+        let entry = inspect_certificates(
+            &location(CertificateFileType::Chain),
+            "node.pem",
+            &to_pem(&[&cert]),
+            None,
+            None,
+            DEFAULT_EXPIRY_THRESHOLDS,
+            now,
+        );
+        let fingerprint = entry.certificates[0].fingerprint.clone().unwrap();
+        let entries = [entry];
+
+        let mut notified = HashMap::new();
+        let notifications =
+            expiry_notifications(&entries, DEFAULT_EXPIRY_THRESHOLDS, &mut notified, now);
+        assert_eq!(notifications.len(), 1);
+        assert_eq!(notified.get(&fingerprint), Some(&30));
+
+        // each threshold is only reported once
+        let notifications = expiry_notifications(
+            &entries,
+            DEFAULT_EXPIRY_THRESHOLDS,
+            &mut notified,
+            now + DAY,
+        );
+        assert!(notifications.is_empty());
+
+        let notifications = expiry_notifications(
+            &entries,
+            DEFAULT_EXPIRY_THRESHOLDS,
+            &mut notified,
+            now + 14 * DAY,
+        );
+        assert_eq!(notifications.len(), 1);
+        assert_eq!(notified.get(&fingerprint), Some(&7));
+
+        let notifications = expiry_notifications(
+            &entries,
+            DEFAULT_EXPIRY_THRESHOLDS,
+            &mut notified,
+            now + 21 * DAY,
+        );
+        assert_eq!(notifications.len(), 1);
+        assert_eq!(notified.get(&fingerprint), Some(&0));
+
+        // not expiring soon
+        let notifications = expiry_notifications(&entries, &[7], &mut notified, now);
+        assert!(notifications.is_empty());
+        assert!(notified.is_empty());
+    }
+}
@@ -53,3 +53,21 @@ pub(crate) fn plugin_cfg_filename() -> PathBuf {
 pub(crate) fn plugin_cfg_lockfile() -> PathBuf {
     acme_config_dir().join("plugins.lck")
 }
+
+pub(crate) fn renewal_state_filename() -> PathBuf {
+    acme_config_dir().join("renewal-state.json")
+}
+
+pub(crate) fn renewal_state_lockfile() -> PathBuf {
+    acme_config_dir().join("renewal-state.lck")
+}
+
+#[cfg(feature = "notify")]
+pub(crate) fn certificate_expiry_state_filename() -> PathBuf {
+    acme_config_dir().join("certificate-expiry.json")
+}
+
+#[cfg(feature = "notify")]
+pub(crate) fn certificate_expiry_state_lockfile() -> PathBuf {
+    acme_config_dir().join("certificate-expiry.lck")
+}
@@ -35,12 +35,28 @@ pub use account_api_impl::{
 #[cfg(feature = "impl")]
 mod plugin_api_impl;
 #[cfg(feature = "impl")]
-pub use plugin_api_impl::{add_plugin, delete_plugin, get_plugin, list_plugins, update_plugin};
+pub use plugin_api_impl::{
+    add_plugin, add_rfc2136_plugin, delete_plugin, get_plugin, list_plugins, update_plugin,
+    update_rfc2136_plugin,
+};
 
 #[cfg(feature = "impl")]
 pub(crate) mod acme_plugin;
 
+#[cfg(feature = "impl")]
+mod rfc2136;
+
 #[cfg(feature = "impl")]
 mod certificate_helpers;
 #[cfg(feature = "impl")]
-pub use certificate_helpers::{create_self_signed_cert, order_certificate, revoke_certificate};
+pub use certificate_helpers::{
+    create_self_signed_cert, order_certificate, order_certificate_replacing, revoke_certificate,
+};
+
+#[cfg(feature = "impl")]
+pub mod certificate_inventory;
+
+#[cfg(feature = "impl")]
+mod renewal;
+#[cfg(feature = "impl")]
+pub use renewal::{check_renewal, load_renewal_state, renew_certificate, save_renewal_state};
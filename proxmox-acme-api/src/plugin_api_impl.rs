@@ -9,7 +9,8 @@ use proxmox_config_digest::ConfigDigest;
 use proxmox_schema::param_bail;
 
 use crate::types::{
-    DeletablePluginProperty, DnsPlugin, DnsPluginCore, DnsPluginCoreUpdater, PluginConfig,
+    DeletablePluginProperty, DeletableRfc2136PluginProperty, DnsPlugin, DnsPluginCore,
+    DnsPluginCoreUpdater, PluginConfig, Rfc2136Plugin, Rfc2136PluginUpdater,
 };
 
 use proxmox_router::{http_bail, RpcEnvironment};
@@ -35,7 +36,8 @@ pub fn get_plugin(id: String, rpcenv: &mut dyn RpcEnvironment) -> Result<PluginC
 }
 
 pub fn add_plugin(r#type: String, core: DnsPluginCore, data: String) -> Result<(), Error> {
-    // Currently we only support DNS plugins and the standalone plugin is "fixed":
+    // Currently we only support DNS plugins and the standalone plugin is "fixed", RFC 2136
+    // plugins are added with `add_rfc2136_plugin`:
     if r#type != "dns" {
         param_bail!("type", "invalid ACME plugin type: {:?}", r#type);
     }
@@ -125,6 +127,102 @@ pub fn update_plugin(
     Ok(())
 }
 
+pub fn add_rfc2136_plugin(plugin: Rfc2136Plugin) -> Result<(), Error> {
+    // make sure the key is usable before storing it
+    crate::rfc2136::Rfc2136Client::new(&plugin)?;
+
+    let id = plugin.id.clone();
+
+    let _lock = super::plugin_config::lock_plugin_config()?;
+
+    let (mut plugins, _digest) = super::plugin_config::plugin_config()?;
+    if plugins.contains_key(&id) {
+        param_bail!("id", "ACME plugin ID {:?} already exists", id);
+    }
+
+    plugins.insert(id, "rfc2136".to_string(), serde_json::to_value(plugin)?);
+
+    super::plugin_config::save_plugin_config(&plugins)?;
+
+    Ok(())
+}
+
+pub fn update_rfc2136_plugin(
+    id: String,
+    update: Rfc2136PluginUpdater,
+    delete: Option<Vec<DeletableRfc2136PluginProperty>>,
+    digest: Option<ConfigDigest>,
+) -> Result<(), Error> {
+    let _lock = super::plugin_config::lock_plugin_config()?;
+
+    let (mut plugins, expected_digest) = super::plugin_config::plugin_config()?;
+
+    expected_digest.detect_modification(digest.as_ref())?;
+
+    match plugins.get_mut(&id) {
+        Some((ty, ref mut entry)) => {
+            if ty != "rfc2136" {
+                bail!("cannot update plugin of type {:?}", ty);
+            }
+
+            let mut plugin = Rfc2136Plugin::deserialize(&*entry)?;
+
+            if let Some(delete) = delete {
+                for delete_prop in delete {
+                    match delete_prop {
+                        DeletableRfc2136PluginProperty::Zone => plugin.zone = None,
+                        DeletableRfc2136PluginProperty::TsigAlgorithm => {
+                            plugin.tsig_algorithm = None
+                        }
+                        DeletableRfc2136PluginProperty::Ttl => plugin.ttl = None,
+                        DeletableRfc2136PluginProperty::Nameservers => plugin.nameservers = None,
+                        DeletableRfc2136PluginProperty::PropagationTimeout => {
+                            plugin.propagation_timeout = None
+                        }
+                        DeletableRfc2136PluginProperty::Disable => plugin.disable = None,
+                    }
+                }
+            }
+            if let Some(server) = update.server {
+                plugin.server = server;
+            }
+            if update.zone.is_some() {
+                plugin.zone = update.zone;
+            }
+            if let Some(tsig_key_name) = update.tsig_key_name {
+                plugin.tsig_key_name = tsig_key_name;
+            }
+            if update.tsig_algorithm.is_some() {
+                plugin.tsig_algorithm = update.tsig_algorithm;
+            }
+            if let Some(tsig_key) = update.tsig_key {
+                plugin.tsig_key = tsig_key;
+            }
+            if update.ttl.is_some() {
+                plugin.ttl = update.ttl;
+            }
+            if update.nameservers.is_some() {
+                plugin.nameservers = update.nameservers;
+            }
+            if update.propagation_timeout.is_some() {
+                plugin.propagation_timeout = update.propagation_timeout;
+            }
+            if update.disable.is_some() {
+                plugin.disable = update.disable;
+            }
+
+            crate::rfc2136::Rfc2136Client::new(&plugin)?;
+
+            *entry = serde_json::to_value(plugin)?;
+        }
+        None => http_bail!(NOT_FOUND, "no such plugin"),
+    }
+
+    super::plugin_config::save_plugin_config(&plugins)?;
+
+    Ok(())
+}
+
 pub fn delete_plugin(id: String) -> Result<(), Error> {
     let _lock = super::plugin_config::lock_plugin_config()?;
 
@@ -143,6 +241,8 @@ fn modify_cfg_for_api(id: &str, ty: &str, data: &Value) -> PluginConfig {
 
     let obj = entry.as_object_mut().unwrap();
     obj.remove("id");
+    // never hand out the TSIG secret of RFC 2136 plugins
+    obj.remove("tsig-key");
     obj.insert("plugin".to_string(), Value::String(id.to_owned()));
     obj.insert("type".to_string(), Value::String(ty.to_owned()));
 
@@ -167,3 +267,41 @@ fn modify_cfg_for_api(id: &str, ty: &str, data: &Value) -> PluginConfig {
         ..Default::default()
     })
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::types::TsigAlgorithm;
+
+    #[test]
+    fn test_rfc2136_plugin_for_api() {
+        let plugin = Rfc2136Plugin {
+            id: "ddns".to_string(),
+            server: "ns1.example.com:5353".to_string(),
+            zone: Some("example.com".to_string()),
+            tsig_key_name: "acme-key".to_string(),
+            tsig_algorithm: Some(TsigAlgorithm::HmacSha512),
+            tsig_key: base64::encode(b"very secret"),
+            ttl: Some(120),
+            nameservers: Some(vec!["192.0.2.1".to_string()]),
+            propagation_timeout: Some(600),
+            disable: None,
+        };
+
+        let config = modify_cfg_for_api("ddns", "rfc2136", &serde_json::to_value(plugin).unwrap());
+        assert_eq!(config.plugin, "ddns");
+        assert_eq!(config.ty, "rfc2136");
+        assert_eq!(config.server.as_deref(), Some("ns1.example.com:5353"));
+        assert_eq!(config.zone.as_deref(), Some("example.com"));
+        assert_eq!(config.tsig_key_name.as_deref(), Some("acme-key"));
+        assert_eq!(config.tsig_algorithm, Some(TsigAlgorithm::HmacSha512));
+        assert_eq!(config.ttl, Some(120));
+        assert_eq!(config.nameservers, Some(vec!["192.0.2.1".to_string()]));
+        assert_eq!(config.propagation_timeout, Some(600));
+
+        let output = serde_json::to_value(&config).unwrap();
+        assert!(output.get("tsig-key").is_none());
+        assert_eq!(output["tsig-key-name"], "acme-key");
+    }
+}
@@ -9,7 +9,7 @@ use proxmox_product_config::{open_api_lockfile, replace_secret_config, ApiLockGu
 use proxmox_schema::{ApiType, Schema};
 use proxmox_section_config::{SectionConfig, SectionConfigData, SectionConfigPlugin};
 
-use crate::types::{DnsPlugin, StandalonePlugin, PLUGIN_ID_SCHEMA};
+use crate::types::{DnsPlugin, Rfc2136Plugin, StandalonePlugin, TlsAlpnPlugin, PLUGIN_ID_SCHEMA};
 
 lazy_static! {
     static ref CONFIG: SectionConfig = init();
@@ -39,6 +39,17 @@ fn init() -> SectionConfig {
     );
     config.register_plugin(standalone_plugin);
 
+    let tls_alpn_schema = match &TlsAlpnPlugin::API_SCHEMA {
+        Schema::Object(schema) => schema,
+        _ => unreachable!(),
+    };
+    let tls_alpn_plugin = SectionConfigPlugin::new(
+        "tls-alpn".to_string(),
+        Some("id".to_string()),
+        tls_alpn_schema,
+    );
+    config.register_plugin(tls_alpn_plugin);
+
     let dns_challenge_schema = match DnsPlugin::API_SCHEMA {
         Schema::AllOf(ref schema) => schema,
         _ => unreachable!(),
@@ -50,6 +61,17 @@ fn init() -> SectionConfig {
     );
     config.register_plugin(dns_challenge_plugin);
 
+    let rfc2136_schema = match &Rfc2136Plugin::API_SCHEMA {
+        Schema::Object(schema) => schema,
+        _ => unreachable!(),
+    };
+    let rfc2136_plugin = SectionConfigPlugin::new(
+        "rfc2136".to_string(),
+        Some("id".to_string()),
+        rfc2136_schema,
+    );
+    config.register_plugin(rfc2136_plugin);
+
     config
 }
 
@@ -73,6 +95,11 @@ pub(crate) fn plugin_config() -> Result<(PluginData, ConfigDigest), Error> {
             .unwrap();
     }
 
+    if !data.sections.contains_key("tls-alpn") {
+        let tls_alpn = TlsAlpnPlugin::default();
+        data.set_data("tls-alpn", "tls-alpn", &tls_alpn).unwrap();
+    }
+
     Ok((PluginData { data }, digest))
 }
 
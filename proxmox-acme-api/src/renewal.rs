@@ -0,0 +1,455 @@
+//! Certificate renewal planning.
+//!
+//! Decides when a certificate should be renewed. If the ACME server supports ACME Renewal
+//! Information (ARI) its suggested window is used, otherwise the window is derived from the
+//! certificate's lifetime. The actual renewal time is chosen randomly within the window, so that
+//! many hosts do not renew at the same time, and failed attempts are retried with an exponential
+//! backoff.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::{bail, Error};
+
+use proxmox_acme::renewal_info::{certificate_id, RenewalInfo};
+use proxmox_product_config::{open_api_lockfile, replace_secret_config, ApiLockGuard};
+use proxmox_rest_server::WorkerTask;
+use proxmox_sys::{task_log, task_warn};
+
+use crate::certificate_helpers::OrderedCertificate;
+use crate::types::{
+    AcmeConfig, AcmeDomain, CertificateInfo, RenewalPlan, RenewalReason, RenewalState,
+};
+
+/// Renew certificates at most this long before they expire if there is no ARI window.
+const DEFAULT_RENEW_BEFORE: i64 = 30 * 24 * 3600;
+
+/// Default interval for fetching the renewal information again.
+const ARI_DEFAULT_CHECK_INTERVAL: i64 = 6 * 3600;
+const ARI_MIN_CHECK_INTERVAL: i64 = 3600;
+const ARI_MAX_CHECK_INTERVAL: i64 = 24 * 3600;
+
+/// Delay after the first failed renewal attempt, doubled for each further failure.
+const BACKOFF_BASE: i64 = 3600;
+const BACKOFF_MAX: i64 = 24 * 3600;
+
+impl RenewalState {
+    /// Check if the renewal information should be fetched (again).
+    pub fn needs_renewal_info(&self, now: i64) -> bool {
+        self.ari_next_check.map(|next| now >= next).unwrap_or(true)
+    }
+
+    /// Store the renewal information returned by the ACME server.
+    pub fn update_renewal_info(&mut self, info: &RenewalInfo, now: i64) -> Result<(), Error> {
+        let start = proxmox_time::parse_rfc3339(&info.suggested_window.start)?;
+        let end = proxmox_time::parse_rfc3339(&info.suggested_window.end)?;
+        if end < start {
+            bail!("invalid renewal window - end is before start");
+        }
+
+        self.ari_window_start = Some(start);
+        self.ari_window_end = Some(end);
+        self.ari_explanation_url = info.explanation_url.clone();
+
+        let interval = info
+            .retry_after
+            .map(|secs| {
+                (secs.min(i64::MAX as u64) as i64)
+                    .clamp(ARI_MIN_CHECK_INTERVAL, ARI_MAX_CHECK_INTERVAL)
+            })
+            .unwrap_or(ARI_DEFAULT_CHECK_INTERVAL);
+        self.ari_next_check = Some(now + interval);
+
+        Ok(())
+    }
+
+    /// Record a successful renewal. The schedule is recomputed for the new certificate.
+    pub fn record_success(&mut self, now: i64) {
+        *self = RenewalState {
+            last_attempt: Some(now),
+            last_success: Some(now),
+            ..Default::default()
+        };
+    }
+
+    /// Record a failed renewal attempt.
+    pub fn record_failure(&mut self, now: i64, error: &Error) {
+        self.last_attempt = Some(now);
+        self.last_error = Some(error.to_string());
+        self.failures += 1;
+    }
+
+    /// Plan the renewal of the certificate described by `info`.
+    ///
+    /// The chosen renewal time is kept in the state and only chosen again if the renewal window
+    /// changes, so the state needs to be saved afterwards.
+    pub fn plan(&mut self, info: &CertificateInfo, now: i64) -> RenewalPlan {
+        self.plan_with(info, now, random_offset)
+    }
+
+    fn plan_with<R>(&mut self, info: &CertificateInfo, now: i64, random: R) -> RenewalPlan
+    where
+        R: FnOnce(i64) -> i64,
+    {
+        self.reset_for_certificate(info);
+
+        let (reason, renew_at) = match self.window(info) {
+            Some((reason, start, end)) => {
+                if self.renew_at.is_none()
+                    || self.window_start != Some(start)
+                    || self.window_end != Some(end)
+                {
+                    self.window_start = Some(start);
+                    self.window_end = Some(end);
+                    self.renew_at = Some(start + random(end - start));
+                }
+                (reason, self.renew_at.unwrap_or(start))
+            }
+            None => (RenewalReason::Unknown, now),
+        };
+
+        let renew_at = match (self.failures, self.last_attempt) {
+            (0, _) | (_, None) => renew_at,
+            (failures, Some(last_attempt)) => renew_at.max(last_attempt + backoff(failures)),
+        };
+
+        RenewalPlan {
+            renew_at,
+            reason,
+            due: now >= renew_at,
+        }
+    }
+
+    /// Forget everything about a previous certificate if the certificate was replaced.
+    fn reset_for_certificate(&mut self, info: &CertificateInfo) {
+        if self.fingerprint != info.fingerprint {
+            *self = RenewalState {
+                fingerprint: info.fingerprint.clone(),
+                last_attempt: self.last_attempt,
+                last_success: self.last_success,
+                ..Default::default()
+            };
+        }
+    }
+
+    /// The renewal window, preferring the one suggested by the ACME server.
+    fn window(&self, info: &CertificateInfo) -> Option<(RenewalReason, i64, i64)> {
+        if let (Some(start), Some(end)) = (self.ari_window_start, self.ari_window_end) {
+            return Some((RenewalReason::Ari, start, end));
+        }
+
+        let notafter = info.notafter?;
+        let renew_before = match info.notbefore {
+            Some(notbefore) if notafter > notbefore => {
+                DEFAULT_RENEW_BEFORE.min((notafter - notbefore) / 3)
+            }
+            _ => DEFAULT_RENEW_BEFORE,
+        };
+
+        Some((
+            RenewalReason::Expiry,
+            notafter - renew_before,
+            notafter - renew_before / 2,
+        ))
+    }
+}
+
+fn backoff(failures: u64) -> i64 {
+    let shift = failures.saturating_sub(1).min(16) as u32;
+    (BACKOFF_BASE << shift).min(BACKOFF_MAX)
+}
+
+/// A uniformly distributed random offset in `0..=range`.
+fn random_offset(range: i64) -> i64 {
+    if range <= 0 {
+        return 0;
+    }
+
+    let mut bytes = [0u8; 8];
+    if openssl::rand::rand_bytes(&mut bytes).is_err() {
+        return range / 2;
+    }
+
+    (u64::from_le_bytes(bytes) % (range as u64 + 1)) as i64
+}
+
+fn lock_renewal_state() -> Result<ApiLockGuard, Error> {
+    open_api_lockfile(crate::renewal_state_lockfile(), None, true)
+}
+
+fn load_renewal_states() -> Result<HashMap<String, RenewalState>, Error> {
+    let filename = crate::renewal_state_filename();
+    match proxmox_sys::fs::file_read_optional_string(&filename)? {
+        Some(content) => Ok(serde_json::from_str(&content)?),
+        None => Ok(HashMap::new()),
+    }
+}
+
+/// Load the renewal state of the certificate `name`.
+pub fn load_renewal_state(name: &str) -> Result<RenewalState, Error> {
+    Ok(load_renewal_states()?.remove(name).unwrap_or_default())
+}
+
+/// Save the renewal state of the certificate `name`.
+pub fn save_renewal_state(name: &str, state: &RenewalState) -> Result<(), Error> {
+    let _lock = lock_renewal_state()?;
+
+    let mut states = load_renewal_states()?;
+    states.insert(name.to_string(), state.clone());
+
+    let raw = serde_json::to_string_pretty(&states)?;
+    replace_secret_config(crate::renewal_state_filename(), raw.as_bytes())
+}
+
+/// Check when the certificate `name` should be renewed.
+///
+/// Fetches the renewal information from the ACME server if it is due and saves the updated
+/// renewal state.
+pub async fn check_renewal(
+    acme_config: &AcmeConfig,
+    name: &str,
+    info: &CertificateInfo,
+) -> Result<RenewalPlan, Error> {
+    let now = proxmox_time::epoch_i64();
+    let mut state = load_renewal_state(name)?;
+    state.reset_for_certificate(info);
+
+    if let Some(pem) = info
+        .pem
+        .as_deref()
+        .filter(|_| state.needs_renewal_info(now))
+    {
+        let mut acme = crate::account_config::load_account_config(&acme_config.account)
+            .await?
+            .client();
+
+        match acme.renewal_info(pem.as_bytes()).await {
+            Ok(Some(renewal_info)) => {
+                if let Err(err) = state.update_renewal_info(&renewal_info, now) {
+                    log::warn!("ignoring renewal information for '{}' - {}", name, err);
+                    state.ari_next_check = Some(now + ARI_DEFAULT_CHECK_INTERVAL);
+                }
+            }
+            // not supported by the ACME server
+            Ok(None) => state.ari_next_check = Some(now + ARI_MAX_CHECK_INTERVAL),
+            Err(err) => {
+                log::warn!("failed to get renewal information for '{}' - {}", name, err);
+                state.ari_next_check = Some(now + ARI_MIN_CHECK_INTERVAL);
+            }
+        }
+    }
+
+    let plan = state.plan(info, now);
+    save_renewal_state(name, &state)?;
+
+    Ok(plan)
+}
+
+/// Renew the certificate `name` if it is due, or unconditionally if `force` is set.
+///
+/// The attempt is recorded in the renewal state. Returns `None` if the certificate is not due
+/// for renewal yet.
+pub async fn renew_certificate(
+    worker: Arc<WorkerTask>,
+    acme_config: &AcmeConfig,
+    name: &str,
+    info: &CertificateInfo,
+    domains: &[AcmeDomain],
+    force: bool,
+) -> Result<Option<OrderedCertificate>, Error> {
+    let now = proxmox_time::epoch_i64();
+    let mut state = load_renewal_state(name)?;
+
+    let plan = state.plan(info, now);
+    if !force && !plan.due {
+        task_log!(
+            worker,
+            "Certificate '{}' is not due for renewal before {}",
+            name,
+            proxmox_time::epoch_to_rfc3339_utc(plan.renew_at)?
+        );
+        save_renewal_state(name, &state)?;
+        return Ok(None);
+    }
+
+    // only servers which provided renewal information support the 'replaces' field
+    let replaces = match (&info.pem, state.ari_window_start) {
+        (Some(pem), Some(_)) => certificate_id(pem.as_bytes()).ok(),
+        _ => None,
+    };
+
+    let result = match crate::certificate_helpers::order_certificate_replacing(
+        Arc::clone(&worker),
+        acme_config,
+        domains,
+        replaces.clone(),
+    )
+    .await
+    {
+        Err(err) if replaces.is_some() && is_already_replaced(&err) => {
+            task_warn!(
+                worker,
+                "Certificate was already replaced, ordering a new one instead"
+            );
+            crate::certificate_helpers::order_certificate(Arc::clone(&worker), acme_config, domains)
+                .await
+        }
+        result => result,
+    };
+
+    let now = proxmox_time::epoch_i64();
+    match &result {
+        Ok(Some(_)) => state.record_success(now),
+        Ok(None) => (),
+        Err(err) => state.record_failure(now, err),
+    }
+    save_renewal_state(name, &state)?;
+
+    result
+}
+
+fn is_already_replaced(err: &Error) -> bool {
+    matches!(
+        err.downcast_ref::<proxmox_acme::Error>(),
+        Some(proxmox_acme::Error::Api(err)) if err.ty == proxmox_acme::error::ALREADY_REPLACED
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const DAY: i64 = 24 * 3600;
+
+    fn certificate(notbefore: i64, notafter: i64, fingerprint: &str) -> CertificateInfo {
+        CertificateInfo {
+            filename: "test.pem".to_string(),
+            subject: String::new(),
+            san: Vec::new(),
+            issuer: String::new(),
+            notbefore: Some(notbefore),
+            notafter: Some(notafter),
+            pem: None,
+            public_key_type: String::new(),
+            public_key_bits: None,
+            fingerprint: Some(fingerprint.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_default_window() {
+        let mut state = RenewalState::default();
+
+        // 90 day certificate: renew between 30 and 15 days before expiry
+        let info = certificate(0, 90 * DAY, "aa");
+        let plan = state.plan_with(&info, DAY, |range| {
+            assert_eq!(range, 15 * DAY);
+            range
+        });
+        assert_eq!(plan.reason, RenewalReason::Expiry);
+        assert_eq!(plan.renew_at, 75 * DAY);
+        assert!(!plan.due);
+        assert_eq!(state.window_start, Some(60 * DAY));
+        assert_eq!(state.window_end, Some(75 * DAY));
+
+        // the chosen time is kept while the window does not change
+        let plan = state.plan_with(&info, 76 * DAY, |_| panic!("re-rolled renewal time"));
+        assert_eq!(plan.renew_at, 75 * DAY);
+        assert!(plan.due);
+
+        // short lived certificates are renewed after two thirds of their lifetime
+        let mut state = RenewalState::default();
+        let info = certificate(0, 6 * DAY, "bb");
+        let plan = state.plan_with(&info, 0, |_| 0);
+        assert_eq!(plan.renew_at, 4 * DAY);
+
+        // no expiry date
+        let mut state = RenewalState::default();
+        let mut info = certificate(0, 0, "cc");
+        info.notafter = None;
+        let plan = state.plan_with(&info, 42, |_| panic!("no window"));
+        assert_eq!(plan.reason, RenewalReason::Unknown);
+        assert!(plan.due);
+    }
+
+    #[test]
+    fn test_ari_window() {
+        let info = certificate(0, 90 * DAY, "aa");
+        let mut state = RenewalState::default();
+        state.plan_with(&info, 0, |_| 0);
+
+        let renewal_info: RenewalInfo = serde_json::from_str(
+            r#"{
+                "suggestedWindow": {
+                    "start": "1970-01-11T00:00:00Z",
+                    "end": "1970-01-12T00:00:00Z"
+                },
+                "explanationURL": "https://acme.example.com/docs/ari"
+            }"#,
+        )
+        .unwrap();
+
+        assert!(state.needs_renewal_info(0));
+        state.update_renewal_info(&renewal_info, 0).unwrap();
+        assert_eq!(state.ari_next_check, Some(ARI_DEFAULT_CHECK_INTERVAL));
+        assert!(!state.needs_renewal_info(3600));
+        assert_eq!(
+            state.ari_explanation_url.as_deref(),
+            Some("https://acme.example.com/docs/ari")
+        );
+
+        // a changed window re-rolls the renewal time
+        let plan = state.plan_with(&info, 0, |range| {
+            assert_eq!(range, DAY);
+            3600
+        });
+        assert_eq!(plan.reason, RenewalReason::Ari);
+        assert_eq!(plan.renew_at, 10 * DAY + 3600);
+
+        // retry-after is clamped
+        let mut renewal_info = renewal_info;
+        renewal_info.retry_after = Some(10);
+        state.update_renewal_info(&renewal_info, 0).unwrap();
+        assert_eq!(state.ari_next_check, Some(ARI_MIN_CHECK_INTERVAL));
+
+        // a new certificate drops the old renewal information
+        let info = certificate(DAY, 91 * DAY, "bb");
+        let plan = state.plan_with(&info, DAY, |_| 0);
+        assert_eq!(plan.reason, RenewalReason::Expiry);
+        assert_eq!(state.ari_window_start, None);
+        assert!(state.needs_renewal_info(DAY));
+    }
+
+    #[test]
+    fn test_backoff() {
+        let info = certificate(0, 90 * DAY, "aa");
+        let mut state = RenewalState::default();
+        let error = anyhow::format_err!("validation failed");
+
+        let plan = state.plan_with(&info, 61 * DAY, |_| 0);
+        assert!(plan.due);
+
+        state.record_failure(61 * DAY, &error);
+        let plan = state.plan_with(&info, 61 * DAY, |_| panic!("re-rolled renewal time"));
+        assert_eq!(plan.renew_at, 61 * DAY + 3600);
+        assert!(!plan.due);
+
+        state.record_failure(61 * DAY, &error);
+        state.record_failure(61 * DAY, &error);
+        let plan = state.plan_with(&info, 61 * DAY, |_| 0);
+        assert_eq!(plan.renew_at, 61 * DAY + 4 * 3600);
+        assert_eq!(state.failures, 3);
+        assert_eq!(state.last_error.as_deref(), Some("validation failed"));
+
+        for _ in 0..20 {
+            state.record_failure(61 * DAY, &error);
+        }
+        let plan = state.plan_with(&info, 61 * DAY, |_| 0);
+        assert_eq!(plan.renew_at, 62 * DAY);
+
+        state.record_success(62 * DAY);
+        assert_eq!(state.failures, 0);
+        assert_eq!(state.last_success, Some(62 * DAY));
+        assert_eq!(state.last_error, None);
+    }
+}
@@ -0,0 +1,1223 @@
+//! Native RFC 2136 dynamic DNS update client.
+//!
+//! Used by the `rfc2136` ACME plugin to add and remove the DNS-01 TXT record without going through
+//! the acme.sh based DNS plugins. Updates are authenticated with TSIG (RFC 8945), propagation is
+//! checked by querying the authoritative name servers directly.
+
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, format_err, Error};
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+
+use crate::types::{Rfc2136Plugin, TsigAlgorithm};
+
+const TYPE_NS: u16 = 2;
+const TYPE_SOA: u16 = 6;
+const TYPE_TXT: u16 = 16;
+const TYPE_TSIG: u16 = 250;
+
+const CLASS_IN: u16 = 1;
+const CLASS_NONE: u16 = 254;
+const CLASS_ANY: u16 = 255;
+
+const OPCODE_UPDATE: u16 = 5;
+
+const FLAG_QR: u16 = 0x8000;
+const FLAG_TC: u16 = 0x0200;
+
+const RCODE_NOERROR: u16 = 0;
+const RCODE_NXDOMAIN: u16 = 3;
+
+const DNS_PORT: u16 = 53;
+const TSIG_FUDGE: u16 = 300;
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+const QUERY_RETRIES: usize = 3;
+const PROPAGATION_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+impl TsigAlgorithm {
+    /// The algorithm name as used in the TSIG record.
+    fn name(self) -> &'static str {
+        match self {
+            TsigAlgorithm::HmacSha1 => "hmac-sha1",
+            TsigAlgorithm::HmacSha256 => "hmac-sha256",
+            TsigAlgorithm::HmacSha384 => "hmac-sha384",
+            TsigAlgorithm::HmacSha512 => "hmac-sha512",
+        }
+    }
+
+    fn digest(self) -> MessageDigest {
+        match self {
+            TsigAlgorithm::HmacSha1 => MessageDigest::sha1(),
+            TsigAlgorithm::HmacSha256 => MessageDigest::sha256(),
+            TsigAlgorithm::HmacSha384 => MessageDigest::sha384(),
+            TsigAlgorithm::HmacSha512 => MessageDigest::sha512(),
+        }
+    }
+}
+
+fn rcode_name(rcode: u16) -> String {
+    match rcode {
+        0 => "NOERROR".to_string(),
+        1 => "FORMERR".to_string(),
+        2 => "SERVFAIL".to_string(),
+        3 => "NXDOMAIN".to_string(),
+        4 => "NOTIMP".to_string(),
+        5 => "REFUSED".to_string(),
+        6 => "YXDOMAIN".to_string(),
+        7 => "YXRRSET".to_string(),
+        8 => "NXRRSET".to_string(),
+        9 => "NOTAUTH".to_string(),
+        10 => "NOTZONE".to_string(),
+        16 => "BADSIG".to_string(),
+        17 => "BADKEY".to_string(),
+        18 => "BADTIME".to_string(),
+        22 => "BADTRUNC".to_string(),
+        other => format!("RCODE {}", other),
+    }
+}
+
+fn name_eq(a: &str, b: &str) -> bool {
+    a.trim_end_matches('.')
+        .eq_ignore_ascii_case(b.trim_end_matches('.'))
+}
+
+fn push_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend_from_slice(&value.to_be_bytes());
+}
+
+fn push_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_be_bytes());
+}
+
+fn push_u48(buf: &mut Vec<u8>, value: u64) {
+    buf.extend_from_slice(&value.to_be_bytes()[2..]);
+}
+
+fn read_u16(data: &[u8], pos: usize) -> Result<u16, Error> {
+    match data.get(pos..pos + 2) {
+        Some(raw) => Ok(u16::from_be_bytes([raw[0], raw[1]])),
+        None => bail!("truncated DNS message"),
+    }
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Result<u32, Error> {
+    Ok(((read_u16(data, pos)? as u32) << 16) | read_u16(data, pos + 2)? as u32)
+}
+
+fn read_u48(data: &[u8], pos: usize) -> Result<u64, Error> {
+    Ok(((read_u16(data, pos)? as u64) << 32) | read_u32(data, pos + 2)? as u64)
+}
+
+/// Encode a domain name in (uncompressed, lower case) wire format.
+fn encode_name(buf: &mut Vec<u8>, name: &str) -> Result<(), Error> {
+    let trimmed = name.strip_suffix('.').unwrap_or(name);
+    let start = buf.len();
+    if !trimmed.is_empty() {
+        for label in trimmed.split('.') {
+            if label.is_empty() || label.len() > 63 {
+                bail!("invalid DNS name '{}'", name);
+            }
+            buf.push(label.len() as u8);
+            buf.extend(label.bytes().map(|b| b.to_ascii_lowercase()));
+        }
+    }
+    buf.push(0);
+    if buf.len() - start > 255 {
+        bail!("DNS name '{}' is too long", name);
+    }
+    Ok(())
+}
+
+/// Parse a (possibly compressed) domain name, returns the name and the position after it.
+fn parse_name(data: &[u8], mut pos: usize) -> Result<(String, usize), Error> {
+    let mut name = String::new();
+    let mut end = None;
+    let mut jumps = 0;
+
+    loop {
+        let len = *data
+            .get(pos)
+            .ok_or_else(|| format_err!("truncated DNS name"))? as usize;
+
+        match len & 0xc0 {
+            0x00 if len == 0 => {
+                pos += 1;
+                break;
+            }
+            0x00 => {
+                let label = data
+                    .get(pos + 1..pos + 1 + len)
+                    .ok_or_else(|| format_err!("truncated DNS name"))?;
+                if !name.is_empty() {
+                    name.push('.');
+                }
+                name.push_str(&String::from_utf8_lossy(label));
+                pos += 1 + len;
+            }
+            0xc0 => {
+                let low = *data
+                    .get(pos + 1)
+                    .ok_or_else(|| format_err!("truncated DNS name"))?
+                    as usize;
+                if end.is_none() {
+                    end = Some(pos + 2);
+                }
+                jumps += 1;
+                if jumps > 64 {
+                    bail!("DNS name compression loop detected");
+                }
+                pos = ((len & 0x3f) << 8) | low;
+            }
+            _ => bail!("unsupported DNS label type"),
+        }
+    }
+
+    Ok((name, end.unwrap_or(pos)))
+}
+
+fn encode_header(buf: &mut Vec<u8>, id: u16, flags: u16, counts: [u16; 4]) {
+    push_u16(buf, id);
+    push_u16(buf, flags);
+    for count in counts {
+        push_u16(buf, count);
+    }
+}
+
+fn encode_question(buf: &mut Vec<u8>, name: &str, ty: u16, class: u16) -> Result<(), Error> {
+    encode_name(buf, name)?;
+    push_u16(buf, ty);
+    push_u16(buf, class);
+    Ok(())
+}
+
+fn encode_record(
+    buf: &mut Vec<u8>,
+    name: &str,
+    ty: u16,
+    class: u16,
+    ttl: u32,
+    rdata: &[u8],
+) -> Result<(), Error> {
+    encode_name(buf, name)?;
+    push_u16(buf, ty);
+    push_u16(buf, class);
+    push_u32(buf, ttl);
+    push_u16(buf, rdata.len() as u16);
+    buf.extend_from_slice(rdata);
+    Ok(())
+}
+
+/// Encode TXT record data, splitting the value into character strings of at most 255 bytes.
+fn encode_txt(value: &str) -> Vec<u8> {
+    let mut rdata = Vec::new();
+    for chunk in value.as_bytes().chunks(255) {
+        rdata.push(chunk.len() as u8);
+        rdata.extend_from_slice(chunk);
+    }
+    if rdata.is_empty() {
+        rdata.push(0);
+    }
+    rdata
+}
+
+/// Decode TXT record data, concatenating all character strings.
+fn parse_txt(rdata: &[u8]) -> Result<String, Error> {
+    let mut value = Vec::new();
+    let mut pos = 0;
+    while pos < rdata.len() {
+        let len = rdata[pos] as usize;
+        let chunk = rdata
+            .get(pos + 1..pos + 1 + len)
+            .ok_or_else(|| format_err!("truncated TXT record"))?;
+        value.extend_from_slice(chunk);
+        pos += 1 + len;
+    }
+    Ok(String::from_utf8_lossy(&value).into_owned())
+}
+
+fn random_id() -> Result<u16, Error> {
+    let mut id = [0u8; 2];
+    openssl::rand::rand_bytes(&mut id)?;
+    Ok(u16::from_be_bytes(id))
+}
+
+/// A resource record of a parsed message, the data is referenced by offset.
+struct Record {
+    name: String,
+    ty: u16,
+    class: u16,
+    /// Offset of the record in the message.
+    offset: usize,
+    rdata_offset: usize,
+    rdata_len: usize,
+}
+
+impl Record {
+    fn rdata<'a>(&self, data: &'a [u8]) -> &'a [u8] {
+        &data[self.rdata_offset..(self.rdata_offset + self.rdata_len)]
+    }
+}
+
+/// A parsed DNS message.
+struct Message {
+    data: Vec<u8>,
+    id: u16,
+    flags: u16,
+    answers: Vec<Record>,
+    authority: Vec<Record>,
+    additional: Vec<Record>,
+}
+
+impl Message {
+    fn parse(data: Vec<u8>) -> Result<Self, Error> {
+        if data.len() < 12 {
+            bail!("DNS message too short ({} bytes)", data.len());
+        }
+
+        let id = read_u16(&data, 0)?;
+        let flags = read_u16(&data, 2)?;
+        let question_count = read_u16(&data, 4)?;
+
+        let mut pos = 12;
+        for _ in 0..question_count {
+            let (_name, next) = parse_name(&data, pos)?;
+            pos = next + 4;
+        }
+
+        let mut sections: [Vec<Record>; 3] = Default::default();
+        for (index, section) in sections.iter_mut().enumerate() {
+            let count = read_u16(&data, 6 + 2 * index)?;
+            for _ in 0..count {
+                let (record, next) = Self::parse_record(&data, pos)?;
+                section.push(record);
+                pos = next;
+            }
+        }
+        let [answers, authority, additional] = sections;
+
+        Ok(Self {
+            data,
+            id,
+            flags,
+            answers,
+            authority,
+            additional,
+        })
+    }
+
+    fn parse_record(data: &[u8], offset: usize) -> Result<(Record, usize), Error> {
+        let (name, pos) = parse_name(data, offset)?;
+        let ty = read_u16(data, pos)?;
+        let class = read_u16(data, pos + 2)?;
+        let rdata_len = read_u16(data, pos + 8)? as usize;
+        let rdata_offset = pos + 10;
+        if data.len() < rdata_offset + rdata_len {
+            bail!("truncated DNS record");
+        }
+
+        let record = Record {
+            name,
+            ty,
+            class,
+            offset,
+            rdata_offset,
+            rdata_len,
+        };
+        Ok((record, rdata_offset + rdata_len))
+    }
+
+    fn rcode(&self) -> u16 {
+        self.flags & 0x000f
+    }
+
+    /// The TSIG record, which is always the last record of the additional section.
+    fn tsig(&self) -> Option<&Record> {
+        self.additional
+            .last()
+            .filter(|record| record.ty == TYPE_TSIG)
+    }
+}
+
+/// The decoded RDATA of a TSIG record.
+struct TsigData {
+    algorithm: String,
+    time_signed: u64,
+    fudge: u16,
+    mac: Vec<u8>,
+    original_id: u16,
+    error: u16,
+    other: Vec<u8>,
+}
+
+impl TsigData {
+    fn parse(data: &[u8], record: &Record) -> Result<Self, Error> {
+        let end = record.rdata_offset + record.rdata_len;
+        let (algorithm, pos) = parse_name(data, record.rdata_offset)?;
+        let time_signed = read_u48(data, pos)?;
+        let fudge = read_u16(data, pos + 6)?;
+        let mac_len = read_u16(data, pos + 8)? as usize;
+        let mac_end = pos + 10 + mac_len;
+        let mac = data
+            .get(pos + 10..mac_end)
+            .ok_or_else(|| format_err!("truncated TSIG record"))?
+            .to_vec();
+        let original_id = read_u16(data, mac_end)?;
+        let error = read_u16(data, mac_end + 2)?;
+        let other_len = read_u16(data, mac_end + 4)? as usize;
+        let other = data
+            .get(mac_end + 6..mac_end + 6 + other_len)
+            .ok_or_else(|| format_err!("truncated TSIG record"))?
+            .to_vec();
+        if mac_end + 6 + other_len != end {
+            bail!("invalid TSIG record length");
+        }
+
+        Ok(Self {
+            algorithm,
+            time_signed,
+            fudge,
+            mac,
+            original_id,
+            error,
+            other,
+        })
+    }
+}
+
+/// A TSIG key used to sign requests and verify responses.
+struct TsigKey {
+    name: String,
+    algorithm: TsigAlgorithm,
+    secret: Vec<u8>,
+}
+
+impl TsigKey {
+    fn mac(&self, parts: &[&[u8]]) -> Result<Vec<u8>, Error> {
+        let key = PKey::hmac(&self.secret)?;
+        let mut signer = Signer::new(self.algorithm.digest(), &key)?;
+        for part in parts {
+            signer.update(part)?;
+        }
+        Ok(signer.sign_to_vec()?)
+    }
+
+    /// The "TSIG variables" which are included in the MAC after the message.
+    fn variables(
+        &self,
+        time_signed: u64,
+        fudge: u16,
+        error: u16,
+        other: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        let mut buf = Vec::new();
+        encode_name(&mut buf, &self.name)?;
+        push_u16(&mut buf, CLASS_ANY);
+        push_u32(&mut buf, 0);
+        encode_name(&mut buf, self.algorithm.name())?;
+        push_u48(&mut buf, time_signed);
+        push_u16(&mut buf, fudge);
+        push_u16(&mut buf, error);
+        push_u16(&mut buf, other.len() as u16);
+        buf.extend_from_slice(other);
+        Ok(buf)
+    }
+
+    /// Sign `message` by appending a TSIG record and return the MAC.
+    ///
+    /// Responses are signed with the MAC of the corresponding request passed as `request_mac`.
+    fn sign(
+        &self,
+        message: &mut Vec<u8>,
+        time_signed: u64,
+        request_mac: Option<&[u8]>,
+    ) -> Result<Vec<u8>, Error> {
+        let variables = self.variables(time_signed, TSIG_FUDGE, 0, &[])?;
+        let mac = match request_mac {
+            Some(request_mac) => self.mac(&[
+                &(request_mac.len() as u16).to_be_bytes(),
+                request_mac,
+                message,
+                &variables,
+            ])?,
+            None => self.mac(&[message, &variables])?,
+        };
+
+        let mut rdata = Vec::new();
+        encode_name(&mut rdata, self.algorithm.name())?;
+        push_u48(&mut rdata, time_signed);
+        push_u16(&mut rdata, TSIG_FUDGE);
+        push_u16(&mut rdata, mac.len() as u16);
+        rdata.extend_from_slice(&mac);
+        rdata.extend_from_slice(&message[0..2]);
+        push_u16(&mut rdata, 0);
+        push_u16(&mut rdata, 0);
+
+        encode_record(message, &self.name, TYPE_TSIG, CLASS_ANY, 0, &rdata)?;
+        let additional = read_u16(message, 10)? + 1;
+        message[10..12].copy_from_slice(&additional.to_be_bytes());
+
+        Ok(mac)
+    }
+
+    /// Verify the TSIG record of a response to a request signed with `request_mac`.
+    fn verify(&self, response: &Message, request_mac: &[u8], now: i64) -> Result<(), Error> {
+        let record = response
+            .tsig()
+            .ok_or_else(|| format_err!("response is not TSIG signed"))?;
+        let tsig = TsigData::parse(&response.data, record)?;
+
+        if !name_eq(&record.name, &self.name) || !name_eq(&tsig.algorithm, self.algorithm.name()) {
+            bail!(
+                "response signed with unexpected key '{}' ({})",
+                record.name,
+                tsig.algorithm
+            );
+        }
+        if tsig.error != 0 {
+            bail!(
+                "server rejected TSIG signature - {}",
+                rcode_name(tsig.error)
+            );
+        }
+
+        let mut message = response.data[..record.offset].to_vec();
+        message[0..2].copy_from_slice(&tsig.original_id.to_be_bytes());
+        let additional = read_u16(&message, 10)? - 1;
+        message[10..12].copy_from_slice(&additional.to_be_bytes());
+
+        let variables = self.variables(tsig.time_signed, tsig.fudge, tsig.error, &tsig.other)?;
+        let expected = self.mac(&[
+            &(request_mac.len() as u16).to_be_bytes(),
+            request_mac,
+            &message,
+            &variables,
+        ])?;
+        if tsig.mac.len() != expected.len() || !openssl::memcmp::eq(&tsig.mac, &expected) {
+            bail!("TSIG verification of response failed");
+        }
+
+        if (now - tsig.time_signed as i64).unsigned_abs() > tsig.fudge as u64 {
+            bail!("TSIG time of response is outside of the allowed time window");
+        }
+
+        Ok(())
+    }
+}
+
+/// Split a `host`, `host:port`, `[ipv6]:port` or plain IPv6 server address.
+fn split_host_port(server: &str) -> Result<(&str, u16), Error> {
+    let parse_port = |port: &str| {
+        port.parse::<u16>()
+            .map_err(|_| format_err!("invalid port in DNS server address '{}'", server))
+    };
+
+    if let Some(rest) = server.strip_prefix('[') {
+        let (host, rest) = rest
+            .split_once(']')
+            .ok_or_else(|| format_err!("invalid DNS server address '{}'", server))?;
+        let port = match rest {
+            "" => DNS_PORT,
+            rest => match rest.strip_prefix(':') {
+                Some(port) => parse_port(port)?,
+                None => bail!("invalid DNS server address '{}'", server),
+            },
+        };
+        return Ok((host, port));
+    }
+
+    match server.rsplit_once(':') {
+        Some((host, port)) if !host.contains(':') => Ok((host, parse_port(port)?)),
+        _ => Ok((server, DNS_PORT)),
+    }
+}
+
+async fn resolve(host: &str, port: u16) -> Result<Vec<SocketAddr>, Error> {
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|err| format_err!("unable to resolve '{}' - {}", host, err))?
+        .collect();
+    if addrs.is_empty() {
+        bail!("unable to resolve '{}' - no addresses found", host);
+    }
+    Ok(addrs)
+}
+
+async fn resolve_server(server: &str) -> Result<Vec<SocketAddr>, Error> {
+    let (host, port) = split_host_port(server)?;
+    resolve(host, port).await
+}
+
+/// Send a request and wait for the response, retrying over TCP if the UDP response was truncated.
+async fn exchange(server: SocketAddr, request: &[u8]) -> Result<Message, Error> {
+    let id = read_u16(request, 0)?;
+    let response = udp_exchange(server, request, id).await?;
+    if response.flags & FLAG_TC != 0 {
+        return tcp_exchange(server, request, id).await;
+    }
+    Ok(response)
+}
+
+async fn udp_exchange(server: SocketAddr, request: &[u8], id: u16) -> Result<Message, Error> {
+    let bind_addr: SocketAddr = if server.is_ipv4() {
+        ([0u8; 4], 0).into()
+    } else {
+        ([0u16; 8], 0).into()
+    };
+    let socket = UdpSocket::bind(bind_addr).await?;
+    socket.connect(server).await?;
+
+    let mut buf = vec![0u8; 65535];
+    for _ in 0..QUERY_RETRIES {
+        socket.send(request).await?;
+        let deadline = tokio::time::Instant::now() + QUERY_TIMEOUT;
+        while let Ok(received) = tokio::time::timeout_at(deadline, socket.recv(&mut buf)).await {
+            let len = received.map_err(|err| format_err!("DNS server {} - {}", server, err))?;
+            // ignore garbage and responses to other (earlier) requests
+            match Message::parse(buf[..len].to_vec()) {
+                Ok(response) if response.id == id && response.flags & FLAG_QR != 0 => {
+                    return Ok(response)
+                }
+                _ => continue,
+            }
+        }
+    }
+
+    bail!("no response from DNS server {}", server);
+}
+
+async fn tcp_exchange(server: SocketAddr, request: &[u8], id: u16) -> Result<Message, Error> {
+    let exchange = async {
+        let mut stream = TcpStream::connect(server).await?;
+        let mut data = Vec::with_capacity(request.len() + 2);
+        push_u16(&mut data, request.len() as u16);
+        data.extend_from_slice(request);
+        stream.write_all(&data).await?;
+
+        let len = stream.read_u16().await? as usize;
+        let mut buf = vec![0u8; len];
+        stream.read_exact(&mut buf).await?;
+        Ok::<_, Error>(buf)
+    };
+
+    let data = tokio::time::timeout(QUERY_TIMEOUT, exchange)
+        .await
+        .map_err(|_| {
+            format_err!(
+                "timeout waiting for TCP response from DNS server {}",
+                server
+            )
+        })?
+        .map_err(|err| format_err!("DNS server {} - {}", server, err))?;
+
+    let response = Message::parse(data)?;
+    if response.id != id {
+        bail!("DNS server {} sent response with wrong id", server);
+    }
+    Ok(response)
+}
+
+/// Send a non-recursive query, we always ask authoritative servers directly.
+async fn query(server: SocketAddr, name: &str, ty: u16) -> Result<Message, Error> {
+    let mut request = Vec::new();
+    encode_header(&mut request, random_id()?, 0, [1, 0, 0, 0]);
+    encode_question(&mut request, name, ty, CLASS_IN)?;
+
+    let response = exchange(server, &request).await?;
+    match response.rcode() {
+        RCODE_NOERROR | RCODE_NXDOMAIN => Ok(response),
+        rcode => bail!(
+            "query for '{}' on {} failed - {}",
+            name,
+            server,
+            rcode_name(rcode)
+        ),
+    }
+}
+
+async fn query_txt(server: SocketAddr, name: &str) -> Result<Vec<String>, Error> {
+    let response = query(server, name, TYPE_TXT).await?;
+    response
+        .answers
+        .iter()
+        .filter(|record| {
+            record.ty == TYPE_TXT && record.class == CLASS_IN && name_eq(&record.name, name)
+        })
+        .map(|record| parse_txt(record.rdata(&response.data)))
+        .collect()
+}
+
+/// Client for adding and removing TXT records with TSIG signed dynamic updates.
+pub(crate) struct Rfc2136Client {
+    server: String,
+    zone: Option<String>,
+    key: TsigKey,
+    ttl: u32,
+    nameservers: Option<Vec<String>>,
+    propagation_timeout: Duration,
+}
+
+impl Rfc2136Client {
+    pub fn new(plugin: &Rfc2136Plugin) -> Result<Self, Error> {
+        let secret = base64::decode(plugin.tsig_key.trim())
+            .map_err(|err| format_err!("invalid tsig-key - {}", err))?;
+        if secret.is_empty() {
+            bail!("invalid tsig-key - key is empty");
+        }
+
+        Ok(Self {
+            server: plugin.server.clone(),
+            zone: plugin.zone.clone(),
+            key: TsigKey {
+                name: plugin.tsig_key_name.clone(),
+                algorithm: plugin.tsig_algorithm.unwrap_or_default(),
+                secret,
+            },
+            ttl: plugin.ttl.unwrap_or(60),
+            nameservers: plugin.nameservers.clone(),
+            propagation_timeout: Duration::from_secs(
+                plugin.propagation_timeout.unwrap_or(300) as u64
+            ),
+        })
+    }
+
+    async fn server_addr(&self) -> Result<SocketAddr, Error> {
+        Ok(resolve_server(&self.server).await?[0])
+    }
+
+    /// Get the configured zone or look up the zone containing `name` via its SOA record.
+    pub async fn find_zone(&self, name: &str) -> Result<String, Error> {
+        if let Some(zone) = &self.zone {
+            return Ok(zone.trim_end_matches('.').to_string());
+        }
+
+        let response = query(self.server_addr().await?, name, TYPE_SOA).await?;
+        response
+            .answers
+            .iter()
+            .chain(response.authority.iter())
+            .find(|record| record.ty == TYPE_SOA)
+            .map(|record| record.name.clone())
+            .ok_or_else(|| {
+                format_err!(
+                    "unable to determine zone of '{}' - no SOA record found",
+                    name
+                )
+            })
+    }
+
+    /// Add a TXT record with `value` to `name`.
+    pub async fn add_txt(&self, name: &str, value: &str) -> Result<(), Error> {
+        self.update(name, value, false).await
+    }
+
+    /// Remove the TXT record with `value` from `name`, other TXT records are left alone.
+    pub async fn delete_txt(&self, name: &str, value: &str) -> Result<(), Error> {
+        self.update(name, value, true).await
+    }
+
+    async fn update(&self, name: &str, value: &str, delete: bool) -> Result<(), Error> {
+        let zone = self.find_zone(name).await?;
+        let server = self.server_addr().await?;
+
+        let mut request = Vec::new();
+        encode_header(
+            &mut request,
+            random_id()?,
+            OPCODE_UPDATE << 11,
+            [1, 0, 1, 0],
+        );
+        encode_question(&mut request, &zone, TYPE_SOA, CLASS_IN)?;
+        if delete {
+            encode_record(
+                &mut request,
+                name,
+                TYPE_TXT,
+                CLASS_NONE,
+                0,
+                &encode_txt(value),
+            )?;
+        } else {
+            encode_record(
+                &mut request,
+                name,
+                TYPE_TXT,
+                CLASS_IN,
+                self.ttl,
+                &encode_txt(value),
+            )?;
+        }
+
+        let now = proxmox_time::epoch_i64();
+        let mac = self.key.sign(&mut request, now as u64, None)?;
+
+        let response = exchange(server, &request).await?;
+        if response.tsig().is_none() && response.rcode() != RCODE_NOERROR {
+            bail!(
+                "DNS update of '{}' in zone '{}' failed - {}",
+                name,
+                zone,
+                rcode_name(response.rcode())
+            );
+        }
+        self.key
+            .verify(&response, &mac, proxmox_time::epoch_i64())
+            .map_err(|err| format_err!("DNS update of '{}' failed - {}", name, err))?;
+        if response.rcode() != RCODE_NOERROR {
+            bail!(
+                "DNS update of '{}' in zone '{}' failed - {}",
+                name,
+                zone,
+                rcode_name(response.rcode())
+            );
+        }
+
+        Ok(())
+    }
+
+    /// The name servers to check for propagation, grouped by name.
+    async fn propagation_servers(
+        &self,
+        name: &str,
+    ) -> Result<Vec<(String, Vec<SocketAddr>)>, Error> {
+        let mut servers = Vec::new();
+
+        if let Some(nameservers) = &self.nameservers {
+            for server in nameservers {
+                servers.push((server.clone(), resolve_server(server).await?));
+            }
+            return Ok(servers);
+        }
+
+        let zone = self.find_zone(name).await?;
+        let response = query(self.server_addr().await?, &zone, TYPE_NS).await?;
+        for record in response
+            .answers
+            .iter()
+            .filter(|record| record.ty == TYPE_NS)
+        {
+            let (server, _) = parse_name(&response.data, record.rdata_offset)?;
+            let addrs = resolve(&server, DNS_PORT).await?;
+            servers.push((server, addrs));
+        }
+        if servers.is_empty() {
+            bail!("no NS records found for zone '{}'", zone);
+        }
+
+        Ok(servers)
+    }
+
+    /// Wait until all authoritative name servers serve the TXT record.
+    ///
+    /// A server counts as updated as soon as one of its addresses returns the record. A
+    /// propagation timeout of 0 disables the check.
+    pub async fn wait_for_propagation<F>(
+        &self,
+        name: &str,
+        value: &str,
+        log: F,
+    ) -> Result<(), Error>
+    where
+        F: Fn(String) + Send + Sync,
+    {
+        if self.propagation_timeout.is_zero() {
+            return Ok(());
+        }
+
+        let deadline = Instant::now() + self.propagation_timeout;
+        let mut pending = self.propagation_servers(name).await?;
+
+        loop {
+            let mut waiting = Vec::new();
+            for (server, addrs) in pending {
+                let mut found = false;
+                for addr in &addrs {
+                    match query_txt(*addr, name).await {
+                        Ok(values) if values.iter().any(|v| v == value) => {
+                            found = true;
+                            break;
+                        }
+                        Ok(_) => (),
+                        Err(err) => log(format!("checking {} failed - {}", server, err)),
+                    }
+                }
+                if found {
+                    log(format!("TXT record for '{}' visible on {}", name, server));
+                } else {
+                    waiting.push((server, addrs));
+                }
+            }
+
+            if waiting.is_empty() {
+                return Ok(());
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                let servers: Vec<&str> =
+                    waiting.iter().map(|(server, _)| server.as_str()).collect();
+                bail!(
+                    "TXT record for '{}' not visible on {} after {} seconds",
+                    name,
+                    servers.join(", "),
+                    self.propagation_timeout.as_secs()
+                );
+            }
+
+            log(format!(
+                "Waiting for TXT record propagation to {} server(s)",
+                waiting.len()
+            ));
+            tokio::time::sleep(PROPAGATION_POLL_INTERVAL.min(deadline - now)).await;
+            pending = waiting;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    const ZONE: &str = "example.com";
+    const SECRET: &[u8] = b"0123456789abcdef";
+
+    fn test_key(secret: &[u8]) -> TsigKey {
+        TsigKey {
+            name: "test-key".to_string(),
+            algorithm: TsigAlgorithm::HmacSha256,
+            secret: secret.to_vec(),
+        }
+    }
+
+    fn test_plugin(server: SocketAddr, secret: &[u8], timeout: u32) -> Rfc2136Plugin {
+        Rfc2136Plugin {
+            id: "test".to_string(),
+            server: server.to_string(),
+            zone: None,
+            tsig_key_name: "test-key".to_string(),
+            tsig_algorithm: None,
+            tsig_key: base64::encode(secret),
+            ttl: None,
+            nameservers: Some(vec![server.to_string()]),
+            propagation_timeout: Some(timeout),
+            disable: None,
+        }
+    }
+
+    /// Minimal authoritative server for `ZONE` accepting TSIG signed updates.
+    struct StandIn {
+        key: TsigKey,
+        records: Mutex<Vec<(String, String)>>,
+    }
+
+    impl StandIn {
+        fn respond(
+            request: &Message,
+            rcode: u16,
+            answers: &[Vec<u8>],
+            authority: &[Vec<u8>],
+        ) -> Vec<u8> {
+            let (_, question_end) = parse_name(&request.data, 12).unwrap();
+            let flags = FLAG_QR | (request.flags & 0x7800) | rcode;
+            let mut response = Vec::new();
+            encode_header(
+                &mut response,
+                request.id,
+                flags,
+                [1, answers.len() as u16, authority.len() as u16, 0],
+            );
+            response.extend_from_slice(&request.data[12..question_end + 4]);
+            for record in answers.iter().chain(authority) {
+                response.extend_from_slice(record);
+            }
+            response
+        }
+
+        fn handle_update(&self, request: &Message) -> Vec<u8> {
+            let record = request.tsig().expect("unsigned update");
+            let tsig = TsigData::parse(&request.data, record).unwrap();
+
+            let mut message = request.data[..record.offset].to_vec();
+            message[10..12].copy_from_slice(&0u16.to_be_bytes());
+            let variables = self
+                .key
+                .variables(tsig.time_signed, tsig.fudge, tsig.error, &tsig.other)
+                .unwrap();
+            let expected = self.key.mac(&[&message, &variables]).unwrap();
+
+            if expected != tsig.mac {
+                // unsigned NOTAUTH response with the BADSIG TSIG error
+                let mut response = Self::respond(request, 9, &[], &[]);
+                let mut rdata = Vec::new();
+                encode_name(&mut rdata, self.key.algorithm.name()).unwrap();
+                push_u48(&mut rdata, tsig.time_signed);
+                push_u16(&mut rdata, tsig.fudge);
+                push_u16(&mut rdata, 0);
+                push_u16(&mut rdata, request.id);
+                push_u16(&mut rdata, 16);
+                push_u16(&mut rdata, 0);
+                encode_record(
+                    &mut response,
+                    &self.key.name,
+                    TYPE_TSIG,
+                    CLASS_ANY,
+                    0,
+                    &rdata,
+                )
+                .unwrap();
+                response[10..12].copy_from_slice(&1u16.to_be_bytes());
+                return response;
+            }
+
+            let (zone, _) = parse_name(&request.data, 12).unwrap();
+            let rcode = if name_eq(&zone, ZONE) {
+                let mut records = self.records.lock().unwrap();
+                for update in &request.authority {
+                    assert_eq!(update.ty, TYPE_TXT);
+                    let value = parse_txt(update.rdata(&request.data)).unwrap();
+                    match update.class {
+                        CLASS_IN => records.push((update.name.clone(), value)),
+                        CLASS_NONE => records
+                            .retain(|(name, v)| !(name_eq(name, &update.name) && *v == value)),
+                        other => panic!("unexpected update class {}", other),
+                    }
+                }
+                RCODE_NOERROR
+            } else {
+                10
+            };
+
+            let mut response = Self::respond(request, rcode, &[], &[]);
+            self.key
+                .sign(
+                    &mut response,
+                    proxmox_time::epoch_i64() as u64,
+                    Some(&tsig.mac),
+                )
+                .unwrap();
+            response
+        }
+
+        fn handle_query(&self, request: &Message) -> Vec<u8> {
+            let (name, pos) = parse_name(&request.data, 12).unwrap();
+            let ty = read_u16(&request.data, pos).unwrap();
+
+            let mut answers = Vec::new();
+            let mut authority = Vec::new();
+            match ty {
+                TYPE_TXT => {
+                    for (_, value) in self
+                        .records
+                        .lock()
+                        .unwrap()
+                        .iter()
+                        .filter(|(record, _)| name_eq(record, &name))
+                    {
+                        let mut record = Vec::new();
+                        encode_record(
+                            &mut record,
+                            &name,
+                            TYPE_TXT,
+                            CLASS_IN,
+                            60,
+                            &encode_txt(value),
+                        )
+                        .unwrap();
+                        answers.push(record);
+                    }
+                }
+                TYPE_SOA => {
+                    let mut rdata = Vec::new();
+                    encode_name(&mut rdata, "ns1.example.com").unwrap();
+                    encode_name(&mut rdata, "hostmaster.example.com").unwrap();
+                    for value in [1, 3600, 600, 86400, 60] {
+                        push_u32(&mut rdata, value);
+                    }
+                    let mut record = Vec::new();
+                    encode_record(&mut record, ZONE, TYPE_SOA, CLASS_IN, 3600, &rdata).unwrap();
+                    if name_eq(&name, ZONE) {
+                        answers.push(record);
+                    } else {
+                        authority.push(record);
+                    }
+                }
+                _ => (),
+            }
+
+            Self::respond(request, RCODE_NOERROR, &answers, &authority)
+        }
+    }
+
+    async fn start_stand_in() -> (SocketAddr, Arc<StandIn>) {
+        let stand_in = Arc::new(StandIn {
+            key: test_key(SECRET),
+            records: Mutex::new(Vec::new()),
+        });
+
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+
+        let server = Arc::clone(&stand_in);
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 65535];
+            loop {
+                let (len, peer) = socket.recv_from(&mut buf).await.unwrap();
+                let request = Message::parse(buf[..len].to_vec()).unwrap();
+                let response = if (request.flags >> 11) & 0xf == OPCODE_UPDATE {
+                    server.handle_update(&request)
+                } else {
+                    server.handle_query(&request)
+                };
+                socket.send_to(&response, peer).await.unwrap();
+            }
+        });
+
+        (addr, stand_in)
+    }
+
+    #[test]
+    fn test_tsig_request_mac() {
+        let mut request = Vec::new();
+        encode_header(&mut request, 0x1234, OPCODE_UPDATE << 11, [1, 0, 1, 0]);
+        encode_question(&mut request, "example.com", TYPE_SOA, CLASS_IN).unwrap();
+        encode_record(
+            &mut request,
+            "_acme-challenge.example.com",
+            TYPE_TXT,
+            CLASS_IN,
+            60,
+            &encode_txt("test-value"),
+        )
+        .unwrap();
+        let unsigned_len = request.len();
+
+        let mac = test_key(SECRET)
+            .sign(&mut request, 1700000000, None)
+            .unwrap();
+        assert_eq!(
+            hex::encode(&mac),
+            "bd3a737591cdf4b030c9e5b051ccd1d852a5e8da10af7daaee46fd0463509f7e"
+        );
+
+        let message = Message::parse(request).unwrap();
+        assert_eq!(message.additional.len(), 1);
+        let record = message.tsig().unwrap();
+        assert_eq!(record.offset, unsigned_len);
+        assert_eq!(record.name, "test-key");
+        let tsig = TsigData::parse(&message.data, record).unwrap();
+        assert_eq!(tsig.algorithm, "hmac-sha256");
+        assert_eq!(tsig.time_signed, 1700000000);
+        assert_eq!(tsig.original_id, 0x1234);
+        assert_eq!(tsig.mac, mac);
+    }
+
+    #[test]
+    fn test_parse_compressed_name() {
+        let mut data = vec![0u8; 12];
+        encode_name(&mut data, "Example.COM").unwrap();
+        // "_acme-challenge" + pointer to offset 12
+        data.push(15);
+        data.extend_from_slice(b"_acme-challenge");
+        data.extend_from_slice(&[0xc0, 12]);
+        let end = data.len();
+        // pointer to itself
+        data.extend_from_slice(&[0xc0, end as u8]);
+
+        assert_eq!(
+            parse_name(&data, 12).unwrap(),
+            ("example.com".to_string(), 25)
+        );
+        assert_eq!(
+            parse_name(&data, 25).unwrap(),
+            ("_acme-challenge.example.com".to_string(), end)
+        );
+        assert!(parse_name(&data, end).is_err());
+    }
+
+    #[test]
+    fn test_txt_encoding() {
+        let value = "x".repeat(300);
+        let rdata = encode_txt(&value);
+        assert_eq!(rdata.len(), 302);
+        assert_eq!(rdata[0], 255);
+        assert_eq!(rdata[256], 45);
+        assert_eq!(parse_txt(&rdata).unwrap(), value);
+        assert!(parse_txt(&[5, b'a']).is_err());
+    }
+
+    #[test]
+    fn test_split_host_port() {
+        assert_eq!(
+            split_host_port("ns1.example.com").unwrap(),
+            ("ns1.example.com", 53)
+        );
+        assert_eq!(
+            split_host_port("192.0.2.1:5353").unwrap(),
+            ("192.0.2.1", 5353)
+        );
+        assert_eq!(split_host_port("2001:db8::1").unwrap(), ("2001:db8::1", 53));
+        assert_eq!(
+            split_host_port("[2001:db8::1]").unwrap(),
+            ("2001:db8::1", 53)
+        );
+        assert_eq!(
+            split_host_port("[2001:db8::1]:5353").unwrap(),
+            ("2001:db8::1", 5353)
+        );
+        assert!(split_host_port("ns1.example.com:dns").is_err());
+        assert!(split_host_port("[2001:db8::1]5353").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_update_and_propagation() {
+        let (addr, stand_in) = start_stand_in().await;
+        let client = Rfc2136Client::new(&test_plugin(addr, SECRET, 10)).unwrap();
+        let name = "_acme-challenge.host.example.com";
+
+        assert_eq!(client.find_zone(name).await.unwrap(), ZONE);
+
+        client.add_txt(name, "other").await.unwrap();
+        client.add_txt(name, "challenge").await.unwrap();
+        let log = Mutex::new(Vec::new());
+        client
+            .wait_for_propagation(name, "challenge", |msg| log.lock().unwrap().push(msg))
+            .await
+            .unwrap();
+        assert_eq!(log.lock().unwrap().len(), 1);
+
+        client.delete_txt(name, "challenge").await.unwrap();
+        assert_eq!(
+            *stand_in.records.lock().unwrap(),
+            vec![(name.to_string(), "other".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_update_bad_key() {
+        let (addr, stand_in) = start_stand_in().await;
+        let client = Rfc2136Client::new(&test_plugin(addr, b"wrong secret", 10)).unwrap();
+        let name = "_acme-challenge.example.com";
+
+        let err = client.add_txt(name, "challenge").await.unwrap_err();
+        assert!(err.to_string().contains("BADSIG"), "{}", err);
+        assert!(stand_in.records.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_propagation_timeout() {
+        let (addr, _stand_in) = start_stand_in().await;
+        let client = Rfc2136Client::new(&test_plugin(addr, SECRET, 1)).unwrap();
+
+        let err = client
+            .wait_for_propagation("_acme-challenge.example.com", "challenge", |_| ())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("not visible"), "{}", err);
+    }
+}
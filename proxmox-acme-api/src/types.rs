@@ -224,10 +224,36 @@ pub const PLUGIN_ID_SCHEMA: Schema = StringSchema::new("ACME Challenge Plugin ID
     .max_length(32)
     .schema();
 
-#[api]
+#[api(
+    properties: {
+        server: {
+            schema: DNS_SERVER_SCHEMA,
+            optional: true,
+        },
+        zone: {
+            format: &DNS_NAME_FORMAT,
+            optional: true,
+        },
+        "tsig-key-name": {
+            format: &DNS_NAME_FORMAT,
+            optional: true,
+        },
+        "tsig-algorithm": {
+            type: TsigAlgorithm,
+            optional: true,
+        },
+        nameservers: {
+            type: Array,
+            optional: true,
+            items: { schema: DNS_SERVER_SCHEMA },
+        },
+    },
+)]
 #[derive(Clone, Default, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "kebab-case")]
 /// ACME plugin config. The API's format is inherited from PVE/PMG:
+///
+/// RFC 2136 plugins additionally report their settings, except for the TSIG key.
 pub struct PluginConfig {
     /// Plugin ID.
     pub plugin: String,
@@ -253,6 +279,34 @@ pub struct PluginConfig {
     /// Flag to disable the config.
     #[serde(skip_serializing_if = "Option::is_none", default)]
     pub disable: Option<bool>,
+
+    /// Primary name server accepting the UPDATE messages (RFC 2136 plugins).
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub server: Option<String>,
+
+    /// Zone to update (RFC 2136 plugins).
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub zone: Option<String>,
+
+    /// Name of the TSIG key (RFC 2136 plugins).
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub tsig_key_name: Option<String>,
+
+    /// TSIG algorithm (RFC 2136 plugins).
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub tsig_algorithm: Option<TsigAlgorithm>,
+
+    /// TTL of the TXT record (RFC 2136 plugins).
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub ttl: Option<u32>,
+
+    /// Name servers checked for propagation (RFC 2136 plugins).
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub nameservers: Option<Vec<String>>,
+
+    /// Maximum time in seconds to wait for the TXT record to propagate (RFC 2136 plugins).
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub propagation_timeout: Option<u32>,
 }
 
 #[api(
@@ -275,6 +329,26 @@ impl Default for StandalonePlugin {
     }
 }
 
+#[api(
+    properties: {
+        id: { schema: PLUGIN_ID_SCHEMA },
+    },
+)]
+#[derive(Deserialize, Serialize)]
+/// Standalone ACME Plugin for the tls-alpn-01 challenge.
+pub struct TlsAlpnPlugin {
+    /// Plugin ID.
+    id: String,
+}
+
+impl Default for TlsAlpnPlugin {
+    fn default() -> Self {
+        Self {
+            id: "tls-alpn".to_string(),
+        }
+    }
+}
+
 #[api(
     properties: {
         id: { schema: PLUGIN_ID_SCHEMA },
@@ -341,6 +415,344 @@ pub enum DeletablePluginProperty {
     ValidationDelay,
 }
 
+#[api()]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+/// TSIG HMAC algorithm used to sign RFC 2136 updates.
+pub enum TsigAlgorithm {
+    /// HMAC-SHA1
+    HmacSha1,
+    /// HMAC-SHA256
+    #[default]
+    HmacSha256,
+    /// HMAC-SHA384
+    HmacSha384,
+    /// HMAC-SHA512
+    HmacSha512,
+}
+
+/// [Schema] for a DNS server address with optional port.
+pub const DNS_SERVER_SCHEMA: Schema = StringSchema::new(
+    "DNS server address, optionally with port ('host', 'host:port' or '[ipv6]:port').",
+)
+.min_length(1)
+.max_length(256)
+.schema();
+
+#[api(
+    properties: {
+        id: { schema: PLUGIN_ID_SCHEMA },
+        server: { schema: DNS_SERVER_SCHEMA },
+        zone: {
+            format: &DNS_NAME_FORMAT,
+            optional: true,
+        },
+        "tsig-key-name": { format: &DNS_NAME_FORMAT },
+        "tsig-algorithm": {
+            type: TsigAlgorithm,
+            optional: true,
+        },
+        "tsig-key": {
+            description: "Base64 encoded TSIG shared secret.",
+            type: String,
+        },
+        ttl: {
+            default: 60,
+            optional: true,
+            minimum: 1,
+            maximum: 24 * 60 * 60,
+        },
+        nameservers: {
+            type: Array,
+            optional: true,
+            items: { schema: DNS_SERVER_SCHEMA },
+        },
+        "propagation-timeout": {
+            default: 300,
+            optional: true,
+            minimum: 0,
+            maximum: 60 * 60,
+        },
+        disable: {
+            optional: true,
+            default: false,
+        },
+    },
+)]
+/// Native RFC 2136 (dynamic DNS update) ACME Challenge Plugin.
+///
+/// Adds and removes the DNS-01 TXT record with TSIG signed UPDATE messages and waits until the
+/// record is visible on all authoritative name servers of the zone.
+#[derive(Deserialize, Serialize, Updater)]
+#[serde(rename_all = "kebab-case")]
+pub struct Rfc2136Plugin {
+    /// Plugin ID.
+    #[updater(skip)]
+    pub id: String,
+
+    /// Primary name server accepting the UPDATE messages.
+    pub server: String,
+
+    /// Zone to update. Detected from the SOA record if not set.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub zone: Option<String>,
+
+    /// Name of the TSIG key.
+    pub tsig_key_name: String,
+
+    /// TSIG algorithm, defaults to hmac-sha256.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub tsig_algorithm: Option<TsigAlgorithm>,
+
+    pub tsig_key: String,
+
+    /// TTL of the TXT record.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub ttl: Option<u32>,
+
+    /// Name servers checked for propagation. Defaults to the zone's NS records.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub nameservers: Option<Vec<String>>,
+
+    /// Maximum time in seconds to wait for the TXT record to propagate.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub propagation_timeout: Option<u32>,
+
+    /// Flag to disable the config.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub disable: Option<bool>,
+}
+
+#[api()]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Deletable RFC 2136 plugin property names.
+pub enum DeletableRfc2136PluginProperty {
+    /// Delete the zone property
+    Zone,
+    /// Delete the tsig-algorithm property
+    TsigAlgorithm,
+    /// Delete the ttl property
+    Ttl,
+    /// Delete the nameservers property
+    Nameservers,
+    /// Delete the propagation-timeout property
+    PropagationTimeout,
+    /// Delete the disable property
+    Disable,
+}
+
+#[api()]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+/// What the renewal time of a certificate is based on.
+pub enum RenewalReason {
+    /// The renewal window suggested by the ACME server (ARI).
+    Ari,
+    /// The default renewal window before the certificate expires.
+    Expiry,
+    /// The certificate has no known expiry date and should be renewed right away.
+    Unknown,
+}
+
+#[api()]
+/// Persistent renewal state of a certificate, used by the renewal planner.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RenewalState {
+    /// Fingerprint of the certificate this state belongs to.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub fingerprint: Option<String>,
+
+    /// Start of the renewal window suggested by the ACME server (UNIX epoch).
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub ari_window_start: Option<i64>,
+
+    /// End of the renewal window suggested by the ACME server (UNIX epoch).
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub ari_window_end: Option<i64>,
+
+    /// URL explaining the renewal window suggested by the ACME server.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub ari_explanation_url: Option<String>,
+
+    /// When the renewal information should be fetched again (UNIX epoch).
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub ari_next_check: Option<i64>,
+
+    /// The randomly chosen renewal time within the window (UNIX epoch).
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub renew_at: Option<i64>,
+
+    /// Start of the window `renew-at` was chosen from (UNIX epoch).
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub window_start: Option<i64>,
+
+    /// End of the window `renew-at` was chosen from (UNIX epoch).
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub window_end: Option<i64>,
+
+    /// Time of the last renewal attempt (UNIX epoch).
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub last_attempt: Option<i64>,
+
+    /// Time of the last successful renewal (UNIX epoch).
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub last_success: Option<i64>,
+
+    /// Error message of the last failed renewal attempt.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub last_error: Option<String>,
+
+    /// Number of consecutive failed renewal attempts.
+    #[serde(default)]
+    pub failures: u64,
+}
+
+#[api(
+    properties: {
+        reason: { type: RenewalReason },
+    },
+)]
+/// The planned renewal of a certificate.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RenewalPlan {
+    /// When the certificate should be renewed (UNIX epoch).
+    pub renew_at: i64,
+
+    /// What the renewal time is based on.
+    pub reason: RenewalReason,
+
+    /// Whether the renewal is due now.
+    pub due: bool,
+}
+
+#[api()]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+/// Content of a certificate file.
+pub enum CertificateFileType {
+    /// A certificate followed by its intermediate certificates.
+    #[default]
+    Chain,
+    /// A collection of independent (CA) certificates.
+    Bundle,
+}
+
+#[api(
+    properties: {
+        name: { schema: CERTIFICATE_LOCATION_NAME_SCHEMA },
+        "type": {
+            type: CertificateFileType,
+            optional: true,
+        },
+    },
+)]
+/// A certificate location checked by the certificate inventory.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CertificateLocation {
+    /// Name of the location, for example 'node' or 'ldap-ca'.
+    pub name: String,
+
+    /// Path to a PEM file or to a directory containing '.pem' and '.crt' files.
+    pub path: String,
+
+    /// Path to the PEM encoded private key of the chain's certificate.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub key: Option<String>,
+
+    /// Content of the certificate files, defaults to 'chain'.
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none", default)]
+    pub ty: Option<CertificateFileType>,
+}
+
+/// [Schema] for certificate location names.
+pub const CERTIFICATE_LOCATION_NAME_SCHEMA: Schema =
+    StringSchema::new("Certificate location name.")
+        .format(&SAFE_ID_FORMAT)
+        .min_length(1)
+        .max_length(32)
+        .schema();
+
+#[api()]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+/// Type of a problem found by the certificate inventory.
+pub enum CertificateIssueType {
+    /// The file could not be read or parsed.
+    Unreadable,
+    /// The certificates of a chain are not ordered from the leaf to the root.
+    ChainOrder,
+    /// The chain could not be verified against the CA store.
+    Untrusted,
+    /// The private key does not belong to the certificate.
+    KeyMismatch,
+    /// The certificate is not valid yet.
+    NotYetValid,
+    /// The certificate is expired.
+    Expired,
+    /// The certificate expires soon.
+    Expiring,
+}
+
+#[api(
+    properties: {
+        "type": { type: CertificateIssueType },
+    },
+)]
+/// A problem found by the certificate inventory.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CertificateIssue {
+    /// Type of the problem.
+    #[serde(rename = "type")]
+    pub ty: CertificateIssueType,
+
+    /// Index of the affected certificate in the file.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub index: Option<u32>,
+
+    /// Description of the problem.
+    pub message: String,
+}
+
+#[api(
+    properties: {
+        name: { schema: CERTIFICATE_LOCATION_NAME_SCHEMA },
+        "type": { type: CertificateFileType },
+        certificates: {
+            type: Array,
+            items: { type: CertificateInfo },
+        },
+        issues: {
+            type: Array,
+            items: { type: CertificateIssue },
+        },
+    },
+)]
+/// A certificate file found by the certificate inventory.
+#[derive(Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CertificateInventoryEntry {
+    /// Name of the location the file belongs to.
+    pub name: String,
+
+    /// Path of the certificate file.
+    pub filename: String,
+
+    /// Content of the certificate file.
+    #[serde(rename = "type")]
+    pub ty: CertificateFileType,
+
+    /// The certificates contained in the file, in file order.
+    pub certificates: Vec<CertificateInfo>,
+
+    /// Problems found with the certificates.
+    pub issues: Vec<CertificateIssue>,
+}
+
 #[api(
     properties: {
         name: { type: AcmeAccountName },
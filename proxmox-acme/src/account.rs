@@ -16,6 +16,7 @@ use crate::key::{Jwk, PublicKey};
 use crate::order::{NewOrder, Order, OrderData};
 use crate::request::Request;
 use crate::types::{AccountData, AccountStatus, ExternalAccountBinding};
+use crate::util::TlsAlpnCertificate;
 use crate::Error;
 
 /// An ACME Account.
@@ -174,6 +175,16 @@ impl Account {
         Ok(b64u::encode(&digest))
     }
 
+    /// Generate the self signed validation certificate for a tls-alpn-01 token and the domain
+    /// `identifier`.
+    pub fn tls_alpn_01_certificate(
+        &self,
+        token: &str,
+        identifier: &str,
+    ) -> Result<TlsAlpnCertificate, Error> {
+        TlsAlpnCertificate::generate(identifier, &self.key_authorization(token)?)
+    }
+
     /// Prepare a request to update account data.
     ///
     /// This is a rather low level interface. You should know what you're doing.
@@ -6,9 +6,12 @@ use hyper::{Body, Request};
 use serde::{Deserialize, Serialize};
 
 use proxmox_http::client::Client;
+use proxmox_http::{HttpOptions, TlsConfig};
 
 use crate::account::AccountCreator;
 use crate::order::{Order, OrderData};
+use crate::renewal_info::RenewalInfo;
+use crate::util::TlsAlpnCertificate;
 use crate::Request as AcmeRequest;
 use crate::{Account, Authorization, Challenge, Directory, Error, ErrorResponse};
 
@@ -24,17 +27,33 @@ pub struct AcmeClient {
 impl AcmeClient {
     /// Create a new ACME client for a given ACME directory URL.
     pub fn new(directory_url: String) -> Self {
+        let http_client = Client::with_options(Self::http_options());
+        Self::with_http_client(directory_url, http_client)
+    }
+
+    /// Create a new ACME client for a given ACME directory URL, verifying the ACME server
+    /// according to `tls_config`, for example to trust a private CA's certificate.
+    pub fn with_tls_config(
+        directory_url: String,
+        tls_config: &TlsConfig,
+    ) -> Result<Self, anyhow::Error> {
+        let http_client = Client::with_tls_config(tls_config, Self::http_options())?;
+        Ok(Self::with_http_client(directory_url, http_client))
+    }
+
+    fn http_options() -> HttpOptions {
         const USER_AGENT_STRING: &str = "proxmox-acme-client/1.0";
         const TCP_KEEPALIVE_TIME: u32 = 120;
 
-        let options = proxmox_http::HttpOptions {
+        HttpOptions {
             proxy_config: None, // fixme???
             user_agent: Some(USER_AGENT_STRING.to_string()),
             tcp_keepalive: Some(TCP_KEEPALIVE_TIME),
-        };
-
-        let http_client = Client::with_options(options);
+            ..Default::default()
+        }
+    }
 
+    fn with_http_client(directory_url: String, http_client: Client) -> Self {
         Self {
             directory_url,
             account: None,
@@ -101,6 +120,15 @@ impl AcmeClient {
         Ok(Self::need_account(&self.account)?.dns_01_txt_value(token)?)
     }
 
+    /// Shortcut to `account().ok_or_else(...).tls_alpn_01_certificate()`.
+    pub fn tls_alpn_01_certificate(
+        &self,
+        token: &str,
+        identifier: &str,
+    ) -> Result<TlsAlpnCertificate, anyhow::Error> {
+        Ok(Self::need_account(&self.account)?.tls_alpn_01_certificate(token, identifier)?)
+    }
+
     async fn register_account(
         &mut self,
         account: AccountCreator,
@@ -172,14 +200,30 @@ impl AcmeClient {
     /// Please remember to persist the order somewhere (ideally along with the account data) in
     /// order to finish & query it later on.
     pub async fn new_order<I>(&mut self, domains: I) -> Result<Order, anyhow::Error>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        self.new_order_replacing(domains, None).await
+    }
+
+    /// Method to create a new order for a set of domains which replaces an existing certificate.
+    ///
+    /// `replaces` is the ARI identifier of the certificate which is being renewed, see
+    /// [`certificate_id`](crate::renewal_info::certificate_id).
+    pub async fn new_order_replacing<I>(
+        &mut self,
+        domains: I,
+        replaces: Option<String>,
+    ) -> Result<Order, anyhow::Error>
     where
         I: IntoIterator<Item = String>,
     {
         let account = Self::need_account(&self.account)?;
 
-        let order = domains
+        let mut order = domains
             .into_iter()
             .fold(OrderData::new(), |order, domain| order.domain(domain));
+        order.replaces = replaces;
 
         let mut retry = retry();
         loop {
@@ -212,6 +256,28 @@ impl AcmeClient {
         }
     }
 
+    /// Get the ACME renewal information for a PEM or DER formatted certificate.
+    ///
+    /// Returns `None` if the ACME server does not support ARI.
+    pub async fn renewal_info(
+        &mut self,
+        certificate: &[u8],
+    ) -> Result<Option<RenewalInfo>, anyhow::Error> {
+        let certificate_id = crate::renewal_info::certificate_id(certificate)?;
+        let request = match crate::renewal_info::renewal_info_request(
+            self.directory().await?,
+            &certificate_id,
+        ) {
+            Some(request) => request,
+            None => return Ok(None),
+        };
+
+        let response = self.run_request(request).await?;
+        let mut info: RenewalInfo = response.json()?;
+        info.retry_after = response.retry_after;
+        Ok(Some(info))
+    }
+
     /// Low level "POST-as-GET" request.
     async fn post_as_get(&mut self, url: &str) -> Result<AcmeResponse, anyhow::Error> {
         let account = Self::need_account(&self.account)?;
@@ -353,6 +419,7 @@ struct AcmeResponse {
     body: Bytes,
     location: Option<String>,
     got_nonce: bool,
+    retry_after: Option<u64>,
 }
 
 impl AcmeResponse {
@@ -438,10 +505,17 @@ impl AcmeClient {
                 })
                 .transpose()?;
 
+            let retry_after = parts
+                .headers
+                .get("Retry-After")
+                .and_then(|header| header.to_str().ok())
+                .and_then(crate::renewal_info::parse_retry_after);
+
             return Ok(AcmeResponse {
                 body,
                 location,
                 got_nonce,
+                retry_after,
             });
         }
 
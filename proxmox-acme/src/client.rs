@@ -5,10 +5,14 @@ use std::sync::Arc;
 
 use serde::{Deserialize, Serialize};
 
+use proxmox_http::TlsConfig;
+
 use crate::b64u;
 use crate::error;
 use crate::order::OrderData;
+use crate::renewal_info::RenewalInfo;
 use crate::request::ErrorResponse;
+use crate::util::TlsAlpnCertificate;
 use crate::{Account, Authorization, Challenge, Directory, Error, Order, Request};
 
 macro_rules! format_err {
@@ -70,6 +74,10 @@ pub struct Headers {
     /// The 'Location' header usually encodes the URL where an account or order can be queried from
     /// after they were created.
     pub location: Option<String>,
+
+    /// The 'Retry-After' header in seconds, used for renewal information.
+    pub retry_after: Option<u64>,
+
     nonce: Option<String>,
 }
 
@@ -77,17 +85,23 @@ struct Inner {
     agent: Option<ureq::Agent>,
     nonce: Option<String>,
     proxy: Option<String>,
+    tls_config: Option<TlsConfig>,
 }
 
 impl Inner {
     fn agent(&mut self) -> Result<&mut ureq::Agent, Error> {
         if self.agent.is_none() {
-            let connector = Arc::new(
-                native_tls::TlsConnector::new()
-                    .map_err(|err| format_err!("failed to create tls connector: {}", err))?,
-            );
-
-            let mut builder = ureq::AgentBuilder::new().tls_connector(connector);
+            let mut builder = match self.tls_config {
+                Some(ref tls_config) => ureq::AgentBuilder::new().tls_connector(
+                    tls_config
+                        .ureq_tls_connector()
+                        .map_err(|err| format_err!("failed to create tls connector: {}", err))?,
+                ),
+                None => ureq::AgentBuilder::new()
+                    .tls_connector(Arc::new(native_tls::TlsConnector::new().map_err(
+                        |err| format_err!("failed to create tls connector: {}", err),
+                    )?)),
+            };
 
             if let Some(proxy) = self.proxy.as_deref() {
                 builder = builder.proxy(
@@ -107,6 +121,7 @@ impl Inner {
             agent: None,
             nonce: None,
             proxy: None,
+            tls_config: None,
         }
     }
 
@@ -138,6 +153,10 @@ impl Inner {
             headers.location = Some(value.to_owned());
         }
 
+        if let Some(value) = response.header("Retry-After") {
+            headers.retry_after = crate::renewal_info::parse_retry_after(value);
+        }
+
         if let Some(value) = response.header(crate::REPLAY_NONCE) {
             headers.nonce = Some(value.to_owned());
         }
@@ -163,6 +182,11 @@ impl Inner {
         self.agent = None;
     }
 
+    pub fn set_tls_config(&mut self, tls_config: TlsConfig) {
+        self.tls_config = Some(tls_config);
+        self.agent = None;
+    }
+
     /// Low-level API to run an API request. This automatically updates the current nonce!
     fn run_request(&mut self, request: Request) -> Result<HttpResponse, Error> {
         let body = if request.body.is_empty() {
@@ -453,11 +477,24 @@ impl Client {
     /// Please remember to persist the order somewhere (ideally along with the account data) in
     /// order to finish & query it later on.
     pub fn new_order(&mut self, domains: Vec<String>) -> Result<Order, Error> {
+        self.new_order_replacing(domains, None)
+    }
+
+    /// Method to create a new order for a set of domains which replaces an existing certificate.
+    ///
+    /// `replaces` is the ARI identifier of the certificate which is being renewed, see
+    /// [`certificate_id`](crate::renewal_info::certificate_id).
+    pub fn new_order_replacing(
+        &mut self,
+        domains: Vec<String>,
+        replaces: Option<String>,
+    ) -> Result<Order, Error> {
         let account = Self::need_account(&self.account)?;
 
-        let order = domains
+        let mut order = domains
             .into_iter()
             .fold(OrderData::new(), |order, domain| order.domain(domain));
+        order.replaces = replaces;
 
         let mut retry = retry();
         loop {
@@ -487,6 +524,24 @@ impl Client {
         self.post_as_get(url)?.json()
     }
 
+    /// Get the ACME renewal information for a PEM or DER formatted certificate.
+    ///
+    /// Returns `None` if the ACME server does not support ARI.
+    pub fn renewal_info(&mut self, certificate: &[u8]) -> Result<Option<RenewalInfo>, Error> {
+        let certificate_id = crate::renewal_info::certificate_id(certificate)?;
+        let directory =
+            Self::get_directory(&mut self.inner, &mut self.directory, &self.directory_url)?;
+        let request = match crate::renewal_info::renewal_info_request(directory, &certificate_id) {
+            Some(request) => request,
+            None => return Ok(None),
+        };
+
+        let response = self.inner.run_request(request)?;
+        let mut info: RenewalInfo = response.json()?;
+        info.retry_after = response.headers.retry_after;
+        Ok(Some(info))
+    }
+
     /// Low level "POST-as-GET" request.
     pub fn post_as_get(&mut self, url: &str) -> Result<HttpResponse, Error> {
         let account = Self::need_account(&self.account)?;
@@ -543,6 +598,15 @@ impl Client {
         Self::need_account(&self.account)?.dns_01_txt_value(token)
     }
 
+    /// Shortcut to `account().ok_or_else(...).tls_alpn_01_certificate()`.
+    pub fn tls_alpn_01_certificate(
+        &self,
+        token: &str,
+        identifier: &str,
+    ) -> Result<TlsAlpnCertificate, Error> {
+        Self::need_account(&self.account)?.tls_alpn_01_certificate(token, identifier)
+    }
+
     /// Low-level API to run an n API request. This automatically updates the current nonce!
     pub fn run_request(&mut self, request: Request) -> Result<HttpResponse, Error> {
         self.inner.run_request(request)
@@ -594,6 +658,12 @@ impl Client {
     pub fn set_proxy(&mut self, proxy: String) {
         self.inner.set_proxy(proxy)
     }
+
+    /// Set the TLS configuration used to verify the ACME server, for example to trust a private
+    /// CA's certificate.
+    pub fn set_tls_config(&mut self, tls_config: TlsConfig) {
+        self.inner.set_tls_config(tls_config)
+    }
 }
 
 /// bad nonce retry count helper
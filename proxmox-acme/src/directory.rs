@@ -34,6 +34,10 @@ pub struct DirectoryData {
     /// Account key rollover URL.
     pub key_change: String,
 
+    /// Base URL of the ACME Renewal Information (ARI) endpoint, if supported.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub renewal_info: Option<String>,
+
     /// Metadata object, for additional information which aren't directly part of the API
     /// itself, such as the terms of service.
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -100,6 +104,11 @@ impl Directory {
         &self.data.new_order
     }
 
+    /// Get the "renewalInfo" URL if the ACME server supports renewal information (ARI).
+    pub fn renewal_info_url(&self) -> Option<&str> {
+        self.data.renewal_info.as_deref()
+    }
+
     /// Access to the in the Acme spec defined metadata structure.
     pub fn meta(&self) -> Option<&Meta> {
         self.data.meta.as_ref()
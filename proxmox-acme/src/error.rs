@@ -10,6 +10,10 @@ pub const BAD_NONCE: &str = "urn:ietf:params:acme:error:badNonce";
 /// The ACME error string for a "user action required" error.
 pub const USER_ACTION_REQUIRED: &str = "urn:ietf:params:acme:error:userActionRequired";
 
+/// The ACME error string for an "already replaced" error, returned when the certificate named in
+/// an order's `replaces` field was already replaced.
+pub const ALREADY_REPLACED: &str = "urn:ietf:params:acme:error:alreadyReplaced";
+
 /// Error types returned by this crate.
 #[derive(Debug)]
 #[must_use = "unused errors have no effect"]
@@ -42,6 +42,9 @@ pub mod error;
 #[cfg(feature = "impl")]
 pub mod order;
 
+#[cfg(feature = "impl")]
+pub mod renewal_info;
+
 #[cfg(feature = "impl")]
 pub mod util;
 
@@ -111,6 +111,10 @@ pub struct OrderData {
     /// URL at which the issued certificate can be fetched once it is available.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub certificate: Option<String>,
+
+    /// The ARI certificate identifier of the certificate this order is a renewal for.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub replaces: Option<String>,
 }
 
 impl OrderData {
@@ -124,6 +128,15 @@ impl OrderData {
         self.identifiers.push(Identifier::Dns(domain));
         self
     }
+
+    /// Builder-style method to mark the order as a renewal of the certificate with the ARI
+    /// identifier `certificate_id`.
+    ///
+    /// See [`certificate_id`](crate::renewal_info::certificate_id).
+    pub fn replaces(mut self, certificate_id: String) -> Self {
+        self.replaces = Some(certificate_id);
+        self
+    }
 }
 
 /// Represents an order for a new certificate. This combines the order's own location (URL) with
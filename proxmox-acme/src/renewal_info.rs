@@ -0,0 +1,141 @@
+//! ACME Renewal Information (ARI) support.
+//!
+//! The ARI extension lets the ACME server suggest a time window in which a certificate should be
+//! renewed, which allows CAs to spread renewals out or to request early renewals, for example when
+//! certificates have to be revoked.
+
+use openssl::x509::X509;
+use serde::{Deserialize, Serialize};
+
+use crate::b64u;
+use crate::directory::Directory;
+use crate::request::Request;
+use crate::Error;
+
+/// The renewal window suggested by the ACME server.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SuggestedWindow {
+    /// Start of the window as RFC3339 formatted time string.
+    pub start: String,
+
+    /// End of the window as RFC3339 formatted time string.
+    pub end: String,
+}
+
+/// The renewal information object returned by the `renewalInfo` endpoint.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RenewalInfo {
+    /// The window in which the certificate should be renewed.
+    pub suggested_window: SuggestedWindow,
+
+    /// An optional URL pointing to a page explaining why the window was chosen.
+    #[serde(
+        rename = "explanationURL",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub explanation_url: Option<String>,
+
+    /// The number of seconds after which the renewal information should be checked again, taken
+    /// from the `Retry-After` header of the response.
+    #[serde(skip)]
+    pub retry_after: Option<u64>,
+}
+
+/// Compute the ARI certificate identifier of a PEM or DER formatted certificate.
+///
+/// This is the base64url encoded key identifier of the certificate's authority key identifier
+/// extension and the base64url encoded DER serial number, joined with a dot. The identifier is
+/// also used for the `replaces` field of new orders.
+pub fn certificate_id(certificate: &[u8]) -> Result<String, Error> {
+    let certificate = if certificate.starts_with(b"-----") {
+        X509::from_pem(certificate)?
+    } else {
+        X509::from_der(certificate)?
+    };
+
+    let key_id = certificate
+        .authority_key_id()
+        .ok_or_else(|| Error::Custom("certificate has no authority key identifier".to_string()))?;
+
+    // The serial is encoded as the contents of a DER INTEGER, which needs a leading zero byte if
+    // the most significant bit is set.
+    let mut serial = certificate.serial_number().to_bn()?.to_vec();
+    if serial.first().map(|b| b & 0x80 != 0).unwrap_or(true) {
+        serial.insert(0, 0);
+    }
+
+    Ok(format!(
+        "{}.{}",
+        b64u::encode(key_id.as_slice()),
+        b64u::encode(&serial)
+    ))
+}
+
+/// Create the (unauthenticated `GET`) request to fetch the renewal information of a certificate
+/// with the identifier from [`certificate_id`].
+///
+/// Returns `None` if the ACME server does not support ARI.
+pub fn renewal_info_request(directory: &Directory, certificate_id: &str) -> Option<Request> {
+    let url = directory.renewal_info_url()?;
+
+    Some(Request {
+        url: format!("{}/{}", url.trim_end_matches('/'), certificate_id),
+        method: "GET",
+        content_type: "",
+        body: String::new(),
+        expected: 200,
+    })
+}
+
+/// Parse the value of a `Retry-After` header given in seconds.
+///
+/// The HTTP-date form is not supported and ignored.
+#[cfg(any(feature = "client", feature = "async-client"))]
+pub(crate) fn parse_retry_after(value: &str) -> Option<u64> {
+    value.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Example certificate from the ARI specification (RFC 9773, section 4.1).
+    const ARI_EXAMPLE_CERT: &str = "\
+-----BEGIN CERTIFICATE-----
+MIIBQzCB66ADAgECAgUAh2VDITAKBggqhkjOPQQDAjAVMRMwEQYDVQQDEwpFeGFt
+cGxlIENBMCIYDzAwMDEwMTAxMDAwMDAwWhgPMDAwMTAxMDEwMDAwMDBaMBYxFDAS
+BgNVBAMTC2V4YW1wbGUuY29tMFkwEwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAEeBZu
+7cbpAYNXZLbbh8rNIzuOoqOOtmxA1v7cRm//AwyMwWxyHz4zfwmBhcSrf47NUAFf
+qzLQ2PPQxdTXREYEnKMjMCEwHwYDVR0jBBgwFoAUaYhba4dGQEHhs3uEe6CuLN4B
+yNQwCgYIKoZIzj0EAwIDRwAwRAIge09+S5TZAlw5tgtiVvuERV6cT4mfutXIlwTb
++FYN/8oCIClDsqBklhB9KAelFiYt9+6FDj3z4KGVelYM5MdsO3pK
+-----END CERTIFICATE-----
+";
+
+    #[test]
+    fn test_certificate_id() {
+        let expected = "aYhba4dGQEHhs3uEe6CuLN4ByNQ.AIdlQyE";
+
+        let pem = ARI_EXAMPLE_CERT.as_bytes();
+        assert_eq!(certificate_id(pem).unwrap(), expected);
+
+        let der = X509::from_pem(pem).unwrap().to_der().unwrap();
+        assert_eq!(certificate_id(&der).unwrap(), expected);
+
+        assert!(certificate_id(b"not a certificate").is_err());
+    }
+
+    #[cfg(any(feature = "client", feature = "async-client"))]
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after("21600"), Some(21600));
+        assert_eq!(parse_retry_after(" 60 "), Some(60));
+        assert_eq!(parse_retry_after("0"), Some(0));
+        assert_eq!(parse_retry_after("-1"), None);
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), None);
+        assert_eq!(parse_retry_after(""), None);
+    }
+}
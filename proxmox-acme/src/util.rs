@@ -2,11 +2,14 @@
 
 use std::collections::HashMap;
 
+use openssl::asn1::{Asn1Integer, Asn1Object, Asn1OctetString, Asn1Time};
+use openssl::bn::{BigNum, MsbOption};
+use openssl::ec::{EcGroup, EcKey};
 use openssl::hash::MessageDigest;
 use openssl::nid::Nid;
-use openssl::pkey::PKey;
+use openssl::pkey::{PKey, Private};
 use openssl::rsa::Rsa;
-use openssl::x509::{self, X509Name, X509Req};
+use openssl::x509::{self, X509Extension, X509Name, X509Req, X509};
 
 use crate::Error;
 
@@ -83,3 +86,81 @@ impl Csr {
         })
     }
 }
+
+/// The ALPN protocol name used for the `tls-alpn-01` challenge (RFC 8737).
+pub const ACME_TLS_ALPN_PROTOCOL: &[u8] = b"acme-tls/1";
+
+/// Object identifier of the `id-pe-acmeIdentifier` certificate extension (RFC 8737).
+pub const ACME_IDENTIFIER_OID: &str = "1.3.6.1.5.5.7.1.31";
+
+/// Create the critical `acmeIdentifier` certificate extension for a key authorization.
+///
+/// The extension value is the DER encoded octet string of the SHA-256 digest of the key
+/// authorization.
+pub fn acme_identifier_extension(key_authorization: &str) -> Result<X509Extension, Error> {
+    let digest = openssl::sha::sha256(key_authorization.as_bytes());
+
+    let mut der = vec![0x04, digest.len() as u8];
+    der.extend_from_slice(&digest);
+
+    let oid = Asn1Object::from_str(ACME_IDENTIFIER_OID)?;
+    let value = Asn1OctetString::new_from_bytes(&der)?;
+    Ok(X509Extension::new_from_der(&oid, true, &value)?)
+}
+
+/// A self signed validation certificate for the `tls-alpn-01` challenge.
+pub struct TlsAlpnCertificate {
+    /// The validation certificate.
+    pub certificate: X509,
+
+    /// The private key of the validation certificate.
+    pub private_key: PKey<Private>,
+}
+
+impl TlsAlpnCertificate {
+    /// Generate a validation certificate for the domain `identifier`.
+    ///
+    /// The certificate contains `identifier` as its only SubjectAlternativeName and the
+    /// `acmeIdentifier` extension for `key_authorization`.
+    pub fn generate(identifier: &str, key_authorization: &str) -> Result<Self, Error> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+        let private_key = EcKey::generate(&group)
+            .and_then(PKey::from_ec_key)
+            .map_err(|err| Error::Ssl("failed to generate EC key: {}", err))?;
+
+        let mut name = X509Name::builder()?;
+        // the common name is limited to 64 characters, the SAN is what counts anyway
+        if identifier.len() <= 64 {
+            name.append_entry_by_nid(Nid::COMMONNAME, identifier)?;
+        }
+        let name = name.build();
+
+        let mut serial = BigNum::new()?;
+        serial.rand(128, MsbOption::MAYBE_ZERO, false)?;
+        let serial = Asn1Integer::from_bn(&serial)?;
+        let not_before = Asn1Time::days_from_now(0)?;
+        let not_after = Asn1Time::days_from_now(7)?;
+
+        let mut cert = X509::builder()?;
+        cert.set_version(2)?;
+        cert.set_serial_number(&serial)?;
+        cert.set_subject_name(&name)?;
+        cert.set_issuer_name(&name)?;
+        cert.set_not_before(&not_before)?;
+        cert.set_not_after(&not_after)?;
+        cert.set_pubkey(&private_key)?;
+
+        let san = x509::extension::SubjectAlternativeName::new()
+            .dns(identifier)
+            .build(&cert.x509v3_context(None, None))?;
+        cert.append_extension(san)?;
+        cert.append_extension(acme_identifier_extension(key_authorization)?)?;
+
+        cert.sign(&private_key, MessageDigest::sha256())?;
+
+        Ok(Self {
+            certificate: cert.build(),
+            private_key,
+        })
+    }
+}
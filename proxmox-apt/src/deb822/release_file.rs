@@ -330,11 +330,12 @@ impl TryFrom<ReleaseFileRaw> for ReleaseFile {
             ),
             changelogs: value.changelogs,
             codename: value.codename,
-            date: value.date.as_deref().map(parse_date),
+            date: value.date.as_deref().and_then(parse_date),
             valid_until: value
                 .extra_fields
                 .get("Valid-Until")
-                .map(|val| parse_date(&val.to_string())),
+                .and_then(|val| val.as_str())
+                .and_then(parse_date),
             description: value.description,
             label: value.label,
             origin: value.origin,
@@ -524,9 +525,72 @@ fn parse_file_reference(
     ))
 }
 
-fn parse_date(_date_str: &str) -> u64 {
-    // TODO implement
-    0
+/// Parse an RFC 2822 date as used in the `Date` and `Valid-Until` fields, e.g.
+/// `Sat, 18 Dec 2021 10:38:58 UTC`, into seconds since the UNIX epoch.
+fn parse_date(date_str: &str) -> Option<u64> {
+    // the day of week is optional and redundant
+    let date_str = match date_str.split_once(',') {
+        Some((_day_of_week, rest)) => rest,
+        None => date_str,
+    };
+
+    let mut parts = date_str.split_ascii_whitespace();
+    let day: u64 = parts.next()?.parse().ok()?;
+    let month = match parts.next()? {
+        "Jan" => 1,
+        "Feb" => 2,
+        "Mar" => 3,
+        "Apr" => 4,
+        "May" => 5,
+        "Jun" => 6,
+        "Jul" => 7,
+        "Aug" => 8,
+        "Sep" => 9,
+        "Oct" => 10,
+        "Nov" => 11,
+        "Dec" => 12,
+        _ => return None,
+    };
+    let year: u64 = parts.next()?.parse().ok()?;
+
+    let mut time = parts.next()?.split(':');
+    let hour: u64 = time.next()?.parse().ok()?;
+    let minute: u64 = time.next()?.parse().ok()?;
+    let second: u64 = time.next().map(|s| s.parse().ok()).unwrap_or(Some(0))?;
+
+    let offset: i64 = match parts.next() {
+        None | Some("UTC") | Some("GMT") | Some("Z") => 0,
+        Some(tz) if tz.len() == 5 && (tz.starts_with('+') || tz.starts_with('-')) => {
+            let hours: i64 = tz[1..3].parse().ok()?;
+            let minutes: i64 = tz[3..5].parse().ok()?;
+            let offset = hours * 3600 + minutes * 60;
+            if tz.starts_with('-') {
+                -offset
+            } else {
+                offset
+            }
+        }
+        Some(_) => return None,
+    };
+
+    if year < 1970 || day == 0 || day > 31 || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    // days since the epoch, see http://howardhinnant.github.io/date_algorithms.html
+    let (y, m) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = y / 400;
+    let yoe = y % 400;
+    let doy = (153 * m + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    let epoch = (days * 86400 + hour * 3600 + minute * 60 + second) as i64 - offset;
+    u64::try_from(epoch).ok()
 }
 
 fn parse_binary_dir(file_name: &str, arch: &str, path: &str) -> Result<FileReferenceType, Error> {
@@ -603,6 +667,27 @@ pub fn test_deb_release_file() {
     //println!("{:?}", parsed);
 
     assert_eq!(parsed.files.len(), 315);
+    assert_eq!(parsed.date, Some(1639823938));
+    assert_eq!(parsed.valid_until, None);
+}
+
+#[test]
+pub fn test_parse_date() {
+    assert_eq!(
+        parse_date("Sat, 18 Dec 2021 10:38:58 UTC"),
+        Some(1639823938)
+    );
+    assert_eq!(
+        parse_date("Mon, 28 Jun 2021 18:05:11 +0000"),
+        Some(1624903511)
+    );
+    assert_eq!(
+        parse_date("Mon, 28 Jun 2021 20:05:11 +0200"),
+        Some(1624903511)
+    );
+    assert_eq!(parse_date("29 Feb 2024 00:00:00 UTC"), Some(1709164800));
+    assert_eq!(parse_date("Tue, 06 Jul 2021 08:07:21 CEST"), None);
+    assert_eq!(parse_date("garbage"), None);
 }
 
 #[test]
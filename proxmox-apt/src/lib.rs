@@ -1,3 +1,4 @@
 pub mod config;
 pub mod deb822;
 pub mod repositories;
+pub mod verification;
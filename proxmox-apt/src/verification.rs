@@ -0,0 +1,550 @@
+//! Verification of APT repository metadata.
+//!
+//! This checks the OpenPGP signatures of `InRelease` or `Release` and `Release.gpg` files against
+//! a keyring, the `Valid-Until` field and the sizes and checksums of the package indices
+//! referenced by the `Release` file. Together, this allows checking whether a (mirrored)
+//! repository is trustworthy without involving APT.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, format_err, Error};
+use sequoia_openpgp::cert::CertParser;
+use sequoia_openpgp::packet::Signature;
+use sequoia_openpgp::parse::stream::{
+    DetachedVerifierBuilder, MessageLayer, MessageStructure, VerificationError, VerificationHelper,
+    VerifierBuilder,
+};
+use sequoia_openpgp::parse::Parse;
+use sequoia_openpgp::policy::StandardPolicy;
+use sequoia_openpgp::types::HashAlgorithm;
+use sequoia_openpgp::{Cert, KeyHandle};
+
+use crate::deb822::{FileReference, ReleaseFile};
+
+/// A set of OpenPGP certificates trusted for signing repository metadata.
+#[derive(Clone, Default)]
+pub struct Keyring {
+    certs: Vec<Cert>,
+}
+
+impl Keyring {
+    /// Parse one or more certificates, either ASCII armored or binary.
+    ///
+    /// This can also be used for keys embedded in the `Signed-By` option of `.sources` files.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, Error> {
+        let certs = CertParser::from_bytes(data)?.collect::<Result<Vec<Cert>, _>>()?;
+        if certs.is_empty() {
+            bail!("no OpenPGP certificates found");
+        }
+        Ok(Self { certs })
+    }
+
+    /// Load a keyring file, as referenced by the `Signed-By` option.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let data = std::fs::read(path)
+            .map_err(|err| format_err!("unable to read keyring {path:?} - {err}"))?;
+        Self::from_bytes(&data)
+            .map_err(|err| format_err!("unable to parse keyring {path:?} - {err}"))
+    }
+
+    /// Load all `.gpg` and `.asc` keyrings from a directory like `/etc/apt/trusted.gpg.d`.
+    pub fn from_dir<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+
+        let mut files: Vec<PathBuf> = std::fs::read_dir(path)
+            .map_err(|err| format_err!("unable to read directory {path:?} - {err}"))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                matches!(
+                    path.extension().and_then(|ext| ext.to_str()),
+                    Some("gpg") | Some("asc")
+                )
+            })
+            .collect();
+        files.sort();
+
+        let mut keyring = Self::default();
+        for file in files {
+            keyring.extend(Self::from_file(file)?);
+        }
+        Ok(keyring)
+    }
+
+    /// Add all certificates from another keyring.
+    pub fn extend(&mut self, other: Keyring) {
+        self.certs.extend(other.certs);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.certs.is_empty()
+    }
+
+    /// Fingerprints of the contained certificates.
+    pub fn fingerprints(&self) -> Vec<String> {
+        self.certs
+            .iter()
+            .map(|cert| cert.fingerprint().to_hex())
+            .collect()
+    }
+}
+
+/// Settings for verifying repository metadata.
+#[derive(Clone, Copy, Debug)]
+pub struct VerificationPolicy {
+    /// Accept signatures using SHA-1, which are rejected by default.
+    pub allow_sha1: bool,
+
+    /// Check the `Valid-Until` field of the release file (default).
+    pub check_valid_until: bool,
+}
+
+impl Default for VerificationPolicy {
+    fn default() -> Self {
+        Self {
+            allow_sha1: false,
+            check_valid_until: true,
+        }
+    }
+}
+
+/// A valid signature made by a key from the keyring.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GoodSignature {
+    /// Fingerprint of the (sub)key which made the signature.
+    pub fingerprint: String,
+    /// Signature creation time as UNIX epoch.
+    pub creation_time: Option<i64>,
+    /// Hash algorithm used for the signature.
+    pub hash_algorithm: String,
+}
+
+/// A signature which could not be verified.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SignatureIssue {
+    /// There is no key for the signature's issuer in the keyring.
+    MissingKey { issuer: Option<String> },
+    /// The signature uses a hash algorithm rejected as weak.
+    WeakHash {
+        issuer: Option<String>,
+        algorithm: String,
+    },
+    /// The signature or the signing key expired.
+    Expired {
+        issuer: Option<String>,
+        reason: String,
+    },
+    /// Any other problem, like a bad signature or a revoked key.
+    Invalid {
+        issuer: Option<String>,
+        reason: String,
+    },
+}
+
+impl fmt::Display for SignatureIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn issuer(issuer: &Option<String>) -> &str {
+            issuer.as_deref().unwrap_or("unknown issuer")
+        }
+
+        match self {
+            SignatureIssue::MissingKey { issuer: i } => {
+                write!(f, "no key for signature from {}", issuer(i))
+            }
+            SignatureIssue::WeakHash {
+                issuer: i,
+                algorithm,
+            } => write!(f, "signature from {} uses weak hash {algorithm}", issuer(i)),
+            SignatureIssue::Expired { issuer: i, reason } => {
+                write!(f, "signature from {} expired - {reason}", issuer(i))
+            }
+            SignatureIssue::Invalid { issuer: i, reason } => {
+                write!(f, "invalid signature from {} - {reason}", issuer(i))
+            }
+        }
+    }
+}
+
+fn signature_issuer(sig: &Signature) -> Option<String> {
+    sig.get_issuers().first().map(|handle| handle.to_hex())
+}
+
+fn is_weak_hash(algorithm: HashAlgorithm) -> bool {
+    matches!(
+        algorithm,
+        HashAlgorithm::MD5 | HashAlgorithm::SHA1 | HashAlgorithm::RipeMD
+    )
+}
+
+fn system_time_to_epoch(time: SystemTime) -> Option<i64> {
+    time.duration_since(UNIX_EPOCH)
+        .ok()
+        .and_then(|duration| i64::try_from(duration.as_secs()).ok())
+}
+
+impl SignatureIssue {
+    fn from_error(err: &VerificationError) -> Self {
+        match err {
+            VerificationError::MissingKey { sig } => SignatureIssue::MissingKey {
+                issuer: signature_issuer(sig),
+            },
+            VerificationError::BadKey { sig, ka, error } => {
+                if ka.alive().is_err() {
+                    SignatureIssue::Expired {
+                        issuer: signature_issuer(sig),
+                        reason: error.to_string(),
+                    }
+                } else {
+                    SignatureIssue::Invalid {
+                        issuer: signature_issuer(sig),
+                        reason: error.to_string(),
+                    }
+                }
+            }
+            VerificationError::BadSignature { sig, error, .. } => {
+                if is_weak_hash(sig.hash_algo()) {
+                    SignatureIssue::WeakHash {
+                        issuer: signature_issuer(sig),
+                        algorithm: sig.hash_algo().to_string(),
+                    }
+                } else if sig
+                    .signature_alive(None::<SystemTime>, None::<std::time::Duration>)
+                    .is_err()
+                {
+                    SignatureIssue::Expired {
+                        issuer: signature_issuer(sig),
+                        reason: error.to_string(),
+                    }
+                } else {
+                    SignatureIssue::Invalid {
+                        issuer: signature_issuer(sig),
+                        reason: error.to_string(),
+                    }
+                }
+            }
+            other => SignatureIssue::Invalid {
+                issuer: None,
+                reason: other.to_string(),
+            },
+        }
+    }
+}
+
+struct Helper<'a> {
+    keyring: &'a Keyring,
+    good: Vec<GoodSignature>,
+    issues: Vec<SignatureIssue>,
+}
+
+impl VerificationHelper for Helper<'_> {
+    fn get_certs(&mut self, _ids: &[KeyHandle]) -> sequoia_openpgp::Result<Vec<Cert>> {
+        Ok(self.keyring.certs.clone())
+    }
+
+    fn check(&mut self, structure: MessageStructure) -> sequoia_openpgp::Result<()> {
+        let mut layers = structure.into_iter();
+        let results = match (layers.next(), layers.next()) {
+            (Some(MessageLayer::SignatureGroup { results }), None) => results,
+            // we don't want compression and/or encryption
+            _ => bail!("unexpected OpenPGP message structure - expected plain signed data"),
+        };
+
+        for result in results {
+            match result {
+                Ok(checksum) => self.good.push(GoodSignature {
+                    fingerprint: checksum.ka.fingerprint().to_hex(),
+                    creation_time: checksum
+                        .sig
+                        .signature_creation_time()
+                        .and_then(system_time_to_epoch),
+                    hash_algorithm: checksum.sig.hash_algo().to_string(),
+                }),
+                Err(err) => self.issues.push(SignatureIssue::from_error(&err)),
+            }
+        }
+
+        if self.good.is_empty() {
+            let issues: Vec<String> = self.issues.iter().map(|issue| issue.to_string()).collect();
+            bail!("no valid signature found - {}", issues.join(", "));
+        }
+
+        Ok(())
+    }
+}
+
+/// A release file with at least one valid signature.
+pub struct VerifiedRelease {
+    /// The parsed release file.
+    pub release: ReleaseFile,
+    /// The signed data, without the signature for `InRelease` files.
+    pub data: Vec<u8>,
+    /// The valid signatures.
+    pub good_signatures: Vec<GoodSignature>,
+    /// Additional signatures which could not be verified, for example by keys not in the keyring.
+    pub signature_issues: Vec<SignatureIssue>,
+}
+
+impl VerifiedRelease {
+    fn new(data: Vec<u8>, helper: Helper, policy: &VerificationPolicy) -> Result<Self, Error> {
+        let release = ReleaseFile::try_from(data.as_slice())
+            .map_err(|err| format_err!("unable to parse release file - {err}"))?;
+
+        if policy.check_valid_until {
+            let now = system_time_to_epoch(SystemTime::now())
+                .and_then(|epoch| u64::try_from(epoch).ok())
+                .unwrap_or(0);
+            check_valid_until(&release, now)?;
+        }
+
+        Ok(Self {
+            release,
+            data,
+            good_signatures: helper.good,
+            signature_issues: helper.issues,
+        })
+    }
+}
+
+fn standard_policy(policy: &VerificationPolicy) -> StandardPolicy<'static> {
+    let mut standard_policy = StandardPolicy::new();
+    if policy.allow_sha1 {
+        standard_policy.accept_hash(HashAlgorithm::SHA1);
+    }
+    standard_policy
+}
+
+/// Verify a clearsigned `InRelease` file.
+pub fn verify_inrelease(
+    data: &[u8],
+    keyring: &Keyring,
+    policy: &VerificationPolicy,
+) -> Result<VerifiedRelease, Error> {
+    let standard_policy = standard_policy(policy);
+    let helper = Helper {
+        keyring,
+        good: Vec::new(),
+        issues: Vec::new(),
+    };
+
+    let mut verifier = VerifierBuilder::from_bytes(data)?
+        .with_policy(&standard_policy, None, helper)
+        .map_err(|err| format_err!("signature verification failed - {err}"))?;
+
+    let mut verified = Vec::new();
+    std::io::copy(&mut verifier, &mut verified)
+        .map_err(|err| format_err!("signature verification failed - {err}"))?;
+
+    // The line ending before the signature is not part of the signed text, add it back so the
+    // data matches the corresponding Release file.
+    if !verified.is_empty() && !verified.ends_with(b"\n") {
+        verified.push(b'\n');
+    }
+
+    VerifiedRelease::new(verified, verifier.into_helper(), policy)
+}
+
+/// Verify a `Release` file and its detached signature from `Release.gpg`.
+pub fn verify_release(
+    data: &[u8],
+    signature: &[u8],
+    keyring: &Keyring,
+    policy: &VerificationPolicy,
+) -> Result<VerifiedRelease, Error> {
+    let standard_policy = standard_policy(policy);
+    let helper = Helper {
+        keyring,
+        good: Vec::new(),
+        issues: Vec::new(),
+    };
+
+    let mut verifier = DetachedVerifierBuilder::from_bytes(signature)?.with_policy(
+        &standard_policy,
+        None,
+        helper,
+    )?;
+
+    verifier
+        .verify_bytes(data)
+        .map_err(|err| format_err!("signature verification failed - {err}"))?;
+
+    VerifiedRelease::new(data.to_vec(), verifier.into_helper(), policy)
+}
+
+/// Check the `Valid-Until` field of a release file against `now` (seconds since the epoch).
+pub fn check_valid_until(release: &ReleaseFile, now: u64) -> Result<(), Error> {
+    if let Some(valid_until) = release.valid_until {
+        if valid_until < now {
+            bail!(
+                "release file expired {} seconds ago (Valid-Until)",
+                now - valid_until
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Verify the size and checksums of a file referenced by a release file.
+pub fn verify_file_reference(reference: &FileReference, data: &[u8]) -> Result<(), Error> {
+    if data.len() != reference.size {
+        bail!(
+            "size mismatch for '{}': expected {}, got {}",
+            reference.path,
+            reference.size,
+            data.len()
+        );
+    }
+
+    reference
+        .checksums
+        .verify(data)
+        .map_err(|err| format_err!("checksum mismatch for '{}' - {err}", reference.path))
+}
+
+/// Result of checking the package indices of a release against a directory.
+#[derive(Debug, Default)]
+pub struct IndexVerification {
+    /// Paths of the files which were verified successfully.
+    pub verified: Vec<String>,
+    /// Package indices for which none of the (compressed) variants exists.
+    pub missing: Vec<String>,
+    /// Files which exist but don't match the release file, with the reason.
+    pub failed: Vec<(String, String)>,
+}
+
+impl IndexVerification {
+    /// Whether all existing files matched the release file.
+    pub fn is_ok(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+/// Verify all `Packages` and `Sources` files referenced by a release file, relative to
+/// `dist_dir` (the directory containing the release file, e.g. `dists/bookworm`).
+///
+/// Only one variant of each index (for example `Packages.xz`) needs to exist. Indices for which
+/// no variant exists are reported as missing, since mirrors may only contain a subset of the
+/// components or architectures.
+pub fn verify_package_indices(
+    release: &ReleaseFile,
+    dist_dir: &Path,
+) -> Result<IndexVerification, Error> {
+    let mut result = IndexVerification::default();
+
+    let mut basenames: Vec<&String> = release.files.keys().collect();
+    basenames.sort();
+
+    for basename in basenames {
+        let references = &release.files[basename];
+        if !references
+            .iter()
+            .any(|reference| reference.file_type.is_package_index())
+        {
+            continue;
+        }
+
+        let mut found = false;
+        for reference in references {
+            let path = dist_dir.join(&reference.path);
+            let data = match std::fs::read(&path) {
+                Ok(data) => data,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(err) => bail!("unable to read {path:?} - {err}"),
+            };
+            found = true;
+
+            match verify_file_reference(reference, &data) {
+                Ok(()) => result.verified.push(reference.path.clone()),
+                Err(err) => result
+                    .failed
+                    .push((reference.path.clone(), err.to_string())),
+            }
+        }
+
+        if !found {
+            result.missing.push(basename.clone());
+        }
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+fn test_file(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/deb822/verification")
+        .join(name)
+}
+
+#[test]
+pub fn test_verify_inrelease() {
+    let keyring = Keyring::from_file(test_file("test-key.asc")).unwrap();
+    let data = std::fs::read(test_file("dists/test/InRelease")).unwrap();
+
+    let verified = verify_inrelease(&data, &keyring, &VerificationPolicy::default()).unwrap();
+    assert_eq!(verified.good_signatures.len(), 1);
+    assert!(verified.signature_issues.is_empty());
+    assert_eq!(verified.release.codename.as_deref(), Some("test"));
+    assert_eq!(
+        verified.data,
+        std::fs::read(test_file("dists/test/Release")).unwrap()
+    );
+
+    let other = Keyring::from_file(test_file("other-key.asc")).unwrap();
+    assert!(verify_inrelease(&data, &other, &VerificationPolicy::default()).is_err());
+}
+
+#[test]
+pub fn test_verify_release_detached() {
+    let keyring = Keyring::from_file(test_file("test-key.asc")).unwrap();
+    let data = std::fs::read(test_file("dists/test/Release")).unwrap();
+    let signature = std::fs::read(test_file("dists/test/Release.gpg")).unwrap();
+
+    let verified =
+        verify_release(&data, &signature, &keyring, &VerificationPolicy::default()).unwrap();
+    assert_eq!(verified.good_signatures.len(), 1);
+
+    let mut modified = data.clone();
+    modified.extend(b"Extra: field\n");
+    assert!(verify_release(
+        &modified,
+        &signature,
+        &keyring,
+        &VerificationPolicy::default()
+    )
+    .is_err());
+}
+
+#[test]
+pub fn test_verify_valid_until() {
+    let keyring = Keyring::from_file(test_file("test-key.asc")).unwrap();
+    let data = std::fs::read(test_file("InRelease-expired")).unwrap();
+
+    assert!(verify_inrelease(&data, &keyring, &VerificationPolicy::default()).is_err());
+
+    let policy = VerificationPolicy {
+        check_valid_until: false,
+        ..Default::default()
+    };
+    let verified = verify_inrelease(&data, &keyring, &policy).unwrap();
+    assert!(check_valid_until(&verified.release, 1639823938).is_ok());
+}
+
+#[test]
+pub fn test_verify_package_indices() {
+    let keyring = Keyring::from_file(test_file("test-key.asc")).unwrap();
+    let data = std::fs::read(test_file("dists/test/InRelease")).unwrap();
+    let verified = verify_inrelease(&data, &keyring, &VerificationPolicy::default()).unwrap();
+
+    let result = verify_package_indices(&verified.release, &test_file("dists/test")).unwrap();
+    assert!(result.is_ok());
+    assert_eq!(
+        result.verified,
+        vec!["main/binary-amd64/Packages".to_string()]
+    );
+    assert!(result.missing.is_empty());
+
+    let reference = &verified.release.files["main/binary-amd64/Packages"][0];
+    assert!(verify_file_reference(reference, b"tampered").is_err());
+}
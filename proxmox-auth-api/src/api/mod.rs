@@ -1,7 +1,7 @@
 use std::future::Future;
 use std::net::IpAddr;
 use std::pin::Pin;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 
 use anyhow::{format_err, Error};
 use percent_encoding::percent_decode_str;
@@ -10,7 +10,8 @@ use proxmox_rest_server::{extract_cookie, AuthError};
 use proxmox_tfa::api::{OpenUserChallengeData, TfaConfig};
 
 use crate::auth_key::{HMACKey, Keyring};
-use crate::types::{Authid, RealmRef, Userid, UsernameRef};
+use crate::jwt::{JwksCache, Jwt, JwtValidation};
+use crate::types::{Authid, Realm, RealmRef, Userid, Username, UsernameRef};
 
 mod access;
 mod ticket;
@@ -84,6 +85,47 @@ pub trait AuthContext: Send + Sync {
         let _ = (userid, password, path, privs, port);
         Ok(None)
     }
+
+    /// Lookup the configuration for bearer tokens (JWTs) issued by `issuer`.
+    ///
+    /// The default rejects all bearer tokens.
+    fn lookup_jwt_issuer(&self, issuer: &str) -> Option<Arc<JwtIssuer>> {
+        let _ = issuer;
+        None
+    }
+}
+
+/// Configuration for accepting JWT bearer tokens issued by an external (OpenID Connect) provider.
+pub struct JwtIssuer {
+    /// The claims to validate. The issuer needs to match the one the configuration was looked up
+    /// with.
+    pub validation: JwtValidation,
+
+    /// The keys used to verify the token signatures, usually the provider's `jwks_uri`.
+    pub jwks: JwksCache,
+
+    /// The realm users authenticated via this issuer belong to.
+    pub realm: Realm,
+
+    /// The claim containing the user name, eg. `"sub"` or `"preferred_username"`.
+    pub username_claim: String,
+}
+
+impl JwtIssuer {
+    /// Verify a bearer token and map it to a user id.
+    pub fn verify_token(&self, token: Jwt) -> Result<Userid, Error> {
+        let jwks = self.jwks.get(token.kid())?;
+        let claims = token.verify(&jwks, &self.validation)?;
+
+        let username = claims
+            .get(&self.username_claim)
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| format_err!("JWT without '{}' claim", self.username_claim))?;
+        let username = Username::try_from(username.to_string())
+            .map_err(|err| format_err!("JWT with invalid user name - {err}"))?;
+
+        Ok(Userid::from((username, self.realm.clone())))
+    }
 }
 
 /// When verifying TFA challenges we need to be able to update the TFA config without interference
@@ -123,14 +165,57 @@ struct UserAuthData {
 enum AuthData {
     User(UserAuthData),
     ApiToken(String),
+    Bearer(String),
 }
 
+/// Check the authentication data of a request and return the authenticated auth id.
+///
+/// Verifying a bearer token may need to fetch the issuer's key set, which blocks the calling
+/// thread. Use [`http_check_auth_async`] when calling from async code.
 pub fn http_check_auth(
     headers: &http::HeaderMap,
     method: &http::Method,
 ) -> Result<String, AuthError> {
     let auth_context = auth_context()?;
 
+    match check_auth_data(auth_context, headers, method)? {
+        CheckedAuth::AuthId(auth_id) => Ok(auth_id),
+        CheckedAuth::Bearer(jwt_issuer, token) => {
+            let userid = jwt_issuer.verify_token(token)?;
+            check_bearer_user(auth_context, userid)
+        }
+    }
+}
+
+/// Like [`http_check_auth`], but verifies bearer tokens on a blocking thread.
+pub async fn http_check_auth_async(
+    headers: &http::HeaderMap,
+    method: &http::Method,
+) -> Result<String, AuthError> {
+    let auth_context = auth_context()?;
+
+    match check_auth_data(auth_context, headers, method)? {
+        CheckedAuth::AuthId(auth_id) => Ok(auth_id),
+        CheckedAuth::Bearer(jwt_issuer, token) => {
+            let userid = tokio::task::spawn_blocking(move || jwt_issuer.verify_token(token))
+                .await
+                .map_err(|err| format_err!("failed to verify JWT - {err}"))??;
+            check_bearer_user(auth_context, userid)
+        }
+    }
+}
+
+enum CheckedAuth {
+    AuthId(String),
+    /// A bearer token from a known issuer, which still needs to be verified.
+    Bearer(Arc<JwtIssuer>, Jwt),
+}
+
+fn check_auth_data(
+    auth_context: &dyn AuthContext,
+    headers: &http::HeaderMap,
+    method: &http::Method,
+) -> Result<CheckedAuth, AuthError> {
     let auth_data = extract_auth_data(auth_context, headers);
     match auth_data {
         Some(AuthData::User(user_auth_data)) => {
@@ -165,7 +250,7 @@ pub fn http_check_auth(
                 }
             }
 
-            Ok(auth_id.to_string())
+            Ok(CheckedAuth::AuthId(auth_id.to_string()))
         }
         Some(AuthData::ApiToken(api_token)) => {
             let mut parts = api_token.splitn(2, ':');
@@ -187,12 +272,35 @@ pub fn http_check_auth(
 
             auth_context.verify_token_secret(&tokenid, &tokensecret)?;
 
-            Ok(tokenid.to_string())
+            Ok(CheckedAuth::AuthId(tokenid.to_string()))
+        }
+        Some(AuthData::Bearer(token)) => {
+            let token = Jwt::parse(&token)?;
+            let issuer = token
+                .issuer()
+                .ok_or_else(|| format_err!("JWT without issuer"))?;
+            let jwt_issuer = auth_context
+                .lookup_jwt_issuer(issuer)
+                .ok_or_else(|| format_err!("JWT from unknown issuer"))?;
+            if jwt_issuer.validation.issuer != issuer {
+                return Err(format_err!("JWT issuer configuration mismatch").into());
+            }
+
+            Ok(CheckedAuth::Bearer(jwt_issuer, token))
         }
         None => Err(AuthError::NoData),
     }
 }
 
+fn check_bearer_user(auth_context: &dyn AuthContext, userid: Userid) -> Result<String, AuthError> {
+    let auth_id = Authid::from(userid);
+    if !auth_context.auth_id_is_active(&auth_id)? {
+        return Err(format_err!("user account disabled or expired.").into());
+    }
+
+    Ok(auth_id.to_string())
+}
+
 fn extract_auth_data(
     auth_context: &dyn AuthContext,
     headers: &http::HeaderMap,
@@ -212,6 +320,12 @@ fn extract_auth_data(
     let token_prefix = auth_context.auth_token_prefix();
     match headers.get(http::header::AUTHORIZATION).map(|v| v.to_str()) {
         Some(Ok(v)) => {
+            if let Some((scheme, token)) = v.split_once(' ') {
+                // authentication schemes are case insensitive (RFC 7235)
+                if scheme.eq_ignore_ascii_case("Bearer") {
+                    return Some(AuthData::Bearer(token.trim().to_owned()));
+                }
+            }
             if !v.starts_with(token_prefix) {
                 return None;
             }
@@ -225,3 +339,187 @@ fn extract_auth_data(
         _ => None,
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use openssl::pkey::{PKey, Private};
+    use openssl::sign::Signer;
+    use serde_json::json;
+
+    use super::*;
+    use crate::jwt::Jwks;
+
+    const ISSUER: &str = "https://idp.example.com";
+
+    struct TestContext {
+        keyring: Keyring,
+        csrf_secret: &'static HMACKey,
+        issuer: Arc<JwtIssuer>,
+    }
+
+    impl AuthContext for TestContext {
+        fn lookup_realm(&self, _realm: &RealmRef) -> Option<Box<dyn Authenticator + Send + Sync>> {
+            None
+        }
+
+        fn keyring(&self) -> &Keyring {
+            &self.keyring
+        }
+
+        fn auth_prefix(&self) -> &'static str {
+            "PBS"
+        }
+
+        fn auth_token_prefix(&self) -> &'static str {
+            "PBSAPIToken"
+        }
+
+        fn auth_cookie_name(&self) -> &'static str {
+            "PBSAuthCookie"
+        }
+
+        fn tfa_config_write_lock(&self) -> Result<Box<dyn LockedTfaConfig>, Error> {
+            unreachable!("not used by the tests");
+        }
+
+        fn auth_id_is_active(&self, auth_id: &Authid) -> Result<bool, Error> {
+            Ok(auth_id.user().name().as_str() != "disabled")
+        }
+
+        fn csrf_secret(&self) -> &'static HMACKey {
+            self.csrf_secret
+        }
+
+        fn verify_token_secret(&self, _token_id: &Authid, _secret: &str) -> Result<(), Error> {
+            anyhow::bail!("no API tokens configured");
+        }
+
+        fn lookup_jwt_issuer(&self, issuer: &str) -> Option<Arc<JwtIssuer>> {
+            (issuer == ISSUER).then(|| Arc::clone(&self.issuer))
+        }
+    }
+
+    fn b64url(data: &[u8]) -> String {
+        base64::encode_config(data, base64::URL_SAFE_NO_PAD)
+    }
+
+    fn make_token(key: &PKey<Private>, sub: &str, iss: &str) -> String {
+        let now = crate::time::epoch_i64();
+        let header = json!({ "alg": "EdDSA", "typ": "JWT", "kid": "ed-1" });
+        let claims =
+            json!({ "iss": iss, "aud": "proxmox", "sub": sub, "iat": now, "exp": now + 300 });
+        let data = format!(
+            "{}.{}",
+            b64url(header.to_string().as_bytes()),
+            b64url(claims.to_string().as_bytes()),
+        );
+        let signature = Signer::new_without_digest(key)
+            .unwrap()
+            .sign_oneshot_to_vec(data.as_bytes())
+            .unwrap();
+        format!("{data}.{}", b64url(&signature))
+    }
+
+    fn bearer(token: &str) -> http::HeaderMap {
+        let mut headers = http::HeaderMap::new();
+        headers.insert(
+            http::header::AUTHORIZATION,
+            format!("Bearer {token}").parse().unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn test_bearer_auth() {
+        static FETCHES: AtomicUsize = AtomicUsize::new(0);
+
+        let key = PKey::generate_ed25519().unwrap();
+        let jwks_json = json!({ "keys": [{
+            "kty": "OKP",
+            "crv": "Ed25519",
+            "kid": "ed-1",
+            "x": b64url(&key.raw_public_key().unwrap()),
+        }]})
+        .to_string();
+
+        let issuer = JwtIssuer {
+            validation: JwtValidation::new(ISSUER, "proxmox"),
+            jwks: JwksCache::new(3600, move || {
+                FETCHES.fetch_add(1, Ordering::SeqCst);
+                Jwks::from_json(&jwks_json)
+            }),
+            realm: Realm::try_from("oidc".to_string()).unwrap(),
+            username_claim: "sub".to_string(),
+        };
+
+        let context: &'static TestContext = Box::leak(Box::new(TestContext {
+            keyring: Keyring::generate_new_hmac().unwrap(),
+            csrf_secret: Box::leak(Box::new(HMACKey::generate().unwrap())),
+            issuer: Arc::new(issuer),
+        }));
+        set_auth_context(context);
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        // the sync and async variants must agree
+        let check = |headers: http::HeaderMap, method: http::Method| {
+            let sync_result = http_check_auth(&headers, &method);
+            let async_result =
+                runtime.block_on(async { http_check_auth_async(&headers, &method).await });
+            match (&sync_result, &async_result) {
+                (Ok(a), Ok(b)) => assert_eq!(a, b),
+                (Err(AuthError::NoData), Err(AuthError::NoData)) => (),
+                (Err(AuthError::Generic(_)), Err(AuthError::Generic(_))) => (),
+                _ => panic!("sync and async authentication results differ"),
+            }
+            async_result
+        };
+        let check_ok = |headers: http::HeaderMap, method: http::Method| match check(headers, method)
+        {
+            Ok(auth_id) => auth_id,
+            Err(AuthError::Generic(err)) => panic!("authentication failed - {err}"),
+            Err(AuthError::NoData) => panic!("authentication failed - no data"),
+        };
+
+        let headers = bearer(&make_token(&key, "alice", ISSUER));
+        assert_eq!(check_ok(headers.clone(), http::Method::GET), "alice@oidc");
+        // bearer tokens don't need a CSRF prevention token
+        assert_eq!(check_ok(headers, http::Method::POST), "alice@oidc");
+        // the key set is cached
+        assert_eq!(FETCHES.load(Ordering::SeqCst), 1);
+
+        // the authentication scheme is case insensitive
+        let token = make_token(&key, "alice", ISSUER);
+        let mut headers = http::HeaderMap::new();
+        headers.insert(
+            http::header::AUTHORIZATION,
+            format!("bearer {token}").parse().unwrap(),
+        );
+        assert_eq!(check_ok(headers, http::Method::GET), "alice@oidc");
+
+        // disabled user
+        let headers = bearer(&make_token(&key, "disabled", ISSUER));
+        assert!(check(headers, http::Method::GET).is_err());
+
+        // unknown issuer
+        let headers = bearer(&make_token(&key, "alice", "https://other.example.com"));
+        assert!(check(headers, http::Method::GET).is_err());
+
+        // signed by a different key
+        let other = PKey::generate_ed25519().unwrap();
+        let headers = bearer(&make_token(&other, "alice", ISSUER));
+        assert!(check(headers, http::Method::GET).is_err());
+
+        // garbage
+        assert!(check(bearer("not-a-jwt"), http::Method::GET).is_err());
+
+        // no authentication data at all
+        assert!(matches!(
+            check(http::HeaderMap::new(), http::Method::GET),
+            Err(AuthError::NoData)
+        ));
+    }
+}
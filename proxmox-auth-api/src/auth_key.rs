@@ -29,7 +29,7 @@ impl PrivateKey {
         })
     }
 
-    /// Generate a new EC auth key.
+    /// Generate a new EC auth key (Ed25519).
     pub fn generate_ec() -> Result<Self, Error> {
         Ok(Self {
             key: PKey::generate_ed25519()
@@ -86,6 +86,11 @@ impl PrivateKey {
         PublicKey::from_pem(&self.public_key_to_pem()?)
     }
 
+    /// Get the key id of this key. This is the same as the key id of the matching public key.
+    pub fn key_id(&self) -> Result<String, Error> {
+        public_key_id(&self.key)
+    }
+
     pub(self) fn sign(&self, digest: MessageDigest, data: &[u8]) -> Result<Vec<u8>, Error> {
         let mut signer = if self.key.id() == Id::ED25519 {
             // ed25519 does not support signing with digest
@@ -132,6 +137,11 @@ impl PublicKey {
 
         bail!("unexpected key data")
     }
+
+    /// Get the key id of this key.
+    pub fn key_id(&self) -> Result<String, Error> {
+        public_key_id(&self.key)
+    }
 }
 
 impl From<PKey<Public>> for PublicKey {
@@ -195,6 +205,14 @@ impl HMACKey {
         Ok(false)
     }
 
+    /// Get the key id of this key.
+    ///
+    /// The id is derived from a signature over a fixed string, so it does not leak the secret.
+    pub fn key_id(&self) -> Result<String, Error> {
+        let digest = self.sign(MessageDigest::sha256(), b"proxmox-auth-key-id")?;
+        Ok(format_key_id(&digest))
+    }
+
     /// This outputs the hmac key *without* any encryption just encoded as base64.
     pub fn to_base64(&self) -> Result<String, Error> {
         let bytes = self
@@ -217,24 +235,96 @@ impl HMACKey {
     }
 }
 
+/// Number of bytes of the key fingerprint used as key id.
+const KEY_ID_BYTES: usize = 8;
+
+fn format_key_id(digest: &[u8]) -> String {
+    use std::fmt::Write;
+
+    digest[..KEY_ID_BYTES]
+        .iter()
+        .fold(String::with_capacity(KEY_ID_BYTES * 2), |mut id, byte| {
+            let _ = write!(id, "{byte:02x}");
+            id
+        })
+}
+
+fn public_key_id<P: HasPublic>(key: &PKeyRef<P>) -> Result<String, Error> {
+    let der = key
+        .public_key_to_der()
+        .map_err(|err| format_err!("failed to encode public key as DER - {err}"))?;
+    Ok(format_key_id(&openssl::sha::sha256(&der)))
+}
+
 enum SigningKey {
     Private(PrivateKey),
     Hmac(HMACKey),
 }
 
+impl SigningKey {
+    fn key_id(&self) -> Result<String, Error> {
+        match self {
+            SigningKey::Private(key) => key.key_id(),
+            SigningKey::Hmac(key) => key.key_id(),
+        }
+    }
+
+    fn into_verification_key(self) -> VerificationKey {
+        match self {
+            SigningKey::Private(key) => VerificationKey::Private(key),
+            SigningKey::Hmac(key) => VerificationKey::Hmac(key),
+        }
+    }
+}
+
 enum VerificationKey {
     Public(PublicKey),
+    Private(PrivateKey),
     Hmac(HMACKey),
 }
 
+impl VerificationKey {
+    fn key_id(&self) -> Result<String, Error> {
+        match self {
+            VerificationKey::Public(key) => key.key_id(),
+            VerificationKey::Private(key) => key.key_id(),
+            VerificationKey::Hmac(key) => key.key_id(),
+        }
+    }
+}
+
+struct VerificationEntry {
+    key: VerificationKey,
+    /// The key id, computed once when the key is added.
+    key_id: Option<String>,
+    /// Epoch after which this key is no longer used for verification.
+    expire: Option<i64>,
+}
+
+impl VerificationEntry {
+    fn is_expired(&self, now: i64) -> bool {
+        matches!(self.expire, Some(expire) if expire <= now)
+    }
+}
+
 /// A key ring for authentication.
 ///
 /// This can hold one active signing key for new tickets (either an HMAC secret or an assymmetric
 /// key), and optionally multiple public keys and HMAC secrets for verifying them in order to
 /// support key rollover.
+///
+/// Keys are identified by a key id derived from the key material (see [`PrivateKey::key_id`]),
+/// which can be embedded into tickets (see [`set_embed_key_id`](Keyring::set_embed_key_id)), so
+/// that verification only needs to check the matching key.
+/// Use [`rotate_private_key`](Keyring::rotate_private_key) or
+/// [`rotate_hmac_key`](Keyring::rotate_hmac_key) to replace the signing key while keeping the
+/// previous keys around for verification for a limited time.
 pub struct Keyring {
     signing_key: Option<SigningKey>,
-    public_keys: Vec<VerificationKey>,
+    /// The key id of `signing_key`, computed once when the key is set.
+    signing_key_id: Option<String>,
+    public_keys: Vec<VerificationEntry>,
+    embed_key_id: bool,
 }
 
 impl Keyring {
@@ -253,37 +343,157 @@ impl Keyring {
     pub fn new() -> Self {
         Self {
             signing_key: None,
+            signing_key_id: None,
             public_keys: Vec::new(),
+            embed_key_id: false,
         }
     }
 
     pub fn with_public_key(key: PublicKey) -> Self {
-        Self {
-            signing_key: None,
-            public_keys: vec![VerificationKey::Public(key)],
-        }
+        let mut this = Self::new();
+        this.add_public_key(key);
+        this
     }
 
     pub fn with_private_key(key: PrivateKey) -> Self {
-        Self {
-            signing_key: Some(SigningKey::Private(key)),
-            public_keys: Vec::new(),
-        }
+        let mut this = Self::new();
+        this.replace_signing_key(SigningKey::Private(key));
+        this
     }
 
     pub fn with_hmac_key(key: HMACKey) -> Self {
-        Self {
-            signing_key: Some(SigningKey::Hmac(key)),
-            public_keys: Vec::new(),
-        }
+        let mut this = Self::new();
+        this.replace_signing_key(SigningKey::Hmac(key));
+        this
     }
 
     pub fn add_public_key(&mut self, key: PublicKey) {
-        self.public_keys.push(VerificationKey::Public(key));
+        self.push_verification_key(VerificationKey::Public(key), None);
     }
 
     pub fn add_hmac_key(&mut self, key: HMACKey) {
-        self.public_keys.push(VerificationKey::Hmac(key));
+        self.push_verification_key(VerificationKey::Hmac(key), None);
+    }
+
+    /// Set whether newly signed tickets should contain the id of the signing key.
+    ///
+    /// This is disabled by default, since tickets with a key id cannot be parsed by older
+    /// versions. Tickets with and without key id can always be verified.
+    pub fn set_embed_key_id(&mut self, embed: bool) {
+        self.embed_key_id = embed;
+    }
+
+    /// Whether newly signed tickets contain the id of the signing key.
+    pub fn embed_key_id(&self) -> bool {
+        self.embed_key_id
+    }
+
+    /// Add a public key which is only used for verification until the epoch `expire`.
+    pub fn add_public_key_with_expire(&mut self, key: PublicKey, expire: i64) {
+        self.push_verification_key(VerificationKey::Public(key), Some(expire));
+    }
+
+    /// Add an HMAC key which is only used for verification until the epoch `expire`.
+    pub fn add_hmac_key_with_expire(&mut self, key: HMACKey, expire: i64) {
+        self.push_verification_key(VerificationKey::Hmac(key), Some(expire));
+    }
+
+    fn push_verification_key(&mut self, key: VerificationKey, expire: Option<i64>) {
+        let key_id = key.key_id().ok();
+        self.push_verification_entry(key, key_id, expire);
+    }
+
+    fn push_verification_entry(
+        &mut self,
+        key: VerificationKey,
+        key_id: Option<String>,
+        expire: Option<i64>,
+    ) {
+        self.public_keys.push(VerificationEntry {
+            key,
+            key_id,
+            expire,
+        });
+    }
+
+    /// Set a new signing key, returning the previous one along with its key id.
+    fn replace_signing_key(&mut self, key: SigningKey) -> Option<(SigningKey, Option<String>)> {
+        let key_id = key.key_id().ok();
+        let old_key_id = std::mem::replace(&mut self.signing_key_id, key_id);
+        self.signing_key
+            .replace(key)
+            .map(|old_key| (old_key, old_key_id))
+    }
+
+    /// Replace the signing key with a new private key.
+    ///
+    /// See [`rotate_hmac_key`](Keyring::rotate_hmac_key).
+    pub fn rotate_private_key(&mut self, key: PrivateKey, keep: usize, lifetime: i64) {
+        self.rotate(SigningKey::Private(key), keep, lifetime)
+    }
+
+    /// Replace the signing key with a new HMAC key.
+    ///
+    /// The previous signing key is kept for verifying existing tickets for `lifetime` seconds,
+    /// which should be at least [`TICKET_LIFETIME`](crate::TICKET_LIFETIME). At most `keep`
+    /// previously rotated keys are retained, older and expired ones are dropped. Verification
+    /// keys without an expiration time (added via [`add_public_key`](Keyring::add_public_key) or
+    /// [`add_hmac_key`](Keyring::add_hmac_key)) are not affected.
+    pub fn rotate_hmac_key(&mut self, key: HMACKey, keep: usize, lifetime: i64) {
+        self.rotate(SigningKey::Hmac(key), keep, lifetime)
+    }
+
+    fn rotate(&mut self, key: SigningKey, keep: usize, lifetime: i64) {
+        let now = crate::time::epoch_i64();
+
+        if let Some((old, old_key_id)) = self.replace_signing_key(key) {
+            self.push_verification_entry(
+                old.into_verification_key(),
+                old_key_id,
+                Some(now + lifetime),
+            );
+        }
+
+        self.prune_expired_at(now);
+
+        let rotated = self
+            .public_keys
+            .iter()
+            .filter(|entry| entry.expire.is_some())
+            .count();
+        let mut to_remove = rotated.saturating_sub(keep);
+        // entries are in insertion order, so the oldest rotated keys come first
+        self.public_keys.retain(|entry| {
+            if to_remove > 0 && entry.expire.is_some() {
+                to_remove -= 1;
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    /// Remove all verification keys whose expiration time has passed.
+    pub fn prune_expired(&mut self) {
+        self.prune_expired_at(crate::time::epoch_i64());
+    }
+
+    fn prune_expired_at(&mut self, now: i64) {
+        self.public_keys.retain(|entry| !entry.is_expired(now));
+    }
+
+    /// Get the key id of the current signing key.
+    pub fn signing_key_id(&self) -> Result<String, Error> {
+        let key = self
+            .signing_key
+            .as_ref()
+            .ok_or_else(|| format_err!("no private key available for signing"))?;
+
+        match &self.signing_key_id {
+            Some(key_id) => Ok(key_id.clone()),
+            // computing the id failed when the key was set, report why
+            None => key.key_id(),
+        }
     }
 
     pub fn verify(
@@ -291,6 +501,20 @@ impl Keyring {
         digest: MessageDigest,
         signature: &[u8],
         data: &[u8],
+    ) -> Result<bool, Error> {
+        self.verify_with_key_id(None, digest, signature, data)
+    }
+
+    /// Verify a signature, only checking the key matching `key_id` if one is provided.
+    ///
+    /// Without a key id, all keys in the key ring are tried. Expired verification keys are never
+    /// used.
+    pub fn verify_with_key_id(
+        &self,
+        key_id: Option<&str>,
+        digest: MessageDigest,
+        signature: &[u8],
+        data: &[u8],
     ) -> Result<bool, Error> {
         fn verify_with<P: HasPublic>(
             key: &PKeyRef<P>,
@@ -312,20 +536,38 @@ impl Keyring {
         }
 
         if let Some(key) = &self.signing_key {
-            match key {
-                SigningKey::Private(key) if verify_with(&key.key, digest, signature, data)? => {
-                    return Ok(true)
+            if key_id.is_none() || key_id == self.signing_key_id.as_deref() {
+                match key {
+                    SigningKey::Private(key) if verify_with(&key.key, digest, signature, data)? => {
+                        return Ok(true)
+                    }
+                    SigningKey::Hmac(key) if key.verify(digest, signature, data)? => {
+                        return Ok(true)
+                    }
+                    _ => (),
                 }
-                SigningKey::Hmac(key) if key.verify(digest, signature, data)? => return Ok(true),
-                _ => (),
             }
         }
 
-        for key in &self.public_keys {
-            match key {
+        let now = crate::time::epoch_i64();
+        for entry in &self.public_keys {
+            if entry.is_expired(now) {
+                continue;
+            }
+
+            if key_id.is_some() && key_id != entry.key_id.as_deref() {
+                continue;
+            }
+
+            match &entry.key {
                 VerificationKey::Public(key) if verify_with(&key.key, digest, signature, data)? => {
                     return Ok(true)
                 }
+                VerificationKey::Private(key)
+                    if verify_with(&key.key, digest, signature, data)? =>
+                {
+                    return Ok(true)
+                }
                 VerificationKey::Hmac(key) if key.verify(digest, signature, data)? => {
                     return Ok(true)
                 }
@@ -0,0 +1,707 @@
+//! Verification of JSON Web Tokens (JWT) issued by external identity providers.
+//!
+//! Only asymmetric signatures (`RS*`, `PS*`, `ES*` and `EdDSA`) are supported, the keys are taken
+//! from a JSON Web Key Set (JWKS) as published via the provider's `jwks_uri`.
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, format_err, Error};
+use openssl::bn::BigNum;
+use openssl::ec::{EcGroup, EcKey};
+use openssl::ecdsa::EcdsaSig;
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{Id, PKey, Public};
+use openssl::rsa::{Padding, Rsa};
+use openssl::sign::{RsaPssSaltlen, Verifier};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+fn decode_b64url(data: &str) -> Result<Vec<u8>, Error> {
+    base64::decode_config(data, base64::URL_SAFE_NO_PAD)
+        .map_err(|err| format_err!("invalid base64url data - {err}"))
+}
+
+/// A signature algorithm as used in the `alg` JWT header field.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Algorithm {
+    RS256,
+    RS384,
+    RS512,
+    PS256,
+    PS384,
+    PS512,
+    ES256,
+    ES384,
+    ES512,
+    EdDSA,
+}
+
+impl std::str::FromStr for Algorithm {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        Ok(match s {
+            "RS256" => Algorithm::RS256,
+            "RS384" => Algorithm::RS384,
+            "RS512" => Algorithm::RS512,
+            "PS256" => Algorithm::PS256,
+            "PS384" => Algorithm::PS384,
+            "PS512" => Algorithm::PS512,
+            "ES256" => Algorithm::ES256,
+            "ES384" => Algorithm::ES384,
+            "ES512" => Algorithm::ES512,
+            "EdDSA" => Algorithm::EdDSA,
+            other => bail!("unsupported JWT signature algorithm '{other}'"),
+        })
+    }
+}
+
+impl Algorithm {
+    fn digest(self) -> MessageDigest {
+        match self {
+            Algorithm::RS256 | Algorithm::PS256 | Algorithm::ES256 => MessageDigest::sha256(),
+            Algorithm::RS384 | Algorithm::PS384 | Algorithm::ES384 => MessageDigest::sha384(),
+            Algorithm::RS512 | Algorithm::PS512 | Algorithm::ES512 => MessageDigest::sha512(),
+            // unused, ed25519 does not support digests
+            Algorithm::EdDSA => MessageDigest::null(),
+        }
+    }
+
+    fn key_type(self) -> Id {
+        match self {
+            Algorithm::RS256
+            | Algorithm::RS384
+            | Algorithm::RS512
+            | Algorithm::PS256
+            | Algorithm::PS384
+            | Algorithm::PS512 => Id::RSA,
+            Algorithm::ES256 | Algorithm::ES384 | Algorithm::ES512 => Id::EC,
+            Algorithm::EdDSA => Id::ED25519,
+        }
+    }
+
+    /// The curve and length of the `r` and `s` components of an ECDSA signature.
+    fn ec_params(self) -> Option<(Nid, usize)> {
+        match self {
+            Algorithm::ES256 => Some((Nid::X9_62_PRIME256V1, 32)),
+            Algorithm::ES384 => Some((Nid::SECP384R1, 48)),
+            Algorithm::ES512 => Some((Nid::SECP521R1, 66)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct RawJwk {
+    kty: String,
+    #[serde(default)]
+    kid: Option<String>,
+    #[serde(default, rename = "use")]
+    key_use: Option<String>,
+    #[serde(default)]
+    alg: Option<String>,
+    #[serde(default)]
+    crv: Option<String>,
+    #[serde(default)]
+    n: Option<String>,
+    #[serde(default)]
+    e: Option<String>,
+    #[serde(default)]
+    x: Option<String>,
+    #[serde(default)]
+    y: Option<String>,
+}
+
+/// A public key from a JSON Web Key Set.
+pub struct Jwk {
+    kid: Option<String>,
+    alg: Option<String>,
+    key: PKey<Public>,
+}
+
+impl Jwk {
+    fn from_raw(raw: RawJwk) -> Result<Self, Error> {
+        fn required(value: &Option<String>, name: &str) -> Result<Vec<u8>, Error> {
+            let value = value
+                .as_deref()
+                .ok_or_else(|| format_err!("JWK is missing the '{name}' parameter"))?;
+            decode_b64url(value)
+        }
+
+        let key = match raw.kty.as_str() {
+            "RSA" => {
+                let n = BigNum::from_slice(&required(&raw.n, "n")?)?;
+                let e = BigNum::from_slice(&required(&raw.e, "e")?)?;
+                PKey::from_rsa(Rsa::from_public_components(n, e)?)?
+            }
+            "EC" => {
+                let nid = match raw.crv.as_deref() {
+                    Some("P-256") => Nid::X9_62_PRIME256V1,
+                    Some("P-384") => Nid::SECP384R1,
+                    Some("P-521") => Nid::SECP521R1,
+                    other => bail!("unsupported JWK EC curve {other:?}"),
+                };
+                let group = EcGroup::from_curve_name(nid)?;
+                let x = BigNum::from_slice(&required(&raw.x, "x")?)?;
+                let y = BigNum::from_slice(&required(&raw.y, "y")?)?;
+                PKey::from_ec_key(EcKey::from_public_key_affine_coordinates(&group, &x, &y)?)?
+            }
+            "OKP" => match raw.crv.as_deref() {
+                Some("Ed25519") => {
+                    PKey::public_key_from_raw_bytes(&required(&raw.x, "x")?, Id::ED25519)?
+                }
+                other => bail!("unsupported JWK OKP curve {other:?}"),
+            },
+            other => bail!("unsupported JWK key type '{other}'"),
+        };
+
+        Ok(Self {
+            kid: raw.kid,
+            alg: raw.alg,
+            key,
+        })
+    }
+
+    /// The key id, if the key has one.
+    pub fn kid(&self) -> Option<&str> {
+        self.kid.as_deref()
+    }
+
+    fn matches(&self, kid: Option<&str>, alg: Algorithm) -> bool {
+        if self.key.id() != alg.key_type() {
+            return false;
+        }
+
+        if let Some(key_alg) = self.alg.as_deref() {
+            if key_alg.parse::<Algorithm>().ok() != Some(alg) {
+                return false;
+            }
+        }
+
+        match kid {
+            Some(kid) => self.kid.as_deref() == Some(kid),
+            None => true,
+        }
+    }
+
+    fn verify(&self, alg: Algorithm, signature: &[u8], data: &[u8]) -> Result<bool, Error> {
+        if let Some((nid, len)) = alg.ec_params() {
+            // JWS uses the raw `r || s` representation, openssl wants DER
+            if signature.len() != 2 * len {
+                return Ok(false);
+            }
+            let ec_key = self.key.ec_key()?;
+            if ec_key.group().curve_name() != Some(nid) {
+                return Ok(false);
+            }
+            let r = BigNum::from_slice(&signature[..len])?;
+            let s = BigNum::from_slice(&signature[len..])?;
+            let signature = EcdsaSig::from_private_components(r, s)?.to_der()?;
+
+            let mut verifier = Verifier::new(alg.digest(), &self.key)?;
+            return Ok(verifier.verify_oneshot(&signature, data)?);
+        }
+
+        let mut verifier = if alg == Algorithm::EdDSA {
+            Verifier::new_without_digest(&self.key)?
+        } else {
+            Verifier::new(alg.digest(), &self.key)?
+        };
+
+        if matches!(alg, Algorithm::PS256 | Algorithm::PS384 | Algorithm::PS512) {
+            verifier.set_rsa_padding(Padding::PKCS1_PSS)?;
+            verifier.set_rsa_pss_saltlen(RsaPssSaltlen::DIGEST_LENGTH)?;
+        }
+
+        Ok(verifier.verify_oneshot(signature, data)?)
+    }
+}
+
+/// A JSON Web Key Set.
+#[derive(Default)]
+pub struct Jwks {
+    keys: Vec<Jwk>,
+}
+
+impl Jwks {
+    /// Parse a JSON Web Key Set. Keys which are not meant for signatures or use unsupported key
+    /// types are skipped.
+    pub fn from_json(data: &str) -> Result<Self, Error> {
+        #[derive(Deserialize)]
+        struct RawJwks {
+            keys: Vec<Value>,
+        }
+
+        let raw: RawJwks = serde_json::from_str(data)
+            .map_err(|err| format_err!("failed to parse JWKS - {err}"))?;
+
+        let mut keys = Vec::new();
+        for key in raw.keys {
+            let Ok(key) = serde_json::from_value::<RawJwk>(key) else {
+                continue;
+            };
+            if key.key_use.as_deref().map(|u| u == "sig").unwrap_or(true) {
+                if let Ok(key) = Jwk::from_raw(key) {
+                    keys.push(key);
+                }
+            }
+        }
+
+        Ok(Self { keys })
+    }
+
+    /// The contained keys.
+    pub fn keys(&self) -> &[Jwk] {
+        &self.keys
+    }
+
+    fn contains_kid(&self, kid: &str) -> bool {
+        self.keys.iter().any(|key| key.kid.as_deref() == Some(kid))
+    }
+}
+
+/// A cache for a JSON Web Key Set which is refreshed via a callback.
+///
+/// The key set is refreshed when it is older than the configured maximum age, or when a token
+/// refers to an unknown key id, the latter being rate limited to once per minute.
+pub struct JwksCache {
+    fetch: Box<dyn Fn() -> Result<Jwks, Error> + Send + Sync>,
+    max_age: i64,
+    state: Mutex<JwksCacheState>,
+}
+
+struct JwksCacheState {
+    jwks: Option<Arc<Jwks>>,
+    last_fetch: i64,
+    /// The error of the last failed fetch, reported while no key set is available.
+    last_error: Option<String>,
+}
+
+/// Minimum interval between refreshes caused by unknown key ids, and between retries while no
+/// key set could be fetched.
+const JWKS_MIN_REFRESH_INTERVAL: i64 = 60;
+
+impl JwksCache {
+    /// Create a new cache. `fetch` is called to (re)load the key set, for example by querying
+    /// the provider's `jwks_uri`.
+    pub fn new<F>(max_age: i64, fetch: F) -> Self
+    where
+        F: Fn() -> Result<Jwks, Error> + Send + Sync + 'static,
+    {
+        Self {
+            fetch: Box::new(fetch),
+            max_age,
+            state: Mutex::new(JwksCacheState {
+                jwks: None,
+                last_fetch: 0,
+                last_error: None,
+            }),
+        }
+    }
+
+    /// Create a cache with a fixed key set which is never refreshed.
+    pub fn with_jwks(jwks: Jwks) -> Self {
+        Self {
+            fetch: Box::new(|| bail!("refreshing a static JWKS is not possible")),
+            max_age: i64::MAX,
+            state: Mutex::new(JwksCacheState {
+                jwks: Some(Arc::new(jwks)),
+                last_fetch: crate::time::epoch_i64(),
+                last_error: None,
+            }),
+        }
+    }
+
+    /// Get the key set, refreshing it if required for a token signed with the key `kid`.
+    ///
+    /// The key set is fetched without holding the cache lock. While one caller refreshes it,
+    /// others keep using the previous keys. Without any keys, fetches are retried at most every
+    /// [`JWKS_MIN_REFRESH_INTERVAL`] seconds, failing in the meantime.
+    pub fn get(&self, kid: Option<&str>) -> Result<Arc<Jwks>, Error> {
+        self.get_at(kid, crate::time::epoch_i64())
+    }
+
+    fn get_at(&self, kid: Option<&str>, now: i64) -> Result<Arc<Jwks>, Error> {
+        let previous = {
+            let mut state = self.state.lock().unwrap();
+
+            let refresh = match &state.jwks {
+                None => now.saturating_sub(state.last_fetch) > JWKS_MIN_REFRESH_INTERVAL,
+                Some(_) if now.saturating_sub(state.last_fetch) > self.max_age => true,
+                Some(jwks) => match kid {
+                    Some(kid) if !jwks.contains_kid(kid) => {
+                        now - state.last_fetch > JWKS_MIN_REFRESH_INTERVAL
+                    }
+                    _ => false,
+                },
+            };
+
+            if !refresh {
+                return match &state.jwks {
+                    Some(jwks) => Ok(Arc::clone(jwks)),
+                    None => match &state.last_error {
+                        Some(err) => bail!("failed to fetch JWKS - {err}"),
+                        None => bail!("JWKS is currently being fetched"),
+                    },
+                };
+            }
+
+            // claim the refresh, concurrent callers see a fresh key set until we're done
+            state.last_fetch = now;
+            state.jwks.clone()
+        };
+
+        match (self.fetch)() {
+            Ok(jwks) => {
+                let jwks = Arc::new(jwks);
+                let mut state = self.state.lock().unwrap();
+                state.jwks = Some(Arc::clone(&jwks));
+                state.last_fetch = now;
+                state.last_error = None;
+                Ok(jwks)
+            }
+            Err(err) => {
+                self.state.lock().unwrap().last_error = Some(err.to_string());
+                // keep using the old keys if we have them
+                previous.ok_or(err)
+            }
+        }
+    }
+}
+
+/// The claims which need to be checked for a JWT to be accepted.
+#[derive(Clone, Debug)]
+pub struct JwtValidation {
+    /// The expected `iss` claim.
+    pub issuer: String,
+
+    /// The `aud` claim must contain this value.
+    pub audience: String,
+
+    /// Allowed clock skew in seconds when checking `exp`, `nbf` and `iat`.
+    pub leeway: i64,
+}
+
+impl JwtValidation {
+    pub fn new(issuer: impl Into<String>, audience: impl Into<String>) -> Self {
+        Self {
+            issuer: issuer.into(),
+            audience: audience.into(),
+            leeway: 60,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct JwtHeader {
+    alg: String,
+    #[serde(default)]
+    kid: Option<String>,
+}
+
+/// A parsed but not yet verified JSON Web Token in compact serialization.
+pub struct Jwt {
+    alg: Algorithm,
+    kid: Option<String>,
+    claims: Map<String, Value>,
+    signed_data: String,
+    signature: Vec<u8>,
+}
+
+impl Jwt {
+    /// Parse a token without verifying it.
+    pub fn parse(token: &str) -> Result<Self, Error> {
+        let mut parts = token.split('.');
+        let (Some(header), Some(payload), Some(signature), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            bail!("invalid JWT - expected three parts");
+        };
+
+        let header: JwtHeader = serde_json::from_slice(&decode_b64url(header)?)
+            .map_err(|err| format_err!("invalid JWT header - {err}"))?;
+        let claims: Map<String, Value> = serde_json::from_slice(&decode_b64url(payload)?)
+            .map_err(|err| format_err!("invalid JWT payload - {err}"))?;
+
+        Ok(Self {
+            alg: header.alg.parse()?,
+            kid: header.kid,
+            claims,
+            signed_data: token[..(token.len() - signature.len() - 1)].to_string(),
+            signature: decode_b64url(signature)?,
+        })
+    }
+
+    /// The unverified `iss` claim, used to find the matching validation configuration.
+    pub fn issuer(&self) -> Option<&str> {
+        self.claims.get("iss").and_then(Value::as_str)
+    }
+
+    /// The key id from the token header, if any.
+    pub fn kid(&self) -> Option<&str> {
+        self.kid.as_deref()
+    }
+
+    /// The unverified claims.
+    pub fn unverified_claims(&self) -> &Map<String, Value> {
+        &self.claims
+    }
+
+    /// Verify the token's signature against a key set and check its claims.
+    pub fn verify(
+        self,
+        jwks: &Jwks,
+        validation: &JwtValidation,
+    ) -> Result<Map<String, Value>, Error> {
+        let mut valid = false;
+        for key in jwks
+            .keys
+            .iter()
+            .filter(|k| k.matches(self.kid.as_deref(), self.alg))
+        {
+            if key.verify(self.alg, &self.signature, self.signed_data.as_bytes())? {
+                valid = true;
+                break;
+            }
+        }
+        if !valid {
+            bail!("JWT with invalid signature");
+        }
+
+        if self.issuer() != Some(validation.issuer.as_str()) {
+            bail!("JWT with unexpected issuer");
+        }
+
+        let audience_matches = match self.claims.get("aud") {
+            Some(Value::String(aud)) => *aud == validation.audience,
+            Some(Value::Array(list)) => list
+                .iter()
+                .any(|aud| aud.as_str() == Some(validation.audience.as_str())),
+            _ => false,
+        };
+        if !audience_matches {
+            bail!("JWT with unexpected audience");
+        }
+
+        let now = crate::time::epoch_i64();
+        let time_claim = |name: &str| -> Result<Option<i64>, Error> {
+            match self.claims.get(name) {
+                None => Ok(None),
+                Some(value) => value
+                    .as_i64()
+                    .or_else(|| value.as_f64().map(|v| v as i64))
+                    .map(Some)
+                    .ok_or_else(|| format_err!("JWT with invalid '{name}' claim")),
+            }
+        };
+
+        match time_claim("exp")? {
+            Some(exp) if now - validation.leeway < exp => (),
+            Some(_) => bail!("JWT expired"),
+            None => bail!("JWT without expiration time"),
+        }
+
+        if let Some(nbf) = time_claim("nbf")? {
+            if now + validation.leeway < nbf {
+                bail!("JWT not yet valid");
+            }
+        }
+
+        if let Some(iat) = time_claim("iat")? {
+            if now + validation.leeway < iat {
+                bail!("JWT issued in the future");
+            }
+        }
+
+        Ok(self.claims)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use openssl::pkey::{PKey, Private};
+    use openssl::rsa::Rsa;
+    use openssl::sign::Signer;
+    use serde_json::json;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use anyhow::bail;
+
+    use super::{Jwks, JwksCache, Jwt, JwtValidation, JWKS_MIN_REFRESH_INTERVAL};
+
+    fn b64url(data: &[u8]) -> String {
+        base64::encode_config(data, base64::URL_SAFE_NO_PAD)
+    }
+
+    fn make_token(key: &PKey<Private>, alg: &str, kid: &str, claims: &serde_json::Value) -> String {
+        let header = json!({ "alg": alg, "typ": "JWT", "kid": kid });
+        let data = format!(
+            "{}.{}",
+            b64url(header.to_string().as_bytes()),
+            b64url(claims.to_string().as_bytes()),
+        );
+
+        let signature = if alg == "EdDSA" {
+            Signer::new_without_digest(key)
+                .unwrap()
+                .sign_oneshot_to_vec(data.as_bytes())
+                .unwrap()
+        } else {
+            Signer::new(openssl::hash::MessageDigest::sha256(), key)
+                .unwrap()
+                .sign_oneshot_to_vec(data.as_bytes())
+                .unwrap()
+        };
+
+        format!("{data}.{}", b64url(&signature))
+    }
+
+    fn claims(now: i64) -> serde_json::Value {
+        json!({
+            "iss": "https://idp.example.com",
+            "aud": ["proxmox", "other"],
+            "sub": "runner",
+            "iat": now,
+            "exp": now + 300,
+        })
+    }
+
+    #[test]
+    fn test_jwt_rs256() {
+        let rsa = Rsa::generate(2048).unwrap();
+        let jwks = Jwks::from_json(
+            &json!({ "keys": [{
+                "kty": "RSA",
+                "kid": "rsa-1",
+                "use": "sig",
+                "n": b64url(&rsa.n().to_vec()),
+                "e": b64url(&rsa.e().to_vec()),
+            }]})
+            .to_string(),
+        )
+        .unwrap();
+        let key = PKey::from_rsa(rsa).unwrap();
+        let validation = JwtValidation::new("https://idp.example.com", "proxmox");
+        let now = crate::time::epoch_i64();
+
+        let token = make_token(&key, "RS256", "rsa-1", &claims(now));
+        let jwt = Jwt::parse(&token).unwrap();
+        assert_eq!(jwt.issuer(), Some("https://idp.example.com"));
+        let verified = jwt.verify(&jwks, &validation).unwrap();
+        assert_eq!(verified["sub"], "runner");
+
+        // unknown key id
+        let token = make_token(&key, "RS256", "rsa-2", &claims(now));
+        assert!(Jwt::parse(&token)
+            .unwrap()
+            .verify(&jwks, &validation)
+            .is_err());
+
+        // expired
+        let token = make_token(&key, "RS256", "rsa-1", &claims(now - 3600));
+        assert!(Jwt::parse(&token)
+            .unwrap()
+            .verify(&jwks, &validation)
+            .is_err());
+
+        // wrong audience
+        let validation = JwtValidation::new("https://idp.example.com", "somebody-else");
+        let token = make_token(&key, "RS256", "rsa-1", &claims(now));
+        assert!(Jwt::parse(&token)
+            .unwrap()
+            .verify(&jwks, &validation)
+            .is_err());
+    }
+
+    #[test]
+    fn test_jwt_eddsa() {
+        let key = PKey::generate_ed25519().unwrap();
+        let jwks = Jwks::from_json(
+            &json!({ "keys": [{
+                "kty": "OKP",
+                "crv": "Ed25519",
+                "kid": "ed-1",
+                "x": b64url(&key.raw_public_key().unwrap()),
+            }]})
+            .to_string(),
+        )
+        .unwrap();
+        let validation = JwtValidation::new("https://idp.example.com", "proxmox");
+        let now = crate::time::epoch_i64();
+
+        let token = make_token(&key, "EdDSA", "ed-1", &claims(now));
+        Jwt::parse(&token)
+            .unwrap()
+            .verify(&jwks, &validation)
+            .unwrap();
+
+        // tampered payload
+        let mut parts: Vec<String> = token.split('.').map(str::to_string).collect();
+        let mut tampered = claims(now);
+        tampered["sub"] = "root".into();
+        parts[1] = b64url(tampered.to_string().as_bytes());
+        assert!(Jwt::parse(&parts.join("."))
+            .unwrap()
+            .verify(&jwks, &validation)
+            .is_err());
+    }
+
+    #[test]
+    fn test_jwt_reject_unsupported_alg() {
+        let token = format!(
+            "{}.{}.",
+            b64url(json!({ "alg": "none" }).to_string().as_bytes()),
+            b64url(claims(0).to_string().as_bytes()),
+        );
+        assert!(Jwt::parse(&token).is_err());
+    }
+
+    #[test]
+    fn test_jwks_cache_refresh_interval() {
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let fail = Arc::new(AtomicUsize::new(1));
+        let cache = {
+            let fetches = Arc::clone(&fetches);
+            let fail = Arc::clone(&fail);
+            JwksCache::new(3600, move || {
+                fetches.fetch_add(1, Ordering::SeqCst);
+                if fail.load(Ordering::SeqCst) != 0 {
+                    bail!("issuer unreachable");
+                }
+                Jwks::from_json(r#"{ "keys": [] }"#)
+            })
+        };
+
+        let now = 1_000_000;
+
+        // a failing issuer is not queried on every request
+        let err = cache.get_at(None, now).err().unwrap();
+        assert_eq!(err.to_string(), "issuer unreachable");
+        let err = cache.get_at(None, now + 1).err().unwrap();
+        assert_eq!(err.to_string(), "failed to fetch JWKS - issuer unreachable");
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+
+        // retried after the minimum interval
+        fail.store(0, Ordering::SeqCst);
+        let retry = now + JWKS_MIN_REFRESH_INTERVAL + 1;
+        assert!(cache.get_at(None, retry).is_ok());
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+
+        // unknown key ids only cause a refresh after the minimum interval
+        assert!(cache.get_at(Some("unknown"), retry + 1).is_ok());
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+        let later = retry + JWKS_MIN_REFRESH_INTERVAL + 1;
+        assert!(cache.get_at(Some("unknown"), later).is_ok());
+        assert_eq!(fetches.load(Ordering::SeqCst), 3);
+
+        // a failed refresh keeps the previous keys and is not retried immediately
+        fail.store(1, Ordering::SeqCst);
+        let expired = later + 3601;
+        assert!(cache.get_at(None, expired).is_ok());
+        assert!(cache.get_at(Some("unknown"), expired + 1).is_ok());
+        assert_eq!(fetches.load(Ordering::SeqCst), 4);
+    }
+}
@@ -6,10 +6,12 @@
 //! Each can be enabled via a feature:
 //!
 //! The `pam-authenticator` feature enables the `Pam` type.
+//!
+//! The `jwt` feature enables verification of JSON Web Tokens issued by external identity providers.
 
 pub const TICKET_LIFETIME: i64 = 3600 * 2; // 2 hours
 
-#[cfg(feature = "ticket")]
+#[cfg(any(feature = "ticket", feature = "jwt"))]
 mod time;
 
 #[cfg(feature = "api")]
@@ -27,6 +29,9 @@ pub use auth_key::{HMACKey, Keyring, PrivateKey, PublicKey};
 #[cfg(feature = "ticket")]
 pub mod ticket;
 
+#[cfg(feature = "jwt")]
+pub mod jwt;
+
 #[cfg(feature = "api-types")]
 pub mod types;
 
@@ -36,12 +36,15 @@ impl std::str::FromStr for Empty {
 }
 
 /// An API ticket consists of a ticket type (prefix), type-dependent data, optional additional
-/// authenticaztion data, a timestamp and a signature. We store these values in the form
+/// authenticaztion data, a timestamp, an optional id of the signing key and a signature. We store
+/// these values in the form `<prefix>:<stringified data>:<timestamp>:<key id>:<signature>`. The
+/// key id is empty unless enabled via [`Keyring::set_embed_key_id`], i.e.
 /// `<prefix>:<stringified data>:<timestamp>::<signature>`.
 ///
 /// The signature is made over the string consisting of prefix, data, timestamp and aad joined
 /// together by colons. If there is no additional authentication data it will be skipped together
-/// with the colon separating it from the timestamp.
+/// with the colon separating it from the timestamp. The key id is not part of the signed data, it
+/// is only used to pick the key to verify the signature with.
 pub struct Ticket<T>
 where
     T: ToString + std::str::FromStr,
@@ -49,6 +52,7 @@ where
     prefix: Cow<'static, str>,
     data: String,
     time: i64,
+    key_id: Option<String>,
     signature: Option<Vec<u8>>,
     _type_marker: PhantomData<fn() -> T>,
 }
@@ -64,6 +68,7 @@ where
             prefix: Cow::Borrowed(prefix),
             data: data.to_string(),
             time: crate::time::epoch_i64(),
+            key_id: None,
             signature: None,
             _type_marker: PhantomData,
         })
@@ -79,6 +84,11 @@ where
         self.time
     }
 
+    /// Get the id of the key used to sign this ticket, if it contains one.
+    pub fn key_id(&self) -> Option<&str> {
+        self.key_id.as_deref()
+    }
+
     /// Get the raw string data contained in the ticket. The `verify` method will call `parse()`
     /// this in the end, so using this method directly is discouraged as it does not verify the
     /// signature.
@@ -116,6 +126,15 @@ where
     /// Sign the ticket.
     pub fn sign(&mut self, keyring: &Keyring, aad: Option<&str>) -> Result<String, Error> {
         let mut output = self.ticket_data();
+        let key_id = if keyring.embed_key_id() {
+            Some(
+                keyring
+                    .signing_key_id()
+                    .map_err(|err| format_err!("error signing ticket: {}", err))?,
+            )
+        } else {
+            None
+        };
         let signature = keyring
             .sign(MessageDigest::sha256(), &self.verification_data(aad))
             .map_err(|err| format_err!("error signing ticket: {}", err))?;
@@ -123,10 +142,12 @@ where
         use std::fmt::Write;
         write!(
             &mut output,
-            "::{}",
+            ":{}:{}",
+            key_id.as_deref().unwrap_or(""),
             base64::encode_config(&signature, base64::STANDARD_NO_PAD),
         )?;
 
+        self.key_id = key_id;
         self.signature = Some(signature);
 
         Ok(output)
@@ -158,7 +179,8 @@ where
             bail!("invalid ticket - expired");
         }
 
-        let is_valid = keyring.verify(
+        let is_valid = keyring.verify_with_key_id(
+            self.key_id.as_deref(),
             MessageDigest::sha256(),
             &signature,
             &self.verification_data(aad),
@@ -211,18 +233,23 @@ where
         let remainder = parts
             .next()
             .ok_or_else(|| format_err!("ticket without signature"))?;
-        // <prefix>:<data>:<time>::signature - the 4th `.next()` swallows the first colon in the
-        // double-colon!
-        if !remainder.starts_with(':') {
-            bail!("ticket without signature separator");
-        }
-        let signature = base64::decode_config(&remainder[1..], base64::STANDARD_NO_PAD)
+        // <prefix>:<data>:<time>:<key id>:signature - the key id is empty for older tickets
+        let (key_id, signature) = remainder
+            .split_once(':')
+            .ok_or_else(|| format_err!("ticket without signature separator"))?;
+        let key_id = match key_id {
+            "" => None,
+            id if id.bytes().all(|b| b.is_ascii_hexdigit()) => Some(id.to_string()),
+            _ => bail!("ticket with bad key id"),
+        };
+        let signature = base64::decode_config(signature, base64::STANDARD_NO_PAD)
             .map_err(|err| format_err!("ticket with bad signature: {}", err))?;
 
         Ok(Self {
             prefix: Cow::Owned(prefix.into_owned()),
             data: data.into_owned(),
             time,
+            key_id,
             signature: Some(signature),
             _type_marker: PhantomData,
         })
@@ -331,4 +358,76 @@ mod test {
             false
         });
     }
+
+    fn sign_ticket(keyring: &Keyring) -> Ticket<Testid> {
+        let ticket = Ticket::new("PREFIX", &Testid("root".to_string()))
+            .expect("failed to create Ticket struct")
+            .sign(keyring, None)
+            .expect("failed to sign test ticket");
+        Ticket::parse(&ticket).expect("failed to parse generated test ticket")
+    }
+
+    #[test]
+    fn test_tickets_key_id() {
+        let mut keyring = Keyring::generate_new_ec().expect("failed to generate EC key");
+
+        let ticket = sign_ticket(&keyring);
+        assert_eq!(ticket.key_id(), None);
+        ticket.verify(&keyring, "PREFIX", None).unwrap();
+
+        keyring.set_embed_key_id(true);
+        let ticket = sign_ticket(&keyring);
+        assert_eq!(
+            ticket.key_id(),
+            Some(keyring.signing_key_id().unwrap().as_str())
+        );
+        ticket.verify(&keyring, "PREFIX", None).unwrap();
+
+        assert!(
+            Ticket::<Testid>::parse("PREFIX:root:00000000:not-hex:AAAA").is_err(),
+            "parsed ticket with invalid key id"
+        );
+    }
+
+    #[test]
+    fn test_keyring_rotation() {
+        let mut keyring = Keyring::generate_new_hmac().expect("failed to generate HMAC key");
+        keyring.set_embed_key_id(true);
+
+        let first = sign_ticket(&keyring);
+
+        keyring.rotate_private_key(
+            crate::PrivateKey::generate_ec().unwrap(),
+            2,
+            crate::TICKET_LIFETIME,
+        );
+        let second = sign_ticket(&keyring);
+        assert_ne!(first.key_id(), second.key_id());
+
+        // both the old and new tickets must be accepted
+        first.verify(&keyring, "PREFIX", None).unwrap();
+        second.verify(&keyring, "PREFIX", None).unwrap();
+
+        // keep only a single previous key
+        keyring.rotate_hmac_key(
+            crate::HMACKey::generate().unwrap(),
+            1,
+            crate::TICKET_LIFETIME,
+        );
+        let third = sign_ticket(&keyring);
+        first
+            .verify(&keyring, "PREFIX", None)
+            .expect_err("ticket of dropped key verified");
+        second.verify(&keyring, "PREFIX", None).unwrap();
+        third.verify(&keyring, "PREFIX", None).unwrap();
+
+        // an already expired previous key must not be used anymore
+        keyring.rotate_hmac_key(crate::HMACKey::generate().unwrap(), 1, 0);
+        third
+            .verify(&keyring, "PREFIX", None)
+            .expect_err("ticket of expired key verified");
+        sign_ticket(&keyring)
+            .verify(&keyring, "PREFIX", None)
+            .unwrap();
+    }
 }
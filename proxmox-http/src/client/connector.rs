@@ -1,4 +1,5 @@
 use anyhow::{bail, format_err, Error};
+use std::net::IpAddr;
 use std::os::unix::io::AsRawFd;
 use std::pin::Pin;
 use std::sync::Arc;
@@ -14,7 +15,7 @@ use tokio_openssl::SslStream;
 
 use proxmox_sys::linux::socket::set_tcp_keepalive;
 
-use crate::proxy_config::ProxyConfig;
+use crate::proxy_config::{ProxyConfig, ProxyScheme, ProxySelector};
 use crate::uri::build_authority;
 
 use super::tls::MaybeTlsStream;
@@ -26,7 +27,7 @@ type SharedRateLimit = Arc<dyn ShareableRateLimit>;
 pub struct HttpsConnector {
     connector: HttpConnector,
     ssl_connector: Arc<SslConnector>,
-    proxy: Option<ProxyConfig>,
+    proxy: Option<ProxySelector>,
     tcp_keepalive: u32,
     read_limiter: Option<SharedRateLimit>,
     write_limiter: Option<SharedRateLimit>,
@@ -49,8 +50,14 @@ impl HttpsConnector {
         }
     }
 
+    /// Use `proxy` for all connections.
     pub fn set_proxy(&mut self, proxy: ProxyConfig) {
-        self.proxy = Some(proxy);
+        self.proxy = Some(proxy.into());
+    }
+
+    /// Select the proxy per connection, depending on the target scheme and host.
+    pub fn set_proxy_selector(&mut self, selector: ProxySelector) {
+        self.proxy = Some(selector);
     }
 
     pub fn set_read_limiter(&mut self, limiter: Option<SharedRateLimit>) {
@@ -72,6 +79,199 @@ impl HttpsConnector {
         Ok(MaybeTlsStream::Secured(conn))
     }
 
+    /// Establish a TLS connection to a `https://` proxy.
+    ///
+    /// ALPN is restricted to HTTP/1.1, the proxy protocol is always spoken via HTTP/1.
+    async fn secure_proxy_stream<S: AsyncRead + AsyncWrite + Unpin>(
+        tcp_stream: S,
+        ssl_connector: &SslConnector,
+        host: &str,
+    ) -> Result<MaybeTlsStream<S>, Error> {
+        let mut config = ssl_connector.configure()?;
+        config.set_alpn_protos(b"\x08http/1.1")?;
+        let mut conn: SslStream<S> = SslStream::new(config.into_ssl(host)?, tcp_stream)?;
+        Pin::new(&mut conn)
+            .connect()
+            .await
+            .map_err(|err| format_err!("TLS connection to proxy {} failed - {}", host, err))?;
+        Ok(MaybeTlsStream::Secured(conn))
+    }
+
+    async fn connect_tcp(
+        connector: &mut HttpConnector,
+        host: &str,
+        port: u16,
+        keepalive: u32,
+        read_limiter: Option<SharedRateLimit>,
+        write_limiter: Option<SharedRateLimit>,
+    ) -> Result<RateLimitedStream<TcpStream>, Error> {
+        let authority = build_authority(host, port)?;
+        let uri = Uri::builder()
+            .scheme("http")
+            .authority(authority.as_str())
+            .path_and_query("/")
+            .build()?;
+
+        let tcp_stream = hyper::service::Service::call(connector, uri)
+            .await
+            .map_err(|err| format_err!("error connecting to {} - {}", authority, err))?;
+
+        let _ = set_tcp_keepalive(tcp_stream.as_raw_fd(), keepalive);
+
+        Ok(RateLimitedStream::with_limiter(
+            tcp_stream,
+            read_limiter,
+            write_limiter,
+        ))
+    }
+
+    async fn http_connect<S: AsyncRead + AsyncWrite + Unpin>(
+        stream: &mut S,
+        host: &str,
+        port: u16,
+        authorization: Option<&str>,
+    ) -> Result<(), Error> {
+        use std::fmt::Write as _;
+
+        let mut connect_request = format!("CONNECT {0}:{1} HTTP/1.1\r\n", host, port);
+        if let Some(authorization) = authorization {
+            let _ = write!(
+                connect_request,
+                "Proxy-Authorization: Basic {}\r\n",
+                base64::encode(authorization)
+            );
+        }
+        let _ = write!(connect_request, "Host: {0}:{1}\r\n\r\n", host, port);
+
+        stream.write_all(connect_request.as_bytes()).await?;
+        stream.flush().await?;
+
+        Self::parse_connect_response(stream).await
+    }
+
+    /// Open a tunnel to `host:port` via a SOCKS5 proxy (RFC 1928), optionally authenticating
+    /// with `user:password` (RFC 1929).
+    ///
+    /// With `remote_dns` set, host names are passed on to the proxy instead of being resolved
+    /// locally.
+    async fn socks5_connect<S: AsyncRead + AsyncWrite + Unpin>(
+        stream: &mut S,
+        host: &str,
+        port: u16,
+        remote_dns: bool,
+        authorization: Option<&str>,
+    ) -> Result<(), Error> {
+        let greeting: &[u8] = if authorization.is_some() {
+            &[0x05, 0x02, 0x00, 0x02]
+        } else {
+            &[0x05, 0x01, 0x00]
+        };
+        stream.write_all(greeting).await?;
+        stream.flush().await?;
+
+        let mut reply = [0u8; 2];
+        stream.read_exact(&mut reply).await?;
+        if reply[0] != 0x05 {
+            bail!("socks5 proxy - invalid protocol version {}", reply[0]);
+        }
+        match reply[1] {
+            0x00 => (),
+            0x02 => {
+                let authorization = match authorization {
+                    Some(authorization) => authorization,
+                    None => bail!("socks5 proxy - requested authentication without credentials"),
+                };
+                let (user, password) = authorization.split_once(':').unwrap_or((authorization, ""));
+                if user.len() > 255 || password.len() > 255 {
+                    bail!("socks5 proxy - user name or password too long");
+                }
+
+                let mut request = vec![0x01, user.len() as u8];
+                request.extend(user.as_bytes());
+                request.push(password.len() as u8);
+                request.extend(password.as_bytes());
+                stream.write_all(&request).await?;
+                stream.flush().await?;
+
+                stream.read_exact(&mut reply).await?;
+                if reply[1] != 0x00 {
+                    bail!("socks5 proxy - authentication failed");
+                }
+            }
+            0xff => bail!("socks5 proxy - no acceptable authentication method"),
+            method => bail!("socks5 proxy - unexpected authentication method {}", method),
+        }
+
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let address = match host.parse::<IpAddr>() {
+            Ok(address) => Some(address),
+            Err(_) if remote_dns => None,
+            Err(_) => match tokio::net::lookup_host((host, port)).await?.next() {
+                Some(address) => Some(address.ip()),
+                None => bail!("unable to resolve host '{}'", host),
+            },
+        };
+
+        let mut request = vec![0x05, 0x01, 0x00];
+        match address {
+            Some(IpAddr::V4(address)) => {
+                request.push(0x01);
+                request.extend(address.octets());
+            }
+            Some(IpAddr::V6(address)) => {
+                request.push(0x04);
+                request.extend(address.octets());
+            }
+            None => {
+                if host.len() > 255 {
+                    bail!("socks5 proxy - host name too long");
+                }
+                request.push(0x03);
+                request.push(host.len() as u8);
+                request.extend(host.as_bytes());
+            }
+        }
+        request.extend(port.to_be_bytes());
+        stream.write_all(&request).await?;
+        stream.flush().await?;
+
+        let mut header = [0u8; 4];
+        stream.read_exact(&mut header).await?;
+        if header[0] != 0x05 {
+            bail!("socks5 proxy - invalid protocol version {}", header[0]);
+        }
+        if header[1] != 0x00 {
+            let reason = match header[1] {
+                0x01 => "general failure",
+                0x02 => "connection not allowed by ruleset",
+                0x03 => "network unreachable",
+                0x04 => "host unreachable",
+                0x05 => "connection refused",
+                0x06 => "TTL expired",
+                0x07 => "command not supported",
+                0x08 => "address type not supported",
+                _ => "unknown error",
+            };
+            bail!("socks5 proxy connect failed - {}", reason);
+        }
+
+        // skip the bound address and port
+        let address_len = match header[3] {
+            0x01 => 4,
+            0x04 => 16,
+            0x03 => {
+                let mut len = [0u8; 1];
+                stream.read_exact(&mut len).await?;
+                len[0] as usize
+            }
+            atyp => bail!("socks5 proxy - invalid address type {}", atyp),
+        };
+        let mut bound = vec![0u8; address_len + 2];
+        stream.read_exact(&mut bound).await?;
+
+        Ok(())
+    }
+
     fn parse_status_line(status_line: &str) -> Result<(), Error> {
         if !(status_line.starts_with("HTTP/1.1 200") || status_line.starts_with("HTTP/1.0 200")) {
             bail!("proxy connect failed - invalid status: {}", status_line)
@@ -123,7 +323,7 @@ impl HttpsConnector {
 }
 
 impl hyper::service::Service<Uri> for HttpsConnector {
-    type Response = MaybeTlsStream<RateLimitedStream<TcpStream>>;
+    type Response = MaybeTlsStream<MaybeTlsStream<RateLimitedStream<TcpStream>>>;
     type Error = Error;
     #[allow(clippy::type_complexity)]
     type Future =
@@ -148,95 +348,147 @@ impl hyper::service::Service<Uri> for HttpsConnector {
         let read_limiter = self.read_limiter.clone();
         let write_limiter = self.write_limiter.clone();
 
-        if let Some(ref proxy) = self.proxy {
-            let use_connect = is_https || proxy.force_connect;
-
-            let proxy_authority = match build_authority(&proxy.host, proxy.port) {
-                Ok(authority) => authority,
-                Err(err) => return futures::future::err(err.into()).boxed(),
+        let proxy = self
+            .proxy
+            .as_ref()
+            .and_then(|selector| selector.proxy_for(is_https, &host, port))
+            .cloned();
+
+        async move {
+            let proxy = match proxy {
+                Some(proxy) => proxy,
+                None => {
+                    let tcp_stream = Self::connect_tcp(
+                        &mut connector,
+                        &host,
+                        port,
+                        keepalive,
+                        read_limiter,
+                        write_limiter,
+                    )
+                    .await?;
+
+                    let stream = MaybeTlsStream::Normal(tcp_stream);
+                    return if is_https {
+                        Self::secure_stream(stream, &ssl_connector, &host).await
+                    } else {
+                        Ok(MaybeTlsStream::Normal(stream))
+                    };
+                }
             };
 
-            let proxy_uri = match Uri::builder()
-                .scheme("http")
-                .authority(proxy_authority.as_str())
-                .path_and_query("/")
-                .build()
-            {
-                Ok(uri) => uri,
-                Err(err) => return futures::future::err(err.into()).boxed(),
+            let tcp_stream = Self::connect_tcp(
+                &mut connector,
+                &proxy.host,
+                proxy.port,
+                keepalive,
+                read_limiter,
+                write_limiter,
+            )
+            .await?;
+
+            let mut stream = if proxy.scheme == ProxyScheme::Https {
+                Self::secure_proxy_stream(tcp_stream, &ssl_connector, &proxy.host).await?
+            } else {
+                MaybeTlsStream::Normal(tcp_stream)
             };
 
-            let authorization = proxy.authorization.clone();
-
-            if use_connect {
-                async move {
-                    use std::fmt::Write as _;
-                    let tcp_stream = connector.call(proxy_uri).await.map_err(|err| {
-                        format_err!("error connecting to {} - {}", proxy_authority, err)
-                    })?;
+            let authorization = proxy.authorization.as_deref();
 
-                    let _ = set_tcp_keepalive(tcp_stream.as_raw_fd(), keepalive);
-
-                    let mut tcp_stream =
-                        RateLimitedStream::with_limiter(tcp_stream, read_limiter, write_limiter);
-
-                    let mut connect_request = format!("CONNECT {0}:{1} HTTP/1.1\r\n", host, port);
-                    if let Some(authorization) = authorization {
-                        let _ = write!(
-                            connect_request,
-                            "Proxy-Authorization: Basic {}\r\n",
-                            base64::encode(authorization)
-                        );
+            match proxy.scheme {
+                ProxyScheme::Socks5 | ProxyScheme::Socks5h => {
+                    let remote_dns = proxy.scheme == ProxyScheme::Socks5h;
+                    Self::socks5_connect(&mut stream, &host, port, remote_dns, authorization)
+                        .await?;
+                }
+                ProxyScheme::Http | ProxyScheme::Https => {
+                    if !(is_https || proxy.force_connect) {
+                        // plain HTTP requests are forwarded by the proxy
+                        return Ok(MaybeTlsStream::Proxied(stream));
                     }
-                    let _ = write!(connect_request, "Host: {0}:{1}\r\n\r\n", host, port);
+                    Self::http_connect(&mut stream, &host, port, authorization).await?;
+                }
+            }
 
-                    tcp_stream.write_all(connect_request.as_bytes()).await?;
-                    tcp_stream.flush().await?;
+            if is_https {
+                Self::secure_stream(stream, &ssl_connector, &host).await
+            } else {
+                Ok(MaybeTlsStream::Normal(stream))
+            }
+        }
+        .boxed()
+    }
+}
 
-                    Self::parse_connect_response(&mut tcp_stream).await?;
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_socks5_connect() -> Result<(), Error> {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+
+        let proxy = async move {
+            let mut greeting = [0u8; 4];
+            server.read_exact(&mut greeting).await?;
+            assert_eq!(greeting, [0x05, 0x02, 0x00, 0x02]);
+            server.write_all(&[0x05, 0x02]).await?;
+
+            let mut auth = [0u8; 11];
+            server.read_exact(&mut auth).await?;
+            assert_eq!(&auth, b"\x01\x04user\x04pass");
+            server.write_all(&[0x01, 0x00]).await?;
+
+            let mut request = [0u8; 5 + 11 + 2];
+            server.read_exact(&mut request).await?;
+            assert_eq!(&request, b"\x05\x01\x00\x03\x0bexample.com\x01\xbb");
+            server
+                .write_all(&[0x05, 0x00, 0x00, 0x01, 10, 0, 0, 1, 0x1f, 0x90])
+                .await?;
+
+            server.write_all(b"payload").await?;
+            Ok::<_, Error>(())
+        };
 
-                    if is_https {
-                        Self::secure_stream(tcp_stream, &ssl_connector, &host).await
-                    } else {
-                        Ok(MaybeTlsStream::Normal(tcp_stream))
-                    }
-                }
-                .boxed()
-            } else {
-                async move {
-                    let tcp_stream = connector.call(proxy_uri).await.map_err(|err| {
-                        format_err!("error connecting to {} - {}", proxy_authority, err)
-                    })?;
+        let connect = async move {
+            HttpsConnector::socks5_connect(
+                &mut client,
+                "example.com",
+                443,
+                true,
+                Some("user:pass"),
+            )
+            .await?;
+            let mut payload = [0u8; 7];
+            client.read_exact(&mut payload).await?;
+            assert_eq!(&payload, b"payload");
+            Ok::<_, Error>(())
+        };
 
-                    let _ = set_tcp_keepalive(tcp_stream.as_raw_fd(), keepalive);
+        let (proxy, connect) = futures::join!(proxy, connect);
+        proxy?;
+        connect?;
+
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let proxy = async move {
+            let mut greeting = [0u8; 3];
+            server.read_exact(&mut greeting).await?;
+            server.write_all(&[0x05, 0x00]).await?;
+            let mut request = [0u8; 4 + 4 + 2];
+            server.read_exact(&mut request).await?;
+            assert_eq!(request, [0x05, 0x01, 0x00, 0x01, 192, 168, 0, 1, 0, 80]);
+            server
+                .write_all(&[0x05, 0x05, 0x00, 0x01, 0, 0, 0, 0, 0, 0])
+                .await?;
+            Ok::<_, Error>(())
+        };
+        let connect = HttpsConnector::socks5_connect(&mut client, "192.168.0.1", 80, false, None);
 
-                    let tcp_stream =
-                        RateLimitedStream::with_limiter(tcp_stream, read_limiter, write_limiter);
+        let (proxy, connect) = futures::join!(proxy, connect);
+        proxy?;
+        let err = connect.unwrap_err();
+        assert!(err.to_string().contains("connection refused"));
 
-                    Ok(MaybeTlsStream::Proxied(tcp_stream))
-                }
-                .boxed()
-            }
-        } else {
-            async move {
-                let dst_str = dst.to_string(); // for error messages
-                let tcp_stream = connector
-                    .call(dst)
-                    .await
-                    .map_err(|err| format_err!("error connecting to {} - {}", dst_str, err))?;
-
-                let _ = set_tcp_keepalive(tcp_stream.as_raw_fd(), keepalive);
-
-                let tcp_stream =
-                    RateLimitedStream::with_limiter(tcp_stream, read_limiter, write_limiter);
-
-                if is_https {
-                    Self::secure_stream(tcp_stream, &ssl_connector, &host).await
-                } else {
-                    Ok(MaybeTlsStream::Normal(tcp_stream))
-                }
-            }
-            .boxed()
-        }
+        Ok(())
     }
 }
@@ -14,7 +14,7 @@ use hyper::Body;
 use openssl::ssl::{SslConnector, SslMethod};
 
 use crate::client::HttpsConnector;
-use crate::HttpOptions;
+use crate::{HttpOptions, TlsConfig};
 
 /// Asynchronous HTTP client implementation
 pub struct Client {
@@ -34,6 +34,12 @@ impl Client {
         Self::with_ssl_connector(ssl_connector, options)
     }
 
+    /// Create a client verifying servers according to `tls_config`.
+    pub fn with_tls_config(tls_config: &TlsConfig, options: HttpOptions) -> Result<Self, Error> {
+        let ssl_connector = tls_config.ssl_connector()?;
+        Ok(Self::with_ssl_connector(ssl_connector, options))
+    }
+
     pub fn with_ssl_connector(ssl_connector: SslConnector, options: HttpOptions) -> Self {
         let connector = HttpConnector::new();
         let mut https = HttpsConnector::with_connector(
@@ -41,8 +47,8 @@ impl Client {
             ssl_connector,
             options.tcp_keepalive.unwrap_or(7200),
         );
-        if let Some(ref proxy_config) = options.proxy_config {
-            https.set_proxy(proxy_config.clone());
+        if let Some(proxy_selector) = options.get_proxy_selector() {
+            https.set_proxy_selector(proxy_selector);
         }
         let client = HyperClient::builder().build(https);
         Self { client, options }
@@ -54,13 +60,12 @@ impl Client {
     }
 
     fn add_proxy_headers(&self, request: &mut Request<Body>) -> Result<(), Error> {
-        if request.uri().scheme() != Some(&http::uri::Scheme::HTTPS) {
-            if let Some(ref authorization) = self.options.get_proxy_authorization() {
-                request.headers_mut().insert(
-                    http::header::PROXY_AUTHORIZATION,
-                    HeaderValue::from_str(authorization)?,
-                );
-            }
+        if let Some(ref authorization) = self.options.get_proxy_authorization_for_uri(request.uri())
+        {
+            request.headers_mut().insert(
+                http::header::PROXY_AUTHORIZATION,
+                HeaderValue::from_str(authorization)?,
+            );
         }
         Ok(())
     }
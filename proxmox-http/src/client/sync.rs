@@ -1,24 +1,46 @@
 use std::collections::HashMap;
 use std::io::Read;
 
-use anyhow::Error;
+use anyhow::{bail, Error};
 use http::Response;
 
 use crate::HttpClient;
 use crate::HttpOptions;
+use crate::ProxyScheme;
 
 #[derive(Default)]
 /// Blocking HTTP client for usage with [`HttpClient`].
 pub struct Client {
     options: HttpOptions,
+    #[cfg(feature = "tls-config")]
+    tls_connector: Option<std::sync::Arc<tls::OpensslConnector>>,
 }
 
 impl Client {
     pub fn new(options: HttpOptions) -> Self {
-        Self { options }
+        Self {
+            options,
+            #[cfg(feature = "tls-config")]
+            tls_connector: None,
+        }
+    }
+
+    /// Create a client verifying servers according to `tls_config`.
+    ///
+    /// TLS connections are then handled by openssl instead of ureq's default TLS backend.
+    #[cfg(feature = "tls-config")]
+    pub fn with_tls_config(
+        tls_config: &crate::TlsConfig,
+        options: HttpOptions,
+    ) -> Result<Self, Error> {
+        let connector = tls::OpensslConnector(tls_config.ssl_connector()?);
+        Ok(Self {
+            options,
+            tls_connector: Some(std::sync::Arc::new(connector)),
+        })
     }
 
-    fn agent(&self) -> Result<ureq::Agent, Error> {
+    fn agent(&self, uri: &str) -> Result<ureq::Agent, Error> {
         let mut builder = ureq::AgentBuilder::new();
 
         builder = builder.user_agent(self.options.user_agent.as_deref().unwrap_or(concat!(
@@ -26,10 +48,22 @@ impl Client {
             env!("CARGO_PKG_VERSION")
         )));
 
-        if let Some(proxy_config) = &self.options.proxy_config {
+        let uri: http::Uri = uri.parse()?;
+        if let Some(proxy_config) = self.options.get_proxy_for_uri(&uri) {
+            if proxy_config.scheme != ProxyScheme::Http {
+                bail!(
+                    "proxy scheme '{}' is not supported by the blocking client",
+                    proxy_config.scheme
+                );
+            }
             builder = builder.proxy(ureq::Proxy::new(proxy_config.to_proxy_string()?)?);
         }
 
+        #[cfg(feature = "tls-config")]
+        if let Some(ref connector) = self.tls_connector {
+            builder = builder.tls_connector(std::sync::Arc::clone(connector));
+        }
+
         Ok(builder.build())
     }
 
@@ -102,7 +136,7 @@ impl HttpClient<String, String> for Client {
         uri: &str,
         extra_headers: Option<&HashMap<String, String>>,
     ) -> Result<Response<String>, Error> {
-        let req = self.agent()?.get(uri);
+        let req = self.agent(uri)?.get(uri);
         let req = Self::add_headers(req, None, extra_headers);
 
         Self::call(req).and_then(Self::convert_response_to_string)
@@ -115,7 +149,7 @@ impl HttpClient<String, String> for Client {
         content_type: Option<&str>,
         extra_headers: Option<&HashMap<String, String>>,
     ) -> Result<Response<String>, Error> {
-        let req = self.agent()?.post(uri);
+        let req = self.agent(uri)?.post(uri);
         let req = Self::add_headers(req, content_type, extra_headers);
 
         match body {
@@ -126,9 +160,8 @@ impl HttpClient<String, String> for Client {
     }
 
     fn request(&self, request: http::Request<String>) -> Result<Response<String>, Error> {
-        let mut req = self
-            .agent()?
-            .request(request.method().as_str(), &request.uri().to_string());
+        let uri = request.uri().to_string();
+        let mut req = self.agent(&uri)?.request(request.method().as_str(), &uri);
 
         let orig_headers = request.headers();
 
@@ -148,7 +181,7 @@ impl HttpClient<&[u8], Vec<u8>> for Client {
         uri: &str,
         extra_headers: Option<&HashMap<String, String>>,
     ) -> Result<Response<Vec<u8>>, Error> {
-        let req = self.agent()?.get(uri);
+        let req = self.agent(uri)?.get(uri);
         let req = Self::add_headers(req, None, extra_headers);
 
         Self::call(req).and_then(Self::convert_response_to_vec)
@@ -161,7 +194,7 @@ impl HttpClient<&[u8], Vec<u8>> for Client {
         content_type: Option<&str>,
         extra_headers: Option<&HashMap<String, String>>,
     ) -> Result<Response<Vec<u8>>, Error> {
-        let req = self.agent()?.post(uri);
+        let req = self.agent(uri)?.post(uri);
         let req = Self::add_headers(req, content_type, extra_headers);
 
         match body {
@@ -172,9 +205,8 @@ impl HttpClient<&[u8], Vec<u8>> for Client {
     }
 
     fn request(&self, request: http::Request<&[u8]>) -> Result<Response<Vec<u8>>, Error> {
-        let mut req = self
-            .agent()?
-            .request(request.method().as_str(), &request.uri().to_string());
+        let uri = request.uri().to_string();
+        let mut req = self.agent(&uri)?.request(request.method().as_str(), &uri);
 
         let orig_headers = request.headers();
 
@@ -194,7 +226,7 @@ impl HttpClient<Box<dyn Read>, Box<dyn Read>> for Client {
         uri: &str,
         extra_headers: Option<&HashMap<String, String>>,
     ) -> Result<Response<Box<dyn Read>>, Error> {
-        let req = self.agent()?.get(uri);
+        let req = self.agent(uri)?.get(uri);
         let req = Self::add_headers(req, None, extra_headers);
 
         Self::call(req).and_then(Self::convert_response_to_reader)
@@ -207,7 +239,7 @@ impl HttpClient<Box<dyn Read>, Box<dyn Read>> for Client {
         content_type: Option<&str>,
         extra_headers: Option<&HashMap<String, String>>,
     ) -> Result<Response<Box<dyn Read>>, Error> {
-        let req = self.agent()?.post(uri);
+        let req = self.agent(uri)?.post(uri);
         let req = Self::add_headers(req, content_type, extra_headers);
 
         match body {
@@ -221,9 +253,8 @@ impl HttpClient<Box<dyn Read>, Box<dyn Read>> for Client {
         &self,
         mut request: http::Request<Box<dyn Read>>,
     ) -> Result<Response<Box<dyn Read>>, Error> {
-        let mut req = self
-            .agent()?
-            .request(request.method().as_str(), &request.uri().to_string());
+        let uri = request.uri().to_string();
+        let mut req = self.agent(&uri)?.request(request.method().as_str(), &uri);
         let orig_headers = request.headers();
 
         for header in orig_headers.keys() {
@@ -235,3 +266,56 @@ impl HttpClient<Box<dyn Read>, Box<dyn Read>> for Client {
         Self::send(req, Box::new(request.body_mut())).and_then(Self::convert_response_to_reader)
     }
 }
+
+#[cfg(feature = "tls-config")]
+pub(crate) mod tls {
+    use std::io::{self, Read, Write};
+    use std::net::TcpStream;
+
+    use openssl::ssl::{SslConnector, SslStream};
+    use ureq::ReadWrite;
+
+    /// Use openssl for ureq's TLS connections.
+    pub(crate) struct OpensslConnector(pub(crate) SslConnector);
+
+    impl ureq::TlsConnector for OpensslConnector {
+        fn connect(
+            &self,
+            dns_name: &str,
+            io: Box<dyn ReadWrite>,
+        ) -> Result<Box<dyn ReadWrite>, ureq::Error> {
+            let stream = self.0.connect(dns_name, io).map_err(|err| {
+                io::Error::new(
+                    io::ErrorKind::ConnectionAborted,
+                    format!("TLS handshake failed - {}", err),
+                )
+            })?;
+            Ok(Box::new(OpensslStream(stream)))
+        }
+    }
+
+    #[derive(Debug)]
+    struct OpensslStream(SslStream<Box<dyn ReadWrite>>);
+
+    impl Read for OpensslStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl Write for OpensslStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.0.flush()
+        }
+    }
+
+    impl ReadWrite for OpensslStream {
+        fn socket(&self) -> Option<&TcpStream> {
+            self.0.get_ref().socket()
+        }
+    }
+}
@@ -1,10 +1,15 @@
-use crate::ProxyConfig;
+use http::Uri;
+
+use crate::{ProxyConfig, ProxySelector};
 
 /// Options for an HTTP client.
 #[derive(Default)]
 pub struct HttpOptions {
     /// Proxy configuration
     pub proxy_config: Option<ProxyConfig>,
+    /// Per-scheme proxy selection with `NO_PROXY` exceptions, takes precedence over
+    /// `proxy_config`
+    pub proxy_selector: Option<ProxySelector>,
     /// `User-Agent` header value
     pub user_agent: Option<String>,
     /// TCP keepalive time, defaults to 7200
@@ -21,4 +26,36 @@ impl HttpOptions {
 
         None
     }
+
+    /// The effective proxy selection, combining `proxy_selector` and `proxy_config`.
+    pub fn get_proxy_selector(&self) -> Option<ProxySelector> {
+        match (&self.proxy_selector, &self.proxy_config) {
+            (Some(selector), _) => Some(selector.clone()),
+            (None, Some(proxy_config)) => Some(proxy_config.clone().into()),
+            (None, None) => None,
+        }
+    }
+
+    /// Get the proxy to use for a request to `uri`.
+    pub fn get_proxy_for_uri(&self, uri: &Uri) -> Option<&ProxyConfig> {
+        match self.proxy_selector {
+            Some(ref selector) => selector.proxy_for_uri(uri),
+            None => self.proxy_config.as_ref(),
+        }
+    }
+
+    /// Get the `Proxy-Authorization` credentials needed for a request to `uri`.
+    ///
+    /// This is only required for plain HTTP requests which are forwarded by an HTTP proxy,
+    /// tunneled connections authenticate while connecting.
+    pub fn get_proxy_authorization_for_uri(&self, uri: &Uri) -> Option<String> {
+        let proxy_config = self.get_proxy_for_uri(uri)?;
+        if proxy_config.force_connect
+            || proxy_config.scheme.is_socks()
+            || uri.scheme() == Some(&http::uri::Scheme::HTTPS)
+        {
+            return None;
+        }
+        proxy_config.authorization.clone()
+    }
 }
@@ -9,7 +9,12 @@ pub mod uri;
 #[cfg(feature = "http-helpers")]
 pub mod proxy_config;
 #[cfg(feature = "http-helpers")]
-pub use proxy_config::ProxyConfig;
+pub use proxy_config::{NoProxy, ProxyConfig, ProxyScheme, ProxySelector};
+
+#[cfg(feature = "tls-config")]
+pub mod tls_config;
+#[cfg(feature = "tls-config")]
+pub use tls_config::{ClientCertificate, TlsConfig};
 
 #[cfg(feature = "http-helpers")]
 mod http_options;
@@ -29,6 +34,9 @@ mod rate_limiter;
 #[cfg(feature = "rate-limiter")]
 pub use rate_limiter::{RateLimit, RateLimiter, RateLimiterVec, ShareableRateLimit};
 
+#[cfg(feature = "traffic-control")]
+pub mod traffic_control;
+
 #[cfg(feature = "rate-limited-stream")]
 mod rate_limited_stream;
 #[cfg(feature = "rate-limited-stream")]
@@ -0,0 +1,519 @@
+//! TLS trust configuration shared by the HTTP clients.
+//!
+//! A [`TlsConfig`] describes which server certificates are trusted (additional CA certificates,
+//! certificate fingerprints, SPKI pins or a custom callback), the client certificate to present
+//! and the minimum accepted protocol version. It can be applied to any openssl
+//! [`SslConnectorBuilder`], and is accepted by the async [`Client`](crate::client::Client) and
+//! the sync [`Client`](crate::client::sync::Client). Other `ureq` based clients can use
+//! [`TlsConfig::ureq_tls_connector`].
+
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{bail, format_err, Error};
+use openssl::hash::MessageDigest;
+use openssl::pkey::{PKey, Private};
+use openssl::ssl::{SslConnector, SslConnectorBuilder, SslMethod, SslVerifyMode, SslVersion};
+use openssl::x509::store::X509StoreBuilder;
+use openssl::x509::{X509Ref, X509StoreContextRef, X509};
+
+/// Certificate verification callback, see [`TlsConfig::verify_callback`].
+pub type VerifyCallback = dyn Fn(bool, &mut X509StoreContextRef) -> bool + Send + Sync + 'static;
+
+/// A client certificate used for mutual TLS authentication.
+#[derive(Clone)]
+pub struct ClientCertificate {
+    /// The client certificate.
+    pub certificate: X509,
+    /// Intermediate certificates sent along with the client certificate.
+    pub chain: Vec<X509>,
+    /// The private key belonging to the client certificate.
+    pub key: PKey<Private>,
+}
+
+impl ClientCertificate {
+    /// Load a client certificate from PEM data.
+    ///
+    /// `cert_pem` contains the certificate, optionally followed by its intermediate
+    /// certificates.
+    pub fn from_pem(cert_pem: &[u8], key_pem: &[u8]) -> Result<Self, Error> {
+        let mut certs = X509::stack_from_pem(cert_pem)
+            .map_err(|err| format_err!("failed to parse client certificate - {}", err))?
+            .into_iter();
+        let certificate = certs
+            .next()
+            .ok_or_else(|| format_err!("no client certificate found"))?;
+        let key = PKey::private_key_from_pem(key_pem)
+            .map_err(|err| format_err!("failed to parse client certificate key - {}", err))?;
+
+        Ok(Self {
+            certificate,
+            chain: certs.collect(),
+            key,
+        })
+    }
+
+    /// Load a client certificate and its key from PEM files.
+    pub fn from_pem_files<P: AsRef<Path>, Q: AsRef<Path>>(
+        cert_path: P,
+        key_path: Q,
+    ) -> Result<Self, Error> {
+        let cert_pem = read_file(cert_path.as_ref())?;
+        let key_pem = read_file(key_path.as_ref())?;
+        Self::from_pem(&cert_pem, &key_pem)
+    }
+}
+
+/// TLS trust and client authentication settings.
+///
+/// The default configuration verifies server certificates against the system trust store.
+#[derive(Clone, Default)]
+pub struct TlsConfig {
+    /// Disable certificate verification completely.
+    pub insecure: bool,
+    /// Additional trusted CA certificates.
+    pub ca_certs: Vec<X509>,
+    /// Only trust `ca_certs`, ignoring the system trust store.
+    pub exclusive_ca_certs: bool,
+    /// SHA-256 fingerprints of server certificates which are accepted even if they cannot be
+    /// verified otherwise.
+    pub fingerprints: Vec<[u8; 32]>,
+    /// SHA-256 hashes of pinned public keys (of the DER encoded `SubjectPublicKeyInfo`). If set,
+    /// at least one certificate of the verified chain must match one of them.
+    pub spki_pins: Vec<[u8; 32]>,
+    /// Custom verification callback. It gets the result of all other checks and has the final
+    /// say for every certificate of the chain.
+    pub verify_callback: Option<Arc<VerifyCallback>>,
+    /// Client certificate for mutual TLS authentication.
+    pub client_certificate: Option<ClientCertificate>,
+    /// Minimum accepted TLS protocol version.
+    pub min_version: Option<SslVersion>,
+}
+
+impl TlsConfig {
+    /// Create a default configuration, using the system trust store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add trusted CA certificates from PEM data.
+    pub fn add_ca_pem(&mut self, pem: &[u8]) -> Result<(), Error> {
+        let certs = X509::stack_from_pem(pem)
+            .map_err(|err| format_err!("failed to parse CA certificates - {}", err))?;
+        if certs.is_empty() {
+            bail!("no CA certificate found");
+        }
+        self.ca_certs.extend(certs);
+        Ok(())
+    }
+
+    /// Add trusted CA certificates from a PEM file (bundle).
+    pub fn add_ca_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Error> {
+        let path = path.as_ref();
+        self.add_ca_pem(&read_file(path)?)
+            .map_err(|err| format_err!("{:?} - {}", path, err))
+    }
+
+    /// Accept a server certificate by its SHA-256 fingerprint, in hex notation with optional
+    /// colons (`aa:bb:...`).
+    pub fn add_fingerprint(&mut self, fingerprint: &str) -> Result<(), Error> {
+        self.fingerprints.push(parse_fingerprint(fingerprint)?);
+        Ok(())
+    }
+
+    /// Pin a public key by the base64 encoded SHA-256 hash of its `SubjectPublicKeyInfo`, with
+    /// an optional `sha256//` prefix (as used by curl's `--pinnedpubkey`).
+    pub fn add_spki_pin(&mut self, pin: &str) -> Result<(), Error> {
+        let encoded = pin.strip_prefix("sha256//").unwrap_or(pin);
+        let hash = base64::decode(encoded)
+            .map_err(|err| format_err!("invalid public key pin '{}' - {}", pin, err))?;
+        let hash = <[u8; 32]>::try_from(hash.as_slice())
+            .map_err(|_| format_err!("invalid public key pin '{}' - wrong length", pin))?;
+        self.spki_pins.push(hash);
+        Ok(())
+    }
+
+    /// Apply this configuration to an openssl connector builder.
+    pub fn apply(&self, builder: &mut SslConnectorBuilder) -> Result<(), Error> {
+        if let Some(min_version) = self.min_version {
+            builder.set_min_proto_version(Some(min_version))?;
+        }
+
+        if let Some(ref client_cert) = self.client_certificate {
+            builder.set_certificate(&client_cert.certificate)?;
+            for cert in &client_cert.chain {
+                builder.add_extra_chain_cert(cert.clone())?;
+            }
+            builder.set_private_key(&client_cert.key)?;
+            builder
+                .check_private_key()
+                .map_err(|err| format_err!("client certificate key mismatch - {}", err))?;
+        }
+
+        if self.insecure {
+            builder.set_verify(SslVerifyMode::NONE);
+            return Ok(());
+        }
+
+        if self.exclusive_ca_certs {
+            let mut store = X509StoreBuilder::new()?;
+            for cert in &self.ca_certs {
+                store.add_cert(cert.clone())?;
+            }
+            builder.set_cert_store(store.build());
+        } else {
+            for cert in &self.ca_certs {
+                builder.cert_store_mut().add_cert(cert.clone())?;
+            }
+        }
+
+        if self.fingerprints.is_empty()
+            && self.spki_pins.is_empty()
+            && self.verify_callback.is_none()
+        {
+            builder.set_verify(SslVerifyMode::PEER);
+            return Ok(());
+        }
+
+        let fingerprints = self.fingerprints.clone();
+        let spki_pins = self.spki_pins.clone();
+        let callback = self.verify_callback.clone();
+
+        builder.set_verify_callback(SslVerifyMode::PEER, move |mut valid, ctx| {
+            if !valid && !fingerprints.is_empty() {
+                valid = leaf_certificate(ctx)
+                    .and_then(|cert| cert.digest(MessageDigest::sha256()).ok())
+                    .map(|digest| fingerprints.iter().any(|fp| fp[..] == digest[..]))
+                    .unwrap_or(false);
+            }
+
+            // the leaf is checked last, at that point the whole chain is available
+            if valid && !spki_pins.is_empty() && ctx.error_depth() == 0 {
+                valid = chain_matches_spki_pins(ctx, &spki_pins);
+            }
+
+            match callback {
+                Some(ref callback) => callback(valid, ctx),
+                None => valid,
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Build an openssl client connector with this configuration.
+    pub fn ssl_connector(&self) -> Result<SslConnector, Error> {
+        let mut builder = SslConnector::builder(SslMethod::tls_client())?;
+        self.apply(&mut builder)?;
+        Ok(builder.build())
+    }
+
+    /// Build a TLS connector for [`ureq`] agents with this configuration.
+    #[cfg(feature = "client-sync")]
+    pub fn ureq_tls_connector(&self) -> Result<Arc<impl ureq::TlsConnector>, Error> {
+        let connector = crate::client::sync::tls::OpensslConnector(self.ssl_connector()?);
+        Ok(Arc::new(connector))
+    }
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, Error> {
+    std::fs::read(path).map_err(|err| format_err!("unable to read {:?} - {}", path, err))
+}
+
+fn parse_fingerprint(fingerprint: &str) -> Result<[u8; 32], Error> {
+    let hex: Vec<u8> = fingerprint.bytes().filter(|&b| b != b':').collect();
+    if hex.len() != 64 {
+        bail!("invalid fingerprint '{}' - wrong length", fingerprint);
+    }
+
+    let mut out = [0u8; 32];
+    for (i, pair) in hex.chunks(2).enumerate() {
+        let pair = std::str::from_utf8(pair).ok();
+        out[i] = pair
+            .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+            .ok_or_else(|| format_err!("invalid fingerprint '{}'", fingerprint))?;
+    }
+
+    Ok(out)
+}
+
+fn leaf_certificate(ctx: &X509StoreContextRef) -> Option<&X509Ref> {
+    match ctx.chain() {
+        Some(chain) => chain.get(0),
+        None => ctx.current_cert(),
+    }
+}
+
+fn chain_matches_spki_pins(ctx: &X509StoreContextRef, pins: &[[u8; 32]]) -> bool {
+    let chain = match ctx.chain() {
+        Some(chain) => chain,
+        None => return false,
+    };
+
+    chain.iter().any(|cert| match spki_hash(cert) {
+        Ok(hash) => pins.contains(&hash),
+        Err(_) => false,
+    })
+}
+
+fn spki_hash(cert: &X509Ref) -> Result<[u8; 32], Error> {
+    let spki = cert.public_key()?.public_key_to_der()?;
+    let digest = openssl::hash::hash(MessageDigest::sha256(), &spki)?;
+    Ok(<[u8; 32]>::try_from(&digest[..])?)
+}
+
+#[cfg(test)]
+mod test {
+    use std::os::unix::net::UnixStream;
+
+    use openssl::asn1::Asn1Time;
+    use openssl::bn::BigNum;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::nid::Nid;
+    use openssl::ssl::{SslAcceptor, SslVerifyMode};
+    use openssl::x509::extension::{BasicConstraints, SubjectAlternativeName};
+    use openssl::x509::X509NameBuilder;
+
+    use super::*;
+
+    fn generate_key() -> PKey<Private> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap()
+    }
+
+    /// Create a certificate for `localhost`, signed by `issuer` or self-signed.
+    fn generate_cert(
+        common_name: &str,
+        key: &PKey<Private>,
+        issuer: Option<(&X509, &PKey<Private>)>,
+        ca: bool,
+    ) -> X509 {
+        let mut subject = X509NameBuilder::new().unwrap();
+        subject.append_entry_by_text("CN", common_name).unwrap();
+        let subject = subject.build();
+
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        let serial = BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap();
+        builder.set_serial_number(&serial).unwrap();
+        builder.set_subject_name(&subject).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        builder.set_pubkey(key).unwrap();
+
+        let (issuer_name, signing_key) = match issuer {
+            Some((issuer, issuer_key)) => (issuer.subject_name(), issuer_key),
+            None => (subject.as_ref(), key),
+        };
+        builder.set_issuer_name(issuer_name).unwrap();
+        if ca {
+            let constraints = BasicConstraints::new().critical().ca().build().unwrap();
+            builder.append_extension(constraints).unwrap();
+        } else {
+            let san = SubjectAlternativeName::new()
+                .dns("localhost")
+                .build(&builder.x509v3_context(issuer.map(|(cert, _)| &**cert), None))
+                .unwrap();
+            builder.append_extension(san).unwrap();
+        }
+
+        builder.sign(signing_key, MessageDigest::sha256()).unwrap();
+        builder.build()
+    }
+
+    /// Perform a handshake against a server using `cert`, returns the client certificate the
+    /// server received.
+    fn handshake(
+        config: &TlsConfig,
+        cert: &X509,
+        key: &PKey<Private>,
+    ) -> Result<Option<X509>, Error> {
+        let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server())?;
+        acceptor.set_certificate(cert)?;
+        acceptor.set_private_key(key)?;
+        acceptor.set_verify_callback(SslVerifyMode::PEER, |_, _| true);
+        let acceptor = acceptor.build();
+
+        let (client, server) = UnixStream::pair()?;
+        let server = std::thread::spawn(move || {
+            acceptor
+                .accept(server)
+                .ok()
+                .and_then(|stream| stream.ssl().peer_certificate())
+        });
+
+        let result = config.ssl_connector()?.connect("localhost", client);
+        let client_cert = match result {
+            Ok(stream) => {
+                drop(stream);
+                server.join().unwrap()
+            }
+            Err(err) => bail!("handshake failed - {}", err),
+        };
+
+        Ok(client_cert)
+    }
+
+    fn fingerprint_string(cert: &X509) -> String {
+        let digest = cert.digest(MessageDigest::sha256()).unwrap();
+        digest
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<Vec<_>>()
+            .join(":")
+    }
+
+    #[test]
+    fn test_parse() {
+        let mut config = TlsConfig::new();
+        config
+            .add_fingerprint(&format!("{}:ff", ["00"; 31].join(":")))
+            .unwrap();
+        assert_eq!(config.fingerprints[0][31], 0xff);
+        assert!(config.add_fingerprint("00:11").is_err());
+        assert!(config.add_fingerprint(&"zz".repeat(32)).is_err());
+
+        config
+            .add_spki_pin("sha256//47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=")
+            .unwrap();
+        assert_eq!(config.spki_pins[0][0], 0xe3);
+        assert!(config.add_spki_pin("AAAA").is_err());
+        assert!(config.add_ca_pem(b"garbage").is_err());
+    }
+
+    #[test]
+    fn test_trust() -> Result<(), Error> {
+        let ca_key = generate_key();
+        let ca_cert = generate_cert("test ca", &ca_key, None, true);
+        let key = generate_key();
+        let cert = generate_cert("localhost", &key, Some((&ca_cert, &ca_key)), false);
+        let self_signed = generate_cert("localhost", &key, None, false);
+
+        // unknown CA
+        let mut config = TlsConfig::new();
+        assert!(handshake(&config, &cert, &key).is_err());
+
+        // additional CA
+        config.add_ca_pem(&ca_cert.to_pem()?)?;
+        handshake(&config, &cert, &key)?;
+
+        config.exclusive_ca_certs = true;
+        handshake(&config, &cert, &key)?;
+
+        // pinning a key not part of the chain
+        let other_key = generate_key();
+        let other_spki = other_key.public_key_to_der()?;
+        let other_pin = openssl::hash::hash(MessageDigest::sha256(), &other_spki)?;
+        config.add_spki_pin(&base64::encode(other_pin))?;
+        assert!(handshake(&config, &cert, &key).is_err());
+
+        // pinning the CA key
+        config.add_spki_pin(&base64::encode(spki_hash(&ca_cert)?))?;
+        handshake(&config, &cert, &key)?;
+
+        // fingerprints
+        let mut config = TlsConfig::new();
+        assert!(handshake(&config, &self_signed, &key).is_err());
+        config.add_fingerprint(&fingerprint_string(&self_signed))?;
+        handshake(&config, &self_signed, &key)?;
+        assert!(handshake(&config, &cert, &key).is_err());
+
+        // callback has the final say
+        config.verify_callback = Some(Arc::new(|_, _| false));
+        assert!(handshake(&config, &self_signed, &key).is_err());
+
+        let config = TlsConfig {
+            insecure: true,
+            ..Default::default()
+        };
+        handshake(&config, &self_signed, &key)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_client_certificate() -> Result<(), Error> {
+        let key = generate_key();
+        let cert = generate_cert("localhost", &key, None, false);
+
+        let client_key = generate_key();
+        let client_cert = generate_cert("client", &client_key, None, false);
+
+        let mut config = TlsConfig {
+            insecure: true,
+            min_version: Some(SslVersion::TLS1_2),
+            ..Default::default()
+        };
+        assert!(handshake(&config, &cert, &key)?.is_none());
+
+        config.client_certificate = Some(ClientCertificate::from_pem(
+            &client_cert.to_pem()?,
+            &client_key.private_key_to_pem_pkcs8()?,
+        )?);
+        let received = handshake(&config, &cert, &key)?.unwrap();
+        assert_eq!(received.to_der()?, client_cert.to_der()?);
+
+        // key does not match the certificate
+        config.client_certificate = Some(ClientCertificate {
+            certificate: client_cert,
+            chain: Vec::new(),
+            key,
+        });
+        assert!(config.ssl_connector().is_err());
+
+        Ok(())
+    }
+
+    #[cfg(feature = "client-sync")]
+    #[test]
+    fn test_ureq_tls_connector() -> Result<(), Error> {
+        use std::io::{Read, Write};
+        use std::net::TcpListener;
+
+        let ca_key = generate_key();
+        let ca_cert = generate_cert("test ca", &ca_key, None, true);
+        let key = generate_key();
+        let cert = generate_cert("localhost", &key, Some((&ca_cert, &ca_key)), false);
+
+        let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server())?;
+        acceptor.set_certificate(&cert)?;
+        acceptor.set_private_key(&key)?;
+        let acceptor = acceptor.build();
+
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("https://localhost:{}/", listener.local_addr()?.port());
+        let server = std::thread::spawn(move || {
+            for stream in listener.incoming().take(2) {
+                let mut stream = match acceptor.accept(stream.unwrap()) {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+                let mut buf = [0u8; 4096];
+                let _ = stream.read(&mut buf);
+                let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok");
+            }
+        });
+
+        let get = |config: &TlsConfig| -> Result<String, Error> {
+            let agent = ureq::AgentBuilder::new()
+                .tls_connector(config.ureq_tls_connector()?)
+                .build();
+            Ok(agent.get(&url).call()?.into_string()?)
+        };
+
+        let mut config = TlsConfig::new();
+        config.exclusive_ca_certs = true;
+        assert!(get(&config).is_err());
+
+        config.add_ca_pem(&ca_cert.to_pem()?)?;
+        assert_eq!(get(&config)?, "ok");
+
+        server.join().unwrap();
+
+        Ok(())
+    }
+}
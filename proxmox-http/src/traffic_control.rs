@@ -0,0 +1,605 @@
+//! Traffic control rules for rate limited connections.
+//!
+//! A [`TrafficControlCache`] holds a list of [`TrafficControlRule`]s, each with a pair of rate
+//! limiters for incoming and outgoing traffic. The limiters of a rule are shared by all
+//! connections matching it, so the configured rate applies to the sum of their traffic.
+//!
+//! The limiters returned by [`TrafficControlCache::lookup_rate_limiter`] are meant to be used
+//! with a [`RateLimitedStream`](crate::RateLimitedStream) limiter update callback, which
+//! periodically repeats the lookup. This way rule changes and time frames take effect on open
+//! connections as well.
+
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, format_err, Error};
+
+use proxmox_time::{DailyDuration, TmEditor};
+
+use crate::{RateLimit, RateLimiter, ShareableRateLimit};
+
+pub type SharedRateLimit = Arc<dyn ShareableRateLimit>;
+
+/// An IPv4 or IPv6 network in CIDR notation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IpNetwork {
+    address: IpAddr,
+    prefix: u8,
+}
+
+impl IpNetwork {
+    /// Create a new network, host bits of the address are cleared.
+    pub fn new(address: IpAddr, prefix: u8) -> Result<Self, Error> {
+        let address = match address {
+            IpAddr::V4(address) => {
+                if prefix > 32 {
+                    bail!("invalid IPv4 network prefix length {}", prefix);
+                }
+                IpAddr::V4(Ipv4Addr::from(u32::from(address) & ipv4_mask(prefix)))
+            }
+            IpAddr::V6(address) => {
+                if prefix > 128 {
+                    bail!("invalid IPv6 network prefix length {}", prefix);
+                }
+                IpAddr::V6(Ipv6Addr::from(u128::from(address) & ipv6_mask(prefix)))
+            }
+        };
+
+        Ok(Self { address, prefix })
+    }
+
+    /// The network address.
+    pub fn address(&self) -> IpAddr {
+        self.address
+    }
+
+    /// The prefix length.
+    pub fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    /// Check if the network contains an address.
+    ///
+    /// IPv4-mapped IPv6 addresses, as seen on dual stack sockets, are treated as IPv4 addresses.
+    pub fn contains(&self, address: IpAddr) -> bool {
+        let address = match address {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(address),
+            address => address,
+        };
+
+        match (self.address, address) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                u32::from(address) & ipv4_mask(self.prefix) == u32::from(network)
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                u128::from(address) & ipv6_mask(self.prefix) == u128::from(network)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn ipv4_mask(prefix: u8) -> u32 {
+    u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0)
+}
+
+fn ipv6_mask(prefix: u8) -> u128 {
+    u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0)
+}
+
+impl FromStr for IpNetwork {
+    type Err = Error;
+
+    /// Parse a network in CIDR notation, a plain address is a single host network.
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let (address, prefix) = match s.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (s, None),
+        };
+
+        let address: IpAddr = address
+            .parse()
+            .map_err(|_| format_err!("invalid network address '{}'", s))?;
+
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .map_err(|_| format_err!("invalid network prefix length in '{}'", s))?,
+            None if address.is_ipv4() => 32,
+            None => 128,
+        };
+
+        Self::new(address, prefix)
+    }
+}
+
+impl fmt::Display for IpNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix)
+    }
+}
+
+/// A traffic control rule.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TrafficControlRule {
+    /// Unique rule name.
+    pub name: String,
+    /// Client networks, an empty list matches all clients.
+    pub networks: Vec<IpNetwork>,
+    /// Authentication ids, an empty list matches all clients. A user also matches their API
+    /// tokens.
+    pub users: Vec<String>,
+    /// Time frames in which the rule is active (local time), always active if empty.
+    pub timeframes: Vec<DailyDuration>,
+    /// Rate limit for incoming traffic in bytes per second.
+    pub rate_in: Option<u64>,
+    /// Burst size for incoming traffic in bytes, defaults to `rate_in`.
+    pub burst_in: Option<u64>,
+    /// Rate limit for outgoing traffic in bytes per second.
+    pub rate_out: Option<u64>,
+    /// Burst size for outgoing traffic in bytes, defaults to `rate_out`.
+    pub burst_out: Option<u64>,
+}
+
+impl TrafficControlRule {
+    fn check(&self) -> Result<(), Error> {
+        if self.rate_in == Some(0) || self.rate_out == Some(0) {
+            bail!("traffic control rule '{}' has a rate of 0", self.name);
+        }
+        if self.burst_in == Some(0) || self.burst_out == Some(0) {
+            bail!("traffic control rule '{}' has a burst size of 0", self.name);
+        }
+        Ok(())
+    }
+
+    /// How specific a match is, `None` if the rule does not match.
+    ///
+    /// Rules for specific users are preferred over network rules, and more specific (longer)
+    /// network prefixes over shorter ones.
+    fn match_priority(
+        &self,
+        peer: IpAddr,
+        auth_id: Option<&str>,
+        time: Option<&TmEditor>,
+    ) -> Option<(bool, Option<u8>)> {
+        let network = if self.networks.is_empty() {
+            None
+        } else {
+            let prefix = self
+                .networks
+                .iter()
+                .filter(|network| network.contains(peer))
+                .map(|network| network.prefix())
+                .max()?;
+            Some(prefix)
+        };
+
+        let user = if self.users.is_empty() {
+            false
+        } else {
+            let auth_id = auth_id?;
+            if !self.users.iter().any(|user| auth_id_matches(user, auth_id)) {
+                return None;
+            }
+            true
+        };
+
+        if !self.timeframes.is_empty() {
+            let time = time?;
+            if !self
+                .timeframes
+                .iter()
+                .any(|timeframe| timeframe.time_match_with_tm_editor(time))
+            {
+                return None;
+            }
+        }
+
+        Some((user, network))
+    }
+}
+
+fn auth_id_matches(user: &str, auth_id: &str) -> bool {
+    match auth_id.strip_prefix(user) {
+        Some(rest) => rest.is_empty() || (!user.contains('!') && rest.starts_with('!')),
+        None => false,
+    }
+}
+
+/// The limiter of a rule for one direction. Counts the traffic even if it is not limited.
+struct RuleLimiter {
+    limiter: Option<RateLimiter>,
+    traffic: u64,
+}
+
+impl RuleLimiter {
+    fn new(rate: Option<u64>, burst: Option<u64>) -> Self {
+        let mut limiter = Self {
+            limiter: None,
+            traffic: 0,
+        };
+        limiter.set_rate(rate, burst);
+        limiter
+    }
+
+    fn set_rate(&mut self, rate: Option<u64>, burst: Option<u64>) {
+        match rate {
+            Some(rate) => self.update_rate(rate, burst.unwrap_or(rate)),
+            None => self.limiter = None,
+        }
+    }
+}
+
+impl RateLimit for RuleLimiter {
+    fn update_rate(&mut self, rate: u64, bucket_size: u64) {
+        match self.limiter {
+            Some(ref mut limiter) => limiter.update_rate(rate, bucket_size),
+            None => self.limiter = Some(RateLimiter::new(rate, bucket_size)),
+        }
+    }
+
+    fn traffic(&self) -> u64 {
+        self.traffic
+    }
+
+    fn register_traffic(&mut self, current_time: Instant, data_len: u64) -> Duration {
+        self.traffic += data_len;
+        match self.limiter {
+            Some(ref mut limiter) => limiter.register_traffic(current_time, data_len),
+            None => Duration::ZERO,
+        }
+    }
+}
+
+struct RuleState {
+    rule: TrafficControlRule,
+    read_limiter: Arc<Mutex<RuleLimiter>>,
+    write_limiter: Arc<Mutex<RuleLimiter>>,
+    last_update: Instant,
+    last_traffic_in: u64,
+    last_traffic_out: u64,
+    current_rate_in: u64,
+    current_rate_out: u64,
+}
+
+impl RuleState {
+    fn new(rule: TrafficControlRule) -> Self {
+        Self {
+            read_limiter: Arc::new(Mutex::new(RuleLimiter::new(rule.rate_in, rule.burst_in))),
+            write_limiter: Arc::new(Mutex::new(RuleLimiter::new(rule.rate_out, rule.burst_out))),
+            rule,
+            last_update: Instant::now(),
+            last_traffic_in: 0,
+            last_traffic_out: 0,
+            current_rate_in: 0,
+            current_rate_out: 0,
+        }
+    }
+
+    fn update_rule(&mut self, rule: TrafficControlRule) {
+        self.read_limiter
+            .lock()
+            .unwrap()
+            .set_rate(rule.rate_in, rule.burst_in);
+        self.write_limiter
+            .lock()
+            .unwrap()
+            .set_rate(rule.rate_out, rule.burst_out);
+        self.rule = rule;
+    }
+}
+
+/// Traffic statistics of a rule.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TrafficStat {
+    /// The rule name.
+    pub name: String,
+    /// Incoming traffic in bytes, since the rule was created.
+    pub traffic_in: u64,
+    /// Outgoing traffic in bytes, since the rule was created.
+    pub traffic_out: u64,
+    /// Incoming bytes per second, see [`TrafficControlCache::compute_current_rates`].
+    pub rate_in: u64,
+    /// Outgoing bytes per second, see [`TrafficControlCache::compute_current_rates`].
+    pub rate_out: u64,
+}
+
+/// Matches connections against traffic control rules and provides their shared limiters.
+#[derive(Default)]
+pub struct TrafficControlCache {
+    rules: Vec<RuleState>,
+}
+
+impl TrafficControlCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace the rules.
+    ///
+    /// The limiters and traffic counters of rules which keep their name are preserved, so
+    /// connections using them are not reset. Rate changes apply to them immediately.
+    ///
+    /// If several rules match a connection equally well, the first one wins.
+    pub fn update_rules(&mut self, rules: Vec<TrafficControlRule>) -> Result<(), Error> {
+        for (pos, rule) in rules.iter().enumerate() {
+            rule.check()?;
+            if rules[..pos].iter().any(|other| other.name == rule.name) {
+                bail!("duplicate traffic control rule '{}'", rule.name);
+            }
+        }
+
+        let mut old_rules = std::mem::take(&mut self.rules);
+
+        self.rules = rules
+            .into_iter()
+            .map(|rule| {
+                match old_rules
+                    .iter()
+                    .position(|state| state.rule.name == rule.name)
+                {
+                    Some(pos) => {
+                        let mut state = old_rules.swap_remove(pos);
+                        state.update_rule(rule);
+                        state
+                    }
+                    None => RuleState::new(rule),
+                }
+            })
+            .collect();
+
+        Ok(())
+    }
+
+    /// The current rules.
+    pub fn rules(&self) -> impl Iterator<Item = &TrafficControlRule> {
+        self.rules.iter().map(|state| &state.rule)
+    }
+
+    /// Find the rule for a connection and return its name and the limiters for incoming (read)
+    /// and outgoing (write) traffic.
+    ///
+    /// `now` is the current epoch, used to check the rule time frames in local time.
+    pub fn lookup_rate_limiter(
+        &self,
+        peer: IpAddr,
+        auth_id: Option<&str>,
+        now: i64,
+    ) -> (
+        Option<&str>,
+        Option<SharedRateLimit>,
+        Option<SharedRateLimit>,
+    ) {
+        // rules with time frames cannot match if the time is invalid
+        let time = TmEditor::with_epoch(now, false).ok();
+
+        let mut best: Option<(&RuleState, (bool, Option<u8>))> = None;
+        for state in &self.rules {
+            let priority = match state.rule.match_priority(peer, auth_id, time.as_ref()) {
+                Some(priority) => priority,
+                None => continue,
+            };
+            match best {
+                Some((_, best_priority)) if best_priority >= priority => (),
+                _ => best = Some((state, priority)),
+            }
+        }
+
+        match best {
+            Some((state, _)) => {
+                let read_limiter: SharedRateLimit = Arc::clone(&state.read_limiter) as _;
+                let write_limiter: SharedRateLimit = Arc::clone(&state.write_limiter) as _;
+                (
+                    Some(state.rule.name.as_str()),
+                    Some(read_limiter),
+                    Some(write_limiter),
+                )
+            }
+            None => (None, None, None),
+        }
+    }
+
+    /// Update the current rates from the traffic since the last call.
+    ///
+    /// Meant to be called periodically, for example every few seconds.
+    pub fn compute_current_rates(&mut self) {
+        let now = Instant::now();
+        for state in self.rules.iter_mut() {
+            let elapsed = now.duration_since(state.last_update).as_secs_f64();
+            if elapsed <= 0.0 {
+                continue;
+            }
+
+            let traffic_in = state.read_limiter.lock().unwrap().traffic();
+            let traffic_out = state.write_limiter.lock().unwrap().traffic();
+
+            state.current_rate_in = ((traffic_in - state.last_traffic_in) as f64 / elapsed) as u64;
+            state.current_rate_out =
+                ((traffic_out - state.last_traffic_out) as f64 / elapsed) as u64;
+
+            state.last_traffic_in = traffic_in;
+            state.last_traffic_out = traffic_out;
+            state.last_update = now;
+        }
+    }
+
+    /// The traffic counters of all rules.
+    pub fn traffic_stats(&self) -> Vec<TrafficStat> {
+        self.rules
+            .iter()
+            .map(|state| TrafficStat {
+                name: state.rule.name.clone(),
+                traffic_in: state.read_limiter.lock().unwrap().traffic(),
+                traffic_out: state.write_limiter.lock().unwrap().traffic(),
+                rate_in: state.current_rate_in,
+                rate_out: state.current_rate_out,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rule(name: &str, networks: &[&str], users: &[&str]) -> TrafficControlRule {
+        TrafficControlRule {
+            name: name.to_string(),
+            networks: networks.iter().map(|n| n.parse().unwrap()).collect(),
+            users: users.iter().map(|u| u.to_string()).collect(),
+            rate_in: Some(1000),
+            ..Default::default()
+        }
+    }
+
+    fn lookup(cache: &TrafficControlCache, peer: &str, auth_id: Option<&str>) -> Option<String> {
+        let (name, _, _) = cache.lookup_rate_limiter(peer.parse().unwrap(), auth_id, 0);
+        name.map(str::to_string)
+    }
+
+    #[test]
+    fn test_ip_network() {
+        let network: IpNetwork = "192.168.2.77/24".parse().unwrap();
+        assert_eq!(network.to_string(), "192.168.2.0/24");
+        assert!(network.contains("192.168.2.1".parse().unwrap()));
+        assert!(network.contains("::ffff:192.168.2.1".parse().unwrap()));
+        assert!(!network.contains("192.168.3.1".parse().unwrap()));
+        assert!(!network.contains("fd00::1".parse().unwrap()));
+
+        let network: IpNetwork = "fd00:1::/32".parse().unwrap();
+        assert!(network.contains("fd00:1:2::3".parse().unwrap()));
+        assert!(!network.contains("fd00:2::1".parse().unwrap()));
+
+        assert_eq!("10.0.0.1".parse::<IpNetwork>().unwrap().prefix(), 32);
+        assert!("0.0.0.0/0"
+            .parse::<IpNetwork>()
+            .unwrap()
+            .contains("1.2.3.4".parse().unwrap()));
+        assert!("10.0.0.0/33".parse::<IpNetwork>().is_err());
+        assert!("10.0.0/8".parse::<IpNetwork>().is_err());
+    }
+
+    #[test]
+    fn test_rule_matching() {
+        let mut cache = TrafficControlCache::new();
+        cache
+            .update_rules(vec![
+                rule("all", &["0.0.0.0/0", "::/0"], &[]),
+                rule("lan", &["192.168.0.0/16"], &[]),
+                rule("host", &["192.168.2.10"], &[]),
+                rule("backup", &[], &["backup@pbs"]),
+                rule("lan-token", &["192.168.0.0/16"], &["admin@pam!sync"]),
+            ])
+            .unwrap();
+
+        assert_eq!(lookup(&cache, "10.0.0.1", None).as_deref(), Some("all"));
+        assert_eq!(lookup(&cache, "192.168.2.1", None).as_deref(), Some("lan"));
+        assert_eq!(
+            lookup(&cache, "192.168.2.10", None).as_deref(),
+            Some("host")
+        );
+        assert_eq!(
+            lookup(&cache, "192.168.2.10", Some("backup@pbs")).as_deref(),
+            Some("backup")
+        );
+        assert_eq!(
+            lookup(&cache, "10.0.0.1", Some("backup@pbs!token")).as_deref(),
+            Some("backup")
+        );
+        assert_eq!(
+            lookup(&cache, "10.0.0.1", Some("backup@pbs2")).as_deref(),
+            Some("all")
+        );
+        assert_eq!(
+            lookup(&cache, "192.168.2.10", Some("admin@pam!sync")).as_deref(),
+            Some("lan-token")
+        );
+        assert_eq!(
+            lookup(&cache, "10.0.0.1", Some("admin@pam!sync")).as_deref(),
+            Some("all")
+        );
+        assert_eq!(
+            lookup(&cache, "192.168.2.10", Some("admin@pam")).as_deref(),
+            Some("host")
+        );
+
+        cache.update_rules(vec![]).unwrap();
+        assert_eq!(lookup(&cache, "10.0.0.1", None), None);
+    }
+
+    #[test]
+    fn test_timeframes() {
+        let mut cache = TrafficControlCache::new();
+        let mut night = rule("night", &["0.0.0.0/0"], &[]);
+        night.timeframes = vec![proxmox_time::parse_daily_duration("0:00-6:00").unwrap()];
+        cache.update_rules(vec![night]).unwrap();
+
+        let at = |hour| {
+            let mut time = TmEditor::with_epoch(1_700_000_000, false).unwrap();
+            time.set_time(hour, 30, 0).unwrap();
+            time.into_epoch().unwrap()
+        };
+
+        let peer = "10.0.0.1".parse().unwrap();
+        let (name, _, _) = cache.lookup_rate_limiter(peer, None, at(2));
+        assert_eq!(name, Some("night"));
+        let (name, _, _) = cache.lookup_rate_limiter(peer, None, at(7));
+        assert_eq!(name, None);
+    }
+
+    #[test]
+    fn test_reload_and_stats() {
+        let mut cache = TrafficControlCache::new();
+        cache
+            .update_rules(vec![
+                rule("a", &["10.0.0.0/8"], &[]),
+                rule("b", &["10.1.0.0/16"], &[]),
+            ])
+            .unwrap();
+
+        let peer = "10.1.0.1".parse().unwrap();
+        let (_, read, write) = cache.lookup_rate_limiter(peer, None, 0);
+        let (read, write) = (read.unwrap(), write.unwrap());
+
+        // connections share the limiters of a rule
+        let (_, read2, _) = cache.lookup_rate_limiter(peer, None, 0);
+        assert!(Arc::ptr_eq(&read, &read2.unwrap()));
+
+        let now = Instant::now();
+        read.register_traffic(now, 100);
+        write.register_traffic(now, 50);
+
+        // the outgoing direction is not limited, but counted
+        assert_eq!(write.register_traffic(now, 1 << 30), Duration::ZERO);
+
+        let mut b = rule("b", &["10.1.0.0/16"], &[]);
+        b.rate_in = Some(5000);
+        cache
+            .update_rules(vec![b, rule("c", &["10.2.0.0/16"], &[])])
+            .unwrap();
+
+        let (name, read2, _) = cache.lookup_rate_limiter(peer, None, 0);
+        assert_eq!(name, Some("b"));
+        assert!(Arc::ptr_eq(&read, &read2.unwrap()));
+
+        let stats = cache.traffic_stats();
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].name, "b");
+        assert_eq!(stats[0].traffic_in, 100);
+        assert_eq!(stats[0].traffic_out, 50 + (1 << 30));
+        assert_eq!(stats[1].traffic_in, 0);
+
+        cache.compute_current_rates();
+        assert!(cache.traffic_stats()[0].rate_in > 0);
+
+        assert!(cache
+            .update_rules(vec![rule("x", &[], &[]), rule("x", &[], &[])])
+            .is_err());
+        let mut zero = rule("zero", &[], &[]);
+        zero.rate_out = Some(0);
+        assert!(cache.update_rules(vec![zero]).is_err());
+    }
+}
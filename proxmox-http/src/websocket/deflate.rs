@@ -0,0 +1,543 @@
+//! The permessage-deflate extension, see RFC7692
+//!
+//! Only the default LZ77 window size of 15 bits is supported, offers requiring a smaller server
+//! window are declined.
+
+use std::io;
+
+use anyhow::{bail, format_err, Error};
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress, Status};
+
+use proxmox_lang::error::io_err_other;
+
+/// Name of the extension in the `Sec-WebSocket-Extensions` header.
+pub const PERMESSAGE_DEFLATE: &str = "permessage-deflate";
+
+/// The bytes every sync flushed deflate block ends with, which are removed from the message.
+const DEFLATE_TRAILER: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+const MAX_WINDOW_BITS: u8 = 15;
+
+/// Parameters of the permessage-deflate extension.
+///
+/// Used both for the parameters requested by the local endpoint and for the negotiated ones.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DeflateConfig {
+    /// The server resets its compression context after every message.
+    pub server_no_context_takeover: bool,
+    /// The client resets its compression context after every message.
+    pub client_no_context_takeover: bool,
+}
+
+impl DeflateConfig {
+    /// Server side negotiation: pick the first acceptable offer of the client's
+    /// `Sec-WebSocket-Extensions` header values.
+    ///
+    /// Returns the negotiated parameters and the value for the response header.
+    pub fn accept<'a, I>(&self, extension_headers: I) -> Option<(Self, String)>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        for offer in extension_headers.into_iter().flat_map(split_list) {
+            let (name, params) = match parse_extension(offer) {
+                Ok(extension) => extension,
+                Err(_) => continue,
+            };
+            if name != PERMESSAGE_DEFLATE {
+                continue;
+            }
+
+            if let Ok(negotiated) = self.accept_offer(&params) {
+                return Some(negotiated);
+            }
+        }
+
+        None
+    }
+
+    fn accept_offer(&self, params: &[(String, Option<String>)]) -> Result<(Self, String), Error> {
+        let mut negotiated = *self;
+        let mut server_max_window_bits = false;
+
+        check_duplicates(params)?;
+        for (name, value) in params {
+            match (name.as_str(), value) {
+                ("server_no_context_takeover", None) => {
+                    negotiated.server_no_context_takeover = true
+                }
+                ("client_no_context_takeover", None) => {
+                    negotiated.client_no_context_takeover = true
+                }
+                ("server_max_window_bits", Some(value)) => {
+                    // we cannot use a smaller window for compressing
+                    if parse_window_bits(value)? != MAX_WINDOW_BITS {
+                        bail!("unsupported server_max_window_bits");
+                    }
+                    server_max_window_bits = true;
+                }
+                // the client's window is limited by our decompressor's window anyway
+                ("client_max_window_bits", None) => (),
+                ("client_max_window_bits", Some(value)) => {
+                    parse_window_bits(value)?;
+                }
+                _ => bail!("invalid permessage-deflate parameter '{}'", name),
+            }
+        }
+
+        let mut response = PERMESSAGE_DEFLATE.to_string();
+        if negotiated.server_no_context_takeover {
+            response.push_str("; server_no_context_takeover");
+        }
+        if negotiated.client_no_context_takeover {
+            response.push_str("; client_no_context_takeover");
+        }
+        if server_max_window_bits {
+            response.push_str("; server_max_window_bits=15");
+        }
+
+        Ok((negotiated, response))
+    }
+
+    /// Client side: the offer for the `Sec-WebSocket-Extensions` request header.
+    pub fn offer(&self) -> String {
+        let mut offer = PERMESSAGE_DEFLATE.to_string();
+        if self.server_no_context_takeover {
+            offer.push_str("; server_no_context_takeover");
+        }
+        if self.client_no_context_takeover {
+            offer.push_str("; client_no_context_takeover");
+        }
+        offer
+    }
+
+    /// Client side: check the server's `Sec-WebSocket-Extensions` response header values.
+    ///
+    /// Returns `None` if the server did not accept the extension.
+    pub fn check_response<'a, I>(&self, extension_headers: I) -> Result<Option<Self>, Error>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut negotiated = None;
+
+        for extension in extension_headers.into_iter().flat_map(split_list) {
+            let (name, params) = parse_extension(extension)?;
+            if name != PERMESSAGE_DEFLATE {
+                bail!("server accepted unsupported extension '{}'", name);
+            }
+            if negotiated.is_some() {
+                bail!("server accepted {} more than once", PERMESSAGE_DEFLATE);
+            }
+
+            let mut config = Self::default();
+            check_duplicates(&params)?;
+            for (name, value) in params {
+                match (name.as_str(), value) {
+                    ("server_no_context_takeover", None) => {
+                        config.server_no_context_takeover = true
+                    }
+                    ("client_no_context_takeover", None) => {
+                        config.client_no_context_takeover = true
+                    }
+                    // any server window fits into our decompressor's window
+                    ("server_max_window_bits", Some(value)) => {
+                        parse_window_bits(&value)?;
+                    }
+                    // not offered, so the server must not send it
+                    _ => bail!("invalid permessage-deflate response parameter '{}'", name),
+                }
+            }
+
+            if self.server_no_context_takeover && !config.server_no_context_takeover {
+                bail!("server ignored server_no_context_takeover");
+            }
+            // we can always reset our own context, even if the server did not ask for it
+            config.client_no_context_takeover |= self.client_no_context_takeover;
+
+            negotiated = Some(config);
+        }
+
+        Ok(negotiated)
+    }
+}
+
+/// Split a header value list at commas outside of quoted strings.
+fn split_list(value: &str) -> impl Iterator<Item = &str> {
+    let mut parts = Vec::new();
+    let mut in_quotes = false;
+    let mut start = 0;
+
+    for (pos, c) in value.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => {
+                parts.push(&value[start..pos]);
+                start = pos + 1;
+            }
+            _ => (),
+        }
+    }
+    parts.push(&value[start..]);
+
+    parts
+        .into_iter()
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+}
+
+type ExtensionParams = Vec<(String, Option<String>)>;
+
+/// Parse a single extension, e.g. `permessage-deflate; client_max_window_bits=10`.
+fn parse_extension(extension: &str) -> Result<(String, ExtensionParams), Error> {
+    let mut parts = extension.split(';').map(str::trim);
+
+    let name = match parts.next() {
+        Some(name) if is_token(name) => name.to_ascii_lowercase(),
+        _ => bail!("invalid extension '{}'", extension),
+    };
+
+    let mut params = Vec::new();
+    for param in parts {
+        let (name, value) = match param.split_once('=') {
+            Some((name, value)) => {
+                let value = value.trim();
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|value| value.strip_suffix('"'))
+                    .unwrap_or(value);
+                (name.trim(), Some(value.to_string()))
+            }
+            None => (param, None),
+        };
+        if !is_token(name) {
+            bail!("invalid extension parameter '{}'", param);
+        }
+        params.push((name.to_ascii_lowercase(), value));
+    }
+
+    Ok((name, params))
+}
+
+fn is_token(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+fn check_duplicates(params: &[(String, Option<String>)]) -> Result<(), Error> {
+    for (pos, (name, _)) in params.iter().enumerate() {
+        if params[..pos].iter().any(|(other, _)| other == name) {
+            bail!("duplicate permessage-deflate parameter '{}'", name);
+        }
+    }
+    Ok(())
+}
+
+fn parse_window_bits(value: &str) -> Result<u8, Error> {
+    // no leading zeros allowed
+    let bits: u8 = match value.as_bytes().first() {
+        Some(b'1'..=b'9') => value.parse()?,
+        _ => bail!("invalid window bits '{}'", value),
+    };
+    if !(8..=15).contains(&bits) {
+        bail!("window bits out of range: {}", bits);
+    }
+    Ok(bits)
+}
+
+/// Compresses outgoing messages.
+pub(crate) struct DeflateEncoder {
+    compress: Compress,
+    no_context_takeover: bool,
+}
+
+impl DeflateEncoder {
+    pub(crate) fn new(no_context_takeover: bool) -> Self {
+        Self {
+            compress: Compress::new(Compression::default(), false),
+            no_context_takeover,
+        }
+    }
+
+    /// Compress a complete message.
+    pub(crate) fn compress(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut output = Vec::with_capacity(data.len() / 2 + 64);
+        let start = self.compress.total_in();
+
+        loop {
+            let consumed = (self.compress.total_in() - start) as usize;
+            if output.len() == output.capacity() {
+                output.reserve(output.capacity().max(1024));
+            }
+
+            self.compress
+                .compress_vec(&data[consumed..], &mut output, FlushCompress::Sync)
+                .map_err(io_err_other)?;
+
+            // the flush is complete once all input is consumed and the output was not filled up
+            let consumed = (self.compress.total_in() - start) as usize;
+            if consumed == data.len() && output.len() < output.capacity() {
+                break;
+            }
+        }
+
+        if output.ends_with(&DEFLATE_TRAILER) {
+            output.truncate(output.len() - DEFLATE_TRAILER.len());
+        }
+
+        if self.no_context_takeover {
+            self.compress.reset();
+        }
+
+        Ok(output)
+    }
+}
+
+/// Decompresses incoming messages as their payload arrives.
+pub(crate) struct DeflateDecoder {
+    decompress: Decompress,
+    no_context_takeover: bool,
+    input: Vec<u8>,
+    end_of_message: bool,
+    /// The last call produced output, there may be more buffered in the decompressor.
+    pending: bool,
+}
+
+impl DeflateDecoder {
+    pub(crate) fn new(no_context_takeover: bool) -> Self {
+        Self {
+            decompress: Decompress::new(false),
+            no_context_takeover,
+            input: Vec::new(),
+            end_of_message: false,
+            pending: false,
+        }
+    }
+
+    /// Add compressed payload data. `end_of_message` is set for the last data of a message.
+    pub(crate) fn push(&mut self, data: &[u8], end_of_message: bool) {
+        self.input.extend_from_slice(data);
+        if end_of_message {
+            self.input.extend_from_slice(&DEFLATE_TRAILER);
+            self.end_of_message = true;
+        }
+    }
+
+    /// Whether there is buffered input or output which was not handed out yet.
+    pub(crate) fn has_input(&self) -> bool {
+        !self.input.is_empty() || self.end_of_message || self.pending
+    }
+
+    /// Decompress buffered input into `output`, returning the number of bytes written.
+    ///
+    /// Returns 0 if all buffered input was consumed.
+    pub(crate) fn decompress(&mut self, output: &mut [u8]) -> io::Result<usize> {
+        if output.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            let (total_in, total_out) = (self.decompress.total_in(), self.decompress.total_out());
+            let status = self
+                .decompress
+                .decompress(&self.input, output, FlushDecompress::Sync)
+                .map_err(|err| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format_err!("invalid compressed data - {}", err),
+                    )
+                })?;
+
+            let consumed = (self.decompress.total_in() - total_in) as usize;
+            let produced = (self.decompress.total_out() - total_out) as usize;
+            self.input.drain(..consumed);
+
+            if status == Status::StreamEnd {
+                // a final deflate block is allowed, but must not be followed by more data
+                self.decompress.reset(false);
+            }
+
+            self.pending = produced > 0;
+            if produced > 0 {
+                return Ok(produced);
+            }
+
+            if self.input.is_empty() {
+                if self.end_of_message {
+                    self.end_of_message = false;
+                    if self.no_context_takeover {
+                        self.decompress.reset(false);
+                    }
+                }
+                return Ok(0);
+            }
+
+            if consumed == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "invalid compressed data",
+                ));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn decompress_all(decoder: &mut DeflateDecoder) -> Vec<u8> {
+        let mut result = Vec::new();
+        let mut buf = [0u8; 7];
+        loop {
+            match decoder.decompress(&mut buf).unwrap() {
+                0 => return result,
+                n => result.extend_from_slice(&buf[..n]),
+            }
+        }
+    }
+
+    #[test]
+    fn test_negotiation() {
+        let config = DeflateConfig::default();
+
+        let (negotiated, response) = config
+            .accept([
+                "x-webkit-deflate-frame",
+                "permessage-deflate; client_max_window_bits",
+            ])
+            .unwrap();
+        assert_eq!(negotiated, config);
+        assert_eq!(response, "permessage-deflate");
+
+        // the first acceptable offer wins
+        let (negotiated, response) = config
+            .accept([
+                "permessage-deflate; server_max_window_bits=10, \
+                 permessage-deflate; server_no_context_takeover; server_max_window_bits=\"15\"",
+                "permessage-deflate",
+            ])
+            .unwrap();
+        assert!(negotiated.server_no_context_takeover);
+        assert!(!negotiated.client_no_context_takeover);
+        assert_eq!(
+            response,
+            "permessage-deflate; server_no_context_takeover; server_max_window_bits=15"
+        );
+
+        // our own requirements are added to the response
+        let config = DeflateConfig {
+            server_no_context_takeover: false,
+            client_no_context_takeover: true,
+        };
+        let (_, response) = config.accept(["permessage-deflate"]).unwrap();
+        assert_eq!(response, "permessage-deflate; client_no_context_takeover");
+
+        assert!(config
+            .accept(["permessage-deflate; client_no_context_takeover; client_no_context_takeover"])
+            .is_none());
+        assert!(config.accept(["permessage-deflate; foo=bar"]).is_none());
+        assert!(config
+            .accept(["permessage-deflate; client_max_window_bits=07"])
+            .is_none());
+        assert!(config.accept(["other"]).is_none());
+
+        let client = DeflateConfig {
+            server_no_context_takeover: true,
+            client_no_context_takeover: false,
+        };
+        assert_eq!(
+            client.offer(),
+            "permessage-deflate; server_no_context_takeover"
+        );
+        assert_eq!(client.check_response([]).unwrap(), None);
+        assert_eq!(
+            client
+                .check_response([
+                    "permessage-deflate; server_no_context_takeover; client_no_context_takeover"
+                ])
+                .unwrap(),
+            Some(DeflateConfig {
+                server_no_context_takeover: true,
+                client_no_context_takeover: true,
+            })
+        );
+        assert!(client.check_response(["permessage-deflate"]).is_err());
+        assert!(client
+            .check_response([
+                "permessage-deflate; server_no_context_takeover; client_max_window_bits=10"
+            ])
+            .is_err());
+        assert!(client.check_response(["foo"]).is_err());
+    }
+
+    #[test]
+    fn test_rfc7692_examples() {
+        // RFC7692 section 7.2.3.1: "Hello" in a single message
+        let mut decoder = DeflateDecoder::new(false);
+        decoder.push(&[0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00], true);
+        assert_eq!(decompress_all(&mut decoder), b"Hello");
+
+        // section 7.2.3.2: the second "Hello" uses the shared sliding window
+        decoder.push(&[0xf2, 0x00, 0x11, 0x00, 0x00], true);
+        assert_eq!(decompress_all(&mut decoder), b"Hello");
+
+        // section 7.2.3.3: "Hello" as a stored (uncompressed) deflate block
+        let mut decoder = DeflateDecoder::new(true);
+        decoder.push(&[0x00, 0x05, 0x00, 0xfa, 0xff], false);
+        decoder.push(&[0x48, 0x65, 0x6c, 0x6c, 0x6f, 0x00], true);
+        assert_eq!(decompress_all(&mut decoder), b"Hello");
+
+        // section 7.2.3.5: two deflate blocks in one message
+        let mut decoder = DeflateDecoder::new(false);
+        decoder.push(
+            &[
+                0xf2, 0x48, 0x05, 0x00, 0x00, 0x00, 0xff, 0xff, 0xca, 0xc9, 0xc9, 0x07, 0x00,
+            ],
+            true,
+        );
+        assert_eq!(decompress_all(&mut decoder), b"Hello");
+
+        // section 7.2.3.4: a final block, BFINAL set
+        let mut decoder = DeflateDecoder::new(false);
+        decoder.push(&[0xf3, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00, 0x00], true);
+        assert_eq!(decompress_all(&mut decoder), b"Hello");
+
+        let mut decoder = DeflateDecoder::new(false);
+        decoder.push(&[0xff, 0xff, 0xff], true);
+        let mut buf = [0u8; 16];
+        assert!(decoder.decompress(&mut buf).is_err());
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        for no_context_takeover in [false, true] {
+            let mut encoder = DeflateEncoder::new(no_context_takeover);
+            let mut decoder = DeflateDecoder::new(no_context_takeover);
+
+            let text = "proxmox websocket compression ".repeat(1000);
+            let messages: [&[u8]; 4] = [b"", b"Hello", text.as_bytes(), b"Hello"];
+            let mut sizes = Vec::new();
+
+            for message in messages {
+                let compressed = encoder.compress(message).unwrap();
+                assert!(!compressed.ends_with(&DEFLATE_TRAILER));
+                sizes.push(compressed.len());
+
+                // feed the payload in odd chunks like the frame reader does
+                for (pos, chunk) in compressed.chunks(3).enumerate() {
+                    let last = (pos + 1) * 3 >= compressed.len();
+                    decoder.push(chunk, last);
+                }
+                if compressed.is_empty() {
+                    decoder.push(&[], true);
+                }
+                assert_eq!(decompress_all(&mut decoder), message);
+            }
+
+            assert!(sizes[2] < text.len() / 10);
+            // with context takeover the repeated message refers to the previous one
+            assert_eq!(sizes[3] < sizes[1], !no_context_takeover);
+        }
+    }
+}
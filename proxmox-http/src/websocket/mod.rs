@@ -2,6 +2,9 @@
 //!
 //! Provides methods to read and write from websockets The reader and writer take a reader/writer
 //! with AsyncRead/AsyncWrite respectively and provides the same
+//!
+//! The permessage-deflate extension (RFC7692) is supported for both server and client
+//! connections.
 
 use std::cmp::min;
 use std::future::Future;
@@ -12,8 +15,8 @@ use std::task::{Context, Poll};
 use anyhow::{bail, format_err, Error};
 use futures::select;
 use hyper::header::{
-    HeaderMap, HeaderValue, CONNECTION, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY,
-    SEC_WEBSOCKET_PROTOCOL, SEC_WEBSOCKET_VERSION, UPGRADE,
+    HeaderMap, HeaderValue, CONNECTION, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_EXTENSIONS,
+    SEC_WEBSOCKET_KEY, SEC_WEBSOCKET_PROTOCOL, SEC_WEBSOCKET_VERSION, UPGRADE,
 };
 use hyper::{Body, Response, StatusCode};
 use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
@@ -25,6 +28,10 @@ use futures::ready;
 use proxmox_io::ByteBuffer;
 use proxmox_lang::error::io_err_other;
 
+mod deflate;
+pub use deflate::{DeflateConfig, PERMESSAGE_DEFLATE};
+use deflate::{DeflateDecoder, DeflateEncoder};
+
 // see RFC6455 section 7.4.1
 #[derive(Debug, Clone, Copy)]
 #[repr(u16)]
@@ -138,6 +145,21 @@ fn mask_bytes(mask: Option<[u8; 4]>, data: &mut [u8]) {
     }
 }
 
+/// The mask for the remaining payload after `len` bytes were unmasked.
+fn advance_mask(mask: Option<[u8; 4]>, len: usize) -> Option<[u8; 4]> {
+    mask.map(|mut mask| {
+        mask.rotate_left(len & 3);
+        mask
+    })
+}
+
+/// A fresh random mask, as required for every frame sent by a client.
+fn random_mask() -> io::Result<[u8; 4]> {
+    let mut mask = [0u8; 4];
+    openssl::rand::rand_bytes(&mut mask).map_err(io_err_other)?;
+    Ok(mask)
+}
+
 /// Can be used to create a complete WebSocket Frame.
 ///
 /// Takes an optional mask, the data and the frame type
@@ -187,7 +209,18 @@ pub fn create_frame(
     data: &[u8],
     frametype: OpCode,
 ) -> Result<Vec<u8>, WebSocketError> {
-    let first_byte = 0b10000000 | (frametype as u8);
+    create_frame_ext(mask, data, frametype, false)
+}
+
+/// Create a frame, setting the RSV1 bit if the payload is compressed (permessage-deflate).
+fn create_frame_ext(
+    mask: Option<[u8; 4]>,
+    data: &[u8],
+    frametype: OpCode,
+    compressed: bool,
+) -> Result<Vec<u8>, WebSocketError> {
+    let rsv1 = if compressed { 0b01000000 } else { 0 };
+    let first_byte = 0b10000000 | rsv1 | (frametype as u8);
     let len = data.len();
     if (frametype as u8) & 0b00001000 > 0 && len > 125 {
         return Err(WebSocketError::new(
@@ -245,6 +278,8 @@ pub fn create_frame(
 pub struct WebSocketWriter<W: AsyncWrite + Unpin> {
     writer: W,
     mask: Option<[u8; 4]>,
+    random_mask: bool,
+    deflate: Option<DeflateEncoder>,
     frame: Option<(Vec<u8>, usize, usize)>,
 }
 
@@ -254,10 +289,20 @@ impl<W: AsyncWrite + Unpin> WebSocketWriter<W> {
         WebSocketWriter {
             writer,
             mask,
+            random_mask: false,
+            deflate: None,
             frame: None,
         }
     }
 
+    fn frame_mask(&self) -> io::Result<Option<[u8; 4]>> {
+        if self.random_mask {
+            Ok(Some(random_mask()?))
+        } else {
+            Ok(self.mask)
+        }
+    }
+
     pub async fn send_control_frame(
         &mut self,
         mask: Option<[u8; 4]>,
@@ -274,8 +319,17 @@ impl<W: AsyncWrite + Unpin> AsyncWrite for WebSocketWriter<W> {
         let this = Pin::get_mut(self);
 
         if this.frame.is_none() {
-            // create frame buf
-            let frame = match create_frame(this.mask, buf, OpCode::Binary) {
+            let mask = this.frame_mask()?;
+
+            // create frame buf, every write is a complete message
+            let frame = match this.deflate.as_mut() {
+                Some(encoder) => {
+                    let data = encoder.compress(buf)?;
+                    create_frame_ext(mask, &data, OpCode::Binary, true)
+                }
+                None => create_frame(mask, buf, OpCode::Binary),
+            };
+            let frame = match frame {
                 Ok(f) => f,
                 Err(e) => {
                     return Poll::Ready(Err(io_err_other(e)));
@@ -320,6 +374,9 @@ impl<W: AsyncWrite + Unpin> AsyncWrite for WebSocketWriter<W> {
 pub struct FrameHeader {
     /// True if the frame is either non-fragmented, or the last fragment
     pub fin: bool,
+    /// True if the RSV1 bit is set, marking the first frame of a compressed message
+    /// (permessage-deflate)
+    pub compressed: bool,
     /// The optional mask of the frame
     pub mask: Option<[u8; 4]>,
     /// The frametype
@@ -357,6 +414,7 @@ impl FrameHeader {
     ///     None => unreachable!(),
     ///     Some(header) => assert_eq!(header, FrameHeader{
     ///         fin: true,
+    ///         compressed: false,
     ///         mask: None,
     ///         frametype: OpCode::Ping,
     ///         header_len: 2,
@@ -374,8 +432,8 @@ impl FrameHeader {
 
         let data = data;
 
-        // we do not support extensions
-        if data[0] & 0b01110000 > 0 {
+        // RSV1 is used by permessage-deflate, we do not support other extensions
+        if data[0] & 0b00110000 > 0 {
             return Err(WebSocketError::new(
                 WebSocketErrorKind::ProtocolError,
                 "Extensions not supported",
@@ -383,6 +441,7 @@ impl FrameHeader {
         }
 
         let fin = data[0] & 0b10000000 != 0;
+        let compressed = data[0] & 0b01000000 != 0;
         let frametype = match data[0] & 0b1111 {
             0 => OpCode::Continuation,
             1 => OpCode::Text,
@@ -405,6 +464,13 @@ impl FrameHeader {
             ));
         }
 
+        if compressed && frametype.is_control() {
+            return Err(WebSocketError::new(
+                WebSocketErrorKind::ProtocolError,
+                "Control frames cannot be compressed",
+            ));
+        }
+
         let mask_bit = data[1] & 0b10000000 != 0;
         let mut mask_offset = 2;
         let mut payload_offset = 2;
@@ -451,6 +517,7 @@ impl FrameHeader {
 
         Ok(Some(FrameHeader {
             fin,
+            compressed,
             mask,
             frametype,
             payload_len,
@@ -475,6 +542,9 @@ pub struct WebSocketReader<R: AsyncRead> {
     read_buffer: Option<ByteBuffer>,
     header: Option<FrameHeader>,
     state: ReaderState<R>,
+    deflate: Option<DeflateDecoder>,
+    /// Set while a (fragmented) message is received, true if it is compressed.
+    message: Option<bool>,
 }
 
 impl<R: AsyncRead> WebSocketReader<R> {
@@ -498,7 +568,47 @@ impl<R: AsyncRead> WebSocketReader<R> {
             read_buffer: Some(ByteBuffer::with_capacity(capacity)),
             header: None,
             state: ReaderState::NoData,
+            deflate: None,
+            message: None,
+        }
+    }
+
+    /// Check the frame sequence and whether compression was negotiated.
+    fn check_frame(&mut self, header: &FrameHeader) -> Result<(), WebSocketError> {
+        let error = |message| {
+            Err(WebSocketError::new(
+                WebSocketErrorKind::ProtocolError,
+                message,
+            ))
+        };
+
+        if header.is_control_frame() {
+            return Ok(());
         }
+
+        match (header.frametype, self.message) {
+            (OpCode::Continuation, None) => error("Continuation frame without a message"),
+            (OpCode::Continuation, Some(_)) if header.compressed => {
+                error("Only the first frame of a message can be compressed")
+            }
+            (OpCode::Continuation, Some(_)) => Ok(()),
+            (_, Some(_)) => error("Expected a continuation frame"),
+            (_, None) if header.compressed && self.deflate.is_none() => {
+                error("Compression was not negotiated")
+            }
+            (_, None) => {
+                self.message = Some(header.compressed);
+                Ok(())
+            }
+        }
+    }
+
+    /// Report an error on the control channel and return it.
+    fn fail(&mut self, err: WebSocketError) -> Poll<io::Result<()>> {
+        if let Err(err) = self.sender.send(Err(err.clone())) {
+            return Poll::Ready(Err(io_err_other(err)));
+        }
+        Poll::Ready(Err(io_err_other(err)))
     }
 }
 
@@ -525,6 +635,24 @@ impl<R: AsyncRead + Unpin + Send + 'static> AsyncRead for WebSocketReader<R> {
         let this = Pin::get_mut(self);
 
         loop {
+            // hand out already received compressed data first
+            if let Some(decoder) = this.deflate.as_mut().filter(|decoder| decoder.has_input()) {
+                match decoder.decompress(buf.initialize_unfilled()) {
+                    Ok(0) => (),
+                    Ok(len) => {
+                        buf.advance(len);
+                        return Poll::Ready(Ok(()));
+                    }
+                    Err(err) => {
+                        let err = WebSocketError::new(
+                            WebSocketErrorKind::ProtocolError,
+                            &err.to_string(),
+                        );
+                        return this.fail(err);
+                    }
+                }
+            }
+
             match &mut this.state {
                 ReaderState::NoData => {
                     let mut reader = match this.reader.take() {
@@ -581,14 +709,13 @@ impl<R: AsyncRead + Unpin + Send + 'static> AsyncRead for WebSocketReader<R> {
                                     this.read_buffer = Some(read_buffer);
                                     continue;
                                 }
-                                Err(err) => {
-                                    if let Err(err) = this.sender.send(Err(err.clone())) {
-                                        return Poll::Ready(Err(io_err_other(err)));
-                                    }
-                                    return Poll::Ready(Err(io_err_other(err)));
-                                }
+                                Err(err) => return this.fail(err),
                             };
 
+                            if let Err(err) = this.check_frame(&header) {
+                                return this.fail(err);
+                            }
+
                             read_buffer.consume(header.header_len as usize);
                             header
                         }
@@ -616,16 +743,29 @@ impl<R: AsyncRead + Unpin + Send + 'static> AsyncRead for WebSocketReader<R> {
                         continue;
                     }
 
-                    let len = min(buf.remaining(), min(header.payload_len, read_buffer.len()));
+                    let compressed = this.message == Some(true);
+
+                    let len = if compressed {
+                        min(header.payload_len, read_buffer.len())
+                    } else {
+                        min(buf.remaining(), min(header.payload_len, read_buffer.len()))
+                    };
 
                     let mut data = read_buffer.remove_data(len);
                     mask_bytes(header.mask, &mut data);
-                    buf.put_slice(&data);
-
+                    header.mask = advance_mask(header.mask, len);
                     header.payload_len -= len;
 
+                    let end_of_message = header.payload_len == 0 && header.fin;
+                    match this.deflate.as_mut() {
+                        Some(decoder) if compressed => decoder.push(&data, end_of_message),
+                        _ => buf.put_slice(&data),
+                    }
+
                     if header.payload_len > 0 {
                         this.header = Some(header);
+                    } else if end_of_message {
+                        this.message = None;
                     }
 
                     this.state = if read_buffer.is_empty() {
@@ -635,7 +775,7 @@ impl<R: AsyncRead + Unpin + Send + 'static> AsyncRead for WebSocketReader<R> {
                     };
                     this.read_buffer = Some(read_buffer);
 
-                    if len > 0 {
+                    if len > 0 && !compressed {
                         return Poll::Ready(Ok(()));
                     }
                 }
@@ -647,50 +787,112 @@ impl<R: AsyncRead + Unpin + Send + 'static> AsyncRead for WebSocketReader<R> {
 /// Global Identifier for WebSockets, see RFC6455
 pub const MAGIC_WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
 
+/// Compute the `Sec-WebSocket-Accept` value for a `Sec-WebSocket-Key`.
+fn accept_key(key: &str) -> String {
+    let mut sha1 = openssl::sha::Sha1::new();
+    let data = format!("{}{}", key, MAGIC_WEBSOCKET_GUID);
+    sha1.update(data.as_bytes());
+    base64::encode(sha1.finish())
+}
+
 /// Provides methods for connecting one WebSocket endpoint with another
 pub struct WebSocket {
     pub mask: Option<[u8; 4]>,
+    /// The negotiated permessage-deflate parameters.
+    deflate: Option<DeflateConfig>,
+    /// Client connections mask every frame with a new random mask.
+    client: bool,
 }
 
 impl WebSocket {
     /// Returns a new WebSocket instance and the correct WebSocket response derived from the
     /// upgrade request's headers
+    ///
+    /// The permessage-deflate extension is used if the client offers it.
     pub fn new(headers: HeaderMap<HeaderValue>) -> Result<(Self, Response<Body>), Error> {
-        let protocols = headers
-            .get(UPGRADE)
-            .ok_or_else(|| format_err!("missing Upgrade header"))?
-            .to_str()?;
+        Self::with_deflate(headers, Some(DeflateConfig::default()))
+    }
+
+    /// Like [`new`](WebSocket::new), but with the permessage-deflate parameters required by the
+    /// server, or `None` to disable compression.
+    pub fn with_deflate(
+        headers: HeaderMap<HeaderValue>,
+        deflate: Option<DeflateConfig>,
+    ) -> Result<(Self, Response<Body>), Error> {
+        Self::accept_request(&headers, deflate, false)
+    }
+
+    /// Returns a new WebSocket instance and the response for a websocket request, which is
+    /// either an HTTP/1.1 upgrade request or an HTTP/2 extended CONNECT request (RFC8441).
+    ///
+    /// Extended CONNECT requests are recognized by their [`hyper::ext::Protocol`] extension. The
+    /// request's method is not checked, so servers can route them like upgrade requests.
+    ///
+    /// In both cases the connection is available via `hyper::upgrade::on` once the response
+    /// was sent.
+    pub fn from_request_parts(
+        parts: &hyper::http::request::Parts,
+        deflate: Option<DeflateConfig>,
+    ) -> Result<(Self, Response<Body>), Error> {
+        match parts.extensions.get::<hyper::ext::Protocol>() {
+            Some(protocol) if protocol.as_str() == "websocket" => {
+                Self::accept_request(&parts.headers, deflate, true)
+            }
+            Some(_) => bail!("invalid protocol name"),
+            None => Self::accept_request(&parts.headers, deflate, false),
+        }
+    }
+
+    fn accept_request(
+        headers: &HeaderMap<HeaderValue>,
+        deflate: Option<DeflateConfig>,
+        extended_connect: bool,
+    ) -> Result<(Self, Response<Body>), Error> {
+        if !extended_connect {
+            let protocols = headers
+                .get(UPGRADE)
+                .ok_or_else(|| format_err!("missing Upgrade header"))?
+                .to_str()?;
+
+            if protocols != "websocket" {
+                bail!("invalid protocol name");
+            }
+        }
 
         let version = headers
             .get(SEC_WEBSOCKET_VERSION)
             .ok_or_else(|| format_err!("missing websocket version"))?
             .to_str()?;
 
-        let key = headers
-            .get(SEC_WEBSOCKET_KEY)
-            .ok_or_else(|| format_err!("missing websocket key"))?
-            .to_str()?;
-
-        if protocols != "websocket" {
-            bail!("invalid protocol name");
-        }
-
         if version != "13" {
             bail!("invalid websocket version");
         }
 
-        // we ignore extensions
-
-        let mut sha1 = openssl::sha::Sha1::new();
-        let data = format!("{}{}", key, MAGIC_WEBSOCKET_GUID);
-        sha1.update(data.as_bytes());
-        let response_key = base64::encode(sha1.finish());
-
-        let mut response = Response::builder()
-            .status(StatusCode::SWITCHING_PROTOCOLS)
-            .header(UPGRADE, HeaderValue::from_static("websocket"))
-            .header(CONNECTION, HeaderValue::from_static("Upgrade"))
-            .header(SEC_WEBSOCKET_ACCEPT, response_key);
+        // permessage-deflate is the only supported extension, others are ignored
+        let deflate = deflate.and_then(|config| {
+            config.accept(
+                headers
+                    .get_all(SEC_WEBSOCKET_EXTENSIONS)
+                    .iter()
+                    .filter_map(|value| value.to_str().ok()),
+            )
+        });
+
+        let mut response = if extended_connect {
+            // the stream is established with a successful response, there is no key exchange
+            Response::builder().status(StatusCode::OK)
+        } else {
+            let key = headers
+                .get(SEC_WEBSOCKET_KEY)
+                .ok_or_else(|| format_err!("missing websocket key"))?
+                .to_str()?;
+
+            Response::builder()
+                .status(StatusCode::SWITCHING_PROTOCOLS)
+                .header(UPGRADE, HeaderValue::from_static("websocket"))
+                .header(CONNECTION, HeaderValue::from_static("Upgrade"))
+                .header(SEC_WEBSOCKET_ACCEPT, accept_key(key))
+        };
 
         // FIXME: remove compat in PBS 3.x
         //
@@ -700,9 +902,37 @@ impl WebSocket {
             response = response.header(SEC_WEBSOCKET_PROTOCOL, ws_proto)
         }
 
+        let deflate = match deflate {
+            Some((config, extension)) => {
+                response = response.header(SEC_WEBSOCKET_EXTENSIONS, extension);
+                Some(config)
+            }
+            None => None,
+        };
+
         let response = response.body(Body::empty())?;
 
-        Ok((Self { mask: None }, response))
+        Ok((
+            Self {
+                mask: None,
+                deflate,
+                client: false,
+            },
+            response,
+        ))
+    }
+
+    /// The negotiated permessage-deflate parameters, if compression is used.
+    pub fn deflate(&self) -> Option<DeflateConfig> {
+        self.deflate
+    }
+
+    fn frame_mask(&self) -> Result<Option<[u8; 4]>, Error> {
+        if self.client {
+            Ok(Some(random_mask()?))
+        } else {
+            Ok(self.mask)
+        }
     }
 
     pub async fn handle_channel_message<W>(
@@ -716,13 +946,13 @@ impl WebSocket {
         match result {
             Ok((OpCode::Ping, msg)) => {
                 writer
-                    .send_control_frame(self.mask, OpCode::Pong, &msg)
+                    .send_control_frame(self.frame_mask()?, OpCode::Pong, &msg)
                     .await?;
                 Ok(OpCode::Pong)
             }
             Ok((OpCode::Close, msg)) => {
                 writer
-                    .send_control_frame(self.mask, OpCode::Close, &msg)
+                    .send_control_frame(self.frame_mask()?, OpCode::Close, &msg)
                     .await?;
                 Ok(OpCode::Close)
             }
@@ -732,7 +962,11 @@ impl WebSocket {
             }
             Err(err) => {
                 writer
-                    .send_control_frame(self.mask, OpCode::Close, &err.generate_frame_payload())
+                    .send_control_frame(
+                        self.frame_mask()?,
+                        OpCode::Close,
+                        &err.generate_frame_payload(),
+                    )
                     .await?;
                 Err(Error::from(err))
             }
@@ -799,6 +1033,23 @@ impl WebSocket {
         let (tx, mut rx) = mpsc::unbounded_channel();
         let mut wsreader = WebSocketReader::new(usreader, tx);
         let mut wswriter = WebSocketWriter::new(self.mask, uswriter);
+        wswriter.random_mask = self.client;
+
+        if let Some(config) = self.deflate {
+            let (local, remote) = if self.client {
+                (
+                    config.client_no_context_takeover,
+                    config.server_no_context_takeover,
+                )
+            } else {
+                (
+                    config.server_no_context_takeover,
+                    config.client_no_context_takeover,
+                )
+            };
+            wswriter.deflate = Some(DeflateEncoder::new(local));
+            wsreader.deflate = Some(DeflateDecoder::new(remote));
+        }
 
         let ws_future = tokio::io::copy(&mut wsreader, &mut dswriter);
         let term_future = self.copy_to_websocket(&mut dsreader, &mut wswriter, &mut rx);
@@ -812,7 +1063,7 @@ impl WebSocket {
                 Ok(sent_close) if !sent_close => {
                     // status code 1000 => 0x03E8
                     wswriter
-                        .send_control_frame(self.mask, OpCode::Close, &WebSocketErrorKind::Normal.to_be_bytes())
+                        .send_control_frame(self.frame_mask()?, OpCode::Close, &WebSocketErrorKind::Normal.to_be_bytes())
                         .await?;
                     Ok(())
                 }
@@ -822,3 +1073,126 @@ impl WebSocket {
         }
     }
 }
+
+#[cfg(feature = "client")]
+impl WebSocket {
+    /// Connect to a WebSocket server using the given HTTP client and its proxy configuration.
+    ///
+    /// The `uri` can use either the `ws`/`wss` or the `http`/`https` scheme. If `deflate` is set,
+    /// the permessage-deflate extension is offered with the given parameters.
+    ///
+    /// Note that plain `ws` connections are only tunneled through a proxy if the proxy
+    /// configuration has `force_connect` set.
+    ///
+    /// Returns the WebSocket and the upgraded connection, which can be passed to
+    /// [`serve_connection`](WebSocket::serve_connection) as the upstream endpoint.
+    pub async fn connect(
+        client: &crate::client::Client,
+        uri: &str,
+        deflate: Option<DeflateConfig>,
+    ) -> Result<(Self, hyper::upgrade::Upgraded), Error> {
+        let request = hyper::Request::builder()
+            .method(hyper::Method::GET)
+            .uri(uri)
+            .body(Body::empty())?;
+
+        Self::connect_with_request(client, request, deflate).await
+    }
+
+    /// Like [`connect`](WebSocket::connect), but with a prepared `GET` request, for example to
+    /// add authentication headers or a `Sec-WebSocket-Protocol` header.
+    pub async fn connect_with_request(
+        client: &crate::client::Client,
+        mut request: hyper::Request<Body>,
+        deflate: Option<DeflateConfig>,
+    ) -> Result<(Self, hyper::upgrade::Upgraded), Error> {
+        if request.method() != hyper::Method::GET {
+            bail!("websocket upgrade requires a GET request");
+        }
+
+        let scheme = match request.uri().scheme_str() {
+            Some("ws" | "http") => "http",
+            Some("wss" | "https") => "https",
+            _ => bail!("unsupported websocket uri '{}'", request.uri()),
+        };
+        let mut parts = request.uri().clone().into_parts();
+        parts.scheme = Some(scheme.parse()?);
+        *request.uri_mut() = hyper::Uri::from_parts(parts)?;
+
+        let mut key = [0u8; 16];
+        openssl::rand::rand_bytes(&mut key)?;
+        let key = base64::encode(key);
+
+        let headers = request.headers_mut();
+        headers.insert(UPGRADE, HeaderValue::from_static("websocket"));
+        headers.insert(CONNECTION, HeaderValue::from_static("Upgrade"));
+        headers.insert(SEC_WEBSOCKET_VERSION, HeaderValue::from_static("13"));
+        headers.insert(SEC_WEBSOCKET_KEY, HeaderValue::from_str(&key)?);
+        if let Some(config) = deflate {
+            headers.insert(
+                SEC_WEBSOCKET_EXTENSIONS,
+                HeaderValue::from_str(&config.offer())?,
+            );
+        }
+
+        let response = client.request(request).await?;
+
+        let status = response.status();
+        if status != StatusCode::SWITCHING_PROTOCOLS {
+            bail!(
+                "websocket upgrade failed - got status '{}' from server",
+                status
+            );
+        }
+
+        let headers = response.headers();
+        let header_str = |name| -> Result<&str, Error> {
+            match headers.get(&name) {
+                Some(value) => Ok(value.to_str()?),
+                None => bail!("websocket upgrade failed - missing '{}' header", name),
+            }
+        };
+
+        if !header_str(UPGRADE)?.eq_ignore_ascii_case("websocket") {
+            bail!("websocket upgrade failed - invalid 'Upgrade' header");
+        }
+
+        if !header_str(CONNECTION)?
+            .split(',')
+            .any(|token| token.trim().eq_ignore_ascii_case("upgrade"))
+        {
+            bail!("websocket upgrade failed - invalid 'Connection' header");
+        }
+
+        if header_str(SEC_WEBSOCKET_ACCEPT)? != accept_key(&key) {
+            bail!("websocket upgrade failed - invalid 'Sec-WebSocket-Accept' header");
+        }
+
+        let extensions = headers
+            .get_all(SEC_WEBSOCKET_EXTENSIONS)
+            .iter()
+            .map(|value| value.to_str())
+            .collect::<Result<Vec<&str>, _>>()?;
+
+        let deflate = match deflate {
+            Some(config) => config.check_response(extensions)?,
+            None if !extensions.is_empty() => {
+                bail!(
+                    "websocket upgrade failed - server accepted extensions which were not offered"
+                )
+            }
+            None => None,
+        };
+
+        let upgraded = hyper::upgrade::on(response).await?;
+
+        Ok((
+            Self {
+                mask: None,
+                deflate,
+                client: true,
+            },
+            upgraded,
+        ))
+    }
+}
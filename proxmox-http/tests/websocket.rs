@@ -0,0 +1,656 @@
+//! Websocket conformance tests
+//!
+//! Modeled after the cases of the Autobahn test suite: framing, pings, reserved bits and opcodes,
+//! fragmentation, closing handshake and permessage-deflate. The server side runs
+//! [`WebSocket::serve_connection`] over in-memory streams, with the test acting as the client.
+#![cfg(all(feature = "websocket", feature = "client"))]
+
+use anyhow::Error;
+use flate2::{Compression, FlushCompress};
+use hyper::header::{
+    HeaderMap, HeaderValue, SEC_WEBSOCKET_EXTENSIONS, SEC_WEBSOCKET_KEY, SEC_WEBSOCKET_VERSION,
+    UPGRADE,
+};
+use tokio::io::{duplex, AsyncRead, AsyncReadExt, AsyncWriteExt, DuplexStream};
+use tokio::task::JoinHandle;
+
+use proxmox_http::websocket::{DeflateConfig, WebSocket};
+
+const FIN: u8 = 0b1000_0000;
+const RSV1: u8 = 0b0100_0000;
+const RSV2: u8 = 0b0010_0000;
+
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xA;
+
+const MASK: [u8; 4] = [0x37, 0xfa, 0x21, 0x3d];
+
+struct Server {
+    /// The websocket side, the test sends (masked) frames here.
+    ws: DuplexStream,
+    /// The raw data side.
+    data: DuplexStream,
+    /// The negotiated `Sec-WebSocket-Extensions` response header.
+    extensions: Option<String>,
+    task: JoinHandle<Result<(), Error>>,
+}
+
+fn upgrade_headers(extensions: Option<&str>) -> HeaderMap<HeaderValue> {
+    let mut headers = HeaderMap::new();
+    headers.insert(UPGRADE, HeaderValue::from_static("websocket"));
+    headers.insert(SEC_WEBSOCKET_VERSION, HeaderValue::from_static("13"));
+    headers.insert(
+        SEC_WEBSOCKET_KEY,
+        HeaderValue::from_static("dGhlIHNhbXBsZSBub25jZQ=="),
+    );
+    if let Some(extensions) = extensions {
+        headers.insert(
+            SEC_WEBSOCKET_EXTENSIONS,
+            HeaderValue::from_str(extensions).unwrap(),
+        );
+    }
+    headers
+}
+
+fn serve(extensions: Option<&str>) -> Server {
+    let (ws, response) = WebSocket::new(upgrade_headers(extensions)).unwrap();
+    let extensions = response
+        .headers()
+        .get(SEC_WEBSOCKET_EXTENSIONS)
+        .map(|value| value.to_str().unwrap().to_string());
+
+    let (client_ws, server_ws) = duplex(1 << 20);
+    let (client_data, server_data) = duplex(1 << 20);
+    let task = tokio::spawn(async move { ws.serve_connection(server_ws, server_data).await });
+
+    Server {
+        ws: client_ws,
+        data: client_data,
+        extensions,
+        task,
+    }
+}
+
+/// Build a masked client frame, `first` contains the FIN, RSV and opcode bits.
+fn frame(first: u8, data: &[u8]) -> Vec<u8> {
+    let mut frame = vec![first];
+    match data.len() {
+        len @ 0..=125 => frame.push(0x80 | len as u8),
+        len @ 126..=65535 => {
+            frame.push(0x80 | 126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(0x80 | 127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(&MASK);
+    frame.extend(data.iter().enumerate().map(|(i, b)| b ^ MASK[i % 4]));
+    frame
+}
+
+/// Read a server frame, returns the first header byte and the payload.
+async fn read_frame<S: AsyncRead + Unpin>(stream: &mut S) -> (u8, Vec<u8>) {
+    let mut header = [0u8; 2];
+    stream.read_exact(&mut header).await.unwrap();
+    assert_eq!(header[1] & 0x80, 0, "server frames must not be masked");
+
+    let len = match header[1] & 0x7f {
+        126 => stream.read_u16().await.unwrap() as usize,
+        127 => stream.read_u64().await.unwrap() as usize,
+        len => len as usize,
+    };
+
+    let mut payload = vec![0u8; len];
+    stream.read_exact(&mut payload).await.unwrap();
+    (header[0], payload)
+}
+
+async fn read_data(stream: &mut DuplexStream, len: usize) -> Vec<u8> {
+    let mut data = vec![0u8; len];
+    stream.read_exact(&mut data).await.unwrap();
+    data
+}
+
+/// The connection must be failed, either with a close frame with the given code, or by
+/// dropping it.
+async fn expect_failure(mut server: Server, code: u16) {
+    assert!(server.task.await.unwrap().is_err());
+
+    let mut rest = Vec::new();
+    server.ws.read_to_end(&mut rest).await.unwrap();
+    if !rest.is_empty() {
+        assert_eq!(rest[0], FIN | CLOSE);
+        assert_eq!(&rest[2..4], &code.to_be_bytes());
+    }
+}
+
+fn deflate(compress: &mut flate2::Compress, data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(data.len() + 64);
+    compress
+        .compress_vec(data, &mut output, FlushCompress::Sync)
+        .unwrap();
+    assert!(output.ends_with(&[0x00, 0x00, 0xff, 0xff]));
+    output.truncate(output.len() - 4);
+    output
+}
+
+fn inflate(decompress: &mut flate2::Decompress, data: &[u8]) -> Vec<u8> {
+    let mut input = data.to_vec();
+    input.extend_from_slice(&[0x00, 0x00, 0xff, 0xff]);
+    let mut output = Vec::with_capacity(64 * 1024);
+    decompress
+        .decompress_vec(&input, &mut output, flate2::FlushDecompress::Sync)
+        .unwrap();
+    output
+}
+
+#[tokio::test]
+async fn test_framing() {
+    let mut server = serve(None);
+
+    for len in [0, 1, 125, 126, 127, 65535, 65536, 100_000] {
+        let payload: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+
+        // deliver the frame in small chunks
+        for chunk in frame(FIN | BINARY, &payload).chunks(7) {
+            server.ws.write_all(chunk).await.unwrap();
+        }
+        if len > 0 {
+            assert_eq!(read_data(&mut server.data, len).await, payload);
+        }
+
+        // and back, the server may split the data into multiple frames
+        server.data.write_all(&payload).await.unwrap();
+        let mut received = Vec::new();
+        while received.len() < len {
+            let (first, data) = read_frame(&mut server.ws).await;
+            assert_eq!(first, FIN | BINARY);
+            received.extend(data);
+        }
+        assert_eq!(received, payload);
+    }
+
+    // byte by byte delivery
+    let payload = b"Hello, World!";
+    for byte in frame(FIN | TEXT, payload) {
+        server.ws.write_all(&[byte]).await.unwrap();
+    }
+    assert_eq!(read_data(&mut server.data, payload.len()).await, payload);
+}
+
+#[tokio::test]
+async fn test_ping_pong() {
+    let mut server = serve(None);
+
+    for payload in [&b""[..], b"Hello", &[0xfe; 125]] {
+        server
+            .ws
+            .write_all(&frame(FIN | PING, payload))
+            .await
+            .unwrap();
+        assert_eq!(
+            read_frame(&mut server.ws).await,
+            (FIN | PONG, payload.to_vec())
+        );
+    }
+
+    // unsolicited pongs are ignored
+    server
+        .ws
+        .write_all(&frame(FIN | PONG, b"pong"))
+        .await
+        .unwrap();
+    server
+        .ws
+        .write_all(&frame(FIN | PING, b"ping"))
+        .await
+        .unwrap();
+    assert_eq!(
+        read_frame(&mut server.ws).await,
+        (FIN | PONG, b"ping".to_vec())
+    );
+
+    // control frames can not have more than 125 bytes of payload
+    server
+        .ws
+        .write_all(&frame(FIN | PING, &[0; 126]))
+        .await
+        .unwrap();
+    expect_failure(server, 1002).await;
+}
+
+#[tokio::test]
+async fn test_reserved_bits() {
+    let mut server = serve(None);
+    server
+        .ws
+        .write_all(&frame(FIN | RSV2 | BINARY, b"data"))
+        .await
+        .unwrap();
+    expect_failure(server, 1002).await;
+
+    // RSV1 is only valid with a negotiated permessage-deflate
+    let mut server = serve(None);
+    assert_eq!(server.extensions, None);
+    server
+        .ws
+        .write_all(&frame(FIN | RSV1 | BINARY, b"data"))
+        .await
+        .unwrap();
+    expect_failure(server, 1002).await;
+}
+
+#[tokio::test]
+async fn test_reserved_opcodes() {
+    for opcode in [0x3, 0x7, 0xB, 0xF] {
+        let mut server = serve(None);
+        server
+            .ws
+            .write_all(&frame(FIN | opcode, b"data"))
+            .await
+            .unwrap();
+        expect_failure(server, 1002).await;
+    }
+}
+
+#[tokio::test]
+async fn test_fragmentation() {
+    let mut server = serve(None);
+
+    server.ws.write_all(&frame(TEXT, b"frag")).await.unwrap();
+    server
+        .ws
+        .write_all(&frame(FIN | PING, b"ping"))
+        .await
+        .unwrap();
+    server
+        .ws
+        .write_all(&frame(CONTINUATION, b"men"))
+        .await
+        .unwrap();
+    server
+        .ws
+        .write_all(&frame(FIN | CONTINUATION, b"ted"))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        read_frame(&mut server.ws).await,
+        (FIN | PONG, b"ping".to_vec())
+    );
+    assert_eq!(read_data(&mut server.data, 10).await, b"fragmented");
+
+    // fragmented control frames are invalid
+    server.ws.write_all(&frame(PING, b"ping")).await.unwrap();
+    expect_failure(server, 1002).await;
+
+    // continuation without a message
+    let mut server = serve(None);
+    server
+        .ws
+        .write_all(&frame(FIN | CONTINUATION, b"data"))
+        .await
+        .unwrap();
+    expect_failure(server, 1002).await;
+
+    // new message before the previous one is finished
+    let mut server = serve(None);
+    server.ws.write_all(&frame(BINARY, b"first")).await.unwrap();
+    server
+        .ws
+        .write_all(&frame(FIN | BINARY, b"second"))
+        .await
+        .unwrap();
+    expect_failure(server, 1002).await;
+}
+
+#[tokio::test]
+async fn test_close() {
+    let mut server = serve(None);
+
+    let mut payload = 1000u16.to_be_bytes().to_vec();
+    payload.extend_from_slice(b"bye");
+    server
+        .ws
+        .write_all(&frame(FIN | CLOSE, &payload))
+        .await
+        .unwrap();
+
+    assert_eq!(read_frame(&mut server.ws).await, (FIN | CLOSE, payload));
+    server.task.await.unwrap().unwrap();
+
+    // closing the data side sends a normal close
+    let mut server = serve(None);
+    drop(server.data);
+    assert_eq!(
+        read_frame(&mut server.ws).await,
+        (FIN | CLOSE, 1000u16.to_be_bytes().to_vec())
+    );
+    server.task.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_deflate_negotiation() {
+    let server = serve(Some("x-webkit-deflate-frame, permessage-deflate"));
+    assert_eq!(server.extensions.as_deref(), Some("permessage-deflate"));
+
+    let server = serve(Some(
+        "permessage-deflate; client_max_window_bits; server_no_context_takeover",
+    ));
+    assert_eq!(
+        server.extensions.as_deref(),
+        Some("permessage-deflate; server_no_context_takeover")
+    );
+
+    // smaller windows are not supported, the second offer is used
+    let server = serve(Some(
+        "permessage-deflate; server_max_window_bits=10, permessage-deflate",
+    ));
+    assert_eq!(server.extensions.as_deref(), Some("permessage-deflate"));
+
+    let server = serve(Some("permessage-deflate; unknown_parameter"));
+    assert_eq!(server.extensions, None);
+
+    let (ws, response) = WebSocket::with_deflate(
+        upgrade_headers(Some("permessage-deflate")),
+        Some(DeflateConfig {
+            server_no_context_takeover: true,
+            client_no_context_takeover: true,
+        }),
+    )
+    .unwrap();
+    assert_eq!(
+        response.headers()[SEC_WEBSOCKET_EXTENSIONS],
+        "permessage-deflate; server_no_context_takeover; client_no_context_takeover"
+    );
+    assert!(ws.deflate().unwrap().client_no_context_takeover);
+
+    let (ws, response) =
+        WebSocket::with_deflate(upgrade_headers(Some("permessage-deflate")), None).unwrap();
+    assert!(response.headers().get(SEC_WEBSOCKET_EXTENSIONS).is_none());
+    assert!(ws.deflate().is_none());
+}
+
+#[tokio::test]
+async fn test_deflate_rfc7692_examples() {
+    let mut server = serve(Some("permessage-deflate"));
+    assert!(server.extensions.is_some());
+
+    // RFC7692 section 7.2.3.1 and 7.2.3.2, "Hello" twice, with a shared context
+    let hello = [0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00];
+    server
+        .ws
+        .write_all(&frame(FIN | RSV1 | TEXT, &hello))
+        .await
+        .unwrap();
+    server
+        .ws
+        .write_all(&frame(FIN | RSV1 | TEXT, &[0xf2, 0x00, 0x11, 0x00, 0x00]))
+        .await
+        .unwrap();
+    assert_eq!(read_data(&mut server.data, 10).await, b"HelloHello");
+
+    // section 7.2.3.3, a deflate block with no compression
+    server
+        .ws
+        .write_all(&frame(
+            FIN | RSV1 | TEXT,
+            &[
+                0x00, 0x05, 0x00, 0xfa, 0xff, 0x48, 0x65, 0x6c, 0x6c, 0x6f, 0x00,
+            ],
+        ))
+        .await
+        .unwrap();
+    assert_eq!(read_data(&mut server.data, 5).await, b"Hello");
+
+    // section 7.2.3.1, a fragmented compressed message
+    server
+        .ws
+        .write_all(&frame(RSV1 | TEXT, &hello[..3]))
+        .await
+        .unwrap();
+    server
+        .ws
+        .write_all(&frame(FIN | CONTINUATION, &hello[3..]))
+        .await
+        .unwrap();
+    assert_eq!(read_data(&mut server.data, 5).await, b"Hello");
+
+    // uncompressed messages are still allowed
+    server
+        .ws
+        .write_all(&frame(FIN | TEXT, b"Hello"))
+        .await
+        .unwrap();
+    assert_eq!(read_data(&mut server.data, 5).await, b"Hello");
+
+    // compressed control frames are not
+    server
+        .ws
+        .write_all(&frame(FIN | RSV1 | PING, &hello))
+        .await
+        .unwrap();
+    expect_failure(server, 1002).await;
+}
+
+#[tokio::test]
+async fn test_deflate_context_takeover() {
+    for (offer, server_takeover, client_takeover) in [
+        ("permessage-deflate", true, true),
+        (
+            "permessage-deflate; server_no_context_takeover",
+            false,
+            true,
+        ),
+        (
+            "permessage-deflate; client_no_context_takeover",
+            true,
+            false,
+        ),
+        (
+            "permessage-deflate; server_no_context_takeover; client_no_context_takeover",
+            false,
+            false,
+        ),
+    ] {
+        let mut server = serve(Some(offer));
+        assert_eq!(server.extensions.as_deref(), Some(offer));
+
+        let mut compress = flate2::Compress::new(Compression::default(), false);
+        let mut decompress = flate2::Decompress::new(false);
+
+        let message: Vec<u8> = b"permessage-deflate ".repeat(100);
+        for _ in 0..3 {
+            if !client_takeover {
+                compress.reset();
+            }
+            let data = deflate(&mut compress, &message);
+            server
+                .ws
+                .write_all(&frame(FIN | RSV1 | BINARY, &data))
+                .await
+                .unwrap();
+            assert_eq!(read_data(&mut server.data, message.len()).await, message);
+
+            server.data.write_all(&message).await.unwrap();
+            let (first, data) = read_frame(&mut server.ws).await;
+            assert_eq!(first, FIN | RSV1 | BINARY);
+            if !server_takeover {
+                decompress.reset(false);
+            }
+            assert_eq!(inflate(&mut decompress, &data), message);
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_deflate_large_message() {
+    let mut server = serve(Some("permessage-deflate"));
+
+    // compresses well, so a single small frame expands to a lot of data
+    let message = vec![b'x'; 1 << 20];
+    let mut compress = flate2::Compress::new(Compression::best(), false);
+    let data = deflate(&mut compress, &message);
+    assert!(data.len() < 8192);
+
+    server
+        .ws
+        .write_all(&frame(FIN | RSV1 | BINARY, &data))
+        .await
+        .unwrap();
+    assert_eq!(read_data(&mut server.data, message.len()).await, message);
+
+    // invalid deflate data fails the connection
+    server
+        .ws
+        .write_all(&frame(FIN | RSV1 | BINARY, &[0xff, 0xff, 0xff, 0xff]))
+        .await
+        .unwrap();
+    expect_failure(server, 1002).await;
+}
+
+#[tokio::test]
+async fn test_client_connect() {
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request};
+
+    let service = make_service_fn(|_| async {
+        Ok::<_, Error>(service_fn(|mut request: Request<Body>| async move {
+            let (ws, response) = WebSocket::new(request.headers().clone())?;
+            tokio::spawn(async move {
+                let upgraded = hyper::upgrade::on(&mut request).await.unwrap();
+                // echo server
+                let (local, remote) = duplex(1 << 16);
+                let (mut reader, mut writer) = tokio::io::split(remote);
+                tokio::spawn(async move { tokio::io::copy(&mut reader, &mut writer).await });
+                ws.serve_connection(upgraded, local).await
+            });
+            Ok::<_, Error>(response)
+        }))
+    });
+
+    let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(service);
+    let port = server.local_addr().port();
+    tokio::spawn(server);
+
+    let client = proxmox_http::client::Client::new();
+    let uri = format!("ws://127.0.0.1:{port}/");
+
+    for deflate in [
+        None,
+        Some(DeflateConfig::default()),
+        Some(DeflateConfig {
+            server_no_context_takeover: true,
+            client_no_context_takeover: true,
+        }),
+    ] {
+        let (ws, upgraded) = WebSocket::connect(&client, &uri, deflate).await.unwrap();
+        assert_eq!(ws.deflate(), deflate);
+
+        let (mut local, remote) = duplex(1 << 16);
+        let task = tokio::spawn(async move { ws.serve_connection(upgraded, remote).await });
+
+        for message in [&b"Hello"[..], &[0x42; 50_000]] {
+            local.write_all(message).await.unwrap();
+            assert_eq!(read_data(&mut local, message.len()).await, message);
+        }
+
+        drop(local);
+        task.await.unwrap().unwrap();
+    }
+
+    // no websocket server
+    let result = WebSocket::connect(&client, "ws://127.0.0.1:1/", None).await;
+    assert!(result.is_err());
+    let result = WebSocket::connect(&client, "ftp://127.0.0.1/", None).await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn test_http2_extended_connect() {
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Method, Request, StatusCode};
+
+    let service = make_service_fn(|_| async {
+        Ok::<_, Error>(service_fn(|request: Request<Body>| async move {
+            let (parts, body) = request.into_parts();
+            let (ws, response) =
+                WebSocket::from_request_parts(&parts, Some(DeflateConfig::default()))?;
+            let mut request = Request::from_parts(parts, body);
+            tokio::spawn(async move {
+                let upgraded = hyper::upgrade::on(&mut request).await.unwrap();
+                let (local, remote) = duplex(1 << 16);
+                let (mut reader, mut writer) = tokio::io::split(remote);
+                tokio::spawn(async move { tokio::io::copy(&mut reader, &mut writer).await });
+                ws.serve_connection(upgraded, local).await
+            });
+            Ok::<_, Error>(response)
+        }))
+    });
+
+    let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into())
+        .http2_only(true)
+        .http2_enable_connect_protocol()
+        .serve(service);
+    let port = server.local_addr().port();
+    tokio::spawn(server);
+
+    let client = hyper::Client::builder()
+        .http2_only(true)
+        .build_http::<Body>();
+
+    for extensions in [None, Some("permessage-deflate")] {
+        let mut request = Request::builder()
+            .method(Method::CONNECT)
+            .uri(format!("http://127.0.0.1:{port}/"))
+            .extension(hyper::ext::Protocol::from_static("websocket"))
+            .header(SEC_WEBSOCKET_VERSION, "13");
+        if let Some(extensions) = extensions {
+            request = request.header(SEC_WEBSOCKET_EXTENSIONS, extensions);
+        }
+
+        let response = client
+            .request(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response
+                .headers()
+                .get(SEC_WEBSOCKET_EXTENSIONS)
+                .map(|value| value.to_str().unwrap()),
+            extensions
+        );
+
+        let mut upgraded = hyper::upgrade::on(response).await.unwrap();
+        upgraded
+            .write_all(&frame(FIN | BINARY, b"Hello"))
+            .await
+            .unwrap();
+
+        let (first, data) = read_frame(&mut upgraded).await;
+        if extensions.is_some() {
+            assert_eq!(first, FIN | RSV1 | BINARY);
+            let mut decompress = flate2::Decompress::new(false);
+            assert_eq!(inflate(&mut decompress, &data), b"Hello");
+        } else {
+            assert_eq!((first, data), (FIN | BINARY, b"Hello".to_vec()));
+        }
+    }
+
+    // a different protocol is rejected
+    let request = Request::builder()
+        .method(Method::CONNECT)
+        .uri(format!("http://127.0.0.1:{port}/"))
+        .extension(hyper::ext::Protocol::from_static("other"))
+        .header(SEC_WEBSOCKET_VERSION, "13")
+        .body(Body::empty())
+        .unwrap();
+    assert!(client.request(request).await.is_err());
+}
@@ -139,7 +139,13 @@ impl std::str::FromStr for Ticket {
 
         let s = &s[(timestamp_len + 1)..];
 
-        let s = s.strip_prefix(':').ok_or(TicketError)?;
+        // optional key id, empty for tickets without one
+        let key_id_len = s.find(':').ok_or(TicketError)?;
+        if !s[..key_id_len].bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(TicketError);
+        }
+
+        let s = &s[(key_id_len + 1)..];
         if s.is_empty() {
             return Err(TicketError);
         }
@@ -2,11 +2,10 @@ use std::sync::Arc;
 
 use anyhow::{bail, Error};
 use hyper::Body;
-use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
 use tokio::sync::mpsc;
 
 use proxmox_http::client::Client;
-use proxmox_http::HttpOptions;
+use proxmox_http::{HttpOptions, TlsConfig};
 
 use crate::influxdb::utils;
 use crate::{Metrics, MetricsData};
@@ -76,13 +75,11 @@ impl InfluxDbHttp {
         max_body_size: usize,
         channel: mpsc::Receiver<Arc<MetricsData>>,
     ) -> Result<Self, Error> {
-        let client = if verify_tls {
-            Client::with_options(HttpOptions::default())
-        } else {
-            let mut ssl_connector = SslConnector::builder(SslMethod::tls()).unwrap();
-            ssl_connector.set_verify(SslVerifyMode::NONE);
-            Client::with_ssl_connector(ssl_connector.build(), HttpOptions::default())
+        let tls_config = TlsConfig {
+            insecure: !verify_tls,
+            ..Default::default()
         };
+        let client = Client::with_tls_config(&tls_config, HttpOptions::default())?;
 
         let (writeuri, healthuri) = Self::create_uris(uri, organization, bucket)?;
 
@@ -1,13 +1,99 @@
-use anyhow::{bail, Error};
+use anyhow::{bail, format_err, Error};
 
 use proxmox_config_digest::ConfigDigest;
 
 use crate::{parse_vlan_id_from_name, parse_vlan_raw_device_from_name};
 use crate::{
-    DeletableInterfaceProperty, Interface, InterfaceUpdater, LinuxBondMode, NetworkConfigMethod,
-    NetworkInterfaceType,
+    DeletableInterfaceProperty, Interface, InterfaceUpdater, LinuxBondMode, NetworkConfig,
+    NetworkConfigMethod, NetworkInterfaceType,
 };
 
+/// Attach the ports listed on an OVS bridge, physical interfaces are converted to OVS ports.
+///
+/// OVS ports which were removed from the list are converted back to physical interfaces. Internal
+/// ports, bonds and patch ports cannot exist without a bridge, so they cannot be removed from the
+/// list.
+fn sync_ovs_bridge_ports(
+    config: &mut NetworkConfig,
+    bridge: &str,
+    old_ports: &[String],
+) -> Result<(), Error> {
+    let ports = config.lookup(bridge)?.ovs_ports.clone().unwrap_or_default();
+
+    for port in old_ports.iter().filter(|port| !ports.contains(port)) {
+        let Some(interface) = config.interfaces.get_mut(port) else {
+            continue;
+        };
+        if interface.ovs_bridge.as_deref() != Some(bridge) {
+            continue;
+        }
+        match interface.interface_type {
+            NetworkInterfaceType::OvsPort => {
+                interface.interface_type = NetworkInterfaceType::Eth;
+                interface.ovs_bridge = None;
+                interface.ovs_options = None;
+            }
+            NetworkInterfaceType::OvsIntPort
+            | NetworkInterfaceType::OvsBond
+            | NetworkInterfaceType::OvsPatchPort => bail!(
+                "ovs-ports - {:?} '{}' cannot be detached, \
+                 delete it or assign it to another bridge",
+                interface.interface_type,
+                port
+            ),
+            _ => {}
+        }
+    }
+
+    for port in ports.iter() {
+        let interface = config
+            .interfaces
+            .get_mut(port)
+            .ok_or_else(|| format_err!("ovs-ports - interface '{}' does not exist", port))?;
+        match interface.interface_type {
+            NetworkInterfaceType::Eth => interface.interface_type = NetworkInterfaceType::OvsPort,
+            NetworkInterfaceType::OvsPort
+            | NetworkInterfaceType::OvsIntPort
+            | NetworkInterfaceType::OvsBond
+            | NetworkInterfaceType::OvsPatchPort => {}
+            other => bail!(
+                "ovs-ports - interface '{}' has wrong type {:?}",
+                port,
+                other
+            ),
+        }
+        interface.ovs_bridge = Some(bridge.to_string());
+    }
+
+    Ok(())
+}
+
+/// Move an OVS port to another bridge, updating the port lists of both bridges.
+fn set_ovs_port_bridge(config: &mut NetworkConfig, port: &str, bridge: &str) -> Result<(), Error> {
+    match config.interfaces.get(bridge) {
+        Some(entry) if entry.interface_type == NetworkInterfaceType::OvsBridge => {}
+        _ => bail!("ovs-bridge {bridge} does not exist"),
+    }
+
+    for (name, interface) in config.interfaces.iter_mut() {
+        if interface.interface_type != NetworkInterfaceType::OvsBridge {
+            continue;
+        }
+        if name == bridge {
+            let ports = interface.ovs_ports.get_or_insert_with(Vec::new);
+            if !ports.iter().any(|p| p == port) {
+                ports.push(port.to_string());
+            }
+        } else if let Some(ports) = &mut interface.ovs_ports {
+            ports.retain(|p| p != port);
+        }
+    }
+
+    config.lookup_mut(port)?.ovs_bridge = Some(bridge.to_string());
+
+    Ok(())
+}
+
 /// Create network interface configuration.
 pub fn create_interface(iface: String, config: InterfaceUpdater) -> Result<(), Error> {
     let interface_type = match config.interface_type {
@@ -127,6 +213,41 @@ pub fn create_interface(iface: String, config: InterfaceUpdater) -> Result<(), E
             }
             interface.vlan_raw_device = config.vlan_raw_device;
         }
+        NetworkInterfaceType::Vxlan => {
+            if config.vxlan_id.is_none() {
+                bail!("vxlan-id must be set");
+            }
+            interface.vxlan_id = config.vxlan_id;
+            interface.vxlan_local_tunnelip = config.vxlan_local_tunnelip;
+            interface.vxlan_svcnodeip = config.vxlan_svcnodeip;
+            if let Some(list) = &config.vxlan_remoteip {
+                interface.set_vxlan_remoteip_list(list)?;
+            }
+            if let Some(dev) = &config.vxlan_physdev {
+                if !network_config.interfaces.contains_key(dev) {
+                    bail!("vxlan-physdev {dev} does not exist");
+                }
+            }
+            interface.vxlan_physdev = config.vxlan_physdev;
+        }
+        NetworkInterfaceType::WireGuard => {
+            if config.wireguard_config.is_none() {
+                bail!("wireguard-config must be set");
+            }
+            interface.wireguard_config = config.wireguard_config;
+        }
+        NetworkInterfaceType::OvsBridge => {
+            if let Some(ports) = &config.ovs_ports {
+                interface.set_ovs_port_list(ports)?;
+            }
+            interface.ovs_options = config.ovs_options;
+        }
+        NetworkInterfaceType::OvsPort | NetworkInterfaceType::OvsIntPort => {
+            if config.ovs_bridge.is_none() {
+                bail!("ovs-bridge must be set");
+            }
+            interface.ovs_options = config.ovs_options;
+        }
         _ => bail!(
             "creating network interface type '{:?}' is not supported",
             interface_type
@@ -145,7 +266,17 @@ pub fn create_interface(iface: String, config: InterfaceUpdater) -> Result<(), E
         interface.method6 = Some(NetworkConfigMethod::Manual);
     }
 
-    network_config.interfaces.insert(iface, interface);
+    network_config.interfaces.insert(iface.clone(), interface);
+
+    match interface_type {
+        NetworkInterfaceType::OvsBridge => sync_ovs_bridge_ports(&mut network_config, &iface, &[])?,
+        NetworkInterfaceType::OvsPort | NetworkInterfaceType::OvsIntPort => {
+            if let Some(bridge) = &config.ovs_bridge {
+                set_ovs_port_bridge(&mut network_config, &iface, bridge)?;
+            }
+        }
+        _ => {}
+    }
 
     crate::save_config(&network_config)?;
 
@@ -184,6 +315,8 @@ pub fn update_interface(
 
     let interface = network_config.lookup_mut(&iface)?;
 
+    let old_ovs_ports = interface.ovs_ports.clone();
+
     if let Some(interface_type) = update.interface_type {
         if interface_type != interface.interface_type {
             bail!(
@@ -242,6 +375,24 @@ pub fn update_interface(
                 DeletableInterfaceProperty::BondXmitHashPolicy => {
                     interface.bond_xmit_hash_policy = None
                 }
+                DeletableInterfaceProperty::VxlanLocalTunnelip => {
+                    interface.vxlan_local_tunnelip = None;
+                }
+                DeletableInterfaceProperty::VxlanRemoteip => {
+                    interface.vxlan_remoteip = None;
+                }
+                DeletableInterfaceProperty::VxlanSvcnodeip => {
+                    interface.vxlan_svcnodeip = None;
+                }
+                DeletableInterfaceProperty::VxlanPhysdev => {
+                    interface.vxlan_physdev = None;
+                }
+                DeletableInterfaceProperty::OvsPorts => {
+                    interface.set_ovs_ports(Vec::new())?;
+                }
+                DeletableInterfaceProperty::OvsOptions => {
+                    interface.ovs_options = None;
+                }
             }
         }
     }
@@ -341,7 +492,132 @@ pub fn update_interface(
         interface.vlan_raw_device = update.vlan_raw_device;
     }
 
+    if update.vxlan_id.is_some() {
+        interface.check_interface_type(NetworkInterfaceType::Vxlan, "vxlan-id")?;
+        interface.vxlan_id = update.vxlan_id;
+    }
+    if update.vxlan_local_tunnelip.is_some() {
+        interface.check_interface_type(NetworkInterfaceType::Vxlan, "vxlan-local-tunnelip")?;
+        interface.vxlan_local_tunnelip = update.vxlan_local_tunnelip;
+    }
+    if let Some(list) = &update.vxlan_remoteip {
+        interface.set_vxlan_remoteip_list(list)?;
+    }
+    if update.vxlan_svcnodeip.is_some() {
+        interface.check_interface_type(NetworkInterfaceType::Vxlan, "vxlan-svcnodeip")?;
+        interface.vxlan_svcnodeip = update.vxlan_svcnodeip;
+    }
+    if update.vxlan_physdev.is_some() {
+        interface.check_interface_type(NetworkInterfaceType::Vxlan, "vxlan-physdev")?;
+        interface.vxlan_physdev = update.vxlan_physdev;
+    }
+    if update.wireguard_config.is_some() {
+        interface.check_interface_type(NetworkInterfaceType::WireGuard, "wireguard-config")?;
+        interface.wireguard_config = update.wireguard_config;
+    }
+    if let Some(ports) = &update.ovs_ports {
+        interface.set_ovs_port_list(ports)?;
+    }
+    if update.ovs_options.is_some() {
+        if interface.interface_type != NetworkInterfaceType::OvsBridge && !interface.is_ovs_port() {
+            bail!("ovs-options is only valid for OVS interfaces");
+        }
+        interface.ovs_options = update.ovs_options;
+    }
+    if update.ovs_bridge.is_some() && !interface.is_ovs_port() {
+        bail!("ovs-bridge is only valid for OVS ports");
+    }
+
+    if interface.interface_type == NetworkInterfaceType::OvsBridge
+        && interface.ovs_ports != old_ovs_ports
+    {
+        let old_ovs_ports = old_ovs_ports.unwrap_or_default();
+        sync_ovs_bridge_ports(&mut network_config, &iface, &old_ovs_ports)?;
+    }
+    if let Some(bridge) = &update.ovs_bridge {
+        set_ovs_port_bridge(&mut network_config, &iface, bridge)?;
+    }
+
     crate::save_config(&network_config)?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::NetworkParser;
+
+    const OVS_CONFIG: &str = "\
+auto eno1
+iface eno1 inet manual
+\tovs_type OVSPort
+\tovs_bridge vmbr1
+\tovs_options tag=20 vlan_mode=access
+
+auto eno2
+iface eno2 inet manual
+
+auto vmbr1
+iface vmbr1 inet manual
+\tovs_type OVSBridge
+\tovs_ports eno1 mgmt
+
+auto mgmt
+iface mgmt inet static
+\taddress 10.0.0.2/24
+\tovs_type OVSIntPort
+\tovs_bridge vmbr1
+
+";
+
+    fn parse(input: &str) -> NetworkConfig {
+        NetworkParser::new(input.as_bytes())
+            .parse_interfaces(None)
+            .unwrap()
+    }
+
+    fn set_ports(config: &mut NetworkConfig, ports: &[&str]) -> Result<(), Error> {
+        let bridge = config.lookup_mut("vmbr1").unwrap();
+        let old_ports = bridge.ovs_ports.take().unwrap_or_default();
+        bridge.ovs_ports = Some(ports.iter().map(|port| port.to_string()).collect());
+        sync_ovs_bridge_ports(config, "vmbr1", &old_ports)
+    }
+
+    #[test]
+    fn test_ovs_remove_port() {
+        let mut config = parse(OVS_CONFIG);
+        set_ports(&mut config, &["mgmt"]).unwrap();
+
+        let output = String::try_from(config).unwrap();
+        let config = parse(&output);
+
+        let eno1 = config.interfaces.get("eno1").unwrap();
+        assert_eq!(eno1.interface_type, NetworkInterfaceType::Eth);
+        assert_eq!(eno1.ovs_bridge, None);
+        assert_eq!(eno1.ovs_options, None);
+        assert!(!output.contains("ovs_options"));
+
+        assert_eq!(
+            config.interfaces.get("vmbr1").unwrap().ovs_ports,
+            Some(vec!["mgmt".to_string()])
+        );
+
+        // and attach it again
+        let mut config = config;
+        set_ports(&mut config, &["mgmt", "eno1", "eno2"]).unwrap();
+        let config = parse(&String::try_from(config).unwrap());
+        for port in ["eno1", "eno2"] {
+            let port = config.interfaces.get(port).unwrap();
+            assert_eq!(port.interface_type, NetworkInterfaceType::OvsPort);
+            assert_eq!(port.ovs_bridge.as_deref(), Some("vmbr1"));
+        }
+    }
+
+    #[test]
+    fn test_ovs_remove_int_port() {
+        let mut config = parse(OVS_CONFIG);
+        assert!(set_ports(&mut config, &["eno1"]).is_err());
+    }
+}
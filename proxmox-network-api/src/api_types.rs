@@ -6,14 +6,15 @@ use serde::{Deserialize, Serialize};
 use lazy_static::lazy_static;
 use regex::Regex;
 
-use proxmox_schema::api;
 use proxmox_schema::api_types::SAFE_ID_REGEX;
 use proxmox_schema::api_types::{CIDR_V4_SCHEMA, CIDR_V6_SCHEMA};
-use proxmox_schema::api_types::{IP_V4_SCHEMA, IP_V6_SCHEMA};
+use proxmox_schema::api_types::{IP_SCHEMA, IP_V4_SCHEMA, IP_V6_SCHEMA};
 use proxmox_schema::ApiStringFormat;
 use proxmox_schema::ArraySchema;
+use proxmox_schema::IntegerSchema;
 use proxmox_schema::Schema;
 use proxmox_schema::StringSchema;
+use proxmox_schema::{api, const_regex};
 
 lazy_static! {
     pub static ref PHYSICAL_NIC_REGEX: Regex = Regex::new(r"^(?:eth\d+|en[^:.]+|ib\d+)$").unwrap();
@@ -21,6 +22,11 @@ lazy_static! {
         Regex::new(r"^(?P<vlan_raw_device>\S+)\.(?P<vlan_id>\d+)|vlan(?P<vlan_id2>\d+)$").unwrap();
 }
 
+const_regex! {
+    /// Absolute path without whitespace, as it is used in shell commands.
+    pub WIREGUARD_CONFIG_REGEX = r"^/[^\s]+$";
+}
+
 pub const NETWORK_INTERFACE_FORMAT: ApiStringFormat = ApiStringFormat::Pattern(&SAFE_ID_REGEX);
 
 #[api()]
@@ -118,6 +124,25 @@ pub enum NetworkInterfaceType {
     Vlan,
     /// Interface Alias (eth:1)
     Alias,
+    /// VXLAN overlay interface
+    Vxlan,
+    /// WireGuard tunnel
+    WireGuard,
+    /// Open vSwitch bridge
+    #[serde(rename = "OVSBridge")]
+    OvsBridge,
+    /// Open vSwitch port (e.g. a physical interface attached to an OVS bridge)
+    #[serde(rename = "OVSPort")]
+    OvsPort,
+    /// Open vSwitch internal port
+    #[serde(rename = "OVSIntPort")]
+    OvsIntPort,
+    /// Open vSwitch bond, attached to an OVS bridge
+    #[serde(rename = "OVSBond")]
+    OvsBond,
+    /// Open vSwitch patch port, connecting two OVS bridges
+    #[serde(rename = "OVSPatchPort")]
+    OvsPatchPort,
     /// Unknown interface type
     Unknown,
 }
@@ -138,6 +163,23 @@ pub const NETWORK_INTERFACE_LIST_SCHEMA: Schema =
         ))
         .schema();
 
+pub const VXLAN_ID_SCHEMA: Schema = IntegerSchema::new("VXLAN network identifier (VNI).")
+    .minimum(1)
+    .maximum(16777215)
+    .schema();
+
+pub const IP_ARRAY_SCHEMA: Schema = ArraySchema::new("IP address list.", &IP_SCHEMA).schema();
+
+pub const IP_LIST_SCHEMA: Schema = StringSchema::new("A list of IP addresses, comma separated.")
+    .format(&ApiStringFormat::PropertyString(&IP_ARRAY_SCHEMA))
+    .schema();
+
+pub const WIREGUARD_CONFIG_SCHEMA: Schema =
+    StringSchema::new("Path to the WireGuard configuration file (see wg(8)).")
+        .format(&ApiStringFormat::Pattern(&WIREGUARD_CONFIG_REGEX))
+        .max_length(4096)
+        .schema();
+
 #[api(
     properties: {
         name: {
@@ -225,6 +267,51 @@ pub const NETWORK_INTERFACE_LIST_SCHEMA: Schema =
             type: BondXmitHashPolicy,
             optional: true,
         },
+        "vxlan-id": {
+            schema: VXLAN_ID_SCHEMA,
+            optional: true,
+        },
+        "vxlan-local-tunnelip": {
+            schema: IP_SCHEMA,
+            optional: true,
+        },
+        "vxlan-remoteip": {
+            schema: IP_ARRAY_SCHEMA,
+            optional: true,
+        },
+        "vxlan-svcnodeip": {
+            schema: IP_SCHEMA,
+            optional: true,
+        },
+        "vxlan-physdev": {
+            schema: NETWORK_INTERFACE_NAME_SCHEMA,
+            optional: true,
+        },
+        "wireguard-config": {
+            schema: WIREGUARD_CONFIG_SCHEMA,
+            optional: true,
+        },
+        "ovs-bridge": {
+            schema: NETWORK_INTERFACE_NAME_SCHEMA,
+            optional: true,
+        },
+        "ovs-ports": {
+            schema: NETWORK_INTERFACE_ARRAY_SCHEMA,
+            optional: true,
+        },
+        "ovs-bonds": {
+            schema: NETWORK_INTERFACE_ARRAY_SCHEMA,
+            optional: true,
+        },
+        "ovs-patch-peer": {
+            schema: NETWORK_INTERFACE_NAME_SCHEMA,
+            optional: true,
+        },
+        "ovs-options": {
+            description: "Additional Open vSwitch options.",
+            type: String,
+            optional: true,
+        },
     }
 )]
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -291,6 +378,44 @@ pub struct Interface {
     pub bond_primary: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub bond_xmit_hash_policy: Option<BondXmitHashPolicy>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "vxlan-id")]
+    pub vxlan_id: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "vxlan-local-tunnelip")]
+    pub vxlan_local_tunnelip: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "vxlan-remoteip")]
+    pub vxlan_remoteip: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "vxlan-svcnodeip")]
+    pub vxlan_svcnodeip: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "vxlan-physdev")]
+    pub vxlan_physdev: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "wireguard-config")]
+    pub wireguard_config: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "ovs-bridge")]
+    pub ovs_bridge: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "ovs-ports")]
+    pub ovs_ports: Option<Vec<String>>,
+    /// The interfaces of an OVS bond.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "ovs-bonds")]
+    pub ovs_bonds: Option<Vec<String>>,
+    /// The peer of an OVS patch port.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "ovs-patch-peer")]
+    pub ovs_patch_peer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "ovs-options")]
+    pub ovs_options: Option<String>,
 }
 
 impl Interface {
@@ -319,7 +444,47 @@ impl Interface {
             bond_mode: None,
             bond_primary: None,
             bond_xmit_hash_policy: None,
+            vxlan_id: None,
+            vxlan_local_tunnelip: None,
+            vxlan_remoteip: None,
+            vxlan_svcnodeip: None,
+            vxlan_physdev: None,
+            wireguard_config: None,
+            ovs_bridge: None,
+            ovs_ports: None,
+            ovs_bonds: None,
+            ovs_patch_peer: None,
+            ovs_options: None,
+        }
+    }
+
+    /// Bail out if the interface is not of the expected type.
+    pub fn check_interface_type(
+        &self,
+        interface_type: NetworkInterfaceType,
+        property: &str,
+    ) -> Result<(), Error> {
+        if self.interface_type != interface_type {
+            bail!(
+                "interface '{}' - '{}' is only valid for {:?} interfaces (type is {:?})",
+                self.name,
+                property,
+                interface_type,
+                self.interface_type
+            );
         }
+        Ok(())
+    }
+
+    /// Returns true for Open vSwitch ports attached to a bridge, including bonds and patch ports.
+    pub fn is_ovs_port(&self) -> bool {
+        matches!(
+            self.interface_type,
+            NetworkInterfaceType::OvsPort
+                | NetworkInterfaceType::OvsIntPort
+                | NetworkInterfaceType::OvsBond
+                | NetworkInterfaceType::OvsPatchPort
+        )
     }
 
     /// Setter for bridge ports (check if interface type is a bridge)
@@ -360,6 +525,33 @@ impl Interface {
         self.set_bond_slaves(slaves)
     }
 
+    /// Setter for OVS bridge ports (check if interface type is an OVS bridge)
+    pub fn set_ovs_ports(&mut self, ports: Vec<String>) -> Result<(), Error> {
+        self.check_interface_type(NetworkInterfaceType::OvsBridge, "ovs-ports")?;
+        self.ovs_ports = Some(ports);
+        Ok(())
+    }
+
+    /// Setter for OVS bridge ports (check if interface type is an OVS bridge)
+    pub fn set_ovs_port_list(&mut self, ports: &str) -> Result<(), Error> {
+        let ports = Self::split_interface_list(ports)?;
+        self.set_ovs_ports(ports)
+    }
+
+    /// Setter for VXLAN remote IPs (check if interface type is VXLAN)
+    pub fn set_vxlan_remoteip_list(&mut self, list: &str) -> Result<(), Error> {
+        self.check_interface_type(NetworkInterfaceType::Vxlan, "vxlan-remoteip")?;
+        let value = IP_ARRAY_SCHEMA.parse_property_string(list)?;
+        let ips = value
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|v| v.as_str().unwrap().to_string())
+            .collect();
+        self.vxlan_remoteip = Some(ips);
+        Ok(())
+    }
+
     /// Split a network interface list into an array of interface names.
     pub fn split_interface_list(list: &str) -> Result<Vec<String>, Error> {
         let value = NETWORK_INTERFACE_ARRAY_SCHEMA.parse_property_string(list)?;
@@ -410,6 +602,18 @@ pub enum DeletableInterfaceProperty {
     /// Delete bond transmit hash policy
     #[serde(rename = "bond_xmit_hash_policy")]
     BondXmitHashPolicy,
+    /// Delete the VXLAN local tunnel IP
+    VxlanLocalTunnelip,
+    /// Delete the VXLAN remote IP list
+    VxlanRemoteip,
+    /// Delete the VXLAN service node IP
+    VxlanSvcnodeip,
+    /// Delete the VXLAN physical device
+    VxlanPhysdev,
+    /// Delete OVS bridge ports
+    OvsPorts,
+    /// Delete additional OVS options
+    OvsOptions,
 }
 
 #[api(
@@ -498,6 +702,43 @@ pub enum DeletableInterfaceProperty {
                 schema: NETWORK_INTERFACE_LIST_SCHEMA,
                 optional: true,
             },
+            "vxlan-id": {
+                schema: VXLAN_ID_SCHEMA,
+                optional: true,
+            },
+            "vxlan-local-tunnelip": {
+                schema: IP_SCHEMA,
+                optional: true,
+            },
+            "vxlan-remoteip": {
+                schema: IP_LIST_SCHEMA,
+                optional: true,
+            },
+            "vxlan-svcnodeip": {
+                schema: IP_SCHEMA,
+                optional: true,
+            },
+            "vxlan-physdev": {
+                schema: NETWORK_INTERFACE_NAME_SCHEMA,
+                optional: true,
+            },
+            "wireguard-config": {
+                schema: WIREGUARD_CONFIG_SCHEMA,
+                optional: true,
+            },
+            "ovs-bridge": {
+                schema: NETWORK_INTERFACE_NAME_SCHEMA,
+                optional: true,
+            },
+            "ovs-ports": {
+                schema: NETWORK_INTERFACE_LIST_SCHEMA,
+                optional: true,
+            },
+            "ovs-options": {
+                description: "Additional Open vSwitch options.",
+                type: String,
+                optional: true,
+            },
         },
 )]
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -528,4 +769,54 @@ pub struct InterfaceUpdater {
     #[serde(rename = "bond_xmit_hash_policy")]
     pub bond_xmit_hash_policy: Option<BondXmitHashPolicy>,
     pub slaves: Option<String>,
+    pub vxlan_id: Option<u32>,
+    pub vxlan_local_tunnelip: Option<String>,
+    pub vxlan_remoteip: Option<String>,
+    pub vxlan_svcnodeip: Option<String>,
+    pub vxlan_physdev: Option<String>,
+    pub wireguard_config: Option<String>,
+    pub ovs_bridge: Option<String>,
+    pub ovs_ports: Option<String>,
+    pub ovs_options: Option<String>,
+}
+
+#[api()]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+/// Type of a pending network change
+pub enum NetworkChangeKind {
+    /// Interface is configured but does not exist
+    Add,
+    /// Interface exists but was removed from the configuration
+    Remove,
+    /// Interface property differs from the configured value
+    Modify,
+}
+
+#[api(
+    properties: {
+        interface: {
+            schema: NETWORK_INTERFACE_NAME_SCHEMA,
+        },
+        kind: {
+            type: NetworkChangeKind,
+        },
+    }
+)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Difference between the network configuration and the active network state.
+pub struct NetworkChange {
+    /// Interface name
+    pub interface: String,
+    pub kind: NetworkChangeKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Changed property (not set for added or removed interfaces)
+    pub property: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Currently active value
+    pub active: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Configured value, applied on the next network reload
+    pub pending: Option<String>,
 }
@@ -30,6 +30,18 @@ pub enum Token {
     BondMode,
     BondPrimary,
     BondXmitHashPolicy,
+    VxlanId,
+    VxlanLocalTunnelIp,
+    VxlanRemoteIp,
+    VxlanSvcNodeIp,
+    VxlanPhysDev,
+    OvsType,
+    OvsBridge,
+    OvsPorts,
+    OvsBonds,
+    OvsPatchPeer,
+    OvsOptions,
+    OvsMtu,
     EOF,
 }
 
@@ -63,6 +75,30 @@ lazy_static! {
         map.insert("bond_primary", Token::BondPrimary);
         map.insert("bond_xmit_hash_policy", Token::BondXmitHashPolicy);
         map.insert("bond-xmit-hash-policy", Token::BondXmitHashPolicy);
+        map.insert("vxlan-id", Token::VxlanId);
+        map.insert("vxlan_id", Token::VxlanId);
+        map.insert("vxlan-local-tunnelip", Token::VxlanLocalTunnelIp);
+        map.insert("vxlan_local_tunnelip", Token::VxlanLocalTunnelIp);
+        map.insert("vxlan-remoteip", Token::VxlanRemoteIp);
+        map.insert("vxlan_remoteip", Token::VxlanRemoteIp);
+        map.insert("vxlan-svcnodeip", Token::VxlanSvcNodeIp);
+        map.insert("vxlan_svcnodeip", Token::VxlanSvcNodeIp);
+        map.insert("vxlan-physdev", Token::VxlanPhysDev);
+        map.insert("vxlan_physdev", Token::VxlanPhysDev);
+        map.insert("ovs_type", Token::OvsType);
+        map.insert("ovs-type", Token::OvsType);
+        map.insert("ovs_bridge", Token::OvsBridge);
+        map.insert("ovs-bridge", Token::OvsBridge);
+        map.insert("ovs_ports", Token::OvsPorts);
+        map.insert("ovs-ports", Token::OvsPorts);
+        map.insert("ovs_bonds", Token::OvsBonds);
+        map.insert("ovs-bonds", Token::OvsBonds);
+        map.insert("ovs_patch_peer", Token::OvsPatchPeer);
+        map.insert("ovs-patch-peer", Token::OvsPatchPeer);
+        map.insert("ovs_options", Token::OvsOptions);
+        map.insert("ovs-options", Token::OvsOptions);
+        map.insert("ovs_mtu", Token::OvsMtu);
+        map.insert("ovs-mtu", Token::OvsMtu);
         map
     };
 }
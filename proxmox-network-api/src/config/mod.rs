@@ -1,8 +1,11 @@
 mod helper;
 mod lexer;
+mod netlink;
 mod parser;
+mod state;
 
 pub use helper::{assert_ifupdown2_installed, network_reload, parse_cidr};
+pub use state::{AddressState, BondSlaveState, LinkState, NetworkState, RouteState};
 
 use std::collections::{BTreeMap, HashMap, HashSet};
 use std::io::Write;
@@ -13,12 +16,13 @@ use regex::Regex;
 use serde::de::{value, Deserialize, IntoDeserializer};
 
 use super::{
-    BondXmitHashPolicy, Interface, LinuxBondMode, NetworkConfigMethod, NetworkInterfaceType,
+    BondXmitHashPolicy, Interface, LinuxBondMode, NetworkChange, NetworkConfigMethod,
+    NetworkInterfaceType,
 };
 
 use helper::compute_file_diff;
 use helper::get_network_interfaces;
-use parser::NetworkParser;
+pub(crate) use parser::NetworkParser;
 
 use proxmox_config_digest::ConfigDigest;
 use proxmox_product_config::{open_api_lockfile, replace_system_config, ApiLockGuard};
@@ -104,11 +108,74 @@ fn write_iface_attributes(iface: &Interface, w: &mut dyn Write) -> Result<(), Er
                 writeln!(w, "\tvlan-raw-device {vlan_raw_device}")?;
             }
         }
+        NetworkInterfaceType::Vxlan => {
+            if let Some(vxlan_id) = iface.vxlan_id {
+                writeln!(w, "\tvxlan-id {vxlan_id}")?;
+            }
+            if let Some(local_tunnelip) = &iface.vxlan_local_tunnelip {
+                writeln!(w, "\tvxlan-local-tunnelip {local_tunnelip}")?;
+            }
+            if let Some(svcnodeip) = &iface.vxlan_svcnodeip {
+                writeln!(w, "\tvxlan-svcnodeip {svcnodeip}")?;
+            }
+            for remoteip in iface.vxlan_remoteip.iter().flatten() {
+                writeln!(w, "\tvxlan-remoteip {remoteip}")?;
+            }
+            if let Some(physdev) = &iface.vxlan_physdev {
+                writeln!(w, "\tvxlan-physdev {physdev}")?;
+            }
+        }
+        NetworkInterfaceType::WireGuard => {
+            // no native ifupdown2 support, see parser::extract_wireguard_options
+            if let Some(config) = &iface.wireguard_config {
+                writeln!(w, "\tpre-up ip link add dev $IFACE type wireguard")?;
+                writeln!(w, "\tpre-up wg setconf $IFACE {config}")?;
+                writeln!(w, "\tpost-down ip link delete dev $IFACE")?;
+            }
+        }
+        NetworkInterfaceType::OvsBridge => {
+            writeln!(w, "\tovs_type OVSBridge")?;
+            let ports = iface.ovs_ports.as_ref().unwrap_or(&EMPTY_LIST);
+            if !ports.is_empty() {
+                writeln!(w, "\tovs_ports {}", ports.join(" "))?;
+            }
+        }
+        NetworkInterfaceType::OvsPort
+        | NetworkInterfaceType::OvsIntPort
+        | NetworkInterfaceType::OvsBond
+        | NetworkInterfaceType::OvsPatchPort => {
+            let ovs_type = match iface.interface_type {
+                NetworkInterfaceType::OvsPort => "OVSPort",
+                NetworkInterfaceType::OvsIntPort => "OVSIntPort",
+                NetworkInterfaceType::OvsBond => "OVSBond",
+                _ => "OVSPatchPort",
+            };
+            writeln!(w, "\tovs_type {ovs_type}")?;
+            if let Some(bridge) = &iface.ovs_bridge {
+                writeln!(w, "\tovs_bridge {bridge}")?;
+            }
+            if let Some(bonds) = &iface.ovs_bonds {
+                writeln!(w, "\tovs_bonds {}", bonds.join(" "))?;
+            }
+            if let Some(peer) = &iface.ovs_patch_peer {
+                writeln!(w, "\tovs_patch_peer {peer}")?;
+            }
+        }
         _ => {}
     }
 
+    if let Some(options) = &iface.ovs_options {
+        writeln!(w, "\tovs_options {options}")?;
+    }
+
     if let Some(mtu) = iface.mtu {
-        writeln!(w, "\tmtu {}", mtu)?;
+        match iface.interface_type {
+            NetworkInterfaceType::OvsBridge
+            | NetworkInterfaceType::OvsPort
+            | NetworkInterfaceType::OvsIntPort
+            | NetworkInterfaceType::OvsBond => writeln!(w, "\tovs_mtu {}", mtu)?,
+            _ => writeln!(w, "\tmtu {}", mtu)?,
+        }
     }
 
     Ok(())
@@ -335,6 +402,104 @@ impl NetworkConfig {
             if let Some(slaves) = &interface.slaves {
                 check_port_usage(iface, slaves)?;
             }
+            if let Some(ports) = &interface.ovs_ports {
+                check_port_usage(iface, ports)?;
+            }
+            if let Some(bonds) = &interface.ovs_bonds {
+                check_port_usage(iface, bonds)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that OVS bridges and their ports reference each other
+    fn check_ovs_ports(&self) -> Result<(), Error> {
+        for (iface, interface) in self.interfaces.iter() {
+            if interface.interface_type == NetworkInterfaceType::OvsBridge {
+                for port in interface.ovs_ports.iter().flatten() {
+                    match self.interfaces.get(port) {
+                        Some(entry) if entry.is_ovs_port() => {
+                            if entry.ovs_bridge.as_deref() != Some(iface) {
+                                bail!(
+                                    "OVS bridge '{}' - port '{}' is not assigned to this bridge",
+                                    iface,
+                                    port
+                                );
+                            }
+                        }
+                        Some(entry) => bail!(
+                            "OVS bridge '{}' - wrong interface type on port '{}' ({:?})",
+                            iface,
+                            port,
+                            entry.interface_type
+                        ),
+                        None => bail!("OVS bridge '{}' - unable to find port '{}'", iface, port),
+                    }
+                    self.check_mtu(iface, port)?;
+                }
+            } else if interface.is_ovs_port() {
+                if interface.interface_type == NetworkInterfaceType::OvsBond
+                    && interface.ovs_bonds.iter().flatten().next().is_none()
+                {
+                    bail!("OVS bond '{}' - missing ovs_bonds", iface);
+                }
+                let bridge_name = interface
+                    .ovs_bridge
+                    .as_deref()
+                    .ok_or_else(|| format_err!("OVS port '{}' - missing ovs_bridge", iface))?;
+                let listed = match self.interfaces.get(bridge_name) {
+                    Some(bridge) if bridge.interface_type == NetworkInterfaceType::OvsBridge => {
+                        bridge.ovs_ports.iter().flatten().any(|port| port == iface)
+                    }
+                    _ => bail!(
+                        "OVS port '{}' - unable to find OVS bridge '{}'",
+                        iface,
+                        bridge_name
+                    ),
+                };
+                if !listed {
+                    bail!(
+                        "OVS port '{}' - not listed in ovs_ports of bridge '{}'",
+                        iface,
+                        bridge_name
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Check VXLAN attributes and that VNIs are used only once
+    fn check_vxlan(&self) -> Result<(), Error> {
+        let mut used_ids = HashMap::new();
+        for (iface, interface) in self.interfaces.iter() {
+            if interface.interface_type != NetworkInterfaceType::Vxlan {
+                continue;
+            }
+            let vxlan_id = interface
+                .vxlan_id
+                .ok_or_else(|| format_err!("vxlan '{}' - missing vxlan-id", iface))?;
+            if let Some(prev_iface) = used_ids.insert(vxlan_id, iface) {
+                bail!(
+                    "vxlan '{}' - vxlan-id {} is already used on interface '{}'",
+                    iface,
+                    vxlan_id,
+                    prev_iface
+                );
+            }
+            if interface.vxlan_svcnodeip.is_some()
+                && interface.vxlan_remoteip.iter().flatten().next().is_some()
+            {
+                bail!(
+                    "vxlan '{}' - vxlan-svcnodeip and vxlan-remoteip are mutually exclusive",
+                    iface
+                );
+            }
+            if let Some(physdev) = &interface.vxlan_physdev {
+                if !self.interfaces.contains_key(physdev) {
+                    bail!("vxlan '{}' - unable to find physdev '{}'", iface, physdev);
+                }
+            }
         }
         Ok(())
     }
@@ -436,6 +601,8 @@ impl NetworkConfig {
         self.check_port_usage()?;
         self.check_bond_slaves()?;
         self.check_bridge_ports()?;
+        self.check_ovs_ports()?;
+        self.check_vxlan()?;
 
         let mut done = HashSet::new();
 
@@ -522,6 +689,29 @@ pub fn changes() -> Result<String, Error> {
     compute_file_diff(NETWORK_INTERFACES_FILENAME, NETWORK_INTERFACES_NEW_FILENAME)
 }
 
+/// Compute the pending changes per interface and property.
+///
+/// Unlike [`changes`], this compares the configuration against the active state of the system as
+/// reported by netlink, so it also covers changes which were applied outside of the API.
+pub fn pending_changes() -> Result<Vec<NetworkChange>, Error> {
+    let (config, _digest) = config()?;
+
+    let running =
+        match proxmox_sys::fs::file_get_optional_contents(NETWORK_INTERFACES_NEW_FILENAME)? {
+            Some(_) => {
+                let content =
+                    proxmox_sys::fs::file_get_optional_contents(NETWORK_INTERFACES_FILENAME)?
+                        .unwrap_or_default();
+                Some(NetworkParser::new(&content[..]).parse_interfaces(None)?)
+            }
+            None => None,
+        };
+
+    let state = NetworkState::query()?;
+
+    Ok(state.diff(&config, running.as_ref()))
+}
+
 pub fn save_config(config: &NetworkConfig) -> Result<(), Error> {
     let mut raw = Vec::new();
     config.write_config(&mut raw)?;
@@ -720,4 +910,71 @@ iface individual_name inet manual
         assert_eq!(parse_vlan_raw_device_from_name("vmbr0"), None);
         assert_eq!(parse_vlan_raw_device_from_name("vmbr0.200"), Some("vmbr0"));
     }
+
+    #[test]
+    fn test_write_network_config_check_vxlan() {
+        let mut config = NetworkConfig::new();
+        for name in ["vxlan1", "vxlan2"] {
+            let mut iface = Interface::new(name.to_string());
+            iface.interface_type = Vxlan;
+            iface.method = Some(Manual);
+            iface.vxlan_id = Some(100);
+            config.interfaces.insert(name.to_string(), iface);
+        }
+        assert!(String::try_from(config).is_err());
+
+        let mut config = NetworkConfig::new();
+        let mut iface = Interface::new("vxlan1".to_string());
+        iface.interface_type = Vxlan;
+        iface.method = Some(Manual);
+        iface.vxlan_id = Some(100);
+        iface.vxlan_svcnodeip = Some("239.0.0.1".to_string());
+        iface.vxlan_remoteip = Some(vec!["192.0.2.2".to_string()]);
+        config
+            .interfaces
+            .insert("vxlan1".to_string(), iface.clone());
+        assert!(String::try_from(config).is_err());
+
+        let mut config = NetworkConfig::new();
+        iface.vxlan_remoteip = None;
+        config.interfaces.insert("vxlan1".to_string(), iface);
+        assert_eq!(
+            String::try_from(config).unwrap().trim(),
+            "iface vxlan1 inet manual\n\tvxlan-id 100\n\tvxlan-svcnodeip 239.0.0.1"
+        );
+    }
+
+    #[test]
+    fn test_write_network_config_check_ovs_ports() {
+        let mut bridge = Interface::new("vmbr1".to_string());
+        bridge.interface_type = OvsBridge;
+        bridge.method = Some(Manual);
+        bridge.ovs_ports = Some(vec!["eno1".to_string()]);
+
+        let mut port = Interface::new("eno1".to_string());
+        port.interface_type = Eth;
+        port.method = Some(Manual);
+
+        let config = |bridge: &Interface, port: &Interface| NetworkConfig {
+            interfaces: BTreeMap::from([
+                (bridge.name.clone(), bridge.clone()),
+                (port.name.clone(), port.clone()),
+            ]),
+            order: vec![Iface(port.name.clone()), Iface(bridge.name.clone())],
+        };
+
+        // port is no OVS port
+        assert!(String::try_from(config(&bridge, &port)).is_err());
+
+        // port does not reference the bridge
+        port.interface_type = OvsPort;
+        assert!(String::try_from(config(&bridge, &port)).is_err());
+
+        port.ovs_bridge = Some("vmbr1".to_string());
+        assert!(String::try_from(config(&bridge, &port)).is_ok());
+
+        // bridge does not list the port
+        bridge.ovs_ports = None;
+        assert!(String::try_from(config(&bridge, &port)).is_err());
+    }
 }
@@ -0,0 +1,684 @@
+//! Minimal rtnetlink client used to dump links, addresses and routes.
+//!
+//! Only the small subset of the kernel interface needed to inspect the active network state is
+//! implemented here. Constants are defined locally since they are part of the stable kernel ABI
+//! and not all of them are available in older `libc` versions.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+
+use anyhow::{bail, format_err, Error};
+use nix::sys::socket::{
+    recv, sendto, socket, AddressFamily, MsgFlags, NetlinkAddr, SockFlag, SockProtocol, SockType,
+};
+
+const NLMSG_HDRLEN: usize = 16;
+const NLMSG_ERROR: u16 = 2;
+const NLMSG_DONE: u16 = 3;
+
+const NLM_F_REQUEST: u16 = 0x01;
+const NLM_F_MULTI: u16 = 0x02;
+const NLM_F_DUMP_INTR: u16 = 0x10;
+const NLM_F_DUMP: u16 = 0x300;
+
+const RTM_NEWLINK: u16 = 16;
+const RTM_GETLINK: u16 = 18;
+const RTM_NEWADDR: u16 = 20;
+const RTM_GETADDR: u16 = 22;
+const RTM_NEWROUTE: u16 = 24;
+const RTM_GETROUTE: u16 = 26;
+
+const NLA_TYPE_MASK: u16 = 0x3fff;
+
+const IFLA_ADDRESS: u16 = 1;
+const IFLA_IFNAME: u16 = 3;
+const IFLA_MTU: u16 = 4;
+const IFLA_LINK: u16 = 5;
+const IFLA_MASTER: u16 = 10;
+const IFLA_OPERSTATE: u16 = 16;
+const IFLA_LINKINFO: u16 = 18;
+
+const IFLA_INFO_KIND: u16 = 1;
+const IFLA_INFO_DATA: u16 = 2;
+const IFLA_INFO_SLAVE_KIND: u16 = 4;
+const IFLA_INFO_SLAVE_DATA: u16 = 5;
+
+const IFLA_VLAN_ID: u16 = 1;
+
+const IFLA_VXLAN_ID: u16 = 1;
+
+const IFLA_BR_VLAN_FILTERING: u16 = 7;
+
+const IFLA_BOND_MODE: u16 = 1;
+const IFLA_BOND_ACTIVE_SLAVE: u16 = 2;
+const IFLA_BOND_PRIMARY: u16 = 11;
+const IFLA_BOND_XMIT_HASH_POLICY: u16 = 14;
+
+const IFLA_BOND_SLAVE_STATE: u16 = 1;
+const IFLA_BOND_SLAVE_MII_STATUS: u16 = 2;
+const IFLA_BOND_SLAVE_LINK_FAILURE_COUNT: u16 = 3;
+
+const IFA_ADDRESS: u16 = 1;
+const IFA_LOCAL: u16 = 2;
+
+const RTA_DST: u16 = 1;
+const RTA_OIF: u16 = 4;
+const RTA_GATEWAY: u16 = 5;
+const RTA_PRIORITY: u16 = 6;
+const RTA_TABLE: u16 = 15;
+
+const RTN_UNICAST: u8 = 1;
+
+const AF_INET: u8 = libc::AF_INET as u8;
+const AF_INET6: u8 = libc::AF_INET6 as u8;
+
+const fn nlmsg_align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// A link as reported by `RTM_NEWLINK`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct RawLink {
+    pub index: u32,
+    pub flags: u32,
+    pub name: String,
+    pub mac: Option<Vec<u8>>,
+    pub mtu: Option<u32>,
+    pub operstate: Option<u8>,
+    pub master: Option<u32>,
+    pub link: Option<u32>,
+    pub kind: Option<String>,
+    pub slave_kind: Option<String>,
+    pub vlan_id: Option<u16>,
+    pub vxlan_id: Option<u32>,
+    pub bridge_vlan_filtering: Option<bool>,
+    pub bond_mode: Option<u8>,
+    pub bond_active_slave: Option<u32>,
+    pub bond_primary: Option<u32>,
+    pub bond_xmit_hash_policy: Option<u8>,
+    pub bond_slave_state: Option<u8>,
+    pub bond_slave_mii_status: Option<u8>,
+    pub bond_slave_link_failures: Option<u32>,
+}
+
+/// An address as reported by `RTM_NEWADDR`.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct RawAddress {
+    pub index: u32,
+    pub address: IpAddr,
+    pub prefix_len: u8,
+    pub scope: u8,
+}
+
+/// A unicast route as reported by `RTM_NEWROUTE`.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct RawRoute {
+    pub destination: Option<(IpAddr, u8)>,
+    pub gateway: Option<IpAddr>,
+    pub oif: Option<u32>,
+    pub table: u32,
+    pub metric: Option<u32>,
+    pub protocol: u8,
+}
+
+/// A `NETLINK_ROUTE` socket.
+pub(crate) struct RtNetlink {
+    fd: OwnedFd,
+    seq: u32,
+}
+
+impl RtNetlink {
+    pub fn new() -> Result<Self, Error> {
+        let fd = socket(
+            AddressFamily::Netlink,
+            SockType::Raw,
+            SockFlag::SOCK_CLOEXEC,
+            SockProtocol::NetlinkRoute,
+        )
+        .map_err(|err| format_err!("unable to create netlink socket - {}", err))?;
+
+        Ok(Self {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+            seq: 0,
+        })
+    }
+
+    pub fn links(&mut self) -> Result<Vec<RawLink>, Error> {
+        // struct ifinfomsg
+        let header = [0u8; 16];
+        let mut links = Vec::new();
+        for (msg_type, payload) in self.dump(RTM_GETLINK, &header)? {
+            if msg_type == RTM_NEWLINK {
+                links.push(parse_link(&payload)?);
+            }
+        }
+        Ok(links)
+    }
+
+    pub fn addresses(&mut self) -> Result<Vec<RawAddress>, Error> {
+        // struct ifaddrmsg
+        let header = [0u8; 8];
+        let mut addresses = Vec::new();
+        for (msg_type, payload) in self.dump(RTM_GETADDR, &header)? {
+            if msg_type == RTM_NEWADDR {
+                if let Some(address) = parse_address(&payload)? {
+                    addresses.push(address);
+                }
+            }
+        }
+        Ok(addresses)
+    }
+
+    pub fn routes(&mut self) -> Result<Vec<RawRoute>, Error> {
+        // struct rtmsg
+        let header = [0u8; 12];
+        let mut routes = Vec::new();
+        for (msg_type, payload) in self.dump(RTM_GETROUTE, &header)? {
+            if msg_type == RTM_NEWROUTE {
+                if let Some(route) = parse_route(&payload)? {
+                    routes.push(route);
+                }
+            }
+        }
+        Ok(routes)
+    }
+
+    /// Send a dump request and collect all `(type, payload)` pairs of the reply.
+    fn dump(&mut self, msg_type: u16, header: &[u8]) -> Result<Vec<(u16, Vec<u8>)>, Error> {
+        self.seq = self.seq.wrapping_add(1);
+        let seq = self.seq;
+
+        let request = encode_message(msg_type, NLM_F_REQUEST | NLM_F_DUMP, seq, header);
+        sendto(
+            self.fd.as_raw_fd(),
+            &request,
+            &NetlinkAddr::new(0, 0),
+            MsgFlags::empty(),
+        )
+        .map_err(|err| format_err!("unable to send netlink request - {}", err))?;
+
+        let mut result = Vec::new();
+        let mut buffer = vec![0u8; 32 * 1024];
+        loop {
+            let len = recv_datagram(self.fd.as_raw_fd(), &mut buffer)?;
+
+            for message in MessageIter::new(&buffer[..len]) {
+                let message = message?;
+                if message.seq != seq {
+                    continue;
+                }
+                if message.flags & NLM_F_DUMP_INTR != 0 {
+                    bail!("netlink dump was interrupted, please retry");
+                }
+                match message.msg_type {
+                    NLMSG_DONE => return Ok(result),
+                    NLMSG_ERROR => {
+                        let errno = read_i32(message.payload)
+                            .ok_or_else(|| format_err!("short netlink error message"))?;
+                        if errno != 0 {
+                            let err = nix::errno::Errno::from_i32(-errno);
+                            bail!("netlink request failed - {}", err);
+                        }
+                        return Ok(result);
+                    }
+                    other => result.push((other, message.payload.to_vec())),
+                }
+                if message.flags & NLM_F_MULTI == 0 {
+                    return Ok(result);
+                }
+            }
+        }
+    }
+}
+
+/// Receive a single datagram, growing `buffer` if the datagram does not fit.
+fn recv_datagram(fd: RawFd, buffer: &mut Vec<u8>) -> Result<usize, Error> {
+    // with MSG_TRUNC, the real size is returned even if it exceeds the buffer
+    let size = recv(fd, buffer, MsgFlags::MSG_PEEK | MsgFlags::MSG_TRUNC)
+        .map_err(|err| format_err!("unable to receive netlink reply - {}", err))?;
+    if size > buffer.len() {
+        buffer.resize(size, 0);
+    }
+
+    recv(fd, buffer, MsgFlags::empty())
+        .map_err(|err| format_err!("unable to receive netlink reply - {}", err))
+}
+
+fn encode_message(msg_type: u16, flags: u16, seq: u32, payload: &[u8]) -> Vec<u8> {
+    let len = NLMSG_HDRLEN + payload.len();
+    let mut data = Vec::with_capacity(nlmsg_align(len));
+    data.extend_from_slice(&(len as u32).to_ne_bytes());
+    data.extend_from_slice(&msg_type.to_ne_bytes());
+    data.extend_from_slice(&flags.to_ne_bytes());
+    data.extend_from_slice(&seq.to_ne_bytes());
+    data.extend_from_slice(&0u32.to_ne_bytes());
+    data.extend_from_slice(payload);
+    data.resize(nlmsg_align(len), 0);
+    data
+}
+
+struct Message<'a> {
+    msg_type: u16,
+    flags: u16,
+    seq: u32,
+    payload: &'a [u8],
+}
+
+/// Iterator over the netlink messages contained in a single datagram.
+struct MessageIter<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> MessageIter<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+}
+
+impl<'a> Iterator for MessageIter<'a> {
+    type Item = Result<Message<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.len() < NLMSG_HDRLEN {
+            return None;
+        }
+
+        let len = read_u32(self.data).unwrap() as usize;
+        if len < NLMSG_HDRLEN || len > self.data.len() {
+            self.data = &[];
+            return Some(Err(format_err!("invalid netlink message length {}", len)));
+        }
+
+        let message = Message {
+            msg_type: read_u16(&self.data[4..]).unwrap(),
+            flags: read_u16(&self.data[6..]).unwrap(),
+            seq: read_u32(&self.data[8..]).unwrap(),
+            payload: &self.data[NLMSG_HDRLEN..len],
+        };
+
+        self.data = &self.data[nlmsg_align(len).min(self.data.len())..];
+
+        Some(Ok(message))
+    }
+}
+
+/// Iterator over `(type, data)` pairs of netlink attributes.
+pub(crate) struct AttrIter<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> AttrIter<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+}
+
+impl<'a> Iterator for AttrIter<'a> {
+    type Item = (u16, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.len() < 4 {
+            return None;
+        }
+
+        let len = read_u16(self.data).unwrap() as usize;
+        if len < 4 || len > self.data.len() {
+            // truncated attribute, stop parsing
+            self.data = &[];
+            return None;
+        }
+
+        let attr_type = read_u16(&self.data[2..]).unwrap() & NLA_TYPE_MASK;
+        let value = &self.data[4..len];
+
+        self.data = &self.data[nlmsg_align(len).min(self.data.len())..];
+
+        Some((attr_type, value))
+    }
+}
+
+fn read_u16(data: &[u8]) -> Option<u16> {
+    Some(u16::from_ne_bytes(data.get(..2)?.try_into().unwrap()))
+}
+
+fn read_u32(data: &[u8]) -> Option<u32> {
+    Some(u32::from_ne_bytes(data.get(..4)?.try_into().unwrap()))
+}
+
+fn read_i32(data: &[u8]) -> Option<i32> {
+    Some(i32::from_ne_bytes(data.get(..4)?.try_into().unwrap()))
+}
+
+fn read_string(data: &[u8]) -> String {
+    let end = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).into_owned()
+}
+
+fn read_ip(family: u8, data: &[u8]) -> Option<IpAddr> {
+    match family {
+        AF_INET => {
+            let octets: [u8; 4] = data.get(..4)?.try_into().unwrap();
+            Some(IpAddr::V4(Ipv4Addr::from(octets)))
+        }
+        AF_INET6 => {
+            let octets: [u8; 16] = data.get(..16)?.try_into().unwrap();
+            Some(IpAddr::V6(Ipv6Addr::from(octets)))
+        }
+        _ => None,
+    }
+}
+
+pub(crate) fn parse_link(data: &[u8]) -> Result<RawLink, Error> {
+    if data.len() < 16 {
+        bail!("short RTM_NEWLINK message");
+    }
+
+    let mut link = RawLink {
+        index: read_u32(&data[4..]).unwrap(),
+        flags: read_u32(&data[8..]).unwrap(),
+        ..Default::default()
+    };
+
+    for (attr, value) in AttrIter::new(&data[16..]) {
+        match attr {
+            IFLA_IFNAME => link.name = read_string(value),
+            IFLA_ADDRESS => link.mac = Some(value.to_vec()),
+            IFLA_MTU => link.mtu = read_u32(value),
+            IFLA_LINK => link.link = read_u32(value),
+            IFLA_MASTER => link.master = read_u32(value),
+            IFLA_OPERSTATE => link.operstate = value.first().copied(),
+            IFLA_LINKINFO => parse_link_info(&mut link, value),
+            _ => (),
+        }
+    }
+
+    if link.name.is_empty() {
+        bail!("RTM_NEWLINK message for index {} without name", link.index);
+    }
+
+    Ok(link)
+}
+
+fn parse_link_info(link: &mut RawLink, data: &[u8]) {
+    let mut info_data = None;
+    let mut slave_data = None;
+
+    for (attr, value) in AttrIter::new(data) {
+        match attr {
+            IFLA_INFO_KIND => link.kind = Some(read_string(value)),
+            IFLA_INFO_SLAVE_KIND => link.slave_kind = Some(read_string(value)),
+            IFLA_INFO_DATA => info_data = Some(value),
+            IFLA_INFO_SLAVE_DATA => slave_data = Some(value),
+            _ => (),
+        }
+    }
+
+    // the data attributes depend on the kind, which is not necessarily sent first
+    if let Some(data) = info_data {
+        for (attr, value) in AttrIter::new(data) {
+            match (link.kind.as_deref(), attr) {
+                (Some("vlan"), IFLA_VLAN_ID) => link.vlan_id = read_u16(value),
+                (Some("vxlan"), IFLA_VXLAN_ID) => link.vxlan_id = read_u32(value),
+                (Some("bridge"), IFLA_BR_VLAN_FILTERING) => {
+                    link.bridge_vlan_filtering = value.first().map(|v| *v != 0)
+                }
+                (Some("bond"), IFLA_BOND_MODE) => link.bond_mode = value.first().copied(),
+                (Some("bond"), IFLA_BOND_ACTIVE_SLAVE) => link.bond_active_slave = read_u32(value),
+                (Some("bond"), IFLA_BOND_PRIMARY) => link.bond_primary = read_u32(value),
+                (Some("bond"), IFLA_BOND_XMIT_HASH_POLICY) => {
+                    link.bond_xmit_hash_policy = value.first().copied()
+                }
+                _ => (),
+            }
+        }
+    }
+
+    if let (Some("bond"), Some(data)) = (link.slave_kind.as_deref(), slave_data) {
+        for (attr, value) in AttrIter::new(data) {
+            match attr {
+                IFLA_BOND_SLAVE_STATE => link.bond_slave_state = value.first().copied(),
+                IFLA_BOND_SLAVE_MII_STATUS => link.bond_slave_mii_status = value.first().copied(),
+                IFLA_BOND_SLAVE_LINK_FAILURE_COUNT => {
+                    link.bond_slave_link_failures = read_u32(value)
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+pub(crate) fn parse_address(data: &[u8]) -> Result<Option<RawAddress>, Error> {
+    if data.len() < 8 {
+        bail!("short RTM_NEWADDR message");
+    }
+
+    let family = data[0];
+    let prefix_len = data[1];
+    let scope = data[3];
+    let index = read_u32(&data[4..]).unwrap();
+
+    let mut address = None;
+    let mut local = None;
+    for (attr, value) in AttrIter::new(&data[8..]) {
+        match attr {
+            IFA_ADDRESS => address = read_ip(family, value),
+            IFA_LOCAL => local = read_ip(family, value),
+            _ => (),
+        }
+    }
+
+    // for point-to-point links IFA_ADDRESS is the peer, IFA_LOCAL our own address
+    Ok(local.or(address).map(|address| RawAddress {
+        index,
+        address,
+        prefix_len,
+        scope,
+    }))
+}
+
+pub(crate) fn parse_route(data: &[u8]) -> Result<Option<RawRoute>, Error> {
+    if data.len() < 12 {
+        bail!("short RTM_NEWROUTE message");
+    }
+
+    let family = data[0];
+    let dst_len = data[1];
+    let protocol = data[5];
+    let route_type = data[7];
+
+    if route_type != RTN_UNICAST || (family != AF_INET && family != AF_INET6) {
+        return Ok(None);
+    }
+
+    let mut route = RawRoute {
+        destination: None,
+        gateway: None,
+        oif: None,
+        table: data[4] as u32,
+        metric: None,
+        protocol,
+    };
+
+    for (attr, value) in AttrIter::new(&data[12..]) {
+        match attr {
+            RTA_DST => route.destination = read_ip(family, value).map(|ip| (ip, dst_len)),
+            RTA_GATEWAY => route.gateway = read_ip(family, value),
+            RTA_OIF => route.oif = read_u32(value),
+            RTA_PRIORITY => route.metric = read_u32(value),
+            RTA_TABLE => route.table = read_u32(value).unwrap_or(route.table),
+            _ => (),
+        }
+    }
+
+    Ok(Some(route))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attr(attr_type: u16, value: &[u8]) -> Vec<u8> {
+        let len = 4 + value.len();
+        let mut data = Vec::new();
+        data.extend_from_slice(&(len as u16).to_ne_bytes());
+        data.extend_from_slice(&attr_type.to_ne_bytes());
+        data.extend_from_slice(value);
+        data.resize(nlmsg_align(len), 0);
+        data
+    }
+
+    fn ifinfomsg(index: u32, flags: u32) -> Vec<u8> {
+        let mut data = vec![0u8; 4];
+        data.extend_from_slice(&index.to_ne_bytes());
+        data.extend_from_slice(&flags.to_ne_bytes());
+        data.extend_from_slice(&0u32.to_ne_bytes());
+        data
+    }
+
+    #[test]
+    fn test_recv_datagram() {
+        use nix::sys::socket::{send, socketpair};
+
+        let (sender, receiver) = socketpair(
+            AddressFamily::Unix,
+            SockType::Datagram,
+            None,
+            SockFlag::SOCK_CLOEXEC,
+        )
+        .unwrap();
+
+        let large = vec![0x5au8; 48 * 1024];
+        send(sender, &large, MsgFlags::empty()).unwrap();
+        send(sender, b"small", MsgFlags::empty()).unwrap();
+
+        let mut buffer = vec![0u8; 32 * 1024];
+        let len = recv_datagram(receiver, &mut buffer).unwrap();
+        assert_eq!(&buffer[..len], &large[..]);
+        let len = recv_datagram(receiver, &mut buffer).unwrap();
+        assert_eq!(&buffer[..len], b"small");
+
+        nix::unistd::close(sender).unwrap();
+        nix::unistd::close(receiver).unwrap();
+    }
+
+    #[test]
+    fn test_message_iter() {
+        let mut data = encode_message(RTM_NEWLINK, NLM_F_MULTI, 7, &[1, 2, 3]);
+        data.extend(encode_message(
+            NLMSG_DONE,
+            NLM_F_MULTI,
+            7,
+            &0i32.to_ne_bytes(),
+        ));
+
+        let messages: Vec<_> = MessageIter::new(&data).collect::<Result<_, _>>().unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].msg_type, RTM_NEWLINK);
+        assert_eq!(messages[0].seq, 7);
+        assert_eq!(messages[0].payload, &[1, 2, 3]);
+        assert_eq!(messages[1].msg_type, NLMSG_DONE);
+
+        let mut broken = encode_message(RTM_NEWLINK, 0, 1, &[]);
+        broken[0] = 200;
+        assert!(MessageIter::new(&broken).next().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_parse_bond_slave_link() {
+        let mut slave_data = attr(IFLA_BOND_SLAVE_STATE, &[1]);
+        slave_data.extend(attr(IFLA_BOND_SLAVE_MII_STATUS, &[0]));
+        slave_data.extend(attr(
+            IFLA_BOND_SLAVE_LINK_FAILURE_COUNT,
+            &3u32.to_ne_bytes(),
+        ));
+
+        let mut info = attr(IFLA_INFO_SLAVE_KIND, b"bond\0");
+        info.extend(attr(IFLA_INFO_SLAVE_DATA | 0x8000, &slave_data));
+
+        let mut data = ifinfomsg(3, libc::IFF_UP as u32);
+        data.extend(attr(IFLA_IFNAME, b"eno1\0"));
+        data.extend(attr(IFLA_MTU, &9000u32.to_ne_bytes()));
+        data.extend(attr(IFLA_MASTER, &5u32.to_ne_bytes()));
+        data.extend(attr(IFLA_OPERSTATE, &[6]));
+        data.extend(attr(IFLA_LINKINFO | 0x8000, &info));
+
+        let link = parse_link(&data).unwrap();
+        assert_eq!(link.index, 3);
+        assert_eq!(link.name, "eno1");
+        assert_eq!(link.mtu, Some(9000));
+        assert_eq!(link.master, Some(5));
+        assert_eq!(link.operstate, Some(6));
+        assert_eq!(link.kind, None);
+        assert_eq!(link.slave_kind.as_deref(), Some("bond"));
+        assert_eq!(link.bond_slave_state, Some(1));
+        assert_eq!(link.bond_slave_mii_status, Some(0));
+        assert_eq!(link.bond_slave_link_failures, Some(3));
+    }
+
+    #[test]
+    fn test_parse_kind_data() {
+        // data before kind must still be interpreted according to the kind
+        let mut info = attr(IFLA_INFO_DATA, &attr(IFLA_VLAN_ID, &100u16.to_ne_bytes()));
+        info.extend(attr(IFLA_INFO_KIND, b"vlan\0"));
+
+        let mut data = ifinfomsg(7, 0);
+        data.extend(attr(IFLA_IFNAME, b"eno1.100\0"));
+        data.extend(attr(IFLA_LINK, &3u32.to_ne_bytes()));
+        data.extend(attr(IFLA_LINKINFO, &info));
+
+        let link = parse_link(&data).unwrap();
+        assert_eq!(link.kind.as_deref(), Some("vlan"));
+        assert_eq!(link.vlan_id, Some(100));
+        assert_eq!(link.link, Some(3));
+
+        let mut bond_data = attr(IFLA_BOND_MODE, &[4]);
+        bond_data.extend(attr(IFLA_BOND_PRIMARY, &3u32.to_ne_bytes()));
+        bond_data.extend(attr(IFLA_BOND_XMIT_HASH_POLICY, &[1]));
+        let mut info = attr(IFLA_INFO_KIND, b"bond\0");
+        info.extend(attr(IFLA_INFO_DATA, &bond_data));
+
+        let mut data = ifinfomsg(5, 0);
+        data.extend(attr(IFLA_IFNAME, b"bond0\0"));
+        data.extend(attr(IFLA_LINKINFO, &info));
+
+        let link = parse_link(&data).unwrap();
+        assert_eq!(link.bond_mode, Some(4));
+        assert_eq!(link.bond_primary, Some(3));
+        assert_eq!(link.bond_xmit_hash_policy, Some(1));
+        assert_eq!(link.vlan_id, None);
+
+        assert!(parse_link(&ifinfomsg(1, 0)).is_err());
+        assert!(parse_link(&[0u8; 4]).is_err());
+    }
+
+    #[test]
+    fn test_parse_address_and_route() {
+        let mut data = vec![AF_INET, 24, 0, 0];
+        data.extend_from_slice(&2u32.to_ne_bytes());
+        data.extend(attr(IFA_ADDRESS, &[10, 0, 0, 2]));
+        data.extend(attr(IFA_LOCAL, &[10, 0, 0, 1]));
+
+        let address = parse_address(&data).unwrap().unwrap();
+        assert_eq!(address.index, 2);
+        assert_eq!(address.address, "10.0.0.1".parse::<IpAddr>().unwrap());
+        assert_eq!(address.prefix_len, 24);
+
+        let mut data = vec![AF_INET6, 0, 0, 0, 254, 3, 0, RTN_UNICAST];
+        data.extend_from_slice(&0u32.to_ne_bytes());
+        let gateway: Ipv6Addr = "fd00::1".parse().unwrap();
+        data.extend(attr(RTA_GATEWAY, &gateway.octets()));
+        data.extend(attr(RTA_OIF, &2u32.to_ne_bytes()));
+        data.extend(attr(RTA_PRIORITY, &1024u32.to_ne_bytes()));
+
+        let route = parse_route(&data).unwrap().unwrap();
+        assert_eq!(route.destination, None);
+        assert_eq!(route.gateway, Some(IpAddr::V6(gateway)));
+        assert_eq!(route.oif, Some(2));
+        assert_eq!(route.table, 254);
+        assert_eq!(route.metric, Some(1024));
+
+        // local routes are skipped
+        data[7] = 2;
+        assert_eq!(parse_route(&data).unwrap(), None);
+    }
+}
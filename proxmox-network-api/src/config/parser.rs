@@ -85,6 +85,74 @@ fn set_gateway_v6(iface: &mut Interface, gateway: String) -> Result<(), Error> {
     Ok(())
 }
 
+fn ovs_type_from_str(s: &str) -> Result<NetworkInterfaceType, Error> {
+    Ok(match s {
+        "OVSBridge" => NetworkInterfaceType::OvsBridge,
+        "OVSPort" => NetworkInterfaceType::OvsPort,
+        "OVSIntPort" => NetworkInterfaceType::OvsIntPort,
+        "OVSBond" => NetworkInterfaceType::OvsBond,
+        "OVSPatchPort" => NetworkInterfaceType::OvsPatchPort,
+        _ => bail!("unsupported ovs_type '{}'", s),
+    })
+}
+
+lazy_static! {
+    static ref WIREGUARD_LINK_ADD_REGEX: Regex =
+        Regex::new(r"^pre-up ip link add (?:dev )?(\S+) type wireguard$").unwrap();
+    static ref WIREGUARD_SETCONF_REGEX: Regex =
+        Regex::new(r"^pre-up wg setconf (\S+) (\S+)$").unwrap();
+    static ref WIREGUARD_LINK_DEL_REGEX: Regex =
+        Regex::new(r"^post-down ip link del(?:ete)? (?:dev )?(\S+)$").unwrap();
+}
+
+/// Detect WireGuard interfaces by the hook commands creating and configuring the link.
+///
+/// ifupdown2 has no native WireGuard support, so the interface is set up using `pre-up` and
+/// `post-down` commands, see `write_iface_attributes`. Those are moved from the option list into
+/// the `wireguard_config` property.
+fn extract_wireguard_options(interface: &mut Interface) -> Result<(), Error> {
+    let is_own_name = |name: &str| name == "$IFACE" || name == interface.name;
+
+    let mut config = None;
+    for options in [&interface.options, &interface.options6] {
+        for option in options.iter() {
+            if let Some(cap) = WIREGUARD_SETCONF_REGEX.captures(option) {
+                if is_own_name(&cap[1]) {
+                    config = Some(cap[2].to_string());
+                }
+            }
+        }
+    }
+
+    let config = match config {
+        Some(config) => config,
+        None => return Ok(()),
+    };
+
+    set_interface_type(interface, NetworkInterfaceType::WireGuard)?;
+
+    let name = interface.name.clone();
+    let is_hook = |option: &String| {
+        [
+            &*WIREGUARD_LINK_ADD_REGEX,
+            &*WIREGUARD_SETCONF_REGEX,
+            &*WIREGUARD_LINK_DEL_REGEX,
+        ]
+        .iter()
+        .any(|regex| {
+            regex
+                .captures(option)
+                .map(|cap| &cap[1] == "$IFACE" || cap[1] == name)
+                .unwrap_or(false)
+        })
+    };
+    interface.options.retain(|option| !is_hook(option));
+    interface.options6.retain(|option| !is_hook(option));
+    interface.wireguard_config = Some(config);
+
+    Ok(())
+}
+
 fn set_interface_type(
     iface: &mut Interface,
     interface_type: NetworkInterfaceType,
@@ -224,8 +292,8 @@ impl<R: BufRead> NetworkParser<R> {
         Ok(())
     }
 
-    fn parse_iface_mtu(&mut self) -> Result<u64, Error> {
-        self.eat(Token::MTU)?;
+    fn parse_iface_mtu(&mut self, token: Token) -> Result<u64, Error> {
+        self.eat(token)?;
 
         let mtu = self.next_text()?;
         let mtu = match mtu.parse::<u64>() {
@@ -240,6 +308,31 @@ impl<R: BufRead> NetworkParser<R> {
         Ok(mtu)
     }
 
+    fn parse_ip_address(&mut self) -> Result<String, Error> {
+        let address = self.next_text()?;
+        if !IP_REGEX.is_match(&address) {
+            bail!("unable to parse IP address '{}'", address);
+        }
+
+        self.eat(Token::Newline)?;
+
+        Ok(address)
+    }
+
+    fn parse_ip_address_list(&mut self) -> Result<Vec<String>, Error> {
+        let mut list = Vec::new();
+
+        loop {
+            match self.next()? {
+                (Token::Newline, _) => break,
+                (Token::Text, address) if IP_REGEX.is_match(&address) => list.push(address),
+                (_, text) => bail!("unable to parse IP address '{}'", text),
+            }
+        }
+
+        Ok(list)
+    }
+
     fn parse_yes_no(&mut self) -> Result<bool, Error> {
         let text = self.next_text()?;
         let value = match text.to_lowercase().as_str() {
@@ -340,7 +433,11 @@ impl<R: BufRead> NetworkParser<R> {
                     netmask = Some(self.parse_netmask()?);
                 }
                 Token::MTU => {
-                    let mtu = self.parse_iface_mtu()?;
+                    let mtu = self.parse_iface_mtu(Token::MTU)?;
+                    interface.mtu = Some(mtu);
+                }
+                Token::OvsMtu => {
+                    let mtu = self.parse_iface_mtu(Token::OvsMtu)?;
                     interface.mtu = Some(mtu);
                 }
                 Token::BridgeVlanAware => {
@@ -392,6 +489,75 @@ impl<R: BufRead> NetworkParser<R> {
                     set_interface_type(interface, NetworkInterfaceType::Vlan)?;
                     self.eat(Token::Newline)?;
                 }
+                Token::VxlanId => {
+                    self.eat(Token::VxlanId)?;
+                    let vxlan_id = self.next_text()?.parse()?;
+                    interface.vxlan_id = Some(vxlan_id);
+                    set_interface_type(interface, NetworkInterfaceType::Vxlan)?;
+                    self.eat(Token::Newline)?;
+                }
+                Token::VxlanLocalTunnelIp => {
+                    self.eat(Token::VxlanLocalTunnelIp)?;
+                    interface.vxlan_local_tunnelip = Some(self.parse_ip_address()?);
+                    set_interface_type(interface, NetworkInterfaceType::Vxlan)?;
+                }
+                Token::VxlanRemoteIp => {
+                    self.eat(Token::VxlanRemoteIp)?;
+                    // may be given multiple times
+                    let list = self.parse_ip_address_list()?;
+                    interface
+                        .vxlan_remoteip
+                        .get_or_insert_with(Vec::new)
+                        .extend(list);
+                    set_interface_type(interface, NetworkInterfaceType::Vxlan)?;
+                }
+                Token::VxlanSvcNodeIp => {
+                    self.eat(Token::VxlanSvcNodeIp)?;
+                    interface.vxlan_svcnodeip = Some(self.parse_ip_address()?);
+                    set_interface_type(interface, NetworkInterfaceType::Vxlan)?;
+                }
+                Token::VxlanPhysDev => {
+                    self.eat(Token::VxlanPhysDev)?;
+                    interface.vxlan_physdev = Some(self.next_text()?);
+                    set_interface_type(interface, NetworkInterfaceType::Vxlan)?;
+                    self.eat(Token::Newline)?;
+                }
+                Token::OvsType => {
+                    self.eat(Token::OvsType)?;
+                    let ovs_type = ovs_type_from_str(&self.next_text()?)?;
+                    set_interface_type(interface, ovs_type)?;
+                    self.eat(Token::Newline)?;
+                }
+                Token::OvsBridge => {
+                    self.eat(Token::OvsBridge)?;
+                    interface.ovs_bridge = Some(self.next_text()?);
+                    self.eat(Token::Newline)?;
+                }
+                Token::OvsPorts => {
+                    self.eat(Token::OvsPorts)?;
+                    let ports = self.parse_iface_list()?;
+                    interface.ovs_ports = Some(ports);
+                    set_interface_type(interface, NetworkInterfaceType::OvsBridge)?;
+                }
+                Token::OvsBonds => {
+                    self.eat(Token::OvsBonds)?;
+                    let bonds = self.parse_iface_list()?;
+                    interface.ovs_bonds = Some(bonds);
+                    set_interface_type(interface, NetworkInterfaceType::OvsBond)?;
+                }
+                Token::OvsPatchPeer => {
+                    self.eat(Token::OvsPatchPeer)?;
+                    interface.ovs_patch_peer = Some(self.next_text()?);
+                    set_interface_type(interface, NetworkInterfaceType::OvsPatchPort)?;
+                    self.eat(Token::Newline)?;
+                }
+                Token::OvsOptions => {
+                    self.eat(Token::OvsOptions)?;
+                    let options = self.parse_to_eol()?;
+                    if !options.is_empty() {
+                        interface.ovs_options = Some(options);
+                    }
+                }
                 _ => {
                     // parse addon attributes
                     let option = self.parse_to_eol()?;
@@ -579,6 +745,13 @@ impl<R: BufRead> NetworkParser<R> {
         }
 
         for (name, interface) in config.interfaces.iter_mut() {
+            extract_wireguard_options(interface)?;
+            if interface.ovs_bridge.is_some() && !interface.is_ovs_port() {
+                bail!(
+                    "interface '{}' - ovs_bridge requires an OVS port type",
+                    name
+                );
+            }
             if interface.interface_type != NetworkInterfaceType::Unknown {
                 continue;
             }
@@ -859,4 +1032,225 @@ iface individual_name inet static
         assert_eq!(iface.method, Some(NetworkConfigMethod::Static));
         assert_eq!(iface.cidr, Some(String::from("10.0.0.100/16")));
     }
+
+    #[test]
+    fn test_network_config_parser_vxlan() -> Result<(), Error> {
+        let input = "auto lo\n\
+                     iface lo inet loopback\n\
+                     \n\
+                     iface eno1 inet manual\n\
+                     \n\
+                     auto vxlan100\n\
+                     iface vxlan100 inet manual\n\
+                     \tvxlan-id 100\n\
+                     \tvxlan-local-tunnelip 192.0.2.1\n\
+                     \tvxlan-remoteip 192.0.2.2\n\
+                     \tvxlan-remoteip 192.0.2.3\n\
+                     \tvxlan-physdev eno1\n\
+                     \tmtu 1450\n\
+                     \n";
+
+        let config = NetworkParser::new(input.as_bytes()).parse_interfaces(None)?;
+
+        let iface = config.interfaces.get("vxlan100").unwrap();
+        assert_eq!(iface.interface_type, NetworkInterfaceType::Vxlan);
+        assert_eq!(iface.vxlan_id, Some(100));
+        assert_eq!(iface.vxlan_local_tunnelip.as_deref(), Some("192.0.2.1"));
+        assert_eq!(
+            iface.vxlan_remoteip,
+            Some(vec!["192.0.2.2".to_string(), "192.0.2.3".to_string()])
+        );
+        assert_eq!(iface.vxlan_physdev.as_deref(), Some("eno1"));
+        assert!(iface.options.is_empty());
+
+        assert_eq!(String::try_from(config)?, input);
+
+        let input = "iface vxlan1 inet manual\n\tvxlan-remoteip no-ip\n";
+        assert!(NetworkParser::new(input.as_bytes())
+            .parse_interfaces(None)
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_network_config_parser_wireguard() -> Result<(), Error> {
+        let input = "auto lo\n\
+                     iface lo inet loopback\n\
+                     \n\
+                     auto wg0\n\
+                     iface wg0 inet static\n\
+                     \taddress 10.10.0.1/24\n\
+                     \tpre-up ip link add dev $IFACE type wireguard\n\
+                     \tpre-up wg setconf $IFACE /etc/wireguard/wg0.conf\n\
+                     \tpost-down ip link delete dev $IFACE\n\
+                     \n";
+
+        let config = NetworkParser::new(input.as_bytes()).parse_interfaces(None)?;
+
+        let iface = config.interfaces.get("wg0").unwrap();
+        assert_eq!(iface.interface_type, NetworkInterfaceType::WireGuard);
+        assert_eq!(
+            iface.wireguard_config.as_deref(),
+            Some("/etc/wireguard/wg0.conf")
+        );
+        assert!(iface.options.is_empty());
+
+        assert_eq!(String::try_from(config)?, input);
+
+        // hooks using the interface name, unrelated options are kept
+        let input = "iface wg1 inet manual\n\
+                     \tpre-up ip link add wg1 type wireguard\n\
+                     \tpre-up wg setconf wg1 /etc/wireguard/wg1.conf\n\
+                     \tpost-up ip route add 10.20.0.0/16 dev wg1\n";
+
+        let config = NetworkParser::new(input.as_bytes()).parse_interfaces(None)?;
+
+        let iface = config.interfaces.get("wg1").unwrap();
+        assert_eq!(iface.interface_type, NetworkInterfaceType::WireGuard);
+        assert_eq!(
+            iface.wireguard_config.as_deref(),
+            Some("/etc/wireguard/wg1.conf")
+        );
+        assert_eq!(iface.options, ["post-up ip route add 10.20.0.0/16 dev wg1"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_network_config_parser_ovs() -> Result<(), Error> {
+        let input = "auto lo\n\
+                     iface lo inet loopback\n\
+                     \n\
+                     auto eno1\n\
+                     iface eno1 inet manual\n\
+                     \tovs_type OVSPort\n\
+                     \tovs_bridge vmbr1\n\
+                     \n\
+                     auto vmbr1\n\
+                     iface vmbr1 inet manual\n\
+                     \tovs_type OVSBridge\n\
+                     \tovs_ports eno1 mgmt\n\
+                     \tovs_mtu 9000\n\
+                     \n\
+                     auto mgmt\n\
+                     iface mgmt inet static\n\
+                     \taddress 10.0.0.2/24\n\
+                     \tovs_type OVSIntPort\n\
+                     \tovs_bridge vmbr1\n\
+                     \tovs_options tag=10 vlan_mode=access\n\
+                     \n";
+
+        let config = NetworkParser::new(input.as_bytes()).parse_interfaces(None)?;
+
+        let bridge = config.interfaces.get("vmbr1").unwrap();
+        assert_eq!(bridge.interface_type, NetworkInterfaceType::OvsBridge);
+        assert_eq!(
+            bridge.ovs_ports,
+            Some(vec!["eno1".to_string(), "mgmt".to_string()])
+        );
+        assert_eq!(bridge.mtu, Some(9000));
+
+        let port = config.interfaces.get("eno1").unwrap();
+        assert_eq!(port.interface_type, NetworkInterfaceType::OvsPort);
+        assert_eq!(port.ovs_bridge.as_deref(), Some("vmbr1"));
+
+        let int_port = config.interfaces.get("mgmt").unwrap();
+        assert_eq!(int_port.interface_type, NetworkInterfaceType::OvsIntPort);
+        assert_eq!(
+            int_port.ovs_options.as_deref(),
+            Some("tag=10 vlan_mode=access")
+        );
+        assert_eq!(int_port.cidr.as_deref(), Some("10.0.0.2/24"));
+
+        assert_eq!(String::try_from(config)?, input);
+
+        let input = "iface vmbr2 inet manual\n\tovs_type OVSSomething\n";
+        assert!(NetworkParser::new(input.as_bytes())
+            .parse_interfaces(None)
+            .is_err());
+
+        let input = "iface vmbr2 inet manual\n\tbridge-ports none\n\tovs_type OVSBridge\n";
+        assert!(NetworkParser::new(input.as_bytes())
+            .parse_interfaces(None)
+            .is_err());
+
+        let input = "iface eno2 inet manual\n\tovs_bridge vmbr1\n";
+        assert!(NetworkParser::new(input.as_bytes())
+            .parse_interfaces(None)
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_network_config_parser_ovs_bond() -> Result<(), Error> {
+        // as written by Proxmox VE
+        let input = "auto lo\n\
+                     iface lo inet loopback\n\
+                     \n\
+                     iface eno1 inet manual\n\
+                     \n\
+                     iface eno2 inet manual\n\
+                     \n\
+                     auto bond0\n\
+                     iface bond0 inet manual\n\
+                     \tovs_bonds eno1 eno2\n\
+                     \tovs_type OVSBond\n\
+                     \tovs_bridge vmbr0\n\
+                     \tovs_options bond_mode=balance-slb lacp=active\n\
+                     \n\
+                     auto vmbr0\n\
+                     iface vmbr0 inet static\n\
+                     \taddress 192.168.1.2/24\n\
+                     \tgateway 192.168.1.1\n\
+                     \tovs_type OVSBridge\n\
+                     \tovs_ports bond0 patch0\n\
+                     \n\
+                     auto patch0\n\
+                     iface patch0 inet manual\n\
+                     \tovs_type OVSPatchPort\n\
+                     \tovs_bridge vmbr0\n\
+                     \tovs_patch_peer patch1\n\
+                     \n";
+
+        let config = NetworkParser::new(input.as_bytes()).parse_interfaces(None)?;
+
+        let bond = config.interfaces.get("bond0").unwrap();
+        assert_eq!(bond.interface_type, NetworkInterfaceType::OvsBond);
+        assert_eq!(
+            bond.ovs_bonds,
+            Some(vec!["eno1".to_string(), "eno2".to_string()])
+        );
+        assert_eq!(bond.ovs_bridge.as_deref(), Some("vmbr0"));
+        assert_eq!(
+            bond.ovs_options.as_deref(),
+            Some("bond_mode=balance-slb lacp=active")
+        );
+
+        let patch = config.interfaces.get("patch0").unwrap();
+        assert_eq!(patch.interface_type, NetworkInterfaceType::OvsPatchPort);
+        assert_eq!(patch.ovs_patch_peer.as_deref(), Some("patch1"));
+
+        let interfaces = config.interfaces.clone();
+        let output = String::try_from(config)?;
+        assert!(output.contains(
+            "iface bond0 inet manual\n\
+             \tovs_type OVSBond\n\
+             \tovs_bridge vmbr0\n\
+             \tovs_bonds eno1 eno2\n\
+             \tovs_options bond_mode=balance-slb lacp=active\n"
+        ));
+
+        let reparsed = NetworkParser::new(output.as_bytes()).parse_interfaces(None)?;
+        assert_eq!(reparsed.interfaces, interfaces);
+        assert_eq!(String::try_from(reparsed)?, output);
+
+        let input = "iface bond0 inet manual\n\tovs_type OVSBond\n\tovs_bridge vmbr0\n\n\
+                     iface vmbr0 inet manual\n\tovs_type OVSBridge\n\tovs_ports bond0\n";
+        let config = NetworkParser::new(input.as_bytes()).parse_interfaces(None)?;
+        assert!(String::try_from(config).is_err());
+
+        Ok(())
+    }
 }
@@ -0,0 +1,829 @@
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+
+use anyhow::Error;
+use serde::Serialize;
+
+use super::netlink::{RawAddress, RawLink, RawRoute, RtNetlink};
+use super::{parse_cidr, parse_vlan_id_from_name, parse_vlan_raw_device_from_name, NetworkConfig};
+use crate::{
+    BondXmitHashPolicy, Interface, LinuxBondMode, NetworkChange, NetworkChangeKind,
+    NetworkConfigMethod, NetworkInterfaceType,
+};
+
+const IFF_UP: u32 = 0x1;
+const IFF_LOWER_UP: u32 = 0x10000;
+
+const RT_SCOPE_UNIVERSE: u8 = 0;
+const RT_TABLE_MAIN: u32 = 254;
+
+/// State of a bond slave.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct BondSlaveState {
+    /// The slave is active (not a backup slave).
+    pub active: bool,
+    /// The MII link status is up.
+    pub mii_up: bool,
+    /// Number of link failures.
+    pub link_failure_count: u32,
+}
+
+/// Active state of a single network link.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct LinkState {
+    pub index: u32,
+    pub name: String,
+    /// Link kind (`bridge`, `bond`, `vlan`, ...), not set for physical devices.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mac: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mtu: Option<u32>,
+    /// Administrative state (`IFF_UP`).
+    pub up: bool,
+    /// Carrier state (`IFF_LOWER_UP`).
+    pub carrier: bool,
+    /// RFC 2863 operational state.
+    pub operstate: String,
+    /// Bridge or bond this link is enslaved to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub master: Option<String>,
+    /// Lower device of a VLAN.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vlan_id: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vxlan_id: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bridge_vlan_aware: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bond_mode: Option<LinuxBondMode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bond_primary: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bond_active_slave: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bond_xmit_hash_policy: Option<BondXmitHashPolicy>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bond_slave: Option<BondSlaveState>,
+}
+
+/// An address assigned to a link.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct AddressState {
+    pub interface: String,
+    pub address: IpAddr,
+    pub prefix_len: u8,
+    /// Address scope, `0` for global addresses.
+    pub scope: u8,
+}
+
+impl AddressState {
+    /// The address in CIDR notation.
+    pub fn cidr(&self) -> String {
+        format!("{}/{}", self.address, self.prefix_len)
+    }
+
+    fn is_global(&self) -> bool {
+        self.scope == RT_SCOPE_UNIVERSE
+    }
+}
+
+/// A unicast route.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RouteState {
+    /// Destination in CIDR notation, not set for default routes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub destination: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gateway: Option<IpAddr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interface: Option<String>,
+    pub table: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metric: Option<u32>,
+    /// Routing protocol (`RTPROT_*`) which installed the route.
+    pub protocol: u8,
+}
+
+/// Active network state as reported by the kernel.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct NetworkState {
+    pub links: BTreeMap<String, LinkState>,
+    pub addresses: Vec<AddressState>,
+    pub routes: Vec<RouteState>,
+}
+
+fn operstate_name(operstate: Option<u8>) -> &'static str {
+    match operstate {
+        Some(1) => "notpresent",
+        Some(2) => "down",
+        Some(3) => "lowerlayerdown",
+        Some(4) => "testing",
+        Some(5) => "dormant",
+        Some(6) => "up",
+        _ => "unknown",
+    }
+}
+
+fn bond_mode_from_kernel(mode: u8) -> Option<LinuxBondMode> {
+    Some(match mode {
+        0 => LinuxBondMode::BalanceRr,
+        1 => LinuxBondMode::ActiveBackup,
+        2 => LinuxBondMode::BalanceXor,
+        3 => LinuxBondMode::Broadcast,
+        4 => LinuxBondMode::Ieee802_3ad,
+        5 => LinuxBondMode::BalanceTlb,
+        6 => LinuxBondMode::BalanceAlb,
+        _ => return None,
+    })
+}
+
+// the kernel numbering differs from the `BondXmitHashPolicy` representation
+fn xmit_hash_policy_from_kernel(policy: u8) -> Option<BondXmitHashPolicy> {
+    Some(match policy {
+        0 => BondXmitHashPolicy::Layer2,
+        1 => BondXmitHashPolicy::Layer3_4,
+        2 => BondXmitHashPolicy::Layer2_3,
+        _ => return None,
+    })
+}
+
+fn format_mac(mac: &[u8]) -> String {
+    mac.iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+impl NetworkState {
+    /// Query links, addresses and routes via netlink.
+    pub fn query() -> Result<Self, Error> {
+        let mut rtnl = RtNetlink::new()?;
+        let links = rtnl.links()?;
+        let addresses = rtnl.addresses()?;
+        let routes = rtnl.routes()?;
+        Ok(Self::from_raw(links, addresses, routes))
+    }
+
+    pub(crate) fn from_raw(
+        links: Vec<RawLink>,
+        addresses: Vec<RawAddress>,
+        routes: Vec<RawRoute>,
+    ) -> Self {
+        let names: HashMap<u32, String> = links
+            .iter()
+            .map(|link| (link.index, link.name.clone()))
+            .collect();
+        let name_of = |index: Option<u32>| index.and_then(|index| names.get(&index).cloned());
+
+        let links = links
+            .into_iter()
+            .map(|link| {
+                let bond_slave = link.bond_slave_state.map(|state| BondSlaveState {
+                    active: state == 0,
+                    mii_up: link.bond_slave_mii_status == Some(0),
+                    link_failure_count: link.bond_slave_link_failures.unwrap_or(0),
+                });
+
+                // IFLA_LINK is also set for veth peers etc., only keep it for VLANs
+                let parent = match link.kind.as_deref() {
+                    Some("vlan") => name_of(link.link),
+                    _ => None,
+                };
+
+                let state = LinkState {
+                    index: link.index,
+                    kind: link.kind,
+                    mac: link.mac.as_deref().map(format_mac),
+                    mtu: link.mtu,
+                    up: link.flags & IFF_UP != 0,
+                    carrier: link.flags & IFF_LOWER_UP != 0,
+                    operstate: operstate_name(link.operstate).to_string(),
+                    master: name_of(link.master),
+                    parent,
+                    vlan_id: link.vlan_id,
+                    vxlan_id: link.vxlan_id,
+                    bridge_vlan_aware: link.bridge_vlan_filtering,
+                    bond_mode: link.bond_mode.and_then(bond_mode_from_kernel),
+                    bond_primary: name_of(link.bond_primary),
+                    bond_active_slave: name_of(link.bond_active_slave),
+                    bond_xmit_hash_policy: link
+                        .bond_xmit_hash_policy
+                        .and_then(xmit_hash_policy_from_kernel),
+                    bond_slave,
+                    name: link.name,
+                };
+                (state.name.clone(), state)
+            })
+            .collect();
+
+        let addresses = addresses
+            .into_iter()
+            .filter_map(|address| {
+                Some(AddressState {
+                    interface: names.get(&address.index)?.clone(),
+                    address: address.address,
+                    prefix_len: address.prefix_len,
+                    scope: address.scope,
+                })
+            })
+            .collect();
+
+        let routes = routes
+            .into_iter()
+            .map(|route| RouteState {
+                destination: route
+                    .destination
+                    .map(|(address, len)| format!("{}/{}", address, len)),
+                gateway: route.gateway,
+                interface: name_of(route.oif),
+                table: route.table,
+                metric: route.metric,
+                protocol: route.protocol,
+            })
+            .collect();
+
+        Self {
+            links,
+            addresses,
+            routes,
+        }
+    }
+
+    /// Addresses assigned to an interface.
+    pub fn addresses_of<'a>(&'a self, iface: &'a str) -> impl Iterator<Item = &'a AddressState> {
+        self.addresses.iter().filter(move |a| a.interface == iface)
+    }
+
+    fn lower_links(&self, master: &str, kind: &str) -> Vec<&LinkState> {
+        match self.links.get(master) {
+            Some(link) if link.kind.as_deref() == Some(kind) => self
+                .links
+                .values()
+                .filter(|link| link.master.as_deref() == Some(master))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Ports currently attached to a bridge.
+    pub fn bridge_ports(&self, bridge: &str) -> Vec<&LinkState> {
+        self.lower_links(bridge, "bridge")
+    }
+
+    /// Slaves currently enslaved to a bond, their state is available via `bond_slave`.
+    pub fn bond_slaves(&self, bond: &str) -> Vec<&LinkState> {
+        self.lower_links(bond, "bond")
+    }
+
+    /// Default gateway of the main routing table using this interface.
+    pub fn default_gateway(&self, iface: &str, ipv6: bool) -> Option<IpAddr> {
+        self.routes
+            .iter()
+            .filter(|route| {
+                route.destination.is_none()
+                    && route.table == RT_TABLE_MAIN
+                    && route.interface.as_deref() == Some(iface)
+            })
+            .filter_map(|route| route.gateway)
+            .find(|gateway| gateway.is_ipv6() == ipv6)
+    }
+
+    /// Compute the per interface and per property differences between `config` and this state.
+    ///
+    /// Virtual interfaces which are part of the `running` configuration but missing in `config`
+    /// are reported as removed, since applying `config` deletes them.
+    pub fn diff(
+        &self,
+        config: &NetworkConfig,
+        running: Option<&NetworkConfig>,
+    ) -> Vec<NetworkChange> {
+        let mut changes = Vec::new();
+
+        for (name, iface) in config.interfaces.iter() {
+            match iface.interface_type {
+                NetworkInterfaceType::Loopback | NetworkInterfaceType::Alias => continue,
+                _ => (),
+            }
+            if iface.method.is_none() && iface.method6.is_none() {
+                // physical NICs without any configuration
+                continue;
+            }
+
+            match self.links.get(name) {
+                Some(link) => self.diff_interface(iface, link, &mut changes),
+                None => changes.push(NetworkChange {
+                    interface: name.clone(),
+                    kind: NetworkChangeKind::Add,
+                    property: None,
+                    active: None,
+                    pending: None,
+                }),
+            }
+        }
+
+        if let Some(running) = running {
+            for (name, iface) in running.interfaces.iter() {
+                if config.interfaces.contains_key(name) || expected_kind(iface).is_none() {
+                    continue;
+                }
+                if let Some(link) = self.links.get(name) {
+                    changes.push(NetworkChange {
+                        interface: name.clone(),
+                        kind: NetworkChangeKind::Remove,
+                        property: None,
+                        active: link.kind.clone(),
+                        pending: None,
+                    });
+                }
+            }
+        }
+
+        changes
+    }
+
+    fn diff_interface(
+        &self,
+        iface: &Interface,
+        link: &LinkState,
+        changes: &mut Vec<NetworkChange>,
+    ) {
+        let mut modified = |property: &str, active: Option<String>, pending: Option<String>| {
+            if active != pending {
+                changes.push(NetworkChange {
+                    interface: iface.name.clone(),
+                    kind: NetworkChangeKind::Modify,
+                    property: Some(property.to_string()),
+                    active,
+                    pending,
+                });
+            }
+        };
+
+        if let Some(kind) = expected_kind(iface) {
+            if link.kind.as_deref() != Some(kind) {
+                // the interface gets recreated, other properties are meaningless
+                modified("type", link.kind.clone(), Some(kind.to_string()));
+                return;
+            }
+        }
+
+        if iface.autostart && !link.up {
+            modified("active", Some("down".into()), Some("up".into()));
+        }
+
+        if let Some(mtu) = iface.mtu {
+            modified(
+                "mtu",
+                link.mtu.map(|mtu| mtu.to_string()),
+                Some(mtu.to_string()),
+            );
+        }
+
+        for ipv6 in [false, true] {
+            let (method, cidr, gateway) = if ipv6 {
+                (iface.method6, &iface.cidr6, &iface.gateway6)
+            } else {
+                (iface.method, &iface.cidr, &iface.gateway)
+            };
+            if method != Some(NetworkConfigMethod::Static) {
+                continue;
+            }
+            let suffix = if ipv6 { "6" } else { "" };
+
+            let active: Vec<&AddressState> = self
+                .addresses_of(&iface.name)
+                .filter(|a| a.is_global() && a.address.is_ipv6() == ipv6)
+                .collect();
+            let active_list = || {
+                let list: Vec<String> = active.iter().map(|a| a.cidr()).collect();
+                (!list.is_empty()).then(|| list.join(","))
+            };
+
+            match cidr.as_deref().and_then(parse_address) {
+                Some((address, prefix_len)) => {
+                    let present = active
+                        .iter()
+                        .any(|a| a.address == address && a.prefix_len == prefix_len);
+                    if !present {
+                        modified(
+                            &format!("cidr{suffix}"),
+                            active_list(),
+                            Some(format!("{}/{}", address, prefix_len)),
+                        );
+                    }
+                }
+                None => modified(&format!("cidr{suffix}"), active_list(), None),
+            }
+
+            let configured: Option<IpAddr> = gateway.as_deref().and_then(|gw| gw.parse().ok());
+            let active_gateway = self.default_gateway(&iface.name, ipv6);
+            if configured != active_gateway {
+                modified(
+                    &format!("gateway{suffix}"),
+                    active_gateway.map(|gw| gw.to_string()),
+                    configured.map(|gw| gw.to_string()),
+                );
+            }
+        }
+
+        match iface.interface_type {
+            NetworkInterfaceType::Bridge => {
+                let ports = self.bridge_ports(&iface.name);
+                modified(
+                    "bridge_ports",
+                    name_list(ports.iter().map(|l| l.name.as_str())),
+                    name_list(iface.bridge_ports.iter().flatten().map(String::as_str)),
+                );
+                modified(
+                    "bridge_vlan_aware",
+                    Some(link.bridge_vlan_aware.unwrap_or(false).to_string()),
+                    Some(iface.bridge_vlan_aware.unwrap_or(false).to_string()),
+                );
+            }
+            NetworkInterfaceType::Bond => {
+                let slaves = self.bond_slaves(&iface.name);
+                modified(
+                    "slaves",
+                    name_list(slaves.iter().map(|l| l.name.as_str())),
+                    name_list(iface.slaves.iter().flatten().map(String::as_str)),
+                );
+                if let Some(mode) = iface.bond_mode {
+                    modified(
+                        "bond_mode",
+                        link.bond_mode.map(|m| m.to_string()),
+                        Some(mode.to_string()),
+                    );
+                }
+                if let Some(primary) = &iface.bond_primary {
+                    modified(
+                        "bond-primary",
+                        link.bond_primary.clone(),
+                        Some(primary.clone()),
+                    );
+                }
+                let uses_hash_policy = matches!(
+                    link.bond_mode,
+                    Some(LinuxBondMode::BalanceXor | LinuxBondMode::Ieee802_3ad)
+                );
+                if let (Some(policy), true) = (iface.bond_xmit_hash_policy, uses_hash_policy) {
+                    modified(
+                        "bond_xmit_hash_policy",
+                        link.bond_xmit_hash_policy.map(|p| p.to_string()),
+                        Some(policy.to_string()),
+                    );
+                }
+            }
+            NetworkInterfaceType::Vlan => {
+                let vlan_id = iface
+                    .vlan_id
+                    .or_else(|| parse_vlan_id_from_name(&iface.name));
+                modified(
+                    "vlan-id",
+                    link.vlan_id.map(|id| id.to_string()),
+                    vlan_id.map(|id| id.to_string()),
+                );
+                let raw_device = iface
+                    .vlan_raw_device
+                    .as_deref()
+                    .or_else(|| parse_vlan_raw_device_from_name(&iface.name));
+                modified(
+                    "vlan-raw-device",
+                    link.parent.clone(),
+                    raw_device.map(str::to_string),
+                );
+            }
+            NetworkInterfaceType::Vxlan => {
+                modified(
+                    "vxlan-id",
+                    link.vxlan_id.map(|id| id.to_string()),
+                    iface.vxlan_id.map(|id| id.to_string()),
+                );
+            }
+            _ => (),
+        }
+    }
+}
+
+/// The link kind an interface of this type is expected to have.
+fn expected_kind(iface: &Interface) -> Option<&'static str> {
+    match iface.interface_type {
+        NetworkInterfaceType::Bridge => Some("bridge"),
+        NetworkInterfaceType::Bond => Some("bond"),
+        NetworkInterfaceType::Vlan => Some("vlan"),
+        NetworkInterfaceType::Vxlan => Some("vxlan"),
+        NetworkInterfaceType::WireGuard => Some("wireguard"),
+        NetworkInterfaceType::OvsBridge | NetworkInterfaceType::OvsIntPort => Some("openvswitch"),
+        _ => None,
+    }
+}
+
+fn parse_address(cidr: &str) -> Option<(IpAddr, u8)> {
+    let (address, prefix_len, _) = parse_cidr(cidr).ok()?;
+    Some((address.parse().ok()?, prefix_len))
+}
+
+/// Sorted, comma separated list, `None` if empty.
+fn name_list<'a>(names: impl Iterator<Item = &'a str>) -> Option<String> {
+    let mut names: Vec<&str> = names.collect();
+    if names.is_empty() {
+        return None;
+    }
+    names.sort_unstable();
+    Some(names.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::parser::NetworkParser;
+
+    fn link(index: u32, name: &str, kind: Option<&str>) -> RawLink {
+        RawLink {
+            index,
+            flags: IFF_UP | IFF_LOWER_UP,
+            name: name.to_string(),
+            mtu: Some(1500),
+            operstate: Some(6),
+            kind: kind.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    fn address(index: u32, address: &str, prefix_len: u8) -> RawAddress {
+        RawAddress {
+            index,
+            address: address.parse().unwrap(),
+            prefix_len,
+            scope: RT_SCOPE_UNIVERSE,
+        }
+    }
+
+    fn default_route(oif: u32, gateway: &str) -> RawRoute {
+        RawRoute {
+            destination: None,
+            gateway: Some(gateway.parse().unwrap()),
+            oif: Some(oif),
+            table: RT_TABLE_MAIN,
+            metric: None,
+            protocol: 4,
+        }
+    }
+
+    fn parse_config(input: &str) -> NetworkConfig {
+        let existing = HashMap::from([
+            ("eno1".to_string(), true),
+            ("eno2".to_string(), true),
+            ("eno3".to_string(), true),
+        ]);
+        NetworkParser::new(input.as_bytes())
+            .parse_interfaces(Some(&existing))
+            .unwrap()
+    }
+
+    fn state() -> NetworkState {
+        let mut eno1 = link(2, "eno1", None);
+        eno1.master = Some(4);
+        eno1.slave_kind = Some("bond".into());
+        eno1.bond_slave_state = Some(0);
+        eno1.bond_slave_mii_status = Some(0);
+        let mut eno2 = link(3, "eno2", None);
+        eno2.master = Some(4);
+        eno2.slave_kind = Some("bond".into());
+        eno2.bond_slave_state = Some(1);
+        eno2.bond_slave_mii_status = Some(2);
+        eno2.bond_slave_link_failures = Some(2);
+        let mut bond0 = link(4, "bond0", Some("bond"));
+        bond0.master = Some(5);
+        bond0.bond_mode = Some(1);
+        bond0.bond_active_slave = Some(2);
+        let mut vmbr0 = link(5, "vmbr0", Some("bridge"));
+        vmbr0.bridge_vlan_filtering = Some(false);
+        let mut vlan = link(6, "vmbr0.100", Some("vlan"));
+        vlan.link = Some(5);
+        vlan.vlan_id = Some(100);
+
+        NetworkState::from_raw(
+            vec![
+                link(1, "lo", None),
+                eno1,
+                eno2,
+                link(7, "eno3", None),
+                bond0,
+                vmbr0,
+                vlan,
+            ],
+            vec![
+                address(1, "127.0.0.1", 8),
+                address(5, "192.168.0.10", 24),
+                address(5, "fd00::10", 64),
+                RawAddress {
+                    scope: 253,
+                    ..address(5, "fe80::1", 64)
+                },
+            ],
+            vec![default_route(5, "192.168.0.1")],
+        )
+    }
+
+    const CONFIG: &str = "\
+auto lo
+iface lo inet loopback
+
+iface eno1 inet manual
+
+iface eno2 inet manual
+
+auto bond0
+iface bond0 inet manual
+\tbond-slaves eno1 eno2
+\tbond-miimon 100
+\tbond-mode active-backup
+
+auto vmbr0
+iface vmbr0 inet static
+\taddress 192.168.0.10/24
+\tgateway 192.168.0.1
+\tbridge-ports bond0
+\tbridge-stp off
+\tbridge-fd 0
+
+iface vmbr0 inet6 static
+\taddress fd00:0::10/64
+
+auto vmbr0.100
+iface vmbr0.100 inet manual
+";
+
+    #[test]
+    fn test_state_from_raw() {
+        let state = state();
+
+        let bond0 = &state.links["bond0"];
+        assert_eq!(bond0.bond_mode, Some(LinuxBondMode::ActiveBackup));
+        assert_eq!(bond0.bond_active_slave.as_deref(), Some("eno1"));
+        assert_eq!(bond0.master.as_deref(), Some("vmbr0"));
+        assert_eq!(state.links["vmbr0.100"].parent.as_deref(), Some("vmbr0"));
+        assert_eq!(state.links["lo"].operstate, "up");
+
+        let slaves = state.bond_slaves("bond0");
+        assert_eq!(slaves.len(), 2);
+        let eno2 = slaves.iter().find(|l| l.name == "eno2").unwrap();
+        assert_eq!(
+            eno2.bond_slave,
+            Some(BondSlaveState {
+                active: false,
+                mii_up: false,
+                link_failure_count: 2,
+            })
+        );
+
+        let ports: Vec<_> = state
+            .bridge_ports("vmbr0")
+            .iter()
+            .map(|l| &l.name)
+            .collect();
+        assert_eq!(ports, ["bond0"]);
+        assert!(state.bridge_ports("bond0").is_empty());
+
+        assert_eq!(
+            state.default_gateway("vmbr0", false),
+            Some("192.168.0.1".parse().unwrap())
+        );
+        assert_eq!(state.default_gateway("vmbr0", true), None);
+        assert_eq!(state.addresses_of("vmbr0").count(), 3);
+    }
+
+    #[test]
+    fn test_diff_unchanged() {
+        let config = parse_config(CONFIG);
+        assert_eq!(state().diff(&config, Some(&config)), Vec::new());
+    }
+
+    #[test]
+    fn test_diff_changes() {
+        let running = parse_config(CONFIG);
+        let config = parse_config(
+            "\
+auto lo
+iface lo inet loopback
+
+iface eno1 inet manual
+
+iface eno2 inet manual
+
+iface eno3 inet manual
+
+auto bond0
+iface bond0 inet manual
+\tbond-slaves eno1 eno2 eno3
+\tbond-miimon 100
+\tbond-mode 802.3ad
+\tbond-xmit-hash-policy layer3+4
+
+auto vmbr0
+iface vmbr0 inet static
+\taddress 192.168.0.11/24
+\tgateway 192.168.0.1
+\tbridge-ports bond0
+\tbridge-stp off
+\tbridge-fd 0
+\tbridge-vlan-aware yes
+\tmtu 9000
+
+auto vmbr1
+iface vmbr1 inet manual
+\tbridge-ports none
+",
+        );
+
+        let changes = state().diff(&config, Some(&running));
+
+        let modify =
+            |interface: &str, property: &str, active: Option<&str>, pending: Option<&str>| {
+                NetworkChange {
+                    interface: interface.to_string(),
+                    kind: NetworkChangeKind::Modify,
+                    property: Some(property.to_string()),
+                    active: active.map(str::to_string),
+                    pending: pending.map(str::to_string),
+                }
+            };
+
+        assert_eq!(
+            changes,
+            vec![
+                modify("bond0", "slaves", Some("eno1,eno2"), Some("eno1,eno2,eno3")),
+                modify("bond0", "bond_mode", Some("active-backup"), Some("802.3ad")),
+                modify("vmbr0", "mtu", Some("1500"), Some("9000")),
+                modify(
+                    "vmbr0",
+                    "cidr",
+                    Some("192.168.0.10/24"),
+                    Some("192.168.0.11/24")
+                ),
+                modify("vmbr0", "bridge_vlan_aware", Some("false"), Some("true")),
+                NetworkChange {
+                    interface: "vmbr1".to_string(),
+                    kind: NetworkChangeKind::Add,
+                    property: None,
+                    active: None,
+                    pending: None,
+                },
+                NetworkChange {
+                    interface: "vmbr0.100".to_string(),
+                    kind: NetworkChangeKind::Remove,
+                    property: None,
+                    active: Some("vlan".to_string()),
+                    pending: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_link_state() {
+        let config = parse_config(CONFIG);
+
+        let mut state = state();
+        state.links.get_mut("vmbr0").unwrap().up = false;
+        state.links.get_mut("vmbr0.100").unwrap().kind = None;
+        state.routes.clear();
+
+        let changes = state.diff(&config, None);
+        let properties: Vec<_> = changes
+            .iter()
+            .map(|c| (c.interface.as_str(), c.property.as_deref().unwrap()))
+            .collect();
+        assert_eq!(
+            properties,
+            [
+                ("vmbr0", "active"),
+                ("vmbr0", "gateway"),
+                ("vmbr0.100", "type")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_query() {
+        // netlink may not be available in every build environment
+        let state = match NetworkState::query() {
+            Ok(state) => state,
+            Err(_) => return,
+        };
+        let lo = &state.links["lo"];
+        assert!(lo.kind.is_none());
+        assert!(state
+            .addresses_of("lo")
+            .any(|a| a.address == IpAddr::from([127, 0, 0, 1])));
+    }
+}
@@ -22,6 +22,11 @@ pub trait Context: Send + Sync + Debug {
     fn default_sendmail_from(&self) -> String;
     /// Proxy configuration for the current node
     fn http_proxy_config(&self) -> Option<String>;
+    /// TLS trust configuration for HTTP based targets, the system trust store is used if `None`
+    #[cfg(feature = "gotify")]
+    fn http_tls_config(&self) -> Option<proxmox_http::TlsConfig> {
+        None
+    }
     /// Return default config for built-in targets/matchers.
     fn default_config(&self) -> &'static str;
     /// Lookup a template in a certain (optional) namespace
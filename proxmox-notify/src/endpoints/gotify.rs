@@ -145,7 +145,11 @@ impl Endpoint for GotifyEndpoint {
             ..Default::default()
         };
 
-        let client = Client::new(options);
+        let client = match context().http_tls_config() {
+            Some(tls_config) => Client::with_tls_config(&tls_config, options)
+                .map_err(|err| Error::NotifyFailed(self.name().to_string(), err.into()))?,
+            None => Client::new(options),
+        };
         let uri = format!("{}/message", self.config.server);
 
         client
@@ -7,6 +7,8 @@ use http::status::StatusCode;
 
 use openidconnect::{HttpRequest, HttpResponse};
 
+use proxmox_http::TlsConfig;
+
 // Copied from OAuth2 create, because we want to use ureq with
 // native-tls. But current OAuth2 crate pulls in rustls, so we cannot
 // use their 'ureq' feature.
@@ -32,14 +34,21 @@ pub enum Error {
     #[error("TLS error - {0}")]
     Tls(#[from] native_tls::Error),
 
+    /// Invalid TLS configuration.
+    #[error("TLS configuration error - {0}")]
+    TlsConfig(anyhow::Error),
+
     /// Other error.
     #[error("Other error: {0}")]
     Other(String),
 }
 
-fn ureq_agent() -> Result<ureq::Agent, Error> {
-    let mut agent =
-        ureq::AgentBuilder::new().tls_connector(Arc::new(native_tls::TlsConnector::new()?));
+fn ureq_agent(tls_config: Option<&TlsConfig>) -> Result<ureq::Agent, Error> {
+    let mut agent = match tls_config {
+        Some(tls_config) => ureq::AgentBuilder::new()
+            .tls_connector(tls_config.ureq_tls_connector().map_err(Error::TlsConfig)?),
+        None => ureq::AgentBuilder::new().tls_connector(Arc::new(native_tls::TlsConnector::new()?)),
+    };
     if let Ok(val) = env::var("all_proxy").or_else(|_| env::var("ALL_PROXY")) {
         let proxy = ureq::Proxy::new(val).map_err(Box::new)?;
         agent = agent.proxy(proxy);
@@ -52,7 +61,24 @@ fn ureq_agent() -> Result<ureq::Agent, Error> {
 /// Synchronous HTTP client for ureq.
 ///
 pub fn http_client(request: HttpRequest) -> Result<HttpResponse, Error> {
-    let agent = ureq_agent()?;
+    send_request(request, None)
+}
+
+///
+/// Synchronous HTTP client for ureq, verifying servers according to `tls_config`.
+///
+pub fn http_client_with_tls_config(
+    request: HttpRequest,
+    tls_config: &TlsConfig,
+) -> Result<HttpResponse, Error> {
+    send_request(request, Some(tls_config))
+}
+
+pub(crate) fn send_request(
+    request: HttpRequest,
+    tls_config: Option<&TlsConfig>,
+) -> Result<HttpResponse, Error> {
+    let agent = ureq_agent(tls_config)?;
     let mut req = if let Method::POST = request.method {
         agent.post(request.url.as_ref())
     } else {
@@ -81,8 +107,15 @@ pub fn http_client(request: HttpRequest) -> Result<HttpResponse, Error> {
         req.send_bytes(request.body.as_slice())
     } else {
         req.call()
-    }
-    .map_err(Box::new)?;
+    };
+
+    // OAuth2 and OpenID error responses (e.g. `invalid_grant` or `authorization_pending`) use
+    // 4xx status codes, so they must be passed on instead of being treated as transport errors.
+    let response = match response {
+        Ok(response) => response,
+        Err(ureq::Error::Status(_, response)) => response,
+        Err(err) => return Err(Box::new(err).into()),
+    };
 
     let status_code =
         StatusCode::from_u16(response.status()).map_err(|err| Error::Http(err.into()))?;
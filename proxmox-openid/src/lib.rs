@@ -1,11 +1,11 @@
 use std::path::Path;
 
-use anyhow::{format_err, Error};
+use anyhow::{bail, format_err, Error};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
 mod http_client;
-pub use http_client::http_client;
+pub use http_client::{http_client, http_client_with_tls_config};
 
 mod auth_state;
 pub use auth_state::*;
@@ -13,26 +13,40 @@ pub use auth_state::*;
 use openidconnect::{
     //curl::http_client,
     core::{
-        CoreAuthDisplay, CoreAuthPrompt, CoreAuthenticationFlow, CoreClient, CoreGenderClaim,
-        CoreIdTokenClaims, CoreIdTokenVerifier, CoreProviderMetadata,
+        CoreAuthDisplay, CoreAuthPrompt, CoreAuthenticationFlow, CoreClaimName, CoreClaimType,
+        CoreClient, CoreClientAuthMethod, CoreDeviceAuthorizationResponse, CoreGenderClaim,
+        CoreGrantType, CoreIdToken, CoreIdTokenClaims, CoreIdTokenVerifier, CoreJsonWebKey,
+        CoreJsonWebKeySet, CoreJsonWebKeyType, CoreJsonWebKeyUse,
+        CoreJweContentEncryptionAlgorithm, CoreJweKeyManagementAlgorithm, CoreJwsSigningAlgorithm,
+        CoreResponseMode, CoreResponseType, CoreSubjectIdentifierType, CoreTokenResponse,
     },
+    AccessToken,
     AdditionalClaims,
+    AdditionalProviderMetadata,
     AuthenticationContextClass,
     AuthorizationCode,
     ClientId,
     ClientSecret,
     CsrfToken,
+    DeviceAuthorizationUrl,
+    ErrorResponse,
+    HttpRequest,
+    HttpResponse,
     IssuerUrl,
     Nonce,
     OAuth2TokenResponse,
     PkceCodeChallenge,
     PkceCodeVerifier,
+    ProviderMetadata,
     RedirectUrl,
+    RefreshToken,
+    RequestTokenError,
     Scope,
     UserInfoClaims,
-    RequestTokenError,
 };
 
+use proxmox_http::TlsConfig;
+
 /// Stores Additional Claims into a serde_json::Value;
 #[derive(Debug, Deserialize, Serialize)]
 pub struct GenericClaims(Value);
@@ -40,6 +54,205 @@ impl AdditionalClaims for GenericClaims {}
 
 pub type GenericUserInfoClaims = UserInfoClaims<GenericClaims, CoreGenderClaim>;
 
+/// Provider metadata not covered by the core metadata.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ExtraProviderMetadata {
+    /// The URL for RP-initiated logout.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_session_endpoint: Option<String>,
+    /// The URL for the device authorization grant (RFC 8628).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_authorization_endpoint: Option<String>,
+}
+impl AdditionalProviderMetadata for ExtraProviderMetadata {}
+
+type ExtendedProviderMetadata = ProviderMetadata<
+    ExtraProviderMetadata,
+    CoreAuthDisplay,
+    CoreClientAuthMethod,
+    CoreClaimName,
+    CoreClaimType,
+    CoreGrantType,
+    CoreJweContentEncryptionAlgorithm,
+    CoreJweKeyManagementAlgorithm,
+    CoreJwsSigningAlgorithm,
+    CoreJsonWebKeyType,
+    CoreJsonWebKeyUse,
+    CoreJsonWebKey,
+    CoreResponseMode,
+    CoreResponseType,
+    CoreSubjectIdentifierType,
+>;
+
+/// The tokens returned by the provider's token endpoint.
+///
+/// These can be stored in a session in order to re-validate it later via
+/// [`OpenIdAuthenticator::refresh`], to query the userinfo endpoint or to log out.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct OpenIdTokens {
+    pub access_token: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    /// The raw ID token, used as `id_token_hint` on logout.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
+    /// Expiration time of the access token as UNIX epoch, if known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires: Option<i64>,
+}
+
+impl OpenIdTokens {
+    fn from_response(response: &CoreTokenResponse) -> Result<Self, Error> {
+        let id_token = match response.extra_fields().id_token() {
+            Some(token) => serde_json::to_value(token)?.as_str().map(str::to_string),
+            None => None,
+        };
+
+        Ok(Self {
+            access_token: response.access_token().secret().to_string(),
+            refresh_token: response
+                .refresh_token()
+                .map(|token| token.secret().to_string()),
+            id_token,
+            expires: response
+                .expires_in()
+                .map(|duration| proxmox_time::epoch_i64() + duration.as_secs() as i64),
+        })
+    }
+}
+
+/// A pending device authorization (RFC 8628).
+///
+/// The user needs to visit the [`verification_uri`](DeviceAuthorization::verification_uri) and
+/// enter the [`user_code`](DeviceAuthorization::user_code), while
+/// [`OpenIdAuthenticator::wait_for_device_authorization`] polls the token endpoint.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DeviceAuthorization {
+    response: CoreDeviceAuthorizationResponse,
+    ctime: i64,
+}
+
+impl DeviceAuthorization {
+    /// The code the user needs to enter at the verification URI.
+    pub fn user_code(&self) -> &str {
+        self.response.user_code().secret()
+    }
+
+    /// The URI the user needs to visit.
+    pub fn verification_uri(&self) -> &str {
+        self.response.verification_uri().as_str()
+    }
+
+    /// The verification URI including the user code, if provided by the server.
+    pub fn verification_uri_complete(&self) -> Option<&str> {
+        self.response
+            .verification_uri_complete()
+            .map(|uri| uri.secret().as_str())
+    }
+
+    /// The time at which the device code expires as UNIX epoch.
+    pub fn expires(&self) -> i64 {
+        self.ctime + self.response.expires_in().as_secs() as i64
+    }
+}
+
+/// Extract a list of values from the claim `path`, for example to map groups or roles.
+///
+/// Nested claims are addressed with dots (eg. `realm_access.roles`). The claim may either contain
+/// a single string or an array of strings, other values are ignored.
+pub fn claim_values(claims: &Value, path: &str) -> Vec<String> {
+    let mut value = claims;
+    for component in path.split('.') {
+        match value.get(component) {
+            Some(inner) => value = inner,
+            None => return Vec::new(),
+        }
+    }
+
+    match value {
+        Value::String(s) => vec![s.clone()],
+        Value::Array(list) => list
+            .iter()
+            .filter_map(|v| v.as_str().map(str::to_string))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn token_request_error<RE, T>(err: RequestTokenError<RE, T>) -> Error
+where
+    RE: std::error::Error + 'static,
+    T: ErrorResponse + 'static,
+{
+    let msg = match err {
+        RequestTokenError::ServerResponse(provider_err) => {
+            format!("Server returned error response: {:?}", provider_err)
+        }
+        RequestTokenError::Request(req) => {
+            format!("Request failed: {:?}", req)
+        }
+        RequestTokenError::Parse(parse_err, res) => {
+            let body = match std::str::from_utf8(&res) {
+                Ok(text) => text.to_string(),
+                Err(_) => format!("{:?}", &res),
+            };
+            format!(
+                "Failed to parse server response: {} [response={:?}]",
+                parse_err, body
+            )
+        }
+        RequestTokenError::Other(msg) => msg,
+    };
+
+    format_err!("Failed to contact token endpoint: {}", msg)
+}
+
+/// Merge the ID token and userinfo claims, the former take precedence.
+fn merge_claims(
+    id_token_claims: &CoreIdTokenClaims,
+    userinfo_claims: &GenericUserInfoClaims,
+) -> Result<Value, Error> {
+    let mut data = serde_json::to_value(id_token_claims)?;
+
+    let data2 = serde_json::to_value(userinfo_claims)?;
+
+    if let Some(map) = data2.as_object() {
+        for (key, value) in map {
+            if data[key] != Value::Null {
+                continue; // already set
+            }
+            data[key] = value.clone();
+        }
+    }
+
+    Ok(data)
+}
+
+/// A refreshed ID token must belong to the same issuer and subject as the original one (OpenID
+/// Connect Core 1.0, section 12.2).
+fn check_refreshed_claims(
+    original: &CoreIdTokenClaims,
+    refreshed: &CoreIdTokenClaims,
+) -> Result<(), Error> {
+    if refreshed.issuer() != original.issuer() {
+        bail!(
+            "refreshed ID token has a different issuer ({:?} != {:?})",
+            refreshed.issuer().as_str(),
+            original.issuer().as_str(),
+        );
+    }
+
+    if refreshed.subject() != original.subject() {
+        bail!(
+            "refreshed ID token has a different subject ({:?} != {:?})",
+            refreshed.subject().as_str(),
+            original.subject().as_str(),
+        );
+    }
+
+    Ok(())
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct OpenIdConfig {
     pub issuer_url: String,
@@ -57,6 +270,10 @@ pub struct OpenIdConfig {
 pub struct OpenIdAuthenticator {
     client: CoreClient,
     config: OpenIdConfig,
+    jwks: CoreJsonWebKeySet,
+    end_session_endpoint: Option<String>,
+    supports_device_authorization: bool,
+    tls_config: Option<TlsConfig>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -113,21 +330,71 @@ impl PrivateAuthState {
 
 impl OpenIdAuthenticator {
     pub fn discover(config: &OpenIdConfig, redirect_url: &str) -> Result<Self, Error> {
+        Self::discover_impl(config, redirect_url, None)
+    }
+
+    /// Like [`discover`](OpenIdAuthenticator::discover), but verifies the provider's TLS
+    /// certificate according to `tls_config`, for discovery as well as all later requests.
+    pub fn discover_with_tls_config(
+        config: &OpenIdConfig,
+        redirect_url: &str,
+        tls_config: TlsConfig,
+    ) -> Result<Self, Error> {
+        Self::discover_impl(config, redirect_url, Some(tls_config))
+    }
+
+    fn discover_impl(
+        config: &OpenIdConfig,
+        redirect_url: &str,
+        tls_config: Option<TlsConfig>,
+    ) -> Result<Self, Error> {
         let client_id = ClientId::new(config.client_id.clone());
         let client_key = config.client_key.clone().map(ClientSecret::new);
         let issuer_url = IssuerUrl::new(config.issuer_url.clone())?;
 
-        let provider_metadata = CoreProviderMetadata::discover(&issuer_url, http_client)?;
+        let provider_metadata = ExtendedProviderMetadata::discover(&issuer_url, |request| {
+            http_client::send_request(request, tls_config.as_ref())
+        })?;
+        let jwks = provider_metadata.jwks().clone();
+        let end_session_endpoint = provider_metadata
+            .additional_metadata()
+            .end_session_endpoint
+            .clone();
+        let device_authorization_endpoint = provider_metadata
+            .additional_metadata()
+            .device_authorization_endpoint
+            .clone();
 
-        let client = CoreClient::from_provider_metadata(provider_metadata, client_id, client_key)
-            .set_redirect_uri(RedirectUrl::new(String::from(redirect_url))?);
+        let mut client =
+            CoreClient::from_provider_metadata(provider_metadata, client_id, client_key)
+                .set_redirect_uri(RedirectUrl::new(String::from(redirect_url))?);
+
+        let supports_device_authorization = device_authorization_endpoint.is_some();
+        if let Some(url) = device_authorization_endpoint {
+            client = client.set_device_authorization_uri(DeviceAuthorizationUrl::new(url)?);
+        }
 
         Ok(Self {
             client,
             config: config.clone(),
+            jwks,
+            end_session_endpoint,
+            supports_device_authorization,
+            tls_config,
         })
     }
 
+    fn http_request(&self, request: HttpRequest) -> Result<HttpResponse, http_client::Error> {
+        http_client::send_request(request, self.tls_config.as_ref())
+    }
+
+    /// Returns the provider's JSON Web Key Set as fetched during discovery, serialized as JSON.
+    ///
+    /// This can be used to verify JWT bearer tokens issued by the provider.
+    pub fn jwks_json(&self) -> Result<String, Error> {
+        Ok(serde_json::to_string(&self.jwks)?)
+    }
+
     pub fn authorize_url(&self, state_dir: &str, realm: &str) -> Result<String, Error> {
         let private_auth_state = PrivateAuthState::new();
         let public_auth_state = private_auth_state.public_state_string(realm.to_string())?;
@@ -195,36 +462,26 @@ impl OpenIdAuthenticator {
         code: &str,
         private_auth_state: &PrivateAuthState,
     ) -> Result<(CoreIdTokenClaims, GenericUserInfoClaims), Error> {
+        let (id_token_claims, userinfo_claims, _tokens) =
+            self.verify_authorization_code_with_tokens(code, private_auth_state)?;
+
+        Ok((id_token_claims, userinfo_claims))
+    }
+
+    /// Like verify_authorization_code(), but additionally returns the tokens
+    pub fn verify_authorization_code_with_tokens(
+        &self,
+        code: &str,
+        private_auth_state: &PrivateAuthState,
+    ) -> Result<(CoreIdTokenClaims, GenericUserInfoClaims, OpenIdTokens), Error> {
         let code = AuthorizationCode::new(code.to_string());
         // Exchange the code with a token.
         let token_response = self
             .client
             .exchange_code(code)
             .set_pkce_verifier(private_auth_state.pkce_verifier())
-            .request(http_client)
-            .map_err(|err| {
-                let msg = match err {
-                    RequestTokenError::ServerResponse(provider_err) => {
-                        format!("Server returned error response: {:?}", provider_err)
-                    },
-                    RequestTokenError::Request(req) => {
-                        format!("Request failed: {:?}", req)
-                    },
-                    RequestTokenError::Parse(parse_err, res) => {
-                        let body = match std::str::from_utf8(&res) {
-                            Ok(text) => text.to_string(),
-                            Err(_) => format!("{:?}", &res),
-                        };
-                        format!("Failed to parse server response: {} [response={:?}]",
-                            parse_err, body)
-                    },
-                    RequestTokenError::Other(msg) => {
-                        msg
-                    },
-                };
-                
-                format_err!("Failed to contact token endpoint: {}", msg)
-            })?;
+            .request(|request| self.http_request(request))
+            .map_err(token_request_error)?;
 
         let id_token_verifier: CoreIdTokenVerifier = self.client.id_token_verifier();
         let id_token_claims: &CoreIdTokenClaims = token_response
@@ -234,13 +491,11 @@ impl OpenIdAuthenticator {
             .claims(&id_token_verifier, &private_auth_state.nonce)
             .map_err(|err| format_err!("Failed to verify ID token: {}", err))?;
 
-        let userinfo_claims: GenericUserInfoClaims = self
-            .client
-            .user_info(token_response.access_token().to_owned(), None)?
-            .request(http_client)
-            .map_err(|err| format_err!("Failed to contact userinfo endpoint: {}", err))?;
+        let userinfo_claims = self.user_info(token_response.access_token().secret())?;
 
-        Ok((id_token_claims.clone(), userinfo_claims))
+        let tokens = OpenIdTokens::from_response(&token_response)?;
+
+        Ok((id_token_claims.clone(), userinfo_claims, tokens))
     }
 
     /// Like verify_authorization_code(), but returns claims as serde_json::Value
@@ -252,19 +507,616 @@ impl OpenIdAuthenticator {
         let (id_token_claims, userinfo_claims) =
             self.verify_authorization_code(code, private_auth_state)?;
 
-        let mut data = serde_json::to_value(id_token_claims)?;
+        merge_claims(&id_token_claims, &userinfo_claims)
+    }
+
+    /// Use a refresh token to get new tokens from the provider.
+    ///
+    /// This fails if the session was terminated at the provider, so it can be used to
+    /// re-validate a session. If the provider returns a new ID token, it is verified and its
+    /// claims are returned as well. `original_claims` are the claims of the ID token from the
+    /// original authentication, a refreshed ID token must be issued for the same issuer and
+    /// subject.
+    pub fn refresh(
+        &self,
+        refresh_token: &str,
+        original_claims: &CoreIdTokenClaims,
+    ) -> Result<(OpenIdTokens, Option<CoreIdTokenClaims>), Error> {
+        let token_response = self
+            .client
+            .exchange_refresh_token(&RefreshToken::new(refresh_token.to_string()))
+            .request(|request| self.http_request(request))
+            .map_err(token_request_error)?;
+
+        let id_token_claims = match token_response.extra_fields().id_token() {
+            Some(id_token) => {
+                let claims = self.verify_refreshed_id_token(id_token)?;
+                check_refreshed_claims(original_claims, &claims)?;
+                Some(claims)
+            }
+            None => None,
+        };
+
+        let mut tokens = OpenIdTokens::from_response(&token_response)?;
+        if tokens.refresh_token.is_none() {
+            // the provider may keep using the old refresh token
+            tokens.refresh_token = Some(refresh_token.to_string());
+        }
+
+        Ok((tokens, id_token_claims))
+    }
+
+    fn verify_refreshed_id_token(
+        &self,
+        id_token: &CoreIdToken,
+    ) -> Result<CoreIdTokenClaims, Error> {
+        let id_token_verifier: CoreIdTokenVerifier = self.client.id_token_verifier();
+
+        // There is no nonce for refreshed ID tokens, if one is included it is the one from the
+        // original authentication.
+        let claims = id_token
+            .claims(
+                &id_token_verifier,
+                |_nonce: Option<&Nonce>| -> Result<(), String> { Ok(()) },
+            )
+            .map_err(|err| format_err!("Failed to verify ID token: {}", err))?;
+
+        Ok(claims.clone())
+    }
+
+    /// Query the userinfo endpoint using an access token.
+    pub fn user_info(&self, access_token: &str) -> Result<GenericUserInfoClaims, Error> {
+        self.client
+            .user_info(AccessToken::new(access_token.to_string()), None)?
+            .request(|request| self.http_request(request))
+            .map_err(|err| format_err!("Failed to contact userinfo endpoint: {}", err))
+    }
+
+    /// Like user_info(), but merges the claims of an ID token and returns them as
+    /// serde_json::Value
+    pub fn user_info_simple(
+        &self,
+        access_token: &str,
+        id_token_claims: &CoreIdTokenClaims,
+    ) -> Result<Value, Error> {
+        merge_claims(id_token_claims, &self.user_info(access_token)?)
+    }
+
+    /// Whether the provider supports the device authorization grant.
+    pub fn supports_device_authorization(&self) -> bool {
+        self.supports_device_authorization
+    }
+
+    /// Start a device authorization grant (RFC 8628), used for logins on devices without a
+    /// browser, like CLI tools.
+    ///
+    /// The returned user code and verification URI need to be shown to the user, then
+    /// [`wait_for_device_authorization`](Self::wait_for_device_authorization) has to be called.
+    pub fn device_authorization(&self) -> Result<DeviceAuthorization, Error> {
+        let mut request = self
+            .client
+            .exchange_device_code()
+            .map_err(|err| format_err!("device authorization not supported - {}", err))?
+            .add_scope(Scope::new("openid".to_string()));
+
+        if let Some(ref scopes) = self.config.scopes {
+            for scope in scopes.clone() {
+                request = request.add_scope(Scope::new(scope));
+            }
+        }
+
+        let response: CoreDeviceAuthorizationResponse = request
+            .request(|request| self.http_request(request))
+            .map_err(token_request_error)?;
+
+        Ok(DeviceAuthorization {
+            response,
+            ctime: proxmox_time::epoch_i64(),
+        })
+    }
+
+    /// Poll the token endpoint until the user completed the device authorization, the device
+    /// code expired or the request was denied.
+    ///
+    /// The polling interval is the one requested by the server and is increased if the server
+    /// asks to slow down. This produces the same claims as
+    /// [`verify_authorization_code_with_tokens`](Self::verify_authorization_code_with_tokens).
+    pub fn wait_for_device_authorization(
+        &self,
+        authorization: &DeviceAuthorization,
+    ) -> Result<(CoreIdTokenClaims, GenericUserInfoClaims, OpenIdTokens), Error> {
+        self.wait_for_device_authorization_with_sleep(authorization, std::thread::sleep)
+    }
+
+    fn wait_for_device_authorization_with_sleep(
+        &self,
+        authorization: &DeviceAuthorization,
+        sleep: impl Fn(std::time::Duration),
+    ) -> Result<(CoreIdTokenClaims, GenericUserInfoClaims, OpenIdTokens), Error> {
+        let remaining = authorization.expires() - proxmox_time::epoch_i64();
+        if remaining <= 0 {
+            bail!("device code expired");
+        }
+
+        let token_response = self
+            .client
+            .exchange_device_access_token(&authorization.response)
+            .request(
+                |request| self.http_request(request),
+                sleep,
+                Some(std::time::Duration::from_secs(remaining as u64)),
+            )
+            .map_err(token_request_error)?;
+
+        let id_token = token_response
+            .extra_fields()
+            .id_token()
+            .ok_or_else(|| format_err!("Server did not return an ID token"))?;
+
+        // There is no nonce in the device authorization grant.
+        let id_token_verifier: CoreIdTokenVerifier = self.client.id_token_verifier();
+        let id_token_claims = id_token
+            .claims(
+                &id_token_verifier,
+                |_nonce: Option<&Nonce>| -> Result<(), String> { Ok(()) },
+            )
+            .map_err(|err| format_err!("Failed to verify ID token: {}", err))?
+            .clone();
+
+        let userinfo_claims = self.user_info(token_response.access_token().secret())?;
+
+        let tokens = OpenIdTokens::from_response(&token_response)?;
+
+        Ok((id_token_claims, userinfo_claims, tokens))
+    }
+
+    /// Like wait_for_device_authorization(), but returns claims as serde_json::Value
+    pub fn wait_for_device_authorization_simple(
+        &self,
+        authorization: &DeviceAuthorization,
+    ) -> Result<Value, Error> {
+        let (id_token_claims, userinfo_claims, _tokens) =
+            self.wait_for_device_authorization(authorization)?;
+
+        merge_claims(&id_token_claims, &userinfo_claims)
+    }
+
+    /// Whether the provider supports RP-initiated logout.
+    pub fn supports_logout(&self) -> bool {
+        self.end_session_endpoint.is_some()
+    }
+
+    /// Get the URL to redirect the user to for RP-initiated logout.
+    ///
+    /// The `id_token_hint` should be the raw ID token of the session (see
+    /// [`OpenIdTokens::id_token`]), `post_logout_redirect_url` needs to be registered at the
+    /// provider.
+    pub fn logout_url(
+        &self,
+        id_token_hint: Option<&str>,
+        post_logout_redirect_url: Option<&str>,
+        state: Option<&str>,
+    ) -> Result<String, Error> {
+        let endpoint = self
+            .end_session_endpoint
+            .as_deref()
+            .ok_or_else(|| format_err!("OpenID provider does not support RP-initiated logout"))?;
+
+        let mut url = url::Url::parse(endpoint)
+            .map_err(|err| format_err!("invalid end_session_endpoint - {}", err))?;
+
+        {
+            let mut query = url.query_pairs_mut();
+            if let Some(id_token_hint) = id_token_hint {
+                query.append_pair("id_token_hint", id_token_hint);
+            }
+            query.append_pair("client_id", &self.config.client_id);
+            if let Some(redirect_url) = post_logout_redirect_url {
+                query.append_pair("post_logout_redirect_uri", redirect_url);
+            }
+            if let Some(state) = state {
+                query.append_pair("state", state);
+            }
+        }
+
+        Ok(url.to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread::JoinHandle;
+
+    use openidconnect::core::CoreRsaPrivateSigningKey;
+    use openidconnect::{
+        AuthUrl, JsonWebKeyId, JsonWebKeySet, PrivateSigningKey, TokenUrl, UserInfoUrl,
+    };
+    use serde_json::json;
+
+    fn test_authenticator(end_session_endpoint: Option<&str>) -> OpenIdAuthenticator {
+        let config = OpenIdConfig {
+            issuer_url: "https://idp.example.com".to_string(),
+            client_id: "proxmox".to_string(),
+            client_key: None,
+            scopes: None,
+            prompt: None,
+            acr_values: None,
+        };
+
+        let client = CoreClient::new(
+            ClientId::new(config.client_id.clone()),
+            None,
+            IssuerUrl::new(config.issuer_url.clone()).unwrap(),
+            AuthUrl::new("https://idp.example.com/auth".to_string()).unwrap(),
+            None,
+            None,
+            JsonWebKeySet::new(Vec::new()),
+        );
+
+        OpenIdAuthenticator {
+            client,
+            config,
+            jwks: JsonWebKeySet::new(Vec::new()),
+            end_session_endpoint: end_session_endpoint.map(str::to_string),
+            supports_device_authorization: false,
+            tls_config: None,
+        }
+    }
+
+    /// An authenticator talking to a provider at `url`, which signs its ID tokens with `key`.
+    fn mock_authenticator(url: &str, key: &CoreRsaPrivateSigningKey) -> OpenIdAuthenticator {
+        let mut auth = test_authenticator(None);
+        auth.client = CoreClient::new(
+            ClientId::new(auth.config.client_id.clone()),
+            None,
+            IssuerUrl::new(auth.config.issuer_url.clone()).unwrap(),
+            AuthUrl::new(format!("{url}/auth")).unwrap(),
+            Some(TokenUrl::new(format!("{url}/token")).unwrap()),
+            Some(UserInfoUrl::new(format!("{url}/userinfo")).unwrap()),
+            JsonWebKeySet::new(vec![key.as_verification_key()]),
+        );
+        auth
+    }
+
+    /// A minimal HTTP server answering one request per queued `(status, body)` response.
+    ///
+    /// Returns the base URL and a handle resolving to the request lines and bodies received.
+    fn mock_provider(responses: Vec<(u16, Value)>) -> (String, JoinHandle<Vec<(String, String)>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let handle = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+
+            for (status, body) in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
 
-        let data2 = serde_json::to_value(userinfo_claims)?;
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
 
-        if let Some(map) = data2.as_object() {
-            for (key, value) in map {
-                if data[key] != Value::Null {
-                    continue; // already set
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let line = line.trim_end();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            content_length = value.trim().parse().unwrap();
+                        }
+                    }
                 }
-                data[key] = value.clone();
+
+                let mut request_body = vec![0; content_length];
+                reader.read_exact(&mut request_body).unwrap();
+                requests.push((
+                    request_line.trim_end().to_string(),
+                    String::from_utf8(request_body).unwrap(),
+                ));
+
+                let body = body.to_string();
+                write!(
+                    reader.into_inner(),
+                    "HTTP/1.1 {status} Mock\r\nContent-Type: application/json\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len(),
+                )
+                .unwrap();
+            }
+
+            requests
+        });
+
+        (url, handle)
+    }
+
+    fn signing_key() -> CoreRsaPrivateSigningKey {
+        let pem = openssl::rsa::Rsa::generate(2048)
+            .unwrap()
+            .private_key_to_pem()
+            .unwrap();
+        CoreRsaPrivateSigningKey::from_pem(
+            std::str::from_utf8(&pem).unwrap(),
+            Some(JsonWebKeyId::new("key1".to_string())),
+        )
+        .unwrap()
+    }
+
+    fn id_token(claims: CoreIdTokenClaims, key: &CoreRsaPrivateSigningKey) -> Value {
+        let token = CoreIdToken::new(
+            claims,
+            key,
+            CoreJwsSigningAlgorithm::RsaSsaPkcs1V15Sha256,
+            None,
+            None,
+        )
+        .unwrap();
+        serde_json::to_value(token).unwrap()
+    }
+
+    fn id_token_claims(iss: &str, sub: &str) -> CoreIdTokenClaims {
+        serde_json::from_value(json!({
+            "iss": iss,
+            "sub": sub,
+            "aud": "proxmox",
+            "exp": 2000000000,
+            "iat": 1700000000,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_claim_values() {
+        let claims = json!({
+            "groups": ["admins", "users", 5],
+            "role": "auditor",
+            "realm_access": {
+                "roles": ["offline_access", "uma_authorization"],
+            },
+            "count": 3,
+        });
+
+        assert_eq!(claim_values(&claims, "groups"), ["admins", "users"]);
+        assert_eq!(claim_values(&claims, "role"), ["auditor"]);
+        assert_eq!(
+            claim_values(&claims, "realm_access.roles"),
+            ["offline_access", "uma_authorization"],
+        );
+        assert!(claim_values(&claims, "count").is_empty());
+        assert!(claim_values(&claims, "missing").is_empty());
+        assert!(claim_values(&claims, "realm_access.missing").is_empty());
+        assert!(claim_values(&claims, "role.nested").is_empty());
+    }
+
+    #[test]
+    fn test_logout_url() {
+        let auth = test_authenticator(Some("https://idp.example.com/logout?foo=bar"));
+        assert!(auth.supports_logout());
+
+        let url = auth
+            .logout_url(
+                Some("id.token"),
+                Some("https://pve.example.com:8006/"),
+                Some("state 1"),
+            )
+            .unwrap();
+        assert_eq!(
+            url,
+            "https://idp.example.com/logout?foo=bar&id_token_hint=id.token&client_id=proxmox\
+             &post_logout_redirect_uri=https%3A%2F%2Fpve.example.com%3A8006%2F&state=state+1",
+        );
+
+        let url = auth.logout_url(None, None, None).unwrap();
+        assert_eq!(
+            url,
+            "https://idp.example.com/logout?foo=bar&client_id=proxmox"
+        );
+
+        let auth = test_authenticator(None);
+        assert!(!auth.supports_logout());
+        assert!(auth.logout_url(Some("id.token"), None, None).is_err());
+    }
+
+    #[test]
+    fn test_check_refreshed_claims() {
+        let original = id_token_claims("https://idp.example.com", "user1");
+
+        let refreshed = id_token_claims("https://idp.example.com", "user1");
+        check_refreshed_claims(&original, &refreshed).unwrap();
+
+        let refreshed = id_token_claims("https://idp.example.com", "user2");
+        assert!(check_refreshed_claims(&original, &refreshed).is_err());
+
+        let refreshed = id_token_claims("https://other.example.com", "user1");
+        assert!(check_refreshed_claims(&original, &refreshed).is_err());
+    }
+
+    #[test]
+    fn test_refresh() {
+        let key = signing_key();
+        // uses the same key id, but the provider's JWKS does not contain it
+        let other_key = signing_key();
+        let issuer = "https://idp.example.com";
+
+        let token_response = |id_token: Option<Value>, refresh_token: Option<&str>| {
+            let mut response = json!({
+                "access_token": "access2",
+                "token_type": "bearer",
+                "expires_in": 300,
+            });
+            if let Some(id_token) = id_token {
+                response["id_token"] = id_token;
+            }
+            if let Some(refresh_token) = refresh_token {
+                response["refresh_token"] = refresh_token.into();
             }
+            (200, response)
+        };
+
+        let (url, provider) = mock_provider(vec![
+            token_response(Some(id_token(id_token_claims(issuer, "user1"), &key)), None),
+            token_response(None, Some("refresh2")),
+            token_response(Some(id_token(id_token_claims(issuer, "user2"), &key)), None),
+            token_response(
+                Some(id_token(id_token_claims(issuer, "user1"), &other_key)),
+                None,
+            ),
+            (
+                400,
+                json!({ "error": "invalid_grant", "error_description": "session terminated" }),
+            ),
+        ]);
+
+        let auth = mock_authenticator(&url, &key);
+        let original = id_token_claims(issuer, "user1");
+
+        let (tokens, claims) = auth.refresh("refresh1", &original).unwrap();
+        assert_eq!(tokens.access_token, "access2");
+        assert_eq!(tokens.refresh_token.as_deref(), Some("refresh1"));
+        assert!(tokens.id_token.is_some());
+        assert_eq!(claims.unwrap().subject().as_str(), "user1");
+
+        let (tokens, claims) = auth.refresh("refresh1", &original).unwrap();
+        assert_eq!(tokens.refresh_token.as_deref(), Some("refresh2"));
+        assert!(tokens.id_token.is_none());
+        assert!(claims.is_none());
+
+        let err = auth.refresh("refresh1", &original).unwrap_err().to_string();
+        assert!(err.contains("different subject"), "{err}");
+
+        let err = auth.refresh("refresh1", &original).unwrap_err().to_string();
+        assert!(err.starts_with("Failed to verify ID token"), "{err}");
+
+        let err = auth.refresh("refresh1", &original).unwrap_err().to_string();
+        assert!(err.contains("Server returned error response"), "{err}");
+        assert!(err.contains("invalid_grant"), "{err}");
+
+        for (request_line, body) in provider.join().unwrap() {
+            assert!(request_line.starts_with("POST /token "), "{request_line}");
+            assert!(body.contains("grant_type=refresh_token"), "{body}");
+            assert!(body.contains("refresh_token=refresh1"), "{body}");
+        }
+    }
+
+    #[test]
+    fn test_user_info() {
+        let key = signing_key();
+
+        let (url, provider) = mock_provider(vec![
+            (
+                200,
+                json!({ "sub": "user1", "email": "user1@example.com", "groups": ["admins"] }),
+            ),
+            (401, json!({ "error": "invalid_token" })),
+        ]);
+
+        let auth = mock_authenticator(&url, &key);
+
+        let claims = auth.user_info("access1").unwrap();
+        assert_eq!(claims.subject().as_str(), "user1");
+        assert_eq!(claims.email().unwrap().as_str(), "user1@example.com");
+        assert_eq!(
+            claim_values(
+                &serde_json::to_value(claims.additional_claims()).unwrap(),
+                "groups"
+            ),
+            ["admins"],
+        );
+
+        let err = auth.user_info("access1").unwrap_err().to_string();
+        assert!(
+            err.starts_with("Failed to contact userinfo endpoint"),
+            "{err}"
+        );
+
+        for (request_line, _body) in provider.join().unwrap() {
+            assert!(request_line.starts_with("GET /userinfo "), "{request_line}");
         }
+    }
+
+    #[test]
+    fn test_wait_for_device_authorization() {
+        let key = signing_key();
+        let issuer = "https://idp.example.com";
+
+        let device_authorization = |ctime| DeviceAuthorization {
+            response: serde_json::from_value(json!({
+                "device_code": "device1",
+                "user_code": "ABCD-EFGH",
+                "verification_uri": "https://idp.example.com/device",
+                "expires_in": 600,
+                "interval": 1,
+            }))
+            .unwrap(),
+            ctime,
+        };
 
-        Ok(data)
+        let (url, provider) = mock_provider(vec![
+            (400, json!({ "error": "authorization_pending" })),
+            (400, json!({ "error": "slow_down" })),
+            (
+                200,
+                json!({
+                    "access_token": "access1",
+                    "token_type": "bearer",
+                    "refresh_token": "refresh1",
+                    "id_token": id_token(id_token_claims(issuer, "user1"), &key),
+                }),
+            ),
+            (200, json!({ "sub": "user1", "email": "user1@example.com" })),
+            (400, json!({ "error": "authorization_pending" })),
+            (400, json!({ "error": "expired_token" })),
+        ]);
+
+        let auth = mock_authenticator(&url, &key);
+        let sleeps = std::cell::RefCell::new(Vec::new());
+        let sleep = |duration| sleeps.borrow_mut().push(duration);
+
+        let now = proxmox_time::epoch_i64();
+
+        let (claims, userinfo, tokens) = auth
+            .wait_for_device_authorization_with_sleep(&device_authorization(now), sleep)
+            .unwrap();
+        assert_eq!(claims.subject().as_str(), "user1");
+        assert_eq!(userinfo.email().unwrap().as_str(), "user1@example.com");
+        assert_eq!(tokens.access_token, "access1");
+        assert_eq!(tokens.refresh_token.as_deref(), Some("refresh1"));
+        // the interval is increased by 5 seconds on slow_down
+        assert_eq!(
+            sleeps.take(),
+            [
+                std::time::Duration::from_secs(1),
+                std::time::Duration::from_secs(6),
+            ],
+        );
+
+        let err = auth
+            .wait_for_device_authorization_with_sleep(&device_authorization(now), sleep)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("expired_token"), "{err}");
+        assert_eq!(sleeps.take(), [std::time::Duration::from_secs(1)]);
+
+        // expired locally, the provider is not contacted at all
+        let err = auth
+            .wait_for_device_authorization_with_sleep(&device_authorization(now - 600), sleep)
+            .unwrap_err()
+            .to_string();
+        assert_eq!(err, "device code expired");
+        assert!(sleeps.take().is_empty());
+
+        let requests = provider.join().unwrap();
+        assert_eq!(requests.len(), 6);
+        for (request_line, body) in requests.iter().filter(|(_, body)| !body.is_empty()) {
+            assert!(request_line.starts_with("POST /token "), "{request_line}");
+            assert!(body.contains("device_code=device1"), "{body}");
+        }
     }
 }
@@ -78,6 +78,18 @@ impl ApiConfig {
         self.auth_handler(AuthHandler::from_fn(func))
     }
 
+    /// Set the authentication handler from a function which also gets the request's
+    /// [`RestEnvironment`], for example to authenticate via the TLS client certificate.
+    pub fn auth_handler_func_with_env<Func>(self, func: Func) -> Self
+    where
+        Func: for<'a> Fn(&'a HeaderMap, &'a Method, &'a RestEnvironment) -> CheckAuthFuture<'a>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.auth_handler(AuthHandler::from_fn_with_env(func))
+    }
+
     /// This is used for `protected` API calls to proxy to a more privileged service.
     pub fn privileged_addr(mut self, addr: impl Into<PrivilegedAddr>) -> Self {
         self.privileged_addr = Some(addr.into());
@@ -113,9 +125,10 @@ impl ApiConfig {
         &self,
         headers: &HeaderMap,
         method: &Method,
+        rpcenv: &RestEnvironment,
     ) -> Result<(String, Box<dyn UserInformation + Sync + Send>), AuthError> {
         match self.auth_handler.as_ref() {
-            Some(handler) => (handler.func)(headers, method).await,
+            Some(handler) => (handler.func)(headers, method, rpcenv).await,
             None => Err(AuthError::NoData),
         }
     }
@@ -432,13 +445,24 @@ pub type CheckAuthOutput = Result<(String, Box<dyn UserInformation + Send + Sync
 pub type CheckAuthFuture<'a> = Pin<Box<dyn Future<Output = CheckAuthOutput> + Send + 'a>>;
 pub type CheckAuthFunc =
     Box<dyn for<'a> Fn(&'a HeaderMap, &'a Method) -> CheckAuthFuture<'a> + Send + Sync>;
+pub type CheckAuthWithEnvFunc = Box<
+    dyn for<'a> Fn(&'a HeaderMap, &'a Method, &'a RestEnvironment) -> CheckAuthFuture<'a>
+        + Send
+        + Sync,
+>;
 
 pub struct AuthHandler {
-    func: CheckAuthFunc,
+    func: CheckAuthWithEnvFunc,
 }
 
 impl From<CheckAuthFunc> for AuthHandler {
     fn from(func: CheckAuthFunc) -> Self {
+        Self::from_fn_with_env(move |headers, method, _rpcenv| func(headers, method))
+    }
+}
+
+impl From<CheckAuthWithEnvFunc> for AuthHandler {
+    fn from(func: CheckAuthWithEnvFunc) -> Self {
         Self { func }
     }
 }
@@ -450,6 +474,18 @@ impl AuthHandler {
     {
         Self::from(Box::new(func) as CheckAuthFunc)
     }
+
+    /// Like [`from_fn`](AuthHandler::from_fn), but the function also gets the request's
+    /// [`RestEnvironment`], which provides the client address and TLS client certificate.
+    pub fn from_fn_with_env<Func>(func: Func) -> Self
+    where
+        Func: for<'a> Fn(&'a HeaderMap, &'a Method, &'a RestEnvironment) -> CheckAuthFuture<'a>
+            + Send
+            + Sync
+            + 'static,
+    {
+        Self::from(Box::new(func) as CheckAuthWithEnvFunc)
+    }
 }
 
 /// Authentication Error
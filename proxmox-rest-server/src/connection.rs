@@ -8,13 +8,17 @@ use std::pin::Pin;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-use anyhow::{format_err, Context as _, Error};
+use anyhow::{bail, format_err, Context as _, Error};
 use futures::FutureExt;
 use hyper::server::accept;
+use hyper::server::conn::Http;
 use openssl::ec::{EcGroup, EcKey};
 use openssl::nid::Nid;
 use openssl::pkey::{PKey, Private};
-use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod};
+use openssl::ssl::{
+    AlpnError, SslAcceptor, SslAcceptorBuilder, SslFiletype, SslMethod, SslVerifyMode,
+};
+use openssl::x509::store::X509StoreBuilder;
 use openssl::x509::X509;
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::mpsc;
@@ -27,11 +31,196 @@ use proxmox_http::{RateLimitedStream, ShareableRateLimit};
 #[cfg(feature = "rate-limited-stream")]
 pub type SharedRateLimit = Arc<dyn ShareableRateLimit>;
 
+/// The authenticated user of connections using an auth id aware rate limiter lookup.
+///
+/// Entries are tagged with the generation of the [`ConnectionAuthId`] which created them, so a
+/// closing connection does not drop the entry of a newer connection reusing the same address.
+#[cfg(feature = "rate-limited-stream")]
+static CONNECTION_AUTH_IDS: once_cell::sync::Lazy<Mutex<ConnectionAuthIds>> =
+    once_cell::sync::Lazy::new(Default::default);
+
+/// Connection generation and auth id by peer address.
+#[cfg(feature = "rate-limited-stream")]
+type ConnectionAuthIds = std::collections::HashMap<std::net::SocketAddr, (u64, Option<String>)>;
+
+#[cfg(feature = "rate-limited-stream")]
+static CONNECTION_AUTH_ID_GENERATION: std::sync::atomic::AtomicU64 =
+    std::sync::atomic::AtomicU64::new(0);
+
+/// Remember the user which authenticated on the connection from `peer`.
+///
+/// Only connections accepted with [`AcceptBuilder::rate_limiter_lookup_with_auth`] are tracked.
+#[cfg(feature = "rate-limited-stream")]
+pub(crate) fn set_connection_auth_id(peer: std::net::SocketAddr, auth_id: &str) {
+    let mut auth_ids = CONNECTION_AUTH_IDS.lock().unwrap();
+    if let Some((_, entry)) = auth_ids.get_mut(&peer) {
+        if entry.as_deref() != Some(auth_id) {
+            *entry = Some(auth_id.to_string());
+        }
+    }
+}
+
+/// Tracks the auth id of a connection while it is open.
+#[cfg(feature = "rate-limited-stream")]
+struct ConnectionAuthId {
+    peer: std::net::SocketAddr,
+    generation: u64,
+}
+
+#[cfg(feature = "rate-limited-stream")]
+impl ConnectionAuthId {
+    fn new(peer: std::net::SocketAddr) -> Self {
+        let generation =
+            CONNECTION_AUTH_ID_GENERATION.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        CONNECTION_AUTH_IDS
+            .lock()
+            .unwrap()
+            .insert(peer, (generation, None));
+        Self { peer, generation }
+    }
+
+    fn get(&self) -> Option<String> {
+        match CONNECTION_AUTH_IDS.lock().unwrap().get(&self.peer) {
+            Some((generation, auth_id)) if *generation == self.generation => auth_id.clone(),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "rate-limited-stream")]
+impl Drop for ConnectionAuthId {
+    fn drop(&mut self) {
+        let mut auth_ids = CONNECTION_AUTH_IDS.lock().unwrap();
+        if matches!(auth_ids.get(&self.peer), Some((generation, _)) if *generation == self.generation)
+        {
+            auth_ids.remove(&self.peer);
+        }
+    }
+}
+
+/// ALPN protocol list in wire format, preferring HTTP/2.
+const ALPN_H2_HTTP1: &[u8] = b"\x02h2\x08http/1.1";
+
+/// Let TLS clients negotiate HTTP/2 via ALPN, falling back to HTTP/1.1.
+///
+/// Useful for acceptors which are not built with a [`TlsAcceptorBuilder`]. The server must be
+/// able to speak HTTP/2 on these connections, see [`AcceptBuilder::http_protocol`].
+pub fn enable_http2_alpn(acceptor: &mut SslAcceptorBuilder) {
+    acceptor.set_alpn_select_callback(|_ssl, client_protocols| {
+        openssl::ssl::select_next_proto(ALPN_H2_HTTP1, client_protocols).ok_or(AlpnError::NOACK)
+    });
+}
+
 enum Tls {
     KeyCert(PKey<Private>, X509),
     FilesPem(PathBuf, PathBuf),
 }
 
+enum ClientCa {
+    Certs(Vec<X509>),
+    FilePem(PathBuf),
+}
+
+fn asn1_string(data: &openssl::asn1::Asn1StringRef) -> Result<String, Error> {
+    let data = data.to_string()?;
+    if data.contains('\0') {
+        bail!("certificate name contains NUL byte");
+    }
+    Ok(data)
+}
+
+/// A verified TLS client certificate.
+///
+/// Available through [`RestEnvironment::peer_certificate`](crate::RestEnvironment::peer_certificate)
+/// if the [`TlsAcceptorBuilder`] was configured to request client certificates, for example to
+/// map the certificate to an auth id in the auth handler.
+#[derive(Clone, Debug)]
+pub struct PeerCertificate {
+    certificate: X509,
+    subject: String,
+    common_name: Option<String>,
+    subject_alt_names: Vec<String>,
+    fingerprint: String,
+}
+
+impl PeerCertificate {
+    pub fn from_x509(certificate: X509) -> Result<Self, Error> {
+        let mut subject = Vec::new();
+        for entry in certificate.subject_name().entries() {
+            let key = entry.object().nid().short_name()?;
+            subject.push(format!("{}={}", key, asn1_string(entry.data())?));
+        }
+
+        let common_name = match certificate
+            .subject_name()
+            .entries_by_nid(Nid::COMMONNAME)
+            .next()
+        {
+            Some(entry) => Some(asn1_string(entry.data())?),
+            None => None,
+        };
+
+        let mut subject_alt_names = Vec::new();
+        for name in certificate.subject_alt_names().iter().flatten() {
+            if let Some(dns) = name.dnsname() {
+                subject_alt_names.push(format!("DNS:{}", dns));
+            } else if let Some(email) = name.email() {
+                subject_alt_names.push(format!("email:{}", email));
+            } else if let Some(uri) = name.uri() {
+                subject_alt_names.push(format!("URI:{}", uri));
+            } else if let Some(ip) = name.ipaddress() {
+                let ip = match ip.len() {
+                    4 => std::net::IpAddr::from(<[u8; 4]>::try_from(ip)?),
+                    16 => std::net::IpAddr::from(<[u8; 16]>::try_from(ip)?),
+                    _ => continue,
+                };
+                subject_alt_names.push(format!("IP:{}", ip));
+            }
+        }
+
+        let fingerprint = certificate
+            .digest(openssl::hash::MessageDigest::sha256())?
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<Vec<_>>()
+            .join(":");
+
+        Ok(Self {
+            certificate,
+            subject: subject.join(", "),
+            common_name,
+            subject_alt_names,
+            fingerprint,
+        })
+    }
+
+    /// The certificate itself.
+    pub fn certificate(&self) -> &X509 {
+        &self.certificate
+    }
+
+    /// The subject, formatted like `CN=name, O=organization`.
+    pub fn subject(&self) -> &str {
+        &self.subject
+    }
+
+    /// The common name of the subject, if any.
+    pub fn common_name(&self) -> Option<&str> {
+        self.common_name.as_deref()
+    }
+
+    /// The subject alternative names, prefixed with their type (`DNS:`, `email:`, `URI:` or
+    /// `IP:`).
+    pub fn subject_alt_names(&self) -> &[String] {
+        &self.subject_alt_names
+    }
+
+    /// The SHA-256 fingerprint of the certificate, colon separated hex.
+    pub fn fingerprint(&self) -> &str {
+        &self.fingerprint
+    }
+}
+
 /// A builder for an `SslAcceptor` which can be configured either with certificates (or path to PEM
 /// files), or otherwise builds a self-signed certificate on the fly (mostly useful during
 /// development).
@@ -40,6 +229,9 @@ pub struct TlsAcceptorBuilder {
     tls: Option<Tls>,
     cipher_suites: Option<String>,
     cipher_list: Option<String>,
+    http2: bool,
+    client_ca: Option<ClientCa>,
+    require_client_certificate: bool,
 }
 
 impl TlsAcceptorBuilder {
@@ -71,6 +263,38 @@ impl TlsAcceptorBuilder {
         self
     }
 
+    /// Offer HTTP/2 to clients via ALPN, see [`enable_http2_alpn`].
+    ///
+    /// HTTP/2 also needs to be enabled in the [`AcceptBuilder`], otherwise such connections are
+    /// rejected.
+    pub fn http2(mut self, enable: bool) -> Self {
+        self.http2 = enable;
+        self
+    }
+
+    /// Request client certificates and verify them against the CA certificates `ca`.
+    ///
+    /// Connections without a client certificate are still accepted, unless
+    /// [`require_client_certificate`](TlsAcceptorBuilder::require_client_certificate) is set.
+    /// Connections presenting a certificate which cannot be verified are rejected.
+    pub fn client_ca(mut self, ca: Vec<X509>) -> Self {
+        self.client_ca = Some(ClientCa::Certs(ca));
+        self
+    }
+
+    /// Like [`client_ca`](TlsAcceptorBuilder::client_ca), loading the CA certificates from a PEM
+    /// file.
+    pub fn client_ca_path_pem(mut self, path: impl Into<PathBuf>) -> Self {
+        self.client_ca = Some(ClientCa::FilePem(path.into()));
+        self
+    }
+
+    /// Reject connections without a valid client certificate. Requires a client CA.
+    pub fn require_client_certificate(mut self, require: bool) -> Self {
+        self.require_client_certificate = require;
+        self
+    }
+
     pub fn build(self) -> Result<SslAcceptor, Error> {
         let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
 
@@ -127,6 +351,49 @@ impl TlsAcceptorBuilder {
                     .context("failed to set tls acceptor certificate")?;
             }
         }
+        if self.http2 {
+            enable_http2_alpn(&mut acceptor);
+        }
+
+        match self.client_ca {
+            Some(client_ca) => {
+                let certs = match client_ca {
+                    ClientCa::Certs(certs) => certs,
+                    ClientCa::FilePem(path) => {
+                        let pem = std::fs::read(&path)
+                            .with_context(|| format!("failed to read client CA file {:?}", path))?;
+                        X509::stack_from_pem(&pem).context("failed to parse client CA file")?
+                    }
+                };
+                if certs.is_empty() {
+                    bail!("no client CA certificate configured");
+                }
+
+                let mut store = X509StoreBuilder::new()?;
+                for cert in certs {
+                    acceptor
+                        .add_client_ca(&cert)
+                        .context("failed to add client CA")?;
+                    store.add_cert(cert)?;
+                }
+                acceptor
+                    .set_verify_cert_store(store.build())
+                    .context("failed to set client CA store")?;
+
+                let mut mode = SslVerifyMode::PEER;
+                if self.require_client_certificate {
+                    mode |= SslVerifyMode::FAIL_IF_NO_PEER_CERT;
+                }
+                acceptor.set_verify(mode);
+                // session resumption fails without a context once peers are verified
+                acceptor.set_session_id_context(b"proxmox-rest-server")?;
+            }
+            None if self.require_client_certificate => {
+                bail!("requiring client certificates needs a client CA");
+            }
+            None => (),
+        }
+
         acceptor.set_options(openssl::ssl::SslOptions::NO_RENEGOTIATION);
         acceptor.check_private_key().unwrap();
 
@@ -149,13 +416,22 @@ type LookupRateLimiter = dyn Fn(std::net::SocketAddr) -> (Option<SharedRateLimit
     + Sync
     + 'static;
 
+#[cfg(feature = "rate-limited-stream")]
+type LookupRateLimiterWithAuth = dyn Fn(std::net::SocketAddr, Option<&str>) -> (Option<SharedRateLimit>, Option<SharedRateLimit>)
+    + Send
+    + Sync
+    + 'static;
+
 pub struct AcceptBuilder {
     debug: bool,
     tcp_keepalive_time: u32,
     max_pending_accepts: usize,
+    http2: bool,
 
     #[cfg(feature = "rate-limited-stream")]
     lookup_rate_limiter: Option<Arc<LookupRateLimiter>>,
+    #[cfg(feature = "rate-limited-stream")]
+    lookup_rate_limiter_with_auth: Option<Arc<LookupRateLimiterWithAuth>>,
 }
 
 impl Default for AcceptBuilder {
@@ -164,9 +440,12 @@ impl Default for AcceptBuilder {
             debug: false,
             tcp_keepalive_time: 120,
             max_pending_accepts: 1024,
+            http2: false,
 
             #[cfg(feature = "rate-limited-stream")]
             lookup_rate_limiter: None,
+            #[cfg(feature = "rate-limited-stream")]
+            lookup_rate_limiter_with_auth: None,
         }
     }
 }
@@ -191,11 +470,55 @@ impl AcceptBuilder {
         self
     }
 
+    /// Serve HTTP/2 on connections which negotiated it, see [`AcceptBuilder::http_protocol`].
+    pub fn http2(mut self, enable: bool) -> Self {
+        self.http2 = enable;
+        self
+    }
+
+    /// The hyper protocol configuration for serving the accepted connections.
+    ///
+    /// With HTTP/2 enabled, connections are served with HTTP/2 if the client starts with the
+    /// HTTP/2 connection preface, which browsers do after negotiating `h2` via ALPN (see
+    /// [`TlsAcceptorBuilder::http2`]). The extended CONNECT protocol (RFC8441) is enabled as well,
+    /// so websockets can share the multiplexed connection. Otherwise only HTTP/1.1 is served.
+    ///
+    /// ```ignore
+    /// let accept_builder = AcceptBuilder::new().http2(true);
+    /// let protocol = accept_builder.http_protocol();
+    /// let incoming = accept_builder.accept_tls(listener, acceptor);
+    /// hyper::server::Builder::new(incoming, protocol).serve(rest_server).await?;
+    /// ```
+    pub fn http_protocol(&self) -> Http {
+        let mut http = Http::new();
+        if self.http2 {
+            http.http2_enable_connect_protocol();
+        } else {
+            http.http1_only(true);
+        }
+        http
+    }
+
     #[cfg(feature = "rate-limited-stream")]
     pub fn rate_limiter_lookup(mut self, lookup_rate_limiter: Arc<LookupRateLimiter>) -> Self {
         self.lookup_rate_limiter = Some(lookup_rate_limiter);
         self
     }
+
+    /// Like [`rate_limiter_lookup`](AcceptBuilder::rate_limiter_lookup), but the lookup also gets
+    /// the auth id of the last successfully authenticated request on the connection, for
+    /// example to apply per user traffic control rules.
+    ///
+    /// The lookup is repeated periodically, so the limiters change a few seconds after the
+    /// client authenticated. Takes precedence over a lookup set with `rate_limiter_lookup`.
+    #[cfg(feature = "rate-limited-stream")]
+    pub fn rate_limiter_lookup_with_auth(
+        mut self,
+        lookup_rate_limiter: Arc<LookupRateLimiterWithAuth>,
+    ) -> Self {
+        self.lookup_rate_limiter_with_auth = Some(lookup_rate_limiter);
+        self
+    }
 }
 
 impl AcceptBuilder {
@@ -292,6 +615,7 @@ impl AcceptBuilder {
                         acceptor,
                         accept_counter,
                         self.debug,
+                        self.http2,
                         secure_sender.clone(),
                     );
 
@@ -303,6 +627,7 @@ impl AcceptBuilder {
                         acceptor,
                         accept_counter,
                         self.debug,
+                        self.http2,
                         secure_sender.clone(),
                         insecure_sender.clone(),
                     );
@@ -332,9 +657,20 @@ impl AcceptBuilder {
             .context("error while setting SO_KEEPALIVE on socket")?;
 
         #[cfg(feature = "rate-limited-stream")]
-        let socket = match self.lookup_rate_limiter.clone() {
-            Some(lookup) => RateLimitedStream::with_limiter_update_cb(socket, move || lookup(peer)),
-            None => RateLimitedStream::with_limiter(socket, None, None),
+        let socket = match (
+            self.lookup_rate_limiter_with_auth.clone(),
+            self.lookup_rate_limiter.clone(),
+        ) {
+            (Some(lookup), _) => {
+                let auth_id = ConnectionAuthId::new(peer);
+                RateLimitedStream::with_limiter_update_cb(socket, move || {
+                    lookup(peer, auth_id.get().as_deref())
+                })
+            }
+            (None, Some(lookup)) => {
+                RateLimitedStream::with_limiter_update_cb(socket, move || lookup(peer))
+            }
+            (None, None) => RateLimitedStream::with_limiter(socket, None, None),
         };
 
         #[cfg(not(feature = "rate-limited-stream"))]
@@ -348,6 +684,7 @@ impl AcceptBuilder {
         acceptor: Arc<Mutex<SslAcceptor>>,
         accept_counter: Arc<()>,
         debug: bool,
+        http2: bool,
         secure_sender: ClientSender,
     ) {
         let ssl = {
@@ -380,6 +717,12 @@ impl AcceptBuilder {
         let result = accept_future.await;
 
         match result {
+            Ok(Ok(())) if !http2 && secure_stream.ssl().selected_alpn_protocol() == Some(b"h2") => {
+                // the client would start talking HTTP/2 to an HTTP/1.1 only server
+                log::error!(
+                    "https connection rejected - client negotiated HTTP/2, but it is disabled"
+                );
+            }
             Ok(Ok(())) => {
                 if secure_sender.send(Ok(secure_stream)).await.is_err() && debug {
                     log::error!("detected closed connection channel");
@@ -405,6 +748,7 @@ impl AcceptBuilder {
         acceptor: Arc<Mutex<SslAcceptor>>,
         accept_counter: Arc<()>,
         debug: bool,
+        http2: bool,
         secure_sender: ClientSender,
         insecure_sender: InsecureClientSender,
     ) {
@@ -434,7 +778,15 @@ impl AcceptBuilder {
             return;
         }
 
-        Self::do_accept_tls(socket, acceptor, accept_counter, debug, secure_sender).await
+        Self::do_accept_tls(
+            socket,
+            acceptor,
+            accept_counter,
+            debug,
+            http2,
+            secure_sender,
+        )
+        .await
     }
 
     async fn wait_for_client_tls_handshake(incoming_stream: &TcpStream) -> Result<bool, Error> {
@@ -505,3 +857,371 @@ fn contains_tls_handshake_fragment(buf: &[u8]) -> bool {
 
     buf[0] == 0x16 && buf[1] == 0x3 && (((buf[3] as u16) << 8) + buf[4] as u16) <= CONTENT_SIZE
 }
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::pin::Pin;
+
+    use openssl::asn1::Asn1Time;
+    use openssl::bn::BigNum;
+    use openssl::hash::MessageDigest;
+    use openssl::ssl::{Ssl, SslConnector, SslMethod, SslVerifyMode};
+    use openssl::x509::extension::{BasicConstraints, SubjectAlternativeName};
+    use openssl::x509::X509NameBuilder;
+    use tokio::net::UnixStream;
+    use tokio_openssl::SslStream;
+
+    use super::*;
+    use crate::rest::PeerAddress;
+
+    pub(crate) fn generate_key() -> PKey<Private> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap()
+    }
+
+    /// Create a certificate signed by `issuer` or self-signed, without a common name if
+    /// `common_name` is empty.
+    pub(crate) fn generate_cert(
+        common_name: &str,
+        key: &PKey<Private>,
+        issuer: Option<(&X509, &PKey<Private>)>,
+        ca: bool,
+    ) -> X509 {
+        let mut subject = X509NameBuilder::new().unwrap();
+        if !common_name.is_empty() {
+            subject
+                .append_entry_by_nid(Nid::COMMONNAME, common_name)
+                .unwrap();
+        }
+        subject.append_entry_by_text("O", "Proxmox").unwrap();
+        let subject = subject.build();
+
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        let serial = BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap();
+        builder.set_serial_number(&serial).unwrap();
+        builder.set_subject_name(&subject).unwrap();
+        builder.set_pubkey(key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+
+        let (issuer_name, signing_key) = match issuer {
+            Some((issuer, issuer_key)) => (issuer.subject_name(), issuer_key),
+            None => (subject.as_ref(), key),
+        };
+        builder.set_issuer_name(issuer_name).unwrap();
+        if ca {
+            let constraints = BasicConstraints::new().critical().ca().build().unwrap();
+            builder.append_extension(constraints).unwrap();
+        } else {
+            let san = SubjectAlternativeName::new()
+                .dns("client.example.com")
+                .email("client@example.com")
+                .uri("https://example.com/client")
+                .ip("192.0.2.1")
+                .ip("2001:db8::1")
+                .build(&builder.x509v3_context(issuer.map(|(cert, _)| &**cert), None))
+                .unwrap();
+            builder.append_extension(san).unwrap();
+        }
+
+        builder.sign(signing_key, MessageDigest::sha256()).unwrap();
+        builder.build()
+    }
+
+    /// Connect a client offering the certificate `client` to `acceptor`.
+    ///
+    /// Returns the server and client side of the connection.
+    pub(crate) async fn tls_handshake(
+        acceptor: &SslAcceptor,
+        client: Option<(&X509, &PKey<Private>)>,
+    ) -> Result<(SslStream<UnixStream>, SslStream<UnixStream>), Error> {
+        let mut connector = SslConnector::builder(SslMethod::tls())?;
+        connector.set_verify(SslVerifyMode::NONE);
+        if let Some((cert, key)) = client {
+            connector.set_private_key(key)?;
+            connector.set_certificate(cert)?;
+        }
+        let connector = connector.build();
+
+        let (server, client) = UnixStream::pair()?;
+
+        let mut server = SslStream::new(Ssl::new(acceptor.context())?, server)?;
+        let client_ssl = connector.configure()?.into_ssl("localhost")?;
+        let mut client = SslStream::new(client_ssl, client)?;
+
+        let (server_res, _) = futures::join!(
+            Pin::new(&mut server).accept(),
+            Pin::new(&mut client).connect()
+        );
+        server_res?;
+
+        Ok((server, client))
+    }
+
+    #[cfg(feature = "rate-limited-stream")]
+    #[test]
+    fn test_connection_auth_id_reused_address() {
+        let peer: std::net::SocketAddr = "192.0.2.1:4711".parse().unwrap();
+
+        let old = ConnectionAuthId::new(peer);
+        set_connection_auth_id(peer, "old@pam");
+        assert_eq!(old.get().as_deref(), Some("old@pam"));
+
+        let new = ConnectionAuthId::new(peer);
+        assert_eq!(new.get(), None);
+        assert_eq!(old.get(), None);
+
+        drop(old);
+        set_connection_auth_id(peer, "new@pam");
+        assert_eq!(new.get().as_deref(), Some("new@pam"));
+
+        drop(new);
+        assert!(!CONNECTION_AUTH_IDS.lock().unwrap().contains_key(&peer));
+    }
+
+    #[test]
+    fn test_peer_certificate_from_x509() {
+        let ca_key = generate_key();
+        let ca = generate_cert("Test CA", &ca_key, None, true);
+        let cert = generate_cert("client", &generate_key(), Some((&ca, &ca_key)), false);
+
+        let peer = PeerCertificate::from_x509(cert.clone()).unwrap();
+        assert_eq!(peer.subject(), "CN=client, O=Proxmox");
+        assert_eq!(peer.common_name(), Some("client"));
+        assert_eq!(
+            peer.subject_alt_names(),
+            [
+                "DNS:client.example.com",
+                "email:client@example.com",
+                "URI:https://example.com/client",
+                "IP:192.0.2.1",
+                "IP:2001:db8::1",
+            ]
+        );
+
+        let digest = cert.digest(MessageDigest::sha256()).unwrap();
+        let fingerprint = peer.fingerprint();
+        assert_eq!(fingerprint.len(), 32 * 3 - 1);
+        for (part, byte) in fingerprint.split(':').zip(digest.iter()) {
+            assert_eq!(u8::from_str_radix(part, 16).unwrap(), *byte);
+            assert_eq!(part, part.to_lowercase());
+        }
+        assert_eq!(peer.certificate().to_der().unwrap(), cert.to_der().unwrap());
+
+        // no common name
+        let cert = generate_cert("", &generate_key(), Some((&ca, &ca_key)), false);
+        let peer = PeerCertificate::from_x509(cert).unwrap();
+        assert_eq!(peer.subject(), "O=Proxmox");
+        assert_eq!(peer.common_name(), None);
+
+        // embedded NUL bytes must not be truncated or accepted
+        let cert = generate_cert(
+            "client\0.evil",
+            &generate_key(),
+            Some((&ca, &ca_key)),
+            false,
+        );
+        assert!(PeerCertificate::from_x509(cert).is_err());
+    }
+
+    /// Perform a handshake with `acceptor`, returning the verified peer certificate's common name.
+    fn handshake(
+        acceptor: &SslAcceptor,
+        client: Option<(&X509, &PKey<Private>)>,
+    ) -> Result<Option<String>, Error> {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async move {
+            let (server, _client) = tls_handshake(acceptor, client).await?;
+            Ok(PeerAddress::peer_certificate(&server)
+                .and_then(|cert| cert.common_name().map(str::to_string)))
+        })
+    }
+
+    #[test]
+    fn test_client_certificates() {
+        let ca_key = generate_key();
+        let ca = generate_cert("Test CA", &ca_key, None, true);
+        let other_ca_key = generate_key();
+        let other_ca = generate_cert("Other CA", &other_ca_key, None, true);
+
+        let valid_key = generate_key();
+        let valid = generate_cert("valid", &valid_key, Some((&ca, &ca_key)), false);
+        let valid = Some((&valid, &valid_key));
+        let invalid_key = generate_key();
+        let invalid = generate_cert(
+            "invalid",
+            &invalid_key,
+            Some((&other_ca, &other_ca_key)),
+            false,
+        );
+        let invalid = Some((&invalid, &invalid_key));
+        let self_signed_key = generate_key();
+        let self_signed = generate_cert("self-signed", &self_signed_key, None, false);
+        let self_signed = Some((&self_signed, &self_signed_key));
+
+        // client certificates are optional
+        let acceptor = TlsAcceptorBuilder::new()
+            .client_ca(vec![ca.clone()])
+            .build()
+            .unwrap();
+        assert_eq!(handshake(&acceptor, None).unwrap(), None);
+        assert_eq!(
+            handshake(&acceptor, valid).unwrap().as_deref(),
+            Some("valid")
+        );
+        assert!(handshake(&acceptor, invalid).is_err());
+        assert!(handshake(&acceptor, self_signed).is_err());
+
+        // client certificates are required
+        let acceptor = TlsAcceptorBuilder::new()
+            .client_ca(vec![ca.clone()])
+            .require_client_certificate(true)
+            .build()
+            .unwrap();
+        assert!(handshake(&acceptor, None).is_err());
+        assert_eq!(
+            handshake(&acceptor, valid).unwrap().as_deref(),
+            Some("valid")
+        );
+        assert!(handshake(&acceptor, invalid).is_err());
+        assert!(handshake(&acceptor, self_signed).is_err());
+
+        // client certificates are not requested without a client CA
+        let acceptor = TlsAcceptorBuilder::new().build().unwrap();
+        assert_eq!(handshake(&acceptor, valid).unwrap(), None);
+
+        // requiring a client certificate needs a client CA
+        assert!(TlsAcceptorBuilder::new()
+            .require_client_certificate(true)
+            .build()
+            .is_err());
+        assert!(TlsAcceptorBuilder::new()
+            .client_ca(Vec::new())
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn test_unverified_peer_certificate() {
+        let ca_key = generate_key();
+        let ca = generate_cert("Test CA", &ca_key, None, true);
+        let client_key = generate_key();
+        let client = generate_cert("client", &client_key, None, false);
+
+        // an acceptor which ignores verification errors must not expose the certificate
+        let server_key = generate_key();
+        let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+        acceptor.set_private_key(&server_key).unwrap();
+        acceptor
+            .set_certificate(&generate_cert("server", &server_key, None, false))
+            .unwrap();
+        acceptor.add_client_ca(&ca).unwrap();
+        acceptor.set_verify_callback(SslVerifyMode::PEER, |_, _| true);
+        let acceptor = acceptor.build();
+
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let (server, _client) = tls_handshake(&acceptor, Some((&client, &client_key)))
+                .await
+                .unwrap();
+            assert!(server.ssl().peer_certificate().is_some());
+            assert_ne!(
+                server.ssl().verify_result(),
+                openssl::x509::X509VerifyResult::OK
+            );
+            assert!(PeerAddress::peer_certificate(&server).is_none());
+        });
+    }
+
+    /// Run [`AcceptBuilder::do_accept_tls`] for a client offering the ALPN protocols `alpn`.
+    ///
+    /// Returns the protocol negotiated on the accepted connection, or `None` if the connection was
+    /// rejected.
+    fn accept_tls(acceptor: SslAcceptor, http2: bool, alpn: &[u8]) -> Option<Option<Vec<u8>>> {
+        let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+        connector.set_verify(SslVerifyMode::NONE);
+        connector.set_alpn_protos(alpn).unwrap();
+        let connector = connector.build();
+
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async move {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let (client, server) = futures::join!(TcpStream::connect(addr), listener.accept());
+            let (socket, _) = server.unwrap();
+            #[cfg(feature = "rate-limited-stream")]
+            let socket = RateLimitedStream::with_limiter(socket, None, None);
+
+            let client_ssl = connector
+                .configure()
+                .unwrap()
+                .into_ssl("localhost")
+                .unwrap();
+            let mut client = SslStream::new(client_ssl, client.unwrap()).unwrap();
+
+            let (sender, mut receiver) = mpsc::channel(1);
+            let (_, client_res) = futures::join!(
+                AcceptBuilder::do_accept_tls(
+                    socket,
+                    Arc::new(Mutex::new(acceptor)),
+                    Arc::new(()),
+                    false,
+                    http2,
+                    sender,
+                ),
+                Pin::new(&mut client).connect()
+            );
+            client_res.unwrap();
+
+            receiver.try_recv().ok().map(|stream| {
+                stream
+                    .unwrap()
+                    .ssl()
+                    .selected_alpn_protocol()
+                    .map(|protocol| protocol.to_vec())
+            })
+        })
+    }
+
+    #[test]
+    fn test_accept_tls_http2() {
+        const H2_HTTP1: &[u8] = b"\x02h2\x08http/1.1";
+        const HTTP1: &[u8] = b"\x08http/1.1";
+
+        let acceptor = || TlsAcceptorBuilder::new().http2(true).build().unwrap();
+
+        assert_eq!(
+            accept_tls(acceptor(), true, H2_HTTP1),
+            Some(Some(b"h2".to_vec()))
+        );
+        assert_eq!(
+            accept_tls(acceptor(), true, HTTP1),
+            Some(Some(b"http/1.1".to_vec()))
+        );
+
+        // h2 must not be served by an HTTP/1.1 only server
+        assert_eq!(accept_tls(acceptor(), false, H2_HTTP1), None);
+        assert_eq!(
+            accept_tls(acceptor(), false, HTTP1),
+            Some(Some(b"http/1.1".to_vec()))
+        );
+
+        // without HTTP/2 in the TLS acceptor, h2 is never negotiated
+        let acceptor = TlsAcceptorBuilder::new().build().unwrap();
+        assert_eq!(accept_tls(acceptor, false, H2_HTTP1), Some(None));
+    }
+}
@@ -6,6 +6,7 @@
 //! ## Features
 //!
 //! * highly threaded code, uses Rust async
+//! * HTTP/2 negotiated via ALPN, including websockets over HTTP/2 (RFC8441)
 //! * static API definitions using schemas
 //! * restartable systemd daemons using `systemd_notify`
 //! * support for long running worker tasks (threads or async tokio tasks)
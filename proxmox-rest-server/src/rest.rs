@@ -32,6 +32,7 @@ use proxmox_schema::{ObjectSchemaType, ParameterSchema};
 use proxmox_async::stream::AsyncReaderStream;
 use proxmox_compression::{DeflateEncoder, Level};
 
+use crate::connection::PeerCertificate;
 use crate::{
     formatter::*, normalize_path, ApiConfig, AuthError, CompressionMethod, FileLogger,
     RestEnvironment,
@@ -91,6 +92,7 @@ impl<T: PeerAddress> Service<&T> for RestServer {
             Err(err) => Err(format_err!("unable to get peer address - {}", err)),
             Ok(peer) => Ok(ApiService {
                 peer,
+                peer_certificate: ctx.peer_certificate().map(Arc::new),
                 api_config: Arc::clone(&self.api_config),
             }),
         })
@@ -172,6 +174,11 @@ impl Service<Request<Body>> for RedirectService {
 
 pub trait PeerAddress {
     fn peer_addr(&self) -> Result<std::net::SocketAddr, Error>;
+
+    /// The verified client certificate of TLS connections.
+    fn peer_certificate(&self) -> Option<PeerCertificate> {
+        None
+    }
 }
 
 // tokio_openssl's SslStream requires the stream to be pinned in order to accept it, and we need to
@@ -181,12 +188,31 @@ impl<T: PeerAddress> PeerAddress for Pin<Box<T>> {
     fn peer_addr(&self) -> Result<std::net::SocketAddr, Error> {
         T::peer_addr(&**self)
     }
+
+    fn peer_certificate(&self) -> Option<PeerCertificate> {
+        T::peer_certificate(&**self)
+    }
 }
 
 impl<T: PeerAddress> PeerAddress for tokio_openssl::SslStream<T> {
     fn peer_addr(&self) -> Result<std::net::SocketAddr, Error> {
         self.get_ref().peer_addr()
     }
+
+    fn peer_certificate(&self) -> Option<PeerCertificate> {
+        let ssl = self.ssl();
+        if ssl.verify_result() != openssl::x509::X509VerifyResult::OK {
+            return None;
+        }
+        let certificate = ssl.peer_certificate()?;
+        match PeerCertificate::from_x509(certificate) {
+            Ok(certificate) => Some(certificate),
+            Err(err) => {
+                log::error!("unable to parse client certificate - {}", err);
+                None
+            }
+        }
+    }
 }
 
 impl PeerAddress for tokio::net::TcpStream {
@@ -226,6 +252,7 @@ impl<T: PeerAddress> PeerAddress for proxmox_http::RateLimitedStream<T> {
 // not export it.
 pub struct ApiService {
     pub peer: std::net::SocketAddr,
+    pub peer_certificate: Option<Arc<PeerCertificate>>,
     pub api_config: Arc<ApiConfig>,
 }
 
@@ -310,6 +337,22 @@ fn get_user_agent(headers: &HeaderMap) -> Option<String> {
         .ok()
 }
 
+/// The peer address of the connection a request was received on, even if it was proxied.
+#[cfg(feature = "rate-limited-stream")]
+#[derive(Clone, Copy)]
+struct ConnectionPeer(std::net::SocketAddr);
+
+/// Make the authenticated user known to the rate limiter lookup of the connection.
+fn register_connection_auth_id(parts: &Parts, auth_id: &str) {
+    #[cfg(feature = "rate-limited-stream")]
+    if let Some(ConnectionPeer(peer)) = parts.extensions.get::<ConnectionPeer>() {
+        crate::connection::set_connection_auth_id(*peer, auth_id);
+    }
+
+    #[cfg(not(feature = "rate-limited-stream"))]
+    let _ = (parts, auth_id);
+}
+
 impl Service<Request<Body>> for ApiService {
     type Response = Response<Body>;
     type Error = Error;
@@ -320,7 +363,13 @@ impl Service<Request<Body>> for ApiService {
         Poll::Ready(Ok(()))
     }
 
-    fn call(&mut self, req: Request<Body>) -> Self::Future {
+    fn call(&mut self, mut req: Request<Body>) -> Self::Future {
+        #[cfg(feature = "rate-limited-stream")]
+        req.extensions_mut().insert(ConnectionPeer(self.peer));
+        if let Some(ref peer_certificate) = self.peer_certificate {
+            req.extensions_mut().insert(Arc::clone(peer_certificate));
+        }
+
         let path = req.uri().path_and_query().unwrap().as_str().to_owned();
         let method = req.method().clone();
         let user_agent = get_user_agent(req.headers());
@@ -818,7 +867,7 @@ impl ApiConfig {
         req: Request<Body>,
         peer: &std::net::SocketAddr,
     ) -> Result<Response<Body>, Error> {
-        let (parts, body) = req.into_parts();
+        let (mut parts, body) = req.into_parts();
         let method = parts.method.clone();
         let path = normalize_path(parts.uri.path())?;
         let components: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
@@ -835,8 +884,10 @@ impl ApiConfig {
         let mut rpcenv = RestEnvironment::new(env_type, Arc::clone(&self));
 
         rpcenv.set_client_ip(Some(*peer));
+        rpcenv.set_peer_certificate(parts.extensions.get::<Arc<PeerCertificate>>().cloned());
 
         if let Some(handler) = self.find_handler(&components) {
+            route_extended_connect(&mut parts);
             let relative_path_components = &components[handler.prefix.len()..];
             return handler
                 .handle_request(ApiRequestData {
@@ -856,8 +907,9 @@ impl ApiConfig {
         }
 
         if components.is_empty() {
-            match self.check_auth(&parts.headers, &method).await {
+            match self.check_auth(&parts.headers, &method, &rpcenv).await {
                 Ok((auth_id, _user_info)) => {
+                    register_connection_auth_id(&parts, &auth_id);
                     rpcenv.set_auth_id(Some(auth_id));
                     return Ok(self.get_index(rpcenv, parts).await);
                 }
@@ -875,6 +927,18 @@ impl ApiConfig {
     }
 }
 
+/// Websockets over HTTP/2 use an extended CONNECT request (RFC8441) instead of an HTTP/1.1
+/// upgrade `GET` request. Route these to the `GET` method of the api path, the handler can
+/// detect them via the [`hyper::ext::Protocol`] request extension.
+fn route_extended_connect(parts: &mut Parts) {
+    let protocol = parts.extensions.get::<hyper::ext::Protocol>();
+    if parts.method == hyper::Method::CONNECT
+        && protocol.map(|protocol| protocol.as_str()) == Some("websocket")
+    {
+        parts.method = hyper::Method::GET;
+    }
+}
+
 pub(crate) struct Handler {
     pub prefix: &'static [&'static str],
     action: Action,
@@ -981,8 +1045,12 @@ impl Formatted {
             Box::new(EmptyUserInformation {});
 
         if auth_required {
-            match config.check_auth(&parts.headers, &parts.method).await {
+            match config
+                .check_auth(&parts.headers, &parts.method, &rpcenv)
+                .await
+            {
                 Ok((authid, info)) => {
+                    register_connection_auth_id(&parts, &authid);
                     rpcenv.set_auth_id(Some(authid));
                     user_info = info;
                 }
@@ -1087,8 +1155,12 @@ impl Unformatted {
         let user_info: Box<dyn UserInformation + Send + Sync>;
 
         if auth_required {
-            match config.check_auth(&parts.headers, &parts.method).await {
+            match config
+                .check_auth(&parts.headers, &parts.method, &rpcenv)
+                .await
+            {
                 Ok((authid, info)) => {
+                    register_connection_auth_id(&parts, &authid);
                     rpcenv.set_auth_id(Some(authid));
                     user_info = info;
                 }
@@ -1153,3 +1225,213 @@ impl Unformatted {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use hyper::client::conn::{Builder, SendRequest};
+    use hyper::ext::Protocol;
+    use hyper::server::conn::Http;
+    use hyper::Method;
+    use tokio::io::DuplexStream;
+
+    use proxmox_router::{ApiResponseFuture, Router};
+    use proxmox_schema::ObjectSchema;
+
+    use super::*;
+    use crate::api_config::CheckAuthFuture;
+    use crate::connection::test::{generate_cert, generate_key, tls_handshake};
+    use crate::connection::{AcceptBuilder, TlsAcceptorBuilder};
+
+    fn echo_request(
+        parts: Parts,
+        _req_body: Body,
+        _param: Value,
+        _info: &ApiMethod,
+        _rpcenv: Box<dyn RpcEnvironment>,
+    ) -> ApiResponseFuture {
+        Box::pin(async move {
+            let protocol = parts
+                .extensions
+                .get::<Protocol>()
+                .map(|protocol| protocol.as_str().to_string())
+                .unwrap_or_default();
+            // the body of a successful CONNECT response is not sent, the stream is upgraded instead
+            Ok(Response::builder()
+                .header("echo", format!("{} {}", parts.method, protocol))
+                .body(Body::empty())?)
+        })
+    }
+
+    const API_METHOD_ECHO: ApiMethod = ApiMethod::new(
+        &ApiHandler::AsyncHttp(&echo_request),
+        &ObjectSchema::new("Echo the request method and protocol.", &[]),
+    )
+    .access(None, &Permission::World);
+
+    const ROUTER: Router = Router::new().get(&API_METHOD_ECHO);
+
+    fn echo_auth_id(
+        _parts: Parts,
+        _req_body: Body,
+        _param: Value,
+        _info: &ApiMethod,
+        rpcenv: Box<dyn RpcEnvironment>,
+    ) -> ApiResponseFuture {
+        Box::pin(async move {
+            Ok(Response::builder()
+                .header("echo", rpcenv.get_auth_id().unwrap_or_default())
+                .body(Body::empty())?)
+        })
+    }
+
+    const API_METHOD_ECHO_AUTH_ID: ApiMethod = ApiMethod::new(
+        &ApiHandler::AsyncHttp(&echo_auth_id),
+        &ObjectSchema::new("Echo the authenticated auth id.", &[]),
+    )
+    .access(None, &Permission::Anybody);
+
+    const AUTH_ID_ROUTER: Router = Router::new().get(&API_METHOD_ECHO_AUTH_ID);
+
+    /// Map the common name of the client certificate to an auth id.
+    fn certificate_auth<'a>(
+        _headers: &'a HeaderMap,
+        _method: &'a Method,
+        rpcenv: &'a RestEnvironment,
+    ) -> CheckAuthFuture<'a> {
+        Box::pin(async move {
+            match rpcenv
+                .peer_certificate()
+                .and_then(|cert| cert.common_name())
+            {
+                Some(name) => Ok((
+                    format!("{}@cert", name),
+                    Box::new(EmptyUserInformation {}) as Box<dyn UserInformation + Send + Sync>,
+                )),
+                None => Err(AuthError::NoData),
+            }
+        })
+    }
+
+    /// Serve an [`ApiService`] on a connection configured by [`AcceptBuilder::http_protocol`].
+    async fn connect_h2(http2: bool) -> Result<SendRequest<Body>, Error> {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+
+        let config =
+            ApiConfig::new("/var/tmp/", RpcEnvironmentType::PUBLIC).default_api2_handler(&ROUTER);
+        let service = ApiService {
+            peer: ([127, 0, 0, 1], 8007).into(),
+            peer_certificate: None,
+            api_config: Arc::new(config),
+        };
+        let protocol = AcceptBuilder::new().http2(http2).http_protocol();
+        tokio::spawn(async move { protocol.serve_connection(server, service).await });
+
+        let (sender, connection) = Builder::new()
+            .http2_only(true)
+            .handshake::<DuplexStream, Body>(client)
+            .await?;
+        tokio::spawn(connection);
+        Ok(sender)
+    }
+
+    async fn send(
+        sender: &mut SendRequest<Body>,
+        method: Method,
+        protocol: Option<&'static str>,
+    ) -> Result<(StatusCode, String), Error> {
+        let mut request = Request::builder()
+            .method(method)
+            .uri("https://localhost/api2/json")
+            .body(Body::empty())?;
+        if let Some(protocol) = protocol {
+            request
+                .extensions_mut()
+                .insert(Protocol::from_static(protocol));
+        }
+
+        futures::future::poll_fn(|cx| sender.poll_ready(cx)).await?;
+        let response = sender.send_request(request).await?;
+        let echo = match response.headers().get("echo") {
+            Some(echo) => echo.to_str()?.to_string(),
+            None => String::new(),
+        };
+        Ok((response.status(), echo))
+    }
+
+    #[test]
+    fn test_extended_connect() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let mut sender = connect_h2(true).await.unwrap();
+
+            let (status, echo) = send(&mut sender, Method::GET, None).await.unwrap();
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(echo, "GET ");
+
+            // websockets over HTTP/2 are routed to the GET method, keeping the protocol
+            let (status, echo) = send(&mut sender, Method::CONNECT, Some("websocket"))
+                .await
+                .unwrap();
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(echo, "GET websocket");
+
+            // other protocols are not rewritten, no method is found and authentication fails
+            let (status, _) = send(&mut sender, Method::CONNECT, Some("other"))
+                .await
+                .unwrap();
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+            // HTTP/2 is only served if enabled
+            let res = match connect_h2(false).await {
+                Ok(mut sender) => send(&mut sender, Method::GET, None).await,
+                Err(err) => Err(err),
+            };
+            assert!(res.is_err());
+        });
+    }
+
+    #[test]
+    fn test_peer_certificate_auth() {
+        let ca_key = generate_key();
+        let ca = generate_cert("Test CA", &ca_key, None, true);
+        let client_key = generate_key();
+        let client = generate_cert("inventory", &client_key, Some((&ca, &ca_key)), false);
+
+        let acceptor = TlsAcceptorBuilder::new()
+            .client_ca(vec![ca])
+            .build()
+            .unwrap();
+        let config = ApiConfig::new("/var/tmp/", RpcEnvironmentType::PUBLIC)
+            .default_api2_handler(&AUTH_ID_ROUTER)
+            .auth_handler_func_with_env(certificate_auth);
+        let mut server = RestServer::new(config);
+
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            for (certificate, expected) in [
+                (
+                    Some((&client, &client_key)),
+                    (StatusCode::OK, "inventory@cert"),
+                ),
+                (None, (StatusCode::UNAUTHORIZED, "")),
+            ] {
+                let (stream, client) = tls_handshake(&acceptor, certificate).await.unwrap();
+                let service = server.call(&stream).await.unwrap();
+                tokio::spawn(Http::new().serve_connection(stream, service));
+
+                let (mut sender, connection) =
+                    Builder::new().handshake::<_, Body>(client).await.unwrap();
+                tokio::spawn(connection);
+
+                let (status, echo) = send(&mut sender, Method::GET, None).await.unwrap();
+                assert_eq!((status, echo.as_str()), expected);
+            }
+        });
+    }
+}
@@ -70,3 +70,8 @@ pub fn run_command(
 
     Ok(output)
 }
+
+#[cfg(feature = "async-command")]
+mod runner;
+#[cfg(feature = "async-command")]
+pub use runner::{CommandRunner, OutputLine, OutputStream};
@@ -0,0 +1,458 @@
+//! Asynchronous command execution with timeouts and line based output streaming.
+
+use std::ffi::{CString, OsString};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Command, ExitStatus, Stdio};
+use std::time::Duration;
+
+use anyhow::{bail, format_err, Error};
+use nix::sys::resource::{setrlimit, Resource};
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+use crate::WorkerTaskContext;
+
+/// The default time a process gets to exit after `SIGTERM` before it is killed with `SIGKILL`.
+const DEFAULT_KILL_GRACE: Duration = Duration::from_secs(10);
+
+/// Number of trailing stderr lines included in the error of a failed command.
+const ERROR_CONTEXT_LINES: usize = 5;
+
+/// The output stream a line was read from.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// A single line of output, without the trailing newline.
+///
+/// Invalid UTF-8 is replaced with `U+FFFD`.
+#[derive(Clone, Copy, Debug)]
+pub struct OutputLine<'a> {
+    pub stream: OutputStream,
+    pub line: &'a str,
+}
+
+/// Runs a [`Command`] asynchronously.
+///
+/// In contrast to [`run_command`](super::run_command), the output is not buffered as a whole but
+/// passed on line by line, and the command can be limited in run time. When the timeout elapses
+/// the command's process group is sent `SIGTERM`, followed by `SIGKILL` if it does not exit within
+/// the kill grace period.
+///
+/// ```no_run
+/// # use std::process::Command;
+/// # use std::time::Duration;
+/// # use proxmox_sys::command::CommandRunner;
+/// # async fn run() -> Result<(), anyhow::Error> {
+/// let mut command = Command::new("zpool");
+/// command.args(["status", "-P"]);
+///
+/// let output = CommandRunner::new(command)
+///     .timeout(Duration::from_secs(30))
+///     .keep_env(["PATH", "LANG"])
+///     .output()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct CommandRunner {
+    command: Command,
+    timeout: Option<Duration>,
+    kill_grace: Duration,
+    exit_code_check: Option<fn(i32) -> bool>,
+    rlimits: Vec<(Resource, u64, u64)>,
+    cgroup: Option<CString>,
+}
+
+impl CommandRunner {
+    /// Prepare to run a command.
+    ///
+    /// Stdin is connected to `/dev/null`, stdout and stderr are always captured.
+    pub fn new(command: Command) -> Self {
+        Self {
+            command,
+            timeout: None,
+            kill_grace: DEFAULT_KILL_GRACE,
+            exit_code_check: None,
+            rlimits: Vec::new(),
+            cgroup: None,
+        }
+    }
+
+    /// Limit the total run time of the command.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Set the time a timed out command gets to exit after `SIGTERM` before being killed with
+    /// `SIGKILL`. Defaults to 10 seconds.
+    pub fn kill_grace(mut self, grace: Duration) -> Self {
+        self.kill_grace = grace;
+        self
+    }
+
+    /// Set a function deciding which exit codes are considered successful. By default only 0 is.
+    pub fn exit_code_check(mut self, check: fn(i32) -> bool) -> Self {
+        self.exit_code_check = Some(check);
+        self
+    }
+
+    /// Set a resource limit for the command.
+    pub fn rlimit(mut self, resource: Resource, soft: u64, hard: u64) -> Self {
+        self.rlimits.push((resource, soft, hard));
+        self
+    }
+
+    /// Move the command into a cgroup v2 control group, given as its directory, e.g.
+    /// `/sys/fs/cgroup/system.slice/foo.service`.
+    pub fn cgroup<P: AsRef<Path>>(mut self, cgroup: P) -> Result<Self, Error> {
+        let procs = cgroup.as_ref().join("cgroup.procs");
+        self.cgroup = Some(CString::new(procs.as_os_str().as_bytes())?);
+        Ok(self)
+    }
+
+    /// Start the command with an empty environment.
+    pub fn clear_env(mut self) -> Self {
+        self.command.env_clear();
+        self
+    }
+
+    /// Start the command with only the listed variables of the current environment, plus any
+    /// explicitly set on the [`Command`] afterwards.
+    pub fn keep_env<I, K>(mut self, keep: I) -> Self
+    where
+        I: IntoIterator<Item = K>,
+        K: Into<OsString>,
+    {
+        let keep: Vec<OsString> = keep.into_iter().map(Into::into).collect();
+        self.command.env_clear();
+        for (key, value) in std::env::vars_os() {
+            if keep.contains(&key) {
+                self.command.env(key, value);
+            }
+        }
+        self
+    }
+
+    /// Get access to the command, e.g. to set environment variables after clearing the
+    /// environment.
+    pub fn command_mut(&mut self) -> &mut Command {
+        &mut self.command
+    }
+
+    /// Run the command, passing each line of output to `callback`.
+    ///
+    /// Fails if the command times out or exits unsuccessfully, in the latter case the error
+    /// contains the last lines of stderr.
+    pub async fn run<F>(self, callback: F) -> Result<ExitStatus, Error>
+    where
+        F: FnMut(OutputLine),
+    {
+        self.run_inner(callback, None).await
+    }
+
+    /// Run the command, logging stdout as info and stderr as warnings to a worker task.
+    ///
+    /// The command is also terminated if the worker task gets aborted.
+    pub async fn run_with_worker(
+        self,
+        worker: &dyn WorkerTaskContext,
+    ) -> Result<ExitStatus, Error> {
+        self.run_inner(
+            |line| {
+                let level = match line.stream {
+                    OutputStream::Stdout => log::Level::Info,
+                    OutputStream::Stderr => log::Level::Warn,
+                };
+                worker.log(level, &format_args!("{}", line.line));
+            },
+            Some(worker),
+        )
+        .await
+    }
+
+    /// Run the command and return its stdout as a string.
+    pub async fn output(self) -> Result<String, Error> {
+        let mut stdout = String::new();
+        self.run(|line| {
+            if line.stream == OutputStream::Stdout {
+                stdout.push_str(line.line);
+                stdout.push('\n');
+            }
+        })
+        .await?;
+        Ok(stdout)
+    }
+
+    async fn run_inner<F>(
+        mut self,
+        mut callback: F,
+        worker: Option<&dyn WorkerTaskContext>,
+    ) -> Result<ExitStatus, Error>
+    where
+        F: FnMut(OutputLine),
+    {
+        let rlimits = std::mem::take(&mut self.rlimits);
+        let cgroup = self.cgroup.take();
+        unsafe {
+            // Only async-signal-safe calls in here, we may be a multi-threaded process.
+            self.command.pre_exec(move || {
+                if libc::setpgid(0, 0) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                for (resource, soft, hard) in rlimits.iter() {
+                    setrlimit(*resource, *soft, *hard)?;
+                }
+                if let Some(procs) = &cgroup {
+                    let fd = libc::open(procs.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+                    if fd < 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    // "0" refers to the writing process itself
+                    let rc = libc::write(fd, b"0".as_ptr() as *const libc::c_void, 1);
+                    let err = std::io::Error::last_os_error();
+                    libc::close(fd);
+                    if rc != 1 {
+                        return Err(err);
+                    }
+                }
+                Ok(())
+            });
+        }
+
+        let name = format!("{:?}", self.command);
+        let mut command = tokio::process::Command::from(self.command);
+        command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+
+        let mut child = command
+            .spawn()
+            .map_err(|err| format_err!("failed to execute {} - {}", name, err))?;
+        let pgid = Pid::from_raw(
+            child
+                .id()
+                .ok_or_else(|| format_err!("failed to get pid of {}", name))? as i32,
+        );
+
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+        let mut stderr = BufReader::new(child.stderr.take().unwrap());
+        let (mut stdout_buf, mut stderr_buf) = (Vec::new(), Vec::new());
+        let (mut stdout_done, mut stderr_done) = (false, false);
+        let mut last_errors: Vec<String> = Vec::new();
+        let mut status = None;
+
+        let deadline = tokio::time::sleep(self.timeout.unwrap_or(Duration::MAX / 2));
+        tokio::pin!(deadline);
+        let mut abort_check = tokio::time::interval(Duration::from_secs(1));
+
+        let mut emit = |stream: OutputStream, buf: &mut Vec<u8>| {
+            if buf.is_empty() {
+                return;
+            }
+            let mut line = &buf[..];
+            if let Some(rest) = line.strip_suffix(b"\n") {
+                line = rest.strip_suffix(b"\r").unwrap_or(rest);
+            }
+            let line = String::from_utf8_lossy(line);
+            if stream == OutputStream::Stderr {
+                if last_errors.len() == ERROR_CONTEXT_LINES {
+                    last_errors.remove(0);
+                }
+                last_errors.push(line.to_string());
+            }
+            callback(OutputLine {
+                stream,
+                line: &line,
+            });
+            buf.clear();
+        };
+
+        let failure = loop {
+            if stdout_done && stderr_done && status.is_some() {
+                break None;
+            }
+
+            // read_until keeps partially read data in the buffer when cancelled, so a final line
+            // without a newline may only show up once EOF is reached
+            tokio::select! {
+                res = stdout.read_until(b'\n', &mut stdout_buf), if !stdout_done => {
+                    if res? == 0 {
+                        stdout_done = true;
+                    }
+                    if stdout_done || stdout_buf.ends_with(b"\n") {
+                        emit(OutputStream::Stdout, &mut stdout_buf);
+                    }
+                }
+                res = stderr.read_until(b'\n', &mut stderr_buf), if !stderr_done => {
+                    if res? == 0 {
+                        stderr_done = true;
+                    }
+                    if stderr_done || stderr_buf.ends_with(b"\n") {
+                        emit(OutputStream::Stderr, &mut stderr_buf);
+                    }
+                }
+                res = child.wait(), if status.is_none() => status = Some(res?),
+                _ = &mut deadline, if self.timeout.is_some() => {
+                    break Some(format_err!(
+                        "command {} timed out after {:?}",
+                        name,
+                        self.timeout.unwrap(),
+                    ));
+                }
+                _ = abort_check.tick(), if worker.is_some() => {
+                    if let Err(err) = worker.unwrap().check_abort() {
+                        break Some(err);
+                    }
+                }
+            }
+        };
+
+        if let Some(err) = failure {
+            if status.is_none() {
+                let _ = kill(Pid::from_raw(-pgid.as_raw()), Signal::SIGTERM);
+                if tokio::time::timeout(self.kill_grace, child.wait())
+                    .await
+                    .is_err()
+                {
+                    let _ = kill(Pid::from_raw(-pgid.as_raw()), Signal::SIGKILL);
+                    child.wait().await?;
+                }
+            } else {
+                // the main process exited, but something is still holding on to the output
+                let _ = kill(Pid::from_raw(-pgid.as_raw()), Signal::SIGKILL);
+            }
+            return Err(err);
+        }
+
+        let status = status.unwrap();
+        let is_ok = match status.code() {
+            Some(code) => match self.exit_code_check {
+                Some(check_fn) => check_fn(code),
+                None => code == 0,
+            },
+            None => false,
+        };
+
+        if !is_ok {
+            let msg = if last_errors.is_empty() {
+                String::from("no error message")
+            } else {
+                last_errors.join("\n")
+            };
+            match status.code() {
+                Some(code) => bail!("command {} failed - status code: {} - {}", name, code, msg),
+                None => bail!("command {} failed - terminated by signal - {}", name, msg),
+            }
+        }
+
+        Ok(status)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    fn shell(script: &str) -> Command {
+        let mut command = Command::new("sh");
+        command.args(["-c", script]);
+        command
+    }
+
+    #[test]
+    fn test_streaming_output() {
+        let mut lines = Vec::new();
+        let status = block_on(
+            CommandRunner::new(shell("echo one; echo two >&2; printf three"))
+                .run(|line| lines.push((line.stream, line.line.to_string()))),
+        )
+        .unwrap();
+        assert!(status.success());
+
+        let stdout: Vec<_> = lines
+            .iter()
+            .filter(|(stream, _)| *stream == OutputStream::Stdout)
+            .map(|(_, line)| line.as_str())
+            .collect();
+        assert_eq!(stdout, ["one", "three"]);
+        assert!(lines.contains(&(OutputStream::Stderr, "two".to_string())));
+    }
+
+    #[test]
+    fn test_exit_code() {
+        let err = block_on(CommandRunner::new(shell("echo failed >&2; exit 3")).output())
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("status code: 3"), "unexpected error: {err}");
+        assert!(err.contains("failed"), "unexpected error: {err}");
+
+        block_on(
+            CommandRunner::new(shell("exit 3"))
+                .exit_code_check(|code| code == 3)
+                .output(),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_timeout_escalation() {
+        let start = std::time::Instant::now();
+        let err = block_on(
+            CommandRunner::new(shell("trap '' TERM; sleep 30"))
+                .timeout(Duration::from_millis(200))
+                .kill_grace(Duration::from_millis(200))
+                .output(),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("timed out"));
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_environment() {
+        std::env::set_var("PROXMOX_SYS_RUNNER_TEST", "1");
+        let output = block_on(
+            CommandRunner::new(Command::new("/usr/bin/env"))
+                .keep_env(["PROXMOX_SYS_RUNNER_TEST"])
+                .output(),
+        )
+        .unwrap();
+        assert_eq!(output, "PROXMOX_SYS_RUNNER_TEST=1\n");
+
+        let output = block_on(
+            CommandRunner::new(Command::new("/usr/bin/env"))
+                .clear_env()
+                .output(),
+        )
+        .unwrap();
+        assert_eq!(output, "");
+    }
+
+    #[test]
+    fn test_rlimit() {
+        let output = block_on(
+            CommandRunner::new(shell("ulimit -n"))
+                .rlimit(Resource::RLIMIT_NOFILE, 64, 64)
+                .output(),
+        )
+        .unwrap();
+        assert_eq!(output, "64\n");
+    }
+}
@@ -0,0 +1,633 @@
+//! Copying file metadata between files.
+
+use std::ffi::CStr;
+use std::fmt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+
+use anyhow::{format_err, Error};
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
+use nix::sys::stat::{self, FileStat, Mode, SFlag};
+use nix::sys::time::TimeSpec;
+use nix::unistd::{Gid, Uid};
+
+use super::xattr::{
+    fgetxattr, flistxattr, fremovexattr, fsetxattr, is_acl, is_security_capability,
+    is_valid_xattr_name, xattr_acl_access, xattr_acl_default, xattr_name_fcaps,
+};
+use super::{fs_ioc_fsgetxattr, fs_ioc_fssetxattr, FSXAttr};
+
+/// Read only flag reported by `FS_IOC_FSGETXATTR`, cannot be set.
+const FS_XFLAG_HASATTR: u32 = 0x8000_0000;
+
+/// Categories of metadata handled by [`copy_metadata`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MetadataCategory {
+    Ownership,
+    Mode,
+    Timestamps,
+    /// `user.`, `trusted.` and `security.NTACL` extended attributes.
+    XAttrs,
+    /// POSIX access and default ACLs.
+    Acls,
+    /// File capabilities.
+    FCaps,
+    /// Flags like immutable or append-only and the project id, see `FS_IOC_FSGETXATTR`.
+    FsXAttr,
+}
+
+impl fmt::Display for MetadataCategory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            MetadataCategory::Ownership => "ownership",
+            MetadataCategory::Mode => "mode",
+            MetadataCategory::Timestamps => "timestamps",
+            MetadataCategory::XAttrs => "xattrs",
+            MetadataCategory::Acls => "acls",
+            MetadataCategory::FCaps => "file capabilities",
+            MetadataCategory::FsXAttr => "fsxattr flags",
+        })
+    }
+}
+
+/// Selects the metadata copied by [`copy_metadata`]. Everything is selected by default.
+#[derive(Clone, Debug)]
+pub struct CopyMetadataOptions {
+    pub ownership: bool,
+    pub mode: bool,
+    pub timestamps: bool,
+    pub xattrs: bool,
+    pub acls: bool,
+    pub fcaps: bool,
+    pub fsxattr: bool,
+}
+
+impl Default for CopyMetadataOptions {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+impl CopyMetadataOptions {
+    pub const fn all() -> Self {
+        Self {
+            ownership: true,
+            mode: true,
+            timestamps: true,
+            xattrs: true,
+            acls: true,
+            fcaps: true,
+            fsxattr: true,
+        }
+    }
+
+    pub const fn none() -> Self {
+        Self {
+            ownership: false,
+            mode: false,
+            timestamps: false,
+            xattrs: false,
+            acls: false,
+            fcaps: false,
+            fsxattr: false,
+        }
+    }
+}
+
+/// An error copying one category of metadata.
+#[derive(Debug)]
+pub struct MetadataError {
+    /// The affected path, relative to the source directory for recursive copies.
+    pub path: Option<PathBuf>,
+    /// `None` for errors affecting the whole file, like failing to open it.
+    pub category: Option<MetadataCategory>,
+    pub error: Error,
+}
+
+impl fmt::Display for MetadataError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(path) = &self.path {
+            write!(f, "{path:?}: ")?;
+        }
+        match self.category {
+            Some(category) => write!(f, "copying {category} failed - {}", self.error),
+            None => write!(f, "{}", self.error),
+        }
+    }
+}
+
+/// The errors which occurred while copying metadata.
+#[derive(Debug, Default)]
+pub struct MetadataErrors {
+    pub errors: Vec<MetadataError>,
+}
+
+impl MetadataErrors {
+    fn push(&mut self, category: Option<MetadataCategory>, error: Error) {
+        self.errors.push(MetadataError {
+            path: None,
+            category,
+            error,
+        });
+    }
+
+    fn into_result(self) -> Result<(), Self> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl fmt::Display for MetadataErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, error) in self.errors.iter().enumerate() {
+            if i > 0 {
+                f.write_str("; ")?;
+            }
+            write!(f, "{error}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for MetadataErrors {}
+
+/// Copy metadata from `src` to `dst`.
+///
+/// All selected categories are attempted, errors are collected instead of aborting on the first
+/// one. Categories not supported by the source file system are skipped, attributes missing on
+/// the source are removed from the destination.
+///
+/// Flags like immutable are applied last, as they prevent any further changes.
+pub fn copy_metadata(
+    src: RawFd,
+    dst: RawFd,
+    options: &CopyMetadataOptions,
+) -> Result<(), MetadataErrors> {
+    let mut errors = MetadataErrors::default();
+
+    let stat = match stat::fstat(src) {
+        Ok(stat) => stat,
+        Err(err) => {
+            errors.push(None, format_err!("unable to stat source - {err}"));
+            return errors.into_result();
+        }
+    };
+    let is_dir = SFlag::from_bits_truncate(stat.st_mode) & SFlag::S_IFMT == SFlag::S_IFDIR;
+
+    let mut step = |enabled: bool, category, func: &dyn Fn() -> Result<(), Error>| {
+        if enabled {
+            if let Err(err) = func() {
+                errors.push(Some(category), err);
+            }
+        }
+    };
+
+    // changing the owner clears set-id bits and file capabilities, so do it first
+    step(options.ownership, MetadataCategory::Ownership, &|| {
+        copy_ownership(dst, &stat)
+    });
+    step(options.mode, MetadataCategory::Mode, &|| {
+        stat::fchmod(dst, Mode::from_bits_truncate(stat.st_mode))?;
+        Ok(())
+    });
+    // the access ACL includes the group permission bits, so set it after the mode
+    step(options.acls, MetadataCategory::Acls, &|| {
+        copy_xattr(src, dst, xattr_acl_access())?;
+        if is_dir {
+            copy_xattr(src, dst, xattr_acl_default())?;
+        }
+        Ok(())
+    });
+    step(options.xattrs, MetadataCategory::XAttrs, &|| {
+        copy_xattrs(src, dst)
+    });
+    step(options.fcaps, MetadataCategory::FCaps, &|| {
+        copy_xattr(src, dst, xattr_name_fcaps())
+    });
+    step(options.timestamps, MetadataCategory::Timestamps, &|| {
+        copy_timestamps(dst, &stat)
+    });
+    step(options.fsxattr, MetadataCategory::FsXAttr, &|| {
+        copy_fsxattr(src, dst)
+    });
+
+    errors.into_result()
+}
+
+fn copy_ownership(dst: RawFd, stat: &FileStat) -> Result<(), Error> {
+    nix::unistd::fchown(
+        dst,
+        Some(Uid::from_raw(stat.st_uid)),
+        Some(Gid::from_raw(stat.st_gid)),
+    )?;
+    Ok(())
+}
+
+fn timestamps(stat: &FileStat) -> (TimeSpec, TimeSpec) {
+    (
+        TimeSpec::new(stat.st_atime, stat.st_atime_nsec),
+        TimeSpec::new(stat.st_mtime, stat.st_mtime_nsec),
+    )
+}
+
+fn copy_timestamps(dst: RawFd, stat: &FileStat) -> Result<(), Error> {
+    let (atime, mtime) = timestamps(stat);
+    stat::futimens(dst, &atime, &mtime)?;
+    Ok(())
+}
+
+fn xattr_unsupported(err: Errno) -> bool {
+    matches!(err, Errno::EOPNOTSUPP)
+}
+
+/// Copy a single attribute, removing it from `dst` if `src` does not have it.
+fn copy_xattr(src: RawFd, dst: RawFd, name: &CStr) -> Result<(), Error> {
+    match fgetxattr(src, name) {
+        Ok(value) => fsetxattr(dst, name, &value)?,
+        Err(Errno::ENODATA) => match fremovexattr(dst, name) {
+            Ok(()) | Err(Errno::ENODATA) => (),
+            Err(err) if xattr_unsupported(err) => (),
+            Err(err) => return Err(err.into()),
+        },
+        Err(err) if xattr_unsupported(err) => (),
+        Err(err) => return Err(err.into()),
+    }
+    Ok(())
+}
+
+fn copied_xattr(name: &CStr) -> bool {
+    is_valid_xattr_name(name) && !is_security_capability(name) && !is_acl(name)
+}
+
+fn copy_xattrs(src: RawFd, dst: RawFd) -> Result<(), Error> {
+    let src_list = match flistxattr(src) {
+        Ok(list) => list,
+        Err(err) if xattr_unsupported(err) => return Ok(()),
+        Err(err) => return Err(format_err!("listing source xattrs failed - {err}")),
+    };
+
+    let mut failed = Vec::new();
+    for name in src_list.into_iter().filter(|name| copied_xattr(name)) {
+        let result = match fgetxattr(src, name) {
+            Ok(value) => fsetxattr(dst, name, &value),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            failed.push(format!("{} ({err})", name.to_string_lossy()));
+        }
+    }
+
+    match flistxattr(dst) {
+        Ok(dst_list) => {
+            for name in dst_list.into_iter().filter(|name| copied_xattr(name)) {
+                if src_list.into_iter().any(|src_name| src_name == name) {
+                    continue;
+                }
+                if let Err(err) = fremovexattr(dst, name) {
+                    failed.push(format!("removing {} ({err})", name.to_string_lossy()));
+                }
+            }
+        }
+        Err(err) if xattr_unsupported(err) => (),
+        Err(err) => failed.push(format!("listing destination xattrs ({err})")),
+    }
+
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(format_err!("{}", failed.join(", ")))
+    }
+}
+
+fn fsxattr_unsupported(err: Errno) -> bool {
+    matches!(err, Errno::ENOTTY | Errno::EOPNOTSUPP | Errno::EINVAL)
+}
+
+fn copy_fsxattr(src: RawFd, dst: RawFd) -> Result<(), Error> {
+    let mut src_attr = FSXAttr::default();
+    match unsafe { fs_ioc_fsgetxattr(src, &mut src_attr) } {
+        Ok(_) => (),
+        Err(err) if fsxattr_unsupported(err) => return Ok(()),
+        Err(err) => return Err(err.into()),
+    }
+    let xflags = src_attr.fsx_xflags & !FS_XFLAG_HASATTR;
+
+    let mut dst_attr = FSXAttr::default();
+    match unsafe { fs_ioc_fsgetxattr(dst, &mut dst_attr) } {
+        Ok(_) => (),
+        Err(err) if fsxattr_unsupported(err) && xflags == 0 && src_attr.fsx_projid == 0 => {
+            return Ok(())
+        }
+        Err(err) => return Err(err.into()),
+    }
+
+    if dst_attr.fsx_xflags & !FS_XFLAG_HASATTR == xflags
+        && dst_attr.fsx_projid == src_attr.fsx_projid
+    {
+        return Ok(());
+    }
+
+    dst_attr.fsx_xflags = xflags;
+    dst_attr.fsx_projid = src_attr.fsx_projid;
+    unsafe { fs_ioc_fssetxattr(dst, &dst_attr) }?;
+    Ok(())
+}
+
+/// Copy metadata of a whole directory tree onto an existing tree with the same layout, like
+/// after copying the contents without metadata.
+///
+/// Entries missing in `dst` are reported as errors. Regular files and directories get all
+/// selected metadata, symbolic links and special files only ownership, mode (not for symbolic
+/// links) and timestamps. Directories are handled after their contents.
+pub fn copy_metadata_recursive<P: AsRef<Path>, Q: AsRef<Path>>(
+    src: P,
+    dst: Q,
+    options: &CopyMetadataOptions,
+) -> Result<(), MetadataErrors> {
+    let mut errors = MetadataErrors::default();
+    copy_metadata_tree(
+        src.as_ref(),
+        dst.as_ref(),
+        Path::new(""),
+        options,
+        &mut errors,
+    );
+    errors.into_result()
+}
+
+fn copy_metadata_tree(
+    src_root: &Path,
+    dst_root: &Path,
+    relative: &Path,
+    options: &CopyMetadataOptions,
+    errors: &mut MetadataErrors,
+) {
+    let src = src_root.join(relative);
+    let dst = dst_root.join(relative);
+
+    let mut add_error = |category, error: Error| {
+        errors.errors.push(MetadataError {
+            path: Some(relative.to_path_buf()),
+            category,
+            error,
+        });
+    };
+
+    let file_type = match std::fs::symlink_metadata(&src) {
+        Ok(metadata) => metadata.file_type(),
+        Err(err) => return add_error(None, format_err!("unable to stat source - {err}")),
+    };
+    let dst_type = match std::fs::symlink_metadata(&dst) {
+        Ok(metadata) => metadata.file_type(),
+        Err(err) => return add_error(None, format_err!("unable to stat destination - {err}")),
+    };
+    if file_type != dst_type {
+        return add_error(None, format_err!("file type mismatch"));
+    }
+
+    if file_type.is_dir() {
+        match std::fs::read_dir(&src) {
+            Ok(entries) => {
+                let mut names = Vec::new();
+                for entry in entries {
+                    match entry {
+                        Ok(entry) => names.push(entry.file_name()),
+                        Err(err) => {
+                            add_error(None, format_err!("reading directory failed - {err}"))
+                        }
+                    }
+                }
+                names.sort();
+                for name in names {
+                    copy_metadata_tree(src_root, dst_root, &relative.join(name), options, errors);
+                }
+            }
+            Err(err) => {
+                errors.errors.push(MetadataError {
+                    path: Some(relative.to_path_buf()),
+                    category: None,
+                    error: format_err!("reading directory failed - {err}"),
+                });
+            }
+        }
+    }
+
+    let result = if file_type.is_dir() || file_type.is_file() {
+        copy_metadata_path(&src, &dst, options)
+    } else {
+        copy_metadata_special(&src, &dst, file_type.is_symlink(), options)
+    };
+
+    if let Err(err) = result {
+        for mut error in err.errors {
+            error.path = Some(relative.to_path_buf());
+            errors.errors.push(error);
+        }
+    }
+}
+
+fn open_for_metadata(path: &Path) -> Result<std::fs::File, Error> {
+    let flags = OFlag::O_RDONLY | OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC | OFlag::O_NOCTTY;
+    let fd = nix::fcntl::open(path, flags | OFlag::O_NONBLOCK, Mode::empty())
+        .map_err(|err| format_err!("unable to open {path:?} - {err}"))?;
+    Ok(unsafe { std::os::unix::io::FromRawFd::from_raw_fd(fd) })
+}
+
+fn copy_metadata_path(
+    src: &Path,
+    dst: &Path,
+    options: &CopyMetadataOptions,
+) -> Result<(), MetadataErrors> {
+    let mut errors = MetadataErrors::default();
+    let files = open_for_metadata(src).and_then(|src| Ok((src, open_for_metadata(dst)?)));
+    match files {
+        Ok((src, dst)) => return copy_metadata(src.as_raw_fd(), dst.as_raw_fd(), options),
+        Err(err) => errors.push(None, err),
+    }
+    errors.into_result()
+}
+
+/// Symbolic links and special files cannot be opened for modification, use path based calls.
+fn copy_metadata_special(
+    src: &Path,
+    dst: &Path,
+    is_symlink: bool,
+    options: &CopyMetadataOptions,
+) -> Result<(), MetadataErrors> {
+    let mut errors = MetadataErrors::default();
+
+    let stat = match stat::lstat(src) {
+        Ok(stat) => stat,
+        Err(err) => {
+            errors.push(None, format_err!("unable to stat source - {err}"));
+            return errors.into_result();
+        }
+    };
+
+    if options.ownership {
+        if let Err(err) = nix::unistd::fchownat(
+            None,
+            dst,
+            Some(Uid::from_raw(stat.st_uid)),
+            Some(Gid::from_raw(stat.st_gid)),
+            nix::unistd::FchownatFlags::NoFollowSymlink,
+        ) {
+            errors.push(Some(MetadataCategory::Ownership), err.into());
+        }
+    }
+
+    if options.mode && !is_symlink {
+        if let Err(err) = stat::fchmodat(
+            None,
+            dst,
+            Mode::from_bits_truncate(stat.st_mode),
+            stat::FchmodatFlags::FollowSymlink,
+        ) {
+            errors.push(Some(MetadataCategory::Mode), err.into());
+        }
+    }
+
+    if options.timestamps {
+        let (atime, mtime) = timestamps(&stat);
+        if let Err(err) = stat::utimensat(
+            None,
+            dst,
+            &atime,
+            &mtime,
+            stat::UtimensatFlags::NoFollowSymlink,
+        ) {
+            errors.push(Some(MetadataCategory::Timestamps), err.into());
+        }
+    }
+
+    errors.into_result()
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::File;
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    use proxmox_lang::c_str;
+
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "proxmox-sys-metadata-{name}-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn set_times(path: &Path, secs: i64) {
+        let time = TimeSpec::new(secs, 123_456_789);
+        stat::utimensat(
+            None,
+            path,
+            &time,
+            &time,
+            stat::UtimensatFlags::NoFollowSymlink,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_copy_metadata() {
+        let dir = test_dir("file");
+        let src_path = dir.join("src");
+        let dst_path = dir.join("dst");
+        let src = File::create(&src_path).unwrap();
+        let dst = File::create(&dst_path).unwrap();
+
+        std::fs::set_permissions(&src_path, std::fs::Permissions::from_mode(0o640)).unwrap();
+        set_times(&src_path, 1_000_000_000);
+
+        let xattrs = fsetxattr(src.as_raw_fd(), c_str!("user.test"), b"value").is_ok();
+        if xattrs {
+            fsetxattr(dst.as_raw_fd(), c_str!("user.stale"), b"x").unwrap();
+        }
+
+        copy_metadata(
+            src.as_raw_fd(),
+            dst.as_raw_fd(),
+            &CopyMetadataOptions::default(),
+        )
+        .unwrap();
+
+        let metadata = dst_path.metadata().unwrap();
+        assert_eq!(metadata.mode() & 0o7777, 0o640);
+        assert_eq!(metadata.mtime(), 1_000_000_000);
+        assert_eq!(metadata.mtime_nsec(), 123_456_789);
+        assert_eq!(metadata.uid(), src_path.metadata().unwrap().uid());
+
+        if xattrs {
+            assert_eq!(
+                fgetxattr(dst.as_raw_fd(), c_str!("user.test")).unwrap(),
+                b"value"
+            );
+            assert_eq!(
+                fgetxattr(dst.as_raw_fd(), c_str!("user.stale")),
+                Err(Errno::ENODATA)
+            );
+        }
+
+        // only the selected categories are copied
+        std::fs::set_permissions(&src_path, std::fs::Permissions::from_mode(0o600)).unwrap();
+        let options = CopyMetadataOptions {
+            timestamps: true,
+            ..CopyMetadataOptions::none()
+        };
+        copy_metadata(src.as_raw_fd(), dst.as_raw_fd(), &options).unwrap();
+        assert_eq!(dst_path.metadata().unwrap().mode() & 0o7777, 0o640);
+
+        // errors are collected per category
+        let read_only = File::open(&src_path).unwrap();
+        let err = copy_metadata(-1, read_only.as_raw_fd(), &options).unwrap_err();
+        assert_eq!(err.errors.len(), 1);
+        assert_eq!(err.errors[0].category, None);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_copy_metadata_recursive() {
+        let dir = test_dir("tree");
+        for root in ["src", "dst"] {
+            let root = dir.join(root);
+            std::fs::create_dir_all(root.join("sub")).unwrap();
+            std::fs::write(root.join("sub/file"), b"data").unwrap();
+            std::os::unix::fs::symlink("sub/file", root.join("link")).unwrap();
+        }
+        let src = dir.join("src");
+        let dst = dir.join("dst");
+        std::fs::set_permissions(src.join("sub"), std::fs::Permissions::from_mode(0o750)).unwrap();
+        std::fs::set_permissions(src.join("sub/file"), std::fs::Permissions::from_mode(0o604))
+            .unwrap();
+        set_times(&src.join("link"), 1_100_000_000);
+        set_times(&src.join("sub/file"), 1_200_000_000);
+        set_times(&src.join("sub"), 1_300_000_000);
+
+        copy_metadata_recursive(&src, &dst, &CopyMetadataOptions::default()).unwrap();
+
+        let metadata = |path: &str| std::fs::symlink_metadata(dst.join(path)).unwrap();
+        assert_eq!(metadata("sub").mode() & 0o7777, 0o750);
+        assert_eq!(metadata("sub").mtime(), 1_300_000_000);
+        assert_eq!(metadata("sub/file").mode() & 0o7777, 0o604);
+        assert_eq!(metadata("sub/file").mtime(), 1_200_000_000);
+        assert_eq!(metadata("link").mtime(), 1_100_000_000);
+
+        std::fs::write(src.join("sub/extra"), b"").unwrap();
+        let err = copy_metadata_recursive(&src, &dst, &CopyMetadataOptions::default()).unwrap_err();
+        assert_eq!(err.errors.len(), 1);
+        assert_eq!(err.errors[0].path.as_deref(), Some(Path::new("sub/extra")));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
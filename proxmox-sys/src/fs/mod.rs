@@ -22,6 +22,9 @@ pub use read_dir::*;
 mod fsx_attr;
 pub use fsx_attr::*;
 
+mod metadata;
+pub use metadata::*;
+
 pub mod xattr;
 
 /// Change ownership of an open file handle
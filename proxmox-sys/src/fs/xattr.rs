@@ -159,6 +159,16 @@ pub fn fsetxattr(fd: RawFd, name: &CStr, data: &[u8]) -> Result<(), nix::errno::
     Ok(())
 }
 
+/// Remove an extended attribute from a file descriptor.
+pub fn fremovexattr(fd: RawFd, name: &CStr) -> Result<(), nix::errno::Errno> {
+    let result = unsafe { libc::fremovexattr(fd, name.as_ptr()) };
+    if result < 0 {
+        return Err(Errno::last());
+    }
+
+    Ok(())
+}
+
 pub fn fsetxattr_fcaps(fd: RawFd, fcaps: &[u8]) -> Result<(), nix::errno::Errno> {
     // TODO casync checks and removes capabilities if they are set
     fsetxattr(fd, xattr_name_fcaps(), fcaps)
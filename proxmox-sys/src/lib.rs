@@ -15,6 +15,7 @@ pub mod macros;
 pub mod mmap;
 pub mod process_locker;
 pub mod systemd;
+pub mod tracked_lock;
 
 mod worker_task_context;
 pub use worker_task_context::*;
@@ -3,6 +3,7 @@
 use std::fs::File;
 use std::io;
 use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
+use std::time::{Duration, Instant};
 
 use nix::fcntl::OFlag;
 use nix::sys::signal::Signal;
@@ -14,7 +15,7 @@ use nix::NixPath;
 use proxmox_lang::{c_str, error::io_err_other};
 
 use crate::error::SysResult;
-use crate::linux::procfs::{MountInfo, PidStat};
+use crate::linux::procfs::{MountInfo, PidStat, PidStatus};
 use crate::{c_result, c_try};
 
 /// asm-generic pidfd_open syscall number
@@ -113,6 +114,61 @@ impl PidFd {
         PidStat::parse(&data).map_err(io_err_other)
     }
 
+    /// Get the `PidStatus` structure for this process. (`/proc/PID/status`)
+    pub fn get_status(&self) -> io::Result<PidStatus> {
+        let data = self.read_file(c_str!("status"))?;
+        let data = String::from_utf8(data).map_err(io_err_other)?;
+        PidStatus::parse(&data).map_err(io_err_other)
+    }
+
+    /// Wait for the process to exit, or until the timeout elapsed.
+    ///
+    /// Returns `true` if the process exited. A `None` timeout waits indefinitely. This does not
+    /// reap the process, so child processes still need to be waited for via `waitpid`.
+    ///
+    /// This only works on file descriptors obtained via `pidfd_open` (as by [`PidFd::open`]),
+    /// not on file descriptors referring to a `/proc/PID` directory.
+    pub fn wait_exit_timeout(&self, timeout: Option<Duration>) -> io::Result<bool> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            let timeout_ms = match deadline {
+                None => -1,
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    // round up to avoid busy looping on sub-millisecond remainders
+                    (remaining.as_secs_f64() * 1000.0)
+                        .ceil()
+                        .min(libc::c_int::MAX as f64) as libc::c_int
+                }
+            };
+
+            let mut pfd = libc::pollfd {
+                fd: self.fd.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            match c_result!(unsafe { libc::poll(&mut pfd, 1, timeout_ms) }) {
+                Ok(0) if timeout_ms == 0 => return Ok(false),
+                Ok(0) => continue,
+                Ok(_) => return Ok(true),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Wait for the process to exit.
+    ///
+    /// See [`wait_exit_timeout`](PidFd::wait_exit_timeout) for details.
+    pub fn wait_exit(&self) -> io::Result<()> {
+        self.wait_exit_timeout(None).map(drop)
+    }
+
+    /// Check whether the process has already exited, without blocking.
+    pub fn has_exited(&self) -> io::Result<bool> {
+        self.wait_exit_timeout(Some(Duration::ZERO))
+    }
+
     /// Read this process' `/proc/PID/mountinfo` file.
     pub fn get_mount_info(&self) -> io::Result<MountInfo> {
         MountInfo::parse(&self.read_file(c_str!("mountinfo"))?).map_err(io_err_other)
@@ -180,3 +236,20 @@ impl FromRawFd for PidFd {
         Self::try_from_raw_fd(fd).unwrap()
     }
 }
+
+#[test]
+fn test_pidfd_wait_exit() {
+    let mut child = std::process::Command::new("sleep")
+        .arg("0.2")
+        .spawn()
+        .expect("failed to spawn test process");
+    let pidfd = PidFd::open(Pid::from_raw(child.id() as i32)).unwrap();
+
+    assert!(!pidfd.has_exited().unwrap());
+    assert!(pidfd
+        .wait_exit_timeout(Some(Duration::from_secs(10)))
+        .unwrap());
+    assert!(pidfd.has_exited().unwrap());
+
+    child.wait().unwrap();
+}
@@ -0,0 +1,387 @@
+//! Statistics of cgroup v2 (unified hierarchy) control groups.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{format_err, Error};
+use serde::Serialize;
+
+use super::counter_rate;
+use super::mountinfo::Device;
+use super::pressure::{Pressure, PressureResource};
+
+/// The default mount point of the cgroup v2 hierarchy.
+pub const CGROUP_V2_ROOT: &str = "/sys/fs/cgroup";
+
+/// A cgroup v2 control group directory.
+#[derive(Clone, Debug)]
+pub struct CGroup {
+    path: PathBuf,
+}
+
+impl CGroup {
+    /// Access a cgroup by its path relative to the cgroup v2 root, e.g. `system.slice/foo.service`
+    /// or `/lxc/100`.
+    pub fn new<P: AsRef<Path>>(name: P) -> Self {
+        let name = name.as_ref();
+        let name = name.strip_prefix("/").unwrap_or(name);
+        Self::from_path(Path::new(CGROUP_V2_ROOT).join(name))
+    }
+
+    /// Access a cgroup by its absolute path, for hierarchies not mounted in the default location.
+    pub fn from_path<P: Into<PathBuf>>(path: P) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// The cgroup of a process, as found in `/proc/PID/cgroup`.
+    pub fn from_pid(pid: nix::unistd::Pid) -> Result<Self, Error> {
+        let path = format!("/proc/{}/cgroup", pid);
+        let content = std::fs::read_to_string(&path)?;
+        content
+            .lines()
+            .find_map(|line| line.strip_prefix("0::"))
+            .map(Self::new)
+            .ok_or_else(|| format_err!("no cgroup v2 entry found in {}", path))
+    }
+
+    /// The path of this cgroup's directory.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn read_file(&self, file: &str) -> Result<String, Error> {
+        let path = self.path.join(file);
+        std::fs::read_to_string(&path)
+            .map_err(|err| format_err!("unable to read {:?} - {}", path, err))
+    }
+
+    /// Read `cpu.stat`.
+    pub fn cpu_stat(&self) -> Result<CGroupCpuStat, Error> {
+        CGroupCpuStat::parse(&self.read_file("cpu.stat")?)
+    }
+
+    /// Read `memory.current`, the current memory usage in bytes.
+    pub fn memory_current(&self) -> Result<u64, Error> {
+        let value = self.read_file("memory.current")?;
+        value
+            .trim()
+            .parse()
+            .map_err(|err| format_err!("bad value in memory.current - {}", err))
+    }
+
+    /// Read `memory.max`, returns `None` if there is no limit.
+    pub fn memory_max(&self) -> Result<Option<u64>, Error> {
+        match self.read_file("memory.max")?.trim() {
+            "max" => Ok(None),
+            value => value
+                .parse()
+                .map(Some)
+                .map_err(|err| format_err!("bad value in memory.max - {}", err)),
+        }
+    }
+
+    /// Read `memory.stat`.
+    pub fn memory_stat(&self) -> Result<CGroupMemoryStat, Error> {
+        CGroupMemoryStat::parse(&self.read_file("memory.stat")?)
+    }
+
+    /// Read `io.stat`.
+    pub fn io_stat(&self) -> Result<Vec<CGroupIoStat>, Error> {
+        parse_io_stat(&self.read_file("io.stat")?)
+    }
+
+    /// Read the pressure stall information of this cgroup.
+    pub fn pressure(&self, resource: PressureResource) -> Result<Pressure, Error> {
+        Pressure::read_from(self.path.join(format!("{}.pressure", resource.name())))
+    }
+}
+
+/// The contents of a cgroup's `cpu.stat` file. All times are in microseconds.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct CGroupCpuStat {
+    pub usage_usec: u64,
+    pub user_usec: u64,
+    pub system_usec: u64,
+    /// Number of enforcement intervals elapsed, only available with the cpu controller enabled.
+    pub nr_periods: u64,
+    /// Number of times the group has been throttled.
+    pub nr_throttled: u64,
+    /// Total time the group has been throttled.
+    pub throttled_usec: u64,
+}
+
+/// CPU usage computed from two [`CGroupCpuStat`] samples.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct CGroupCpuRate {
+    /// Used CPU time per second, 1.0 means one fully used CPU.
+    pub usage: f64,
+    pub user: f64,
+    pub system: f64,
+    /// Throttled time per second.
+    pub throttled: f64,
+}
+
+impl CGroupCpuStat {
+    /// Parse the contents of a `cpu.stat` file.
+    pub fn parse(content: &str) -> Result<Self, Error> {
+        let mut stat = Self::default();
+        for (key, value) in parse_flat_keyed(content, "cpu.stat")? {
+            match key {
+                "usage_usec" => stat.usage_usec = value,
+                "user_usec" => stat.user_usec = value,
+                "system_usec" => stat.system_usec = value,
+                "nr_periods" => stat.nr_periods = value,
+                "nr_throttled" => stat.nr_throttled = value,
+                "throttled_usec" => stat.throttled_usec = value,
+                _ => (),
+            }
+        }
+        Ok(stat)
+    }
+
+    /// Compute the CPU usage between an older sample and this one, taken `elapsed` apart.
+    pub fn rate(&self, prev: &CGroupCpuStat, elapsed: Duration) -> CGroupCpuRate {
+        const USEC: f64 = 1_000_000.0;
+        CGroupCpuRate {
+            usage: counter_rate(prev.usage_usec, self.usage_usec, elapsed) / USEC,
+            user: counter_rate(prev.user_usec, self.user_usec, elapsed) / USEC,
+            system: counter_rate(prev.system_usec, self.system_usec, elapsed) / USEC,
+            throttled: counter_rate(prev.throttled_usec, self.throttled_usec, elapsed) / USEC,
+        }
+    }
+}
+
+/// The contents of a cgroup's `memory.stat` file.
+///
+/// The available keys depend on the kernel version, so the values are kept in a map, with
+/// accessors for the commonly used ones. Sizes are in bytes.
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(transparent)]
+pub struct CGroupMemoryStat(pub BTreeMap<String, u64>);
+
+impl CGroupMemoryStat {
+    /// Parse the contents of a `memory.stat` file.
+    pub fn parse(content: &str) -> Result<Self, Error> {
+        Ok(Self(
+            parse_flat_keyed(content, "memory.stat")?
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        ))
+    }
+
+    /// Get an arbitrary value, if available.
+    pub fn get(&self, key: &str) -> Option<u64> {
+        self.0.get(key).copied()
+    }
+
+    /// Anonymous memory, e.g. heap and stack.
+    pub fn anon(&self) -> u64 {
+        self.get("anon").unwrap_or(0)
+    }
+
+    /// Page cache memory.
+    pub fn file(&self) -> u64 {
+        self.get("file").unwrap_or(0)
+    }
+
+    /// Kernel memory.
+    pub fn kernel(&self) -> u64 {
+        self.get("kernel").unwrap_or(0)
+    }
+
+    /// Shared memory, e.g. tmpfs and shm.
+    pub fn shmem(&self) -> u64 {
+        self.get("shmem").unwrap_or(0)
+    }
+}
+
+/// A single device line of a cgroup's `io.stat` file.
+#[derive(Clone, Debug, Serialize)]
+pub struct CGroupIoStat {
+    #[serde(skip)]
+    pub device: Device,
+    pub rbytes: u64,
+    pub wbytes: u64,
+    pub rios: u64,
+    pub wios: u64,
+    pub dbytes: u64,
+    pub dios: u64,
+}
+
+/// Per-second I/O rates computed from two [`CGroupIoStat`] samples of the same device.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct CGroupIoRate {
+    pub read_bytes: f64,
+    pub write_bytes: f64,
+    pub read_ops: f64,
+    pub write_ops: f64,
+    pub discard_bytes: f64,
+    pub discard_ops: f64,
+}
+
+impl CGroupIoStat {
+    /// Parse a single line of an `io.stat` file.
+    pub fn parse(line: &str) -> Result<Self, Error> {
+        let mut parts = line.split_ascii_whitespace();
+        let device: Device = parts
+            .next()
+            .ok_or_else(|| format_err!("missing device in io.stat"))?
+            .parse()?;
+
+        let mut stat = Self {
+            device,
+            rbytes: 0,
+            wbytes: 0,
+            rios: 0,
+            wios: 0,
+            dbytes: 0,
+            dios: 0,
+        };
+
+        for part in parts {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| format_err!("invalid field '{}' in io.stat", part))?;
+            let value = match value.parse() {
+                Ok(value) => value,
+                // io.cost and io.latency add non-counter fields
+                Err(_) => continue,
+            };
+            match key {
+                "rbytes" => stat.rbytes = value,
+                "wbytes" => stat.wbytes = value,
+                "rios" => stat.rios = value,
+                "wios" => stat.wios = value,
+                "dbytes" => stat.dbytes = value,
+                "dios" => stat.dios = value,
+                _ => (),
+            }
+        }
+
+        Ok(stat)
+    }
+
+    /// Compute the per-second rates between an older sample and this one, taken `elapsed` apart.
+    pub fn rate(&self, prev: &CGroupIoStat, elapsed: Duration) -> CGroupIoRate {
+        CGroupIoRate {
+            read_bytes: counter_rate(prev.rbytes, self.rbytes, elapsed),
+            write_bytes: counter_rate(prev.wbytes, self.wbytes, elapsed),
+            read_ops: counter_rate(prev.rios, self.rios, elapsed),
+            write_ops: counter_rate(prev.wios, self.wios, elapsed),
+            discard_bytes: counter_rate(prev.dbytes, self.dbytes, elapsed),
+            discard_ops: counter_rate(prev.dios, self.dios, elapsed),
+        }
+    }
+}
+
+/// Parse the contents of an `io.stat` file.
+pub fn parse_io_stat(content: &str) -> Result<Vec<CGroupIoStat>, Error> {
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(CGroupIoStat::parse)
+        .collect()
+}
+
+/// Parse a "flat keyed" cgroup file consisting of `key value` lines.
+fn parse_flat_keyed<'a>(content: &'a str, file: &str) -> Result<Vec<(&'a str, u64)>, Error> {
+    let mut out = Vec::new();
+    for line in content.lines() {
+        let mut parts = line.split_ascii_whitespace();
+        match (parts.next(), parts.next()) {
+            (None, _) => continue,
+            (Some(key), Some(value)) => out.push((
+                key,
+                value
+                    .parse()
+                    .map_err(|err| format_err!("bad value for '{}' in {} - {}", key, file, err))?,
+            )),
+            (Some(key), None) => {
+                return Err(format_err!("missing value for '{}' in {}", key, file))
+            }
+        }
+    }
+    Ok(out)
+}
+
+#[test]
+fn test_cgroup_cpu_stat() {
+    let prev = CGroupCpuStat::parse(
+        "usage_usec 6000000\n\
+         user_usec 4000000\n\
+         system_usec 2000000\n\
+         core_sched.force_idle_usec 0\n\
+         nr_periods 10\n\
+         nr_throttled 2\n\
+         throttled_usec 50000\n",
+    )
+    .expect("failed to parse cpu.stat sample");
+    assert_eq!(prev.usage_usec, 6000000);
+    assert_eq!(prev.nr_throttled, 2);
+
+    let next = CGroupCpuStat::parse(
+        "usage_usec 9000000\n\
+         user_usec 5000000\n\
+         system_usec 4000000\n",
+    )
+    .unwrap();
+    let rate = next.rate(&prev, Duration::from_secs(2));
+    assert_eq!(rate.usage, 1.5);
+    assert_eq!(rate.user, 0.5);
+    assert_eq!(rate.system, 1.0);
+    assert_eq!(rate.throttled, 0.0);
+}
+
+#[test]
+fn test_cgroup_memory_stat() {
+    let stat = CGroupMemoryStat::parse(
+        "anon 1327104\n\
+         file 8192000\n\
+         kernel 532480\n\
+         shmem 0\n\
+         pgfault 1234\n",
+    )
+    .expect("failed to parse memory.stat sample");
+    assert_eq!(stat.anon(), 1327104);
+    assert_eq!(stat.file(), 8192000);
+    assert_eq!(stat.kernel(), 532480);
+    assert_eq!(stat.get("pgfault"), Some(1234));
+    assert_eq!(stat.get("unknown"), None);
+}
+
+#[test]
+fn test_cgroup_io_stat() {
+    let stats = parse_io_stat(
+        "8:16 rbytes=1459200 wbytes=314773504 rios=192 wios=353 dbytes=0 dios=0\n\
+         259:0 rbytes=4096 wbytes=0 rios=1 wios=0 dbytes=0 dios=0 cost.vrate=100.00\n",
+    )
+    .expect("failed to parse io.stat sample");
+    assert_eq!(stats.len(), 2);
+    assert_eq!(stats[0].device, Device::new(8, 16));
+    assert_eq!(stats[0].rbytes, 1459200);
+    assert_eq!(stats[0].wbytes, 314773504);
+    assert_eq!(stats[0].rios, 192);
+    assert_eq!(stats[0].wios, 353);
+    assert_eq!(stats[1].device, Device::new(259, 0));
+    assert_eq!(stats[1].rbytes, 4096);
+
+    let next = CGroupIoStat::parse("8:16 rbytes=2459200 wbytes=314773504 rios=292").unwrap();
+    let rate = next.rate(&stats[0], Duration::from_secs(10));
+    assert_eq!(rate.read_bytes, 100000.0);
+    assert_eq!(rate.read_ops, 10.0);
+    assert_eq!(rate.write_ops, 0.0);
+}
+
+#[test]
+fn test_cgroup_path() {
+    assert_eq!(
+        CGroup::new("/lxc/100").path(),
+        Path::new("/sys/fs/cgroup/lxc/100")
+    );
+    assert_eq!(
+        CGroup::new("system.slice").path(),
+        Path::new("/sys/fs/cgroup/system.slice")
+    );
+}
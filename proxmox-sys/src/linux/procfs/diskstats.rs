@@ -0,0 +1,225 @@
+//! `/proc/diskstats` handling.
+
+use std::time::Duration;
+
+use anyhow::{format_err, Error};
+use serde::Serialize;
+
+use super::counter_rate;
+use super::mountinfo::Device;
+
+/// Size of a sector as used by the counters in `/proc/diskstats`, independent of the device's
+/// actual sector size.
+pub const DISKSTATS_SECTOR_SIZE: u64 = 512;
+
+/// A single line of `/proc/diskstats`.
+///
+/// The discard counters are only available since kernel 4.18 and the flush counters since 5.5,
+/// they are zero when the running kernel does not provide them.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct DiskStat {
+    #[serde(skip)]
+    pub device: Option<Device>,
+    /// The device name, e.g. `sda` or `nvme0n1p1`.
+    pub name: String,
+    /// Number of reads completed successfully.
+    pub reads_completed: u64,
+    /// Number of adjacent reads merged.
+    pub reads_merged: u64,
+    /// Number of sectors read.
+    pub sectors_read: u64,
+    /// Milliseconds spent reading.
+    pub time_reading_ms: u64,
+    /// Number of writes completed successfully.
+    pub writes_completed: u64,
+    /// Number of adjacent writes merged.
+    pub writes_merged: u64,
+    /// Number of sectors written.
+    pub sectors_written: u64,
+    /// Milliseconds spent writing.
+    pub time_writing_ms: u64,
+    /// Number of I/Os currently in progress. This is a gauge, not a counter.
+    pub ios_in_progress: u64,
+    /// Milliseconds spent doing I/Os.
+    pub time_io_ms: u64,
+    /// Weighted milliseconds spent doing I/Os.
+    pub weighted_time_io_ms: u64,
+    /// Number of discards completed successfully.
+    pub discards_completed: u64,
+    /// Number of adjacent discards merged.
+    pub discards_merged: u64,
+    /// Number of sectors discarded.
+    pub sectors_discarded: u64,
+    /// Milliseconds spent discarding.
+    pub time_discarding_ms: u64,
+    /// Number of flush requests completed successfully.
+    pub flushes_completed: u64,
+    /// Milliseconds spent flushing.
+    pub time_flushing_ms: u64,
+}
+
+/// Per-second rates computed from two [`DiskStat`] samples of the same device.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct DiskStatRate {
+    pub read_ops: f64,
+    pub read_bytes: f64,
+    pub write_ops: f64,
+    pub write_bytes: f64,
+    pub discard_ops: f64,
+    pub discard_bytes: f64,
+    /// The fraction (0 - 1.0) of time the device was busy.
+    pub utilization: f64,
+    /// Average time in milliseconds an I/O request waited in the queue (including service time).
+    pub avg_wait_ms: f64,
+}
+
+impl DiskStat {
+    /// Parse a single line of `/proc/diskstats`.
+    pub fn parse(line: &str) -> Result<Self, Error> {
+        let mut parts = line.split_ascii_whitespace();
+
+        let mut next = |what: &'static str| {
+            parts
+                .next()
+                .ok_or_else(|| format_err!("missing '{}' in /proc/diskstats", what))
+        };
+
+        let major: u32 = next("major")?.parse()?;
+        let minor: u32 = next("minor")?.parse()?;
+        let name = next("name")?.to_string();
+
+        let mut counters = [0u64; 17];
+        for (i, counter) in counters.iter_mut().enumerate() {
+            match parts.next() {
+                Some(value) => {
+                    *counter = value.parse().map_err(|err| {
+                        format_err!("bad counter in /proc/diskstats for '{}' - {}", name, err)
+                    })?
+                }
+                // the first 11 counters are always present
+                None if i < 11 => {
+                    return Err(format_err!(
+                        "missing counters in /proc/diskstats for '{}'",
+                        name
+                    ))
+                }
+                None => break,
+            }
+        }
+
+        Ok(Self {
+            device: Some(Device::new(major, minor)),
+            name,
+            reads_completed: counters[0],
+            reads_merged: counters[1],
+            sectors_read: counters[2],
+            time_reading_ms: counters[3],
+            writes_completed: counters[4],
+            writes_merged: counters[5],
+            sectors_written: counters[6],
+            time_writing_ms: counters[7],
+            ios_in_progress: counters[8],
+            time_io_ms: counters[9],
+            weighted_time_io_ms: counters[10],
+            discards_completed: counters[11],
+            discards_merged: counters[12],
+            sectors_discarded: counters[13],
+            time_discarding_ms: counters[14],
+            flushes_completed: counters[15],
+            time_flushing_ms: counters[16],
+        })
+    }
+
+    /// Compute the per-second rates between an older sample and this one, taken `elapsed` apart.
+    pub fn rate(&self, prev: &DiskStat, elapsed: Duration) -> DiskStatRate {
+        let ops = self.reads_completed + self.writes_completed + self.discards_completed;
+        let prev_ops = prev.reads_completed + prev.writes_completed + prev.discards_completed;
+        let delta_ops = ops.saturating_sub(prev_ops);
+
+        let wait = self.time_reading_ms + self.time_writing_ms + self.time_discarding_ms;
+        let prev_wait = prev.time_reading_ms + prev.time_writing_ms + prev.time_discarding_ms;
+
+        let elapsed_ms = elapsed.as_secs_f64() * 1000.0;
+
+        DiskStatRate {
+            read_ops: counter_rate(prev.reads_completed, self.reads_completed, elapsed),
+            read_bytes: counter_rate(prev.sectors_read, self.sectors_read, elapsed)
+                * DISKSTATS_SECTOR_SIZE as f64,
+            write_ops: counter_rate(prev.writes_completed, self.writes_completed, elapsed),
+            write_bytes: counter_rate(prev.sectors_written, self.sectors_written, elapsed)
+                * DISKSTATS_SECTOR_SIZE as f64,
+            discard_ops: counter_rate(prev.discards_completed, self.discards_completed, elapsed),
+            discard_bytes: counter_rate(prev.sectors_discarded, self.sectors_discarded, elapsed)
+                * DISKSTATS_SECTOR_SIZE as f64,
+            utilization: if elapsed_ms > 0.0 {
+                (self.time_io_ms.saturating_sub(prev.time_io_ms) as f64 / elapsed_ms).min(1.0)
+            } else {
+                0.0
+            },
+            avg_wait_ms: if delta_ops > 0 {
+                wait.saturating_sub(prev_wait) as f64 / delta_ops as f64
+            } else {
+                0.0
+            },
+        }
+    }
+}
+
+/// Read `/proc/diskstats`.
+pub fn read_diskstats() -> Result<Vec<DiskStat>, Error> {
+    parse_diskstats(&std::fs::read_to_string("/proc/diskstats")?)
+}
+
+/// Parse the contents of a `/proc/diskstats` file.
+pub fn parse_diskstats(content: &str) -> Result<Vec<DiskStat>, Error> {
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(DiskStat::parse)
+        .collect()
+}
+
+#[test]
+fn test_diskstats() {
+    let stats = parse_diskstats(
+        "   7       0 loop0 0 0 0 0 0 0 0 0 0 0 0\n\
+         \x20259       0 nvme0n1 185307 61044 14478082 41380 1016483 815478 37413826 1102613 0 \
+         620584 1166488 43722 0 191628696 8352 80116 14142\n\
+         \x20259       1 nvme0n1p1 296 1013 13250 71 2 0 2 0 0 120 71 0 0 0 0 0 0\n",
+    )
+    .expect("failed to parse /proc/diskstats sample");
+
+    assert_eq!(stats.len(), 3);
+
+    assert_eq!(stats[0].name, "loop0");
+    assert_eq!(stats[0].discards_completed, 0);
+
+    let nvme = &stats[1];
+    assert_eq!(nvme.device, Some(Device::new(259, 0)));
+    assert_eq!(nvme.name, "nvme0n1");
+    assert_eq!(nvme.reads_completed, 185307);
+    assert_eq!(nvme.sectors_read, 14478082);
+    assert_eq!(nvme.writes_completed, 1016483);
+    assert_eq!(nvme.sectors_written, 37413826);
+    assert_eq!(nvme.time_io_ms, 620584);
+    assert_eq!(nvme.discards_completed, 43722);
+    assert_eq!(nvme.sectors_discarded, 191628696);
+    assert_eq!(nvme.flushes_completed, 80116);
+    assert_eq!(nvme.time_flushing_ms, 14142);
+
+    assert!(DiskStat::parse("8 0 sda 1 2 3").is_err());
+}
+
+#[test]
+fn test_diskstats_rate() {
+    let prev = DiskStat::parse("8 0 sda 100 0 2000 50 10 0 800 20 0 100 70").unwrap();
+    let next = DiskStat::parse("8 0 sda 300 0 6000 150 30 0 2400 60 1 600 270").unwrap();
+
+    let rate = next.rate(&prev, Duration::from_secs(2));
+    assert_eq!(rate.read_ops, 100.0);
+    assert_eq!(rate.read_bytes, 2000.0 * 512.0);
+    assert_eq!(rate.write_ops, 10.0);
+    assert_eq!(rate.write_bytes, 800.0 * 512.0);
+    assert_eq!(rate.utilization, 0.25);
+    assert_eq!(rate.avg_wait_ms, 140.0 / 220.0);
+}
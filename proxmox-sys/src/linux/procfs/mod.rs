@@ -6,7 +6,7 @@ use std::io::{BufRead, BufReader};
 use std::net::{Ipv4Addr, Ipv6Addr};
 use std::str::FromStr;
 use std::sync::RwLock;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use anyhow::{bail, format_err, Error};
 use lazy_static::lazy_static;
@@ -15,9 +15,24 @@ use serde::Serialize;
 
 use crate::fs::{file_read_firstline, read_firstline};
 
+pub mod cgroup;
+pub mod diskstats;
 pub mod mountinfo;
+pub mod pressure;
+pub mod process;
+pub mod vmstat;
+#[doc(inline)]
+pub use cgroup::CGroup;
+#[doc(inline)]
+pub use diskstats::{read_diskstats, DiskStat};
 #[doc(inline)]
 pub use mountinfo::MountInfo;
+#[doc(inline)]
+pub use pressure::{read_pressure, Pressure, PressureResource};
+#[doc(inline)]
+pub use process::{PidIo, PidStatus, ProcessInfo, ProcessTree};
+#[doc(inline)]
+pub use vmstat::{read_vmstat, VmStat};
 
 /// POSIX sysconf call
 pub fn sysconf(name: i32) -> i64 {
@@ -27,6 +42,17 @@ pub fn sysconf(name: i32) -> i64 {
     unsafe { sysconf(name) }
 }
 
+/// Per-second rate of a monotonic counter between two samples taken `elapsed` apart.
+///
+/// A counter going backwards (e.g. after a device was re-added) yields a rate of zero.
+fn counter_rate(prev: u64, current: u64, elapsed: Duration) -> f64 {
+    let secs = elapsed.as_secs_f64();
+    if secs <= 0.0 {
+        return 0.0;
+    }
+    current.saturating_sub(prev) as f64 / secs
+}
+
 lazy_static! {
     pub static ref CLOCK_TICKS: f64 = sysconf(libc::_SC_CLK_TCK) as f64;
 }
@@ -0,0 +1,156 @@
+//! Pressure stall information (PSI) from `/proc/pressure` and cgroup v2 `*.pressure` files.
+
+use std::fmt;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{bail, format_err, Error};
+use serde::Serialize;
+
+/// The resources pressure stall information is available for.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PressureResource {
+    Cpu,
+    Io,
+    Memory,
+}
+
+impl PressureResource {
+    /// The file name used in `/proc/pressure` and, with a `.pressure` suffix, in cgroups.
+    pub fn name(self) -> &'static str {
+        match self {
+            PressureResource::Cpu => "cpu",
+            PressureResource::Io => "io",
+            PressureResource::Memory => "memory",
+        }
+    }
+}
+
+impl fmt::Display for PressureResource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A single `some` or `full` line of a pressure file.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct PressureRecord {
+    /// Percentage of stalled time over the last 10 seconds.
+    pub avg10: f64,
+    /// Percentage of stalled time over the last 60 seconds.
+    pub avg60: f64,
+    /// Percentage of stalled time over the last 300 seconds.
+    pub avg300: f64,
+    /// Total stalled time in microseconds.
+    pub total: u64,
+}
+
+impl PressureRecord {
+    fn parse<'a>(parts: impl Iterator<Item = &'a str>) -> Result<Self, Error> {
+        let mut record = PressureRecord::default();
+        for part in parts {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| format_err!("invalid pressure field '{}'", part))?;
+            match key {
+                "avg10" => record.avg10 = value.parse()?,
+                "avg60" => record.avg60 = value.parse()?,
+                "avg300" => record.avg300 = value.parse()?,
+                "total" => record.total = value.parse()?,
+                _ => (), // ignore unknown fields
+            }
+        }
+        Ok(record)
+    }
+
+    /// The fraction (0 - 1.0) of time stalled between an older record and this one, taken
+    /// `elapsed` apart.
+    pub fn stall_ratio(&self, prev: &PressureRecord, elapsed: Duration) -> f64 {
+        let elapsed_us = elapsed.as_micros() as f64;
+        if elapsed_us <= 0.0 {
+            return 0.0;
+        }
+        (self.total.saturating_sub(prev.total) as f64 / elapsed_us).min(1.0)
+    }
+}
+
+/// The contents of a pressure file.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Pressure {
+    /// Time in which at least some tasks were stalled.
+    pub some: PressureRecord,
+    /// Time in which all non-idle tasks were stalled at once. Not provided for the system wide
+    /// cpu pressure by kernels older than 5.13.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub full: Option<PressureRecord>,
+}
+
+impl Pressure {
+    /// Read the system wide pressure from `/proc/pressure`.
+    pub fn read(resource: PressureResource) -> Result<Self, Error> {
+        Self::read_from(format!("/proc/pressure/{}", resource.name()))
+    }
+
+    /// Read a pressure file from an arbitrary path, such as a cgroup's `cpu.pressure`.
+    pub fn read_from<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .map_err(|err| format_err!("unable to read {:?} - {}", path, err))?;
+        Self::parse(&content)
+    }
+
+    /// Parse the contents of a pressure file.
+    pub fn parse(content: &str) -> Result<Self, Error> {
+        let mut some = None;
+        let mut full = None;
+        for line in content.lines() {
+            let mut parts = line.split_ascii_whitespace();
+            match parts.next() {
+                None => continue,
+                Some("some") => some = Some(PressureRecord::parse(parts)?),
+                Some("full") => full = Some(PressureRecord::parse(parts)?),
+                Some(other) => bail!("unexpected line type '{}' in pressure file", other),
+            }
+        }
+
+        match some {
+            Some(some) => Ok(Self { some, full }),
+            None => bail!("missing 'some' line in pressure file"),
+        }
+    }
+}
+
+/// Read the system wide pressure stall information of a resource.
+pub fn read_pressure(resource: PressureResource) -> Result<Pressure, Error> {
+    Pressure::read(resource)
+}
+
+#[test]
+fn test_pressure() {
+    let pressure = Pressure::parse(
+        "some avg10=0.26 avg60=0.15 avg300=0.03 total=4916679\n\
+         full avg10=0.22 avg60=0.12 avg300=0.03 total=3739553\n",
+    )
+    .expect("failed to parse pressure sample");
+    assert_eq!(pressure.some.avg10, 0.26);
+    assert_eq!(pressure.some.avg60, 0.15);
+    assert_eq!(pressure.some.avg300, 0.03);
+    assert_eq!(pressure.some.total, 4916679);
+    assert_eq!(pressure.full.as_ref().unwrap().total, 3739553);
+
+    let pressure = Pressure::parse("some avg10=2.04 avg60=0.93 avg300=1.38 total=74441650\n")
+        .expect("failed to parse pressure sample without 'full' line");
+    assert!(pressure.full.is_none());
+
+    assert!(Pressure::parse("").is_err());
+
+    let prev = PressureRecord {
+        total: 1_000_000,
+        ..Default::default()
+    };
+    let next = PressureRecord {
+        total: 1_500_000,
+        ..Default::default()
+    };
+    assert_eq!(next.stall_ratio(&prev, Duration::from_secs(2)), 0.25);
+}
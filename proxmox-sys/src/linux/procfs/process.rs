@@ -0,0 +1,499 @@
+//! Per-process information from `/proc/PID`, and process tree snapshots.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
+use std::io;
+use std::os::unix::ffi::OsStringExt;
+use std::path::PathBuf;
+
+use anyhow::{bail, format_err, Error};
+use nix::unistd::Pid;
+use serde::Serialize;
+
+use super::cgroup::CGroup;
+use super::PidStat;
+
+/// Selected contents of the `/proc/PID/status` file. Memory sizes are in bytes.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct PidStatus {
+    /// The command name (possibly truncated to 15 bytes).
+    pub name: String,
+    /// The state character, e.g. `R`, `S`, `D` or `Z`.
+    pub state: char,
+    pub tgid: i32,
+    pub pid: i32,
+    pub ppid: i32,
+    /// Real, effective, saved set and file system uid.
+    pub uid: [u32; 4],
+    /// Real, effective, saved set and file system gid.
+    pub gid: [u32; 4],
+    pub threads: u64,
+    pub vm_peak: u64,
+    pub vm_size: u64,
+    pub vm_hwm: u64,
+    pub vm_rss: u64,
+    pub vm_swap: u64,
+    pub voluntary_ctxt_switches: u64,
+    pub nonvoluntary_ctxt_switches: u64,
+}
+
+impl PidStatus {
+    /// Parse the contents of a `/proc/PID/status` file.
+    pub fn parse(content: &str) -> Result<Self, Error> {
+        fn ids(value: &str) -> Result<[u32; 4], Error> {
+            let mut out = [0u32; 4];
+            let mut parts = value.split_ascii_whitespace();
+            for id in out.iter_mut() {
+                *id = parts
+                    .next()
+                    .ok_or_else(|| format_err!("missing id in /proc/PID/status"))?
+                    .parse()?;
+            }
+            Ok(out)
+        }
+
+        fn kbytes(value: &str) -> Result<u64, Error> {
+            let value = value.trim_end_matches("kB").trim();
+            Ok(value.parse::<u64>()? * 1024)
+        }
+
+        let mut status = Self::default();
+        for line in content.lines() {
+            let (key, value) = match line.split_once(':') {
+                Some((key, value)) => (key, value.trim()),
+                None => continue,
+            };
+            match key {
+                "Name" => status.name = value.to_string(),
+                "State" => {
+                    status.state = value
+                        .chars()
+                        .next()
+                        .ok_or_else(|| format_err!("empty state in /proc/PID/status"))?
+                }
+                "Tgid" => status.tgid = value.parse()?,
+                "Pid" => status.pid = value.parse()?,
+                "PPid" => status.ppid = value.parse()?,
+                "Uid" => status.uid = ids(value)?,
+                "Gid" => status.gid = ids(value)?,
+                "Threads" => status.threads = value.parse()?,
+                "VmPeak" => status.vm_peak = kbytes(value)?,
+                "VmSize" => status.vm_size = kbytes(value)?,
+                "VmHWM" => status.vm_hwm = kbytes(value)?,
+                "VmRSS" => status.vm_rss = kbytes(value)?,
+                "VmSwap" => status.vm_swap = kbytes(value)?,
+                "voluntary_ctxt_switches" => status.voluntary_ctxt_switches = value.parse()?,
+                "nonvoluntary_ctxt_switches" => {
+                    status.nonvoluntary_ctxt_switches = value.parse()?
+                }
+                _ => (),
+            }
+        }
+
+        if status.pid == 0 {
+            bail!("missing 'Pid' in /proc/PID/status");
+        }
+
+        Ok(status)
+    }
+}
+
+/// The contents of the `/proc/PID/io` file.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct PidIo {
+    /// Bytes read via read(2) and similar syscalls, including from the page cache.
+    pub rchar: u64,
+    /// Bytes written via write(2) and similar syscalls, including to the page cache.
+    pub wchar: u64,
+    /// Number of read syscalls.
+    pub syscr: u64,
+    /// Number of write syscalls.
+    pub syscw: u64,
+    /// Bytes actually fetched from the storage layer.
+    pub read_bytes: u64,
+    /// Bytes caused to be sent to the storage layer.
+    pub write_bytes: u64,
+    /// Bytes which were not written due to truncation of dirty page cache.
+    pub cancelled_write_bytes: u64,
+}
+
+impl PidIo {
+    /// Parse the contents of a `/proc/PID/io` file.
+    pub fn parse(content: &str) -> Result<Self, Error> {
+        let mut io = Self::default();
+        for line in content.lines() {
+            let (key, value) = match line.split_once(':') {
+                Some((key, value)) => (key, value.trim().parse()?),
+                None => continue,
+            };
+            match key {
+                "rchar" => io.rchar = value,
+                "wchar" => io.wchar = value,
+                "syscr" => io.syscr = value,
+                "syscw" => io.syscw = value,
+                "read_bytes" => io.read_bytes = value,
+                "write_bytes" => io.write_bytes = value,
+                "cancelled_write_bytes" => io.cancelled_write_bytes = value,
+                _ => (),
+            }
+        }
+        Ok(io)
+    }
+}
+
+/// Split a NUL separated procfs file such as `cmdline` or `environ`.
+fn split_nul(data: Vec<u8>) -> Vec<OsString> {
+    data.split(|b| *b == 0)
+        .filter(|part| !part.is_empty())
+        .map(|part| OsString::from_vec(part.to_vec()))
+        .collect()
+}
+
+/// Map permission errors to `None`, since some files are only accessible to the process owner
+/// or with `ptrace` access.
+fn permission_aware<T>(result: io::Result<T>) -> io::Result<Option<T>> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(err) if err.kind() == io::ErrorKind::PermissionDenied => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Access to the `/proc/PID` directory of a process.
+///
+/// Note that the files are read on each call, and the pid may have been reused by a different
+/// process in the mean time. Compare [`PidStat::starttime`] if this matters.
+#[derive(Clone, Debug)]
+pub struct ProcessInfo {
+    pid: Pid,
+    path: PathBuf,
+}
+
+impl ProcessInfo {
+    /// Access the information of a process.
+    pub fn new(pid: Pid) -> Self {
+        Self {
+            pid,
+            path: PathBuf::from(format!("/proc/{}", pid)),
+        }
+    }
+
+    /// Access the information of the current process.
+    pub fn current() -> Self {
+        Self::new(Pid::this())
+    }
+
+    /// The process id.
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    /// Check whether the process (still) exists.
+    pub fn exists(&self) -> bool {
+        self.path.exists()
+    }
+
+    fn read(&self, file: &str) -> io::Result<Vec<u8>> {
+        std::fs::read(self.path.join(file))
+    }
+
+    fn read_string(&self, file: &str) -> Result<String, Error> {
+        let data = self
+            .read(file)
+            .map_err(|err| format_err!("unable to read {:?} - {}", self.path.join(file), err))?;
+        Ok(String::from_utf8(data)?)
+    }
+
+    /// Read `/proc/PID/stat`.
+    pub fn stat(&self) -> Result<PidStat, Error> {
+        PidStat::read_from_pid(self.pid)
+    }
+
+    /// Read `/proc/PID/status`.
+    pub fn status(&self) -> Result<PidStatus, Error> {
+        PidStatus::parse(&self.read_string("status")?)
+    }
+
+    /// Read `/proc/PID/io`. Returns `None` if we are not allowed to access it.
+    pub fn io(&self) -> Result<Option<PidIo>, Error> {
+        match permission_aware(self.read("io"))? {
+            Some(data) => Ok(Some(PidIo::parse(std::str::from_utf8(&data)?)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Count the open file descriptors. Returns `None` if we are not allowed to access them.
+    pub fn fd_count(&self) -> Result<Option<usize>, Error> {
+        let dir = match permission_aware(std::fs::read_dir(self.path.join("fd")))? {
+            Some(dir) => dir,
+            None => return Ok(None),
+        };
+        let mut count = 0;
+        for entry in dir {
+            entry?;
+            count += 1;
+        }
+        Ok(Some(count))
+    }
+
+    /// Get the cgroup v2 control group of the process.
+    pub fn cgroup(&self) -> Result<CGroup, Error> {
+        CGroup::from_pid(self.pid)
+    }
+
+    /// Read the command line arguments. Kernel threads have an empty command line.
+    ///
+    /// Returns `None` if we are not allowed to access it.
+    pub fn cmdline(&self) -> Result<Option<Vec<OsString>>, Error> {
+        Ok(permission_aware(self.read("cmdline"))?.map(split_nul))
+    }
+
+    /// Read the initial environment of the process. Changes done by the process itself after
+    /// starting up are not visible.
+    ///
+    /// Returns `None` if we are not allowed to access it.
+    pub fn environ(&self) -> Result<Option<Vec<(OsString, OsString)>>, Error> {
+        let data = match permission_aware(self.read("environ"))? {
+            Some(data) => data,
+            None => return Ok(None),
+        };
+
+        Ok(Some(
+            split_nul(data)
+                .into_iter()
+                .map(|entry| {
+                    let mut entry = entry.into_vec();
+                    match entry.iter().position(|b| *b == b'=') {
+                        Some(pos) => {
+                            let value = entry.split_off(pos + 1);
+                            entry.pop();
+                            (OsString::from_vec(entry), OsString::from_vec(value))
+                        }
+                        None => (OsString::from_vec(entry), OsString::new()),
+                    }
+                })
+                .collect(),
+        ))
+    }
+}
+
+/// An entry of a [`ProcessTree`].
+#[derive(Clone, Debug, Serialize)]
+pub struct ProcessTreeEntry {
+    #[serde(serialize_with = "serialize_pid")]
+    pub pid: Pid,
+    #[serde(serialize_with = "serialize_pid")]
+    pub ppid: Pid,
+    pub status: char,
+    pub starttime: u64,
+}
+
+fn serialize_pid<S: serde::Serializer>(pid: &Pid, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_i32(pid.as_raw())
+}
+
+impl From<&PidStat> for ProcessTreeEntry {
+    fn from(stat: &PidStat) -> Self {
+        Self {
+            pid: stat.pid,
+            ppid: stat.ppid,
+            status: stat.status as char,
+            starttime: stat.starttime,
+        }
+    }
+}
+
+/// A snapshot of the parent/child relations of all processes.
+#[derive(Clone, Debug, Default)]
+pub struct ProcessTree {
+    entries: BTreeMap<i32, ProcessTreeEntry>,
+    children: HashMap<i32, Vec<Pid>>,
+}
+
+impl ProcessTree {
+    /// Take a snapshot of all processes currently visible in `/proc`.
+    ///
+    /// Processes exiting while the snapshot is taken are skipped.
+    pub fn snapshot() -> Result<Self, Error> {
+        let mut entries = Vec::new();
+        for entry in std::fs::read_dir("/proc")? {
+            let entry = entry?;
+            let pid = match entry
+                .file_name()
+                .to_str()
+                .and_then(|n| n.parse::<i32>().ok())
+            {
+                Some(pid) => Pid::from_raw(pid),
+                None => continue,
+            };
+            match PidStat::read_from_pid(pid) {
+                Ok(stat) => entries.push(ProcessTreeEntry::from(&stat)),
+                Err(err) => match err.downcast_ref::<io::Error>() {
+                    Some(ioerr) if ioerr.kind() == io::ErrorKind::NotFound => continue,
+                    // exited between reading the directory and the stat file
+                    Some(ioerr) if ioerr.raw_os_error() == Some(libc::ESRCH) => continue,
+                    _ => return Err(err),
+                },
+            }
+        }
+        Ok(Self::from_entries(entries))
+    }
+
+    /// Build a tree from a list of entries.
+    pub fn from_entries<I: IntoIterator<Item = ProcessTreeEntry>>(entries: I) -> Self {
+        let mut tree = Self::default();
+        for entry in entries {
+            tree.children
+                .entry(entry.ppid.as_raw())
+                .or_default()
+                .push(entry.pid);
+            tree.entries.insert(entry.pid.as_raw(), entry);
+        }
+        for children in tree.children.values_mut() {
+            children.sort_unstable();
+        }
+        tree
+    }
+
+    /// Get the entry of a process.
+    pub fn get(&self, pid: Pid) -> Option<&ProcessTreeEntry> {
+        self.entries.get(&pid.as_raw())
+    }
+
+    /// Iterate over all entries, ordered by pid.
+    pub fn iter(&self) -> impl Iterator<Item = &ProcessTreeEntry> {
+        self.entries.values()
+    }
+
+    /// The direct children of a process.
+    pub fn children(&self, pid: Pid) -> &[Pid] {
+        self.children
+            .get(&pid.as_raw())
+            .map(|c| c.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// All descendants of a process, parents before their children.
+    pub fn descendants(&self, pid: Pid) -> Vec<Pid> {
+        let mut out = Vec::new();
+        let mut pos = 0;
+        out.extend_from_slice(self.children(pid));
+        while pos < out.len() {
+            let pid = out[pos];
+            out.extend_from_slice(self.children(pid));
+            pos += 1;
+        }
+        out
+    }
+
+    /// The chain of parents of a process, starting with its direct parent.
+    pub fn ancestors(&self, pid: Pid) -> Vec<Pid> {
+        let mut out = Vec::new();
+        let mut current = pid;
+        while let Some(entry) = self.get(current) {
+            if entry.ppid.as_raw() == 0 || out.contains(&entry.ppid) {
+                break;
+            }
+            out.push(entry.ppid);
+            current = entry.ppid;
+        }
+        out
+    }
+}
+
+#[test]
+fn test_pid_status() {
+    let status = PidStatus::parse(
+        "Name:\tproxmox-backup-proxy\n\
+         Umask:\t0022\n\
+         State:\tS (sleeping)\n\
+         Tgid:\t1234\n\
+         Ngid:\t0\n\
+         Pid:\t1234\n\
+         PPid:\t1\n\
+         TracerPid:\t0\n\
+         Uid:\t34\t34\t34\t34\n\
+         Gid:\t34\t34\t34\t35\n\
+         FDSize:\t128\n\
+         VmPeak:\t  922340 kB\n\
+         VmSize:\t  856804 kB\n\
+         VmHWM:\t   90876 kB\n\
+         VmRSS:\t   80640 kB\n\
+         VmSwap:\t       0 kB\n\
+         Threads:\t21\n\
+         voluntary_ctxt_switches:\t1093\n\
+         nonvoluntary_ctxt_switches:\t12\n",
+    )
+    .expect("failed to parse /proc/PID/status sample");
+    assert_eq!(status.name, "proxmox-backup-proxy");
+    assert_eq!(status.state, 'S');
+    assert_eq!(status.pid, 1234);
+    assert_eq!(status.ppid, 1);
+    assert_eq!(status.uid, [34, 34, 34, 34]);
+    assert_eq!(status.gid, [34, 34, 34, 35]);
+    assert_eq!(status.vm_peak, 922340 * 1024);
+    assert_eq!(status.vm_rss, 80640 * 1024);
+    assert_eq!(status.threads, 21);
+    assert_eq!(status.voluntary_ctxt_switches, 1093);
+    assert_eq!(status.nonvoluntary_ctxt_switches, 12);
+
+    assert!(PidStatus::parse("Name:\tfoo\n").is_err());
+}
+
+#[test]
+fn test_pid_io() {
+    let io = PidIo::parse(
+        "rchar: 323934931\n\
+         wchar: 323929600\n\
+         syscr: 632687\n\
+         syscw: 632675\n\
+         read_bytes: 4096\n\
+         write_bytes: 323932160\n\
+         cancelled_write_bytes: 0\n",
+    )
+    .expect("failed to parse /proc/PID/io sample");
+    assert_eq!(io.rchar, 323934931);
+    assert_eq!(io.syscw, 632675);
+    assert_eq!(io.read_bytes, 4096);
+    assert_eq!(io.write_bytes, 323932160);
+}
+
+#[test]
+fn test_process_info_current() {
+    let info = ProcessInfo::current();
+    assert!(info.exists());
+    let status = info.status().unwrap();
+    assert_eq!(status.pid, Pid::this().as_raw());
+    assert!(info.fd_count().unwrap().unwrap() > 0);
+    assert!(!info.cmdline().unwrap().unwrap().is_empty());
+    info.environ().unwrap();
+}
+
+#[test]
+fn test_process_tree() {
+    let entry = |pid, ppid| ProcessTreeEntry {
+        pid: Pid::from_raw(pid),
+        ppid: Pid::from_raw(ppid),
+        status: 'S',
+        starttime: 0,
+    };
+    let tree = ProcessTree::from_entries(vec![
+        entry(1, 0),
+        entry(10, 1),
+        entry(11, 10),
+        entry(12, 10),
+        entry(13, 12),
+        entry(20, 1),
+    ]);
+
+    let pids = |list: &[Pid]| list.iter().map(|p| p.as_raw()).collect::<Vec<_>>();
+
+    assert_eq!(pids(tree.children(Pid::from_raw(10))), vec![11, 12]);
+    assert_eq!(pids(tree.children(Pid::from_raw(13))), Vec::<i32>::new());
+    assert_eq!(pids(&tree.descendants(Pid::from_raw(10))), vec![11, 12, 13]);
+    assert_eq!(pids(&tree.ancestors(Pid::from_raw(13))), vec![12, 10, 1]);
+    assert_eq!(tree.iter().count(), 6);
+
+    let live = ProcessTree::snapshot().unwrap();
+    assert!(live.get(Pid::this()).is_some());
+}
@@ -0,0 +1,121 @@
+//! `/proc/vmstat` handling.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use anyhow::{format_err, Error};
+use serde::Serialize;
+
+use super::counter_rate;
+
+/// The contents of `/proc/vmstat`.
+///
+/// The set of available keys depends on the kernel version and configuration, so the values are
+/// kept in a map, with accessors for the commonly used counters.
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(transparent)]
+pub struct VmStat(pub BTreeMap<String, u64>);
+
+impl VmStat {
+    /// Read `/proc/vmstat`.
+    pub fn read() -> Result<Self, Error> {
+        Self::parse(&std::fs::read_to_string("/proc/vmstat")?)
+    }
+
+    /// Parse the contents of a `/proc/vmstat` file.
+    pub fn parse(content: &str) -> Result<Self, Error> {
+        let mut map = BTreeMap::new();
+        for line in content.lines() {
+            let mut parts = line.split_ascii_whitespace();
+            let (key, value) = match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) => (key, value),
+                (None, _) => continue,
+                (Some(key), None) => return Err(format_err!("missing value for '{}'", key)),
+            };
+            let value = value
+                .parse()
+                .map_err(|err| format_err!("bad value for '{}' in /proc/vmstat - {}", key, err))?;
+            map.insert(key.to_string(), value);
+        }
+        Ok(Self(map))
+    }
+
+    /// Get an arbitrary value, if available.
+    pub fn get(&self, key: &str) -> Option<u64> {
+        self.0.get(key).copied()
+    }
+
+    /// Compute the per-second rate of a counter between an older sample and this one, taken
+    /// `elapsed` apart.
+    pub fn rate(&self, prev: &VmStat, key: &str, elapsed: Duration) -> Option<f64> {
+        Some(counter_rate(prev.get(key)?, self.get(key)?, elapsed))
+    }
+
+    /// Kilobytes paged in from disk.
+    pub fn pgpgin(&self) -> u64 {
+        self.get("pgpgin").unwrap_or(0)
+    }
+
+    /// Kilobytes paged out to disk.
+    pub fn pgpgout(&self) -> u64 {
+        self.get("pgpgout").unwrap_or(0)
+    }
+
+    /// Pages swapped in.
+    pub fn pswpin(&self) -> u64 {
+        self.get("pswpin").unwrap_or(0)
+    }
+
+    /// Pages swapped out.
+    pub fn pswpout(&self) -> u64 {
+        self.get("pswpout").unwrap_or(0)
+    }
+
+    /// Page faults, both minor and major.
+    pub fn pgfault(&self) -> u64 {
+        self.get("pgfault").unwrap_or(0)
+    }
+
+    /// Major page faults.
+    pub fn pgmajfault(&self) -> u64 {
+        self.get("pgmajfault").unwrap_or(0)
+    }
+
+    /// Number of processes killed by the OOM killer.
+    pub fn oom_kill(&self) -> u64 {
+        self.get("oom_kill").unwrap_or(0)
+    }
+}
+
+/// Read `/proc/vmstat`.
+pub fn read_vmstat() -> Result<VmStat, Error> {
+    VmStat::read()
+}
+
+#[test]
+fn test_vmstat() {
+    let prev = VmStat::parse(
+        "nr_free_pages 3164017\n\
+         pgpgin 10000\n\
+         pgpgout 20000\n\
+         pswpin 0\n\
+         pswpout 0\n\
+         pgfault 500000\n\
+         pgmajfault 100\n",
+    )
+    .expect("failed to parse /proc/vmstat sample");
+    assert_eq!(prev.get("nr_free_pages"), Some(3164017));
+    assert_eq!(prev.pgpgin(), 10000);
+    assert_eq!(prev.pgmajfault(), 100);
+    assert_eq!(prev.oom_kill(), 0);
+    assert_eq!(prev.get("oom_kill"), None);
+
+    let next = VmStat::parse("pgpgin 14000\npgpgout 20000\n").unwrap();
+    let elapsed = Duration::from_secs(4);
+    assert_eq!(next.rate(&prev, "pgpgin", elapsed), Some(1000.0));
+    assert_eq!(next.rate(&prev, "pgpgout", elapsed), Some(0.0));
+    assert_eq!(next.rate(&prev, "pgfault", elapsed), None);
+
+    assert!(VmStat::parse("pgpgin\n").is_err());
+    assert!(VmStat::parse("pgpgin x\n").is_err());
+}
@@ -1,30 +1,108 @@
 //! Log rotation helper
 
-use std::fs::{rename, File};
-use std::io::Read;
+use std::fs::{rename, File, Metadata};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::MetadataExt;
 use std::os::unix::io::{FromRawFd, IntoRawFd};
 use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use anyhow::{bail, Error};
+use anyhow::{bail, format_err, Error};
 use nix::unistd;
 
+use proxmox_time::CalendarEvent;
+
 use crate::fs::{make_tmp_file, CreateOptions};
 
+/// Extensions of compressed log files, used to find them regardless of the configured
+/// compression.
+const COMPRESSED_EXTENSIONS: &[&str] = &["zst", "gz"];
+
+/// Compression used for rotated files. The newest rotated file (`.1`) is never compressed, so
+/// that processes still writing to it after a rename do not lose data.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    /// zstd with the given level, 0 selects zstd's default level.
+    Zstd(i32),
+    /// gzip with the given level from 0 to 9.
+    Gzip(u32),
+}
+
+impl Compression {
+    /// The file extension of files compressed with this method.
+    pub fn extension(self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Zstd(_) => Some("zst"),
+            Compression::Gzip(_) => Some("gz"),
+        }
+    }
+}
+
+/// When to rotate with [`LogRotate::rotate_if_due`].
+#[derive(Clone, Debug)]
+pub enum RotationSchedule {
+    Daily,
+    Weekly,
+    Calendar(CalendarEvent),
+}
+
+impl RotationSchedule {
+    fn event(&self) -> Result<CalendarEvent, Error> {
+        match self {
+            RotationSchedule::Daily => "daily".parse(),
+            RotationSchedule::Weekly => "weekly".parse(),
+            RotationSchedule::Calendar(event) => Ok(event.clone()),
+        }
+    }
+}
+
+fn compressed_extension(path: &Path) -> Option<&str> {
+    let ext = path.extension()?.to_str()?;
+    COMPRESSED_EXTENSIONS.contains(&ext).then_some(ext)
+}
+
+fn epoch_of(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs() as i64,
+        Err(err) => -(err.duration().as_secs() as i64),
+    }
+}
+
 /// Used for rotating log files and iterating over them
+///
+/// ```no_run
+/// # use proxmox_sys::logrotate::{Compression, LogRotate, RotationSchedule};
+/// # fn test() -> Result<(), anyhow::Error> {
+/// let mut logrotate = LogRotate::new("/var/log/proxmox-backup/api/access.log", false, None, None)?
+///     .compression(Compression::Gzip(6))
+///     .schedule(RotationSchedule::Daily)
+///     .max_age(std::time::Duration::from_secs(30 * 24 * 3600))
+///     .copy_truncate(true);
+/// logrotate.rotate_if_due()?;
+/// # Ok(())
+/// # }
+/// ```
 pub struct LogRotate {
     base_path: PathBuf,
-    compress: bool,
+    compression: Compression,
     max_files: Option<usize>,
+    max_age: Option<Duration>,
+    max_total_size: Option<u64>,
+    schedule: Option<RotationSchedule>,
+    copy_truncate: bool,
     /// User logs should be reowned to.
     options: CreateOptions,
 }
 
 impl LogRotate {
     /// Creates a new instance if the path given is a valid file name (iow. does not end with ..)
-    /// 'compress' decides if compresses files will be created on rotation, and if it will search
-    /// '.zst' files when iterating
+    /// 'compress' decides if rotated files will be compressed with zstd, see
+    /// [`compression`](Self::compression) for other methods.
     ///
-    /// 'options' is used for newly created compressed files.
+    /// 'options' is used for newly created rotated files.
     pub fn new<P: AsRef<Path>>(
         path: P,
         compress: bool,
@@ -37,17 +115,58 @@ impl LogRotate {
         Ok(Self {
             base_path: path.as_ref().to_path_buf(),
             options: options.unwrap_or_default(),
-            compress,
+            compression: if compress {
+                Compression::Zstd(0)
+            } else {
+                Compression::None
+            },
             max_files,
+            max_age: None,
+            max_total_size: None,
+            schedule: None,
+            copy_truncate: false,
         })
     }
 
-    /// Returns an iterator over the logrotated file names that exist
+    /// Set the compression for newly rotated files. Existing files keep their compression.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Remove rotated files last modified longer than `max_age` ago.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Remove the oldest rotated files once their total size exceeds `max_total_size` bytes.
+    pub fn max_total_size(mut self, max_total_size: u64) -> Self {
+        self.max_total_size = Some(max_total_size);
+        self
+    }
+
+    /// Set the schedule for [`rotate_if_due`](Self::rotate_if_due).
+    pub fn schedule(mut self, schedule: RotationSchedule) -> Self {
+        self.schedule = Some(schedule);
+        self
+    }
+
+    /// Copy the current file and truncate it instead of renaming it, for files other processes
+    /// keep open. Those should open the file with `O_APPEND`, and lines written between copying
+    /// and truncating are lost.
+    pub fn copy_truncate(mut self, copy_truncate: bool) -> Self {
+        self.copy_truncate = copy_truncate;
+        self
+    }
+
+    /// Returns an iterator over the logrotated file names that exist, newest first.
+    ///
+    /// Compressed files are found regardless of the configured compression.
     pub fn file_names(&self) -> LogRotateFileNames {
         LogRotateFileNames {
             base_path: self.base_path.clone(),
             count: 0,
-            compress: self.compress,
         }
     }
 
@@ -58,30 +177,43 @@ impl LogRotate {
         }
     }
 
+    /// Returns a reader over the whole history, from the oldest rotated file to the current one,
+    /// decompressing files as needed.
+    pub fn reader(&self) -> LogRotateReader {
+        LogRotateReader {
+            file_names: self.file_names().collect(),
+            current: None,
+        }
+    }
+
+    fn rotated_file_name(&self, index: usize, extension: Option<&str>) -> PathBuf {
+        let mut path = self.base_path.clone().into_os_string();
+        path.push(format!(".{index}"));
+        if let Some(extension) = extension {
+            path.push(format!(".{extension}"));
+        }
+        path.into()
+    }
+
     fn compress(
         source_path: &Path,
         target_path: &Path,
+        compression: Compression,
         options: &CreateOptions,
     ) -> Result<(), Error> {
         let mut source = File::open(source_path)?;
         let (fd, tmp_path) = make_tmp_file(target_path, options.clone())?;
         let target = unsafe { File::from_raw_fd(fd.into_raw_fd()) };
-        let mut encoder = match zstd::stream::write::Encoder::new(target, 0) {
-            Ok(encoder) => encoder,
-            Err(err) => {
-                let _ = unistd::unlink(&tmp_path);
-                bail!("creating zstd encoder failed - {}", err);
-            }
-        };
 
-        if let Err(err) = std::io::copy(&mut source, &mut encoder) {
-            let _ = unistd::unlink(&tmp_path);
-            bail!("zstd encoding failed for file {:?} - {}", target_path, err);
-        }
+        let result = match compression {
+            Compression::None => Err(format_err!("no compression method given")),
+            Compression::Zstd(level) => encode_zstd(&mut source, target, level),
+            Compression::Gzip(level) => encode_gzip(&mut source, target, level),
+        };
 
-        if let Err(err) = encoder.finish() {
+        if let Err(err) = result {
             let _ = unistd::unlink(&tmp_path);
-            bail!("zstd finish failed for file {:?} - {}", target_path, err);
+            bail!("compressing file {:?} failed - {}", target_path, err);
         }
 
         if let Err(err) = rename(&tmp_path, target_path) {
@@ -96,43 +228,91 @@ impl LogRotate {
         Ok(())
     }
 
+    fn copy_and_truncate(
+        source_path: &Path,
+        target_path: &Path,
+        options: &CreateOptions,
+    ) -> Result<(), Error> {
+        let mut source = File::options().read(true).write(true).open(source_path)?;
+        let (fd, tmp_path) = make_tmp_file(target_path, options.clone())?;
+        let mut target = unsafe { File::from_raw_fd(fd.into_raw_fd()) };
+
+        if let Err(err) = io::copy(&mut source, &mut target) {
+            let _ = unistd::unlink(&tmp_path);
+            bail!("copying {:?} failed - {}", source_path, err);
+        }
+
+        if let Err(err) = rename(&tmp_path, target_path) {
+            let _ = unistd::unlink(&tmp_path);
+            bail!("rename failed for file {:?} - {}", target_path, err);
+        }
+
+        source
+            .set_len(0)
+            .map_err(|err| format_err!("truncating {:?} failed - {}", source_path, err))
+    }
+
     /// Rotates the files
-    /// if the 'compress' option was given it will compress the newest file
+    /// if a compression was set it will compress the files from the second one on
     ///
     /// e.g. rotates
     /// foo.2.zst => foo.3.zst
     /// foo.1     => foo.2.zst
     /// foo       => foo.1
+    ///
+    /// Afterwards, files exceeding the retention limits are removed.
     pub fn do_rotate(&mut self) -> Result<(), Error> {
-        let mut filenames: Vec<PathBuf> = self.file_names().collect();
-        if filenames.is_empty() {
+        if !self.base_path.is_file() {
             return Ok(()); // no file means nothing to rotate
         }
-        let count = filenames.len() + 1;
+        let filenames: Vec<PathBuf> = self.file_names().collect();
 
-        let mut next_filename = self.base_path.clone().canonicalize()?.into_os_string();
-        next_filename.push(format!(".{}", filenames.len()));
-        if self.compress && count > 2 {
-            next_filename.push(".zst");
+        for (index, source) in filenames.iter().enumerate().skip(1).rev() {
+            match (compressed_extension(source), self.compression.extension()) {
+                (None, Some(extension)) => {
+                    let target = self.rotated_file_name(index + 1, Some(extension));
+                    Self::compress(source, &target, self.compression, &self.options)?;
+                }
+                (extension, _) => rename(source, self.rotated_file_name(index + 1, extension))?,
+            }
         }
 
-        filenames.push(PathBuf::from(next_filename));
+        let target = self.rotated_file_name(1, None);
+        if self.copy_truncate {
+            Self::copy_and_truncate(&self.base_path, &target, &self.options)?;
+        } else {
+            rename(&self.base_path, target)?;
+        }
 
-        for i in (0..count - 1).rev() {
-            if self.compress
-                && filenames[i].extension() != Some(std::ffi::OsStr::new("zst"))
-                && filenames[i + 1].extension() == Some(std::ffi::OsStr::new("zst"))
-            {
-                Self::compress(&filenames[i], &filenames[i + 1], &self.options)?;
-            } else {
-                rename(&filenames[i], &filenames[i + 1])?;
+        self.apply_retention()
+    }
+
+    /// Removes rotated files exceeding the file count, age or total size limits. Once a file
+    /// exceeds a limit, all older files are removed too. The current file is never removed.
+    pub fn apply_retention(&self) -> Result<(), Error> {
+        let now = SystemTime::now();
+        let mut total_size = 0u64;
+        let mut expired = false;
+
+        for (index, file) in self.rotated_file_names().enumerate() {
+            if !expired {
+                let metadata = file.metadata()?;
+                total_size += metadata.len();
+
+                // like before, max_files includes the current file
+                expired = matches!(self.max_files, Some(max) if index + 1 >= max)
+                    || matches!(self.max_total_size, Some(max) if total_size > max)
+                    || match (self.max_age, metadata.modified()) {
+                        (Some(max_age), Ok(modified)) => {
+                            now.duration_since(modified).unwrap_or_default() > max_age
+                        }
+                        _ => false,
+                    };
             }
-        }
 
-        if let Some(max_files) = self.max_files {
-            for file in filenames.iter().skip(max_files) {
-                if let Err(err) = unistd::unlink(file) {
-                    eprintln!("could not remove {:?}: {}", &file, err);
+            if expired {
+                if let Err(err) = unistd::unlink(&file) {
+                    log::error!("could not remove {:?}: {}", &file, err);
                 }
             }
         }
@@ -140,11 +320,62 @@ impl LogRotate {
         Ok(())
     }
 
+    fn rotated_file_names(&self) -> LogRotateFileNames {
+        LogRotateFileNames {
+            base_path: self.base_path.clone(),
+            count: 1,
+        }
+    }
+
+    /// The time of the last rotation, if there is any rotated file.
+    pub fn last_rotation(&self) -> Option<i64> {
+        // renaming or creating the newest rotated file updates its ctime, nothing else touches it
+        let file = self.rotated_file_names().next()?;
+        file.metadata().ok().map(|metadata| metadata.ctime())
+    }
+
+    /// Checks whether a scheduled rotation is due at `now`.
+    ///
+    /// The schedule is computed from the last rotation, or if there was none, from the creation
+    /// time of the current file. Empty files are never rotated.
+    pub fn rotation_due(&self, now: i64) -> Result<bool, Error> {
+        let schedule = match &self.schedule {
+            Some(schedule) => schedule.event()?,
+            None => return Ok(false),
+        };
+
+        let metadata = match self.base_path.metadata() {
+            Ok(metadata) => metadata,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => bail!("unable to open {:?} - {}", self.base_path, err),
+        };
+        if metadata.len() == 0 {
+            return Ok(false);
+        }
+
+        let last = match self.last_rotation() {
+            Some(last) => last,
+            None => creation_time(&metadata),
+        };
+
+        Ok(matches!(schedule.compute_next_event(last)?, Some(next) if next <= now))
+    }
+
+    /// Rotate if the schedule set via [`schedule`](Self::schedule) is due.
+    pub fn rotate_if_due(&mut self) -> Result<bool, Error> {
+        if self.rotation_due(proxmox_time::epoch_i64())? {
+            self.do_rotate()?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
     /// Conditional rotate if file bigger than 'max_size'
     pub fn rotate(&mut self, max_size: u64) -> Result<bool, Error> {
         let metadata = match self.base_path.metadata() {
             Ok(metadata) => metadata,
-            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
             Err(err) => bail!("unable to open {:?} - {}", self.base_path, err),
         };
 
@@ -157,11 +388,42 @@ impl LogRotate {
     }
 }
 
+/// Birth time if supported by the file system, modification time otherwise.
+fn creation_time(metadata: &Metadata) -> i64 {
+    match metadata.created() {
+        Ok(created) => epoch_of(created),
+        Err(_) => metadata.mtime(),
+    }
+}
+
+fn encode_zstd<W: Write>(source: &mut File, target: W, level: i32) -> Result<(), Error> {
+    let mut encoder = zstd::stream::write::Encoder::new(target, level)
+        .map_err(|err| format_err!("creating zstd encoder failed - {}", err))?;
+    io::copy(source, &mut encoder)?;
+    encoder.finish()?;
+    Ok(())
+}
+
+fn encode_gzip<W: Write>(source: &mut File, target: W, level: u32) -> Result<(), Error> {
+    let mut encoder = flate2::write::GzEncoder::new(target, flate2::Compression::new(level));
+    io::copy(source, &mut encoder)?;
+    encoder.finish()?;
+    Ok(())
+}
+
+fn open_log_file(path: &Path) -> io::Result<Box<dyn Read + Send>> {
+    let file = File::open(path)?;
+    Ok(match compressed_extension(path) {
+        Some("zst") => Box::new(zstd::stream::read::Decoder::new(file)?),
+        Some("gz") => Box::new(flate2::read::MultiGzDecoder::new(file)),
+        _ => Box::new(file),
+    })
+}
+
 /// Iterator over logrotated file names
 pub struct LogRotateFileNames {
     base_path: PathBuf,
     count: usize,
-    compress: bool,
 }
 
 impl Iterator for LogRotateFileNames {
@@ -175,22 +437,22 @@ impl Iterator for LogRotateFileNames {
             self.count += 1;
 
             if Path::new(&path).is_file() {
-                Some(path.into())
-            } else if self.compress {
-                path.push(".zst");
-                if Path::new(&path).is_file() {
-                    Some(path.into())
-                } else {
-                    None
-                }
-            } else {
-                None
+                return Some(path.into());
             }
-        } else if self.base_path.is_file() {
-            self.count += 1;
-            Some(self.base_path.to_path_buf())
+
+            COMPRESSED_EXTENSIONS.iter().find_map(|extension| {
+                let mut path = path.clone();
+                path.push(format!(".{extension}"));
+                Path::new(&path).is_file().then(|| path.into())
+            })
         } else {
-            None
+            self.count += 1;
+            if self.base_path.is_file() {
+                Some(self.base_path.to_path_buf())
+            } else {
+                // after a rotation, the history is still available
+                self.next()
+            }
         }
     }
 }
@@ -205,13 +467,155 @@ impl Iterator for LogRotateFiles {
 
     fn next(&mut self) -> Option<Self::Item> {
         let filename = self.file_names.next()?;
-        let file = File::open(&filename).ok()?;
+        open_log_file(&filename).ok()
+    }
+}
+
+/// Reads all logrotated files in chronological order as one stream, see
+/// [`LogRotate::reader`].
+pub struct LogRotateReader {
+    /// remaining files, newest first
+    file_names: Vec<PathBuf>,
+    current: Option<Box<dyn Read + Send>>,
+}
+
+impl Read for LogRotateReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            if let Some(current) = &mut self.current {
+                let got = current.read(buf)?;
+                if got > 0 {
+                    return Ok(got);
+                }
+            }
+            match self.file_names.pop() {
+                Some(path) => self.current = Some(open_log_file(&path)?),
+                None => return Ok(0),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "proxmox-sys-logrotate-{name}-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn read_history(logrotate: &LogRotate) -> String {
+        let mut data = String::new();
+        logrotate.reader().read_to_string(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_rotate_compressed() {
+        let dir = test_dir("compressed");
+        let log = dir.join("test.log");
+        let mut logrotate = LogRotate::new(&log, true, Some(5), None).unwrap();
 
-        if filename.extension() == Some(std::ffi::OsStr::new("zst")) {
-            let encoder = zstd::stream::read::Decoder::new(file).ok()?;
-            return Some(Box::new(encoder));
+        for i in 1..=3 {
+            std::fs::write(&log, format!("line {i}\n")).unwrap();
+            logrotate.do_rotate().unwrap();
         }
+        assert!(dir.join("test.log.1").is_file());
+        assert!(dir.join("test.log.2.zst").is_file());
+        assert!(dir.join("test.log.3.zst").is_file());
+
+        // switching the compression keeps the old files readable
+        let mut logrotate = logrotate.compression(Compression::Gzip(9));
+        std::fs::write(&log, "line 4\n").unwrap();
+        logrotate.do_rotate().unwrap();
+        assert!(dir.join("test.log.2.gz").is_file());
+        assert!(dir.join("test.log.4.zst").is_file());
+
+        std::fs::write(&log, "line 5\n").unwrap();
+        assert_eq!(
+            read_history(&logrotate),
+            "line 1\nline 2\nline 3\nline 4\nline 5\n"
+        );
+
+        // max_files includes the current file
+        logrotate.do_rotate().unwrap();
+        assert_eq!(logrotate.file_names().count(), 4);
+        assert_eq!(read_history(&logrotate), "line 2\nline 3\nline 4\nline 5\n");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_copy_truncate_and_retention() {
+        let dir = test_dir("copytruncate");
+        let log = dir.join("test.log");
+        let mut logrotate = LogRotate::new(&log, false, None, None)
+            .unwrap()
+            .copy_truncate(true)
+            .max_total_size(20);
+
+        let mut file = File::options()
+            .create(true)
+            .append(true)
+            .open(&log)
+            .unwrap();
+        for i in 1..=3 {
+            writeln!(file, "entry {i:03}").unwrap();
+            assert!(logrotate.rotate(5).unwrap());
+            assert_eq!(log.metadata().unwrap().len(), 0);
+        }
+        writeln!(file, "entry 004").unwrap();
+
+        // each file has 10 bytes, so only the two newest rotated files are kept
+        assert_eq!(
+            read_history(&logrotate),
+            "entry 002\nentry 003\nentry 004\n"
+        );
+
+        let logrotate = logrotate.max_age(Duration::from_secs(3600));
+        let old = nix::sys::time::TimeVal::new(proxmox_time::epoch_i64() - 7200, 0);
+        nix::sys::stat::utimes(&dir.join("test.log.1"), &old, &old).unwrap();
+        logrotate.apply_retention().unwrap();
+        assert_eq!(read_history(&logrotate), "entry 004\n");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rotation_schedule() {
+        let dir = test_dir("schedule");
+        let log = dir.join("test.log");
+        let mut logrotate = LogRotate::new(&log, false, None, None)
+            .unwrap()
+            .schedule(RotationSchedule::Daily);
+
+        let now = proxmox_time::epoch_i64();
+        assert!(!logrotate.rotation_due(now + 2 * 86400).unwrap());
+
+        std::fs::write(&log, "data\n").unwrap();
+        assert!(!logrotate.rotation_due(now).unwrap());
+        assert!(logrotate.rotation_due(now + 2 * 86400).unwrap());
+
+        logrotate.do_rotate().unwrap();
+        let last = logrotate.last_rotation().unwrap();
+        assert!((last - now).abs() < 60);
+
+        std::fs::write(&log, "data\n").unwrap();
+        assert!(!logrotate.rotation_due(now).unwrap());
+        assert!(logrotate.rotation_due(now + 2 * 86400).unwrap());
+
+        let logrotate = logrotate.schedule(RotationSchedule::Calendar("*:0/5".parse().unwrap()));
+        assert!(logrotate.rotation_due(now + 600).unwrap());
 
-        Some(Box::new(file))
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -0,0 +1,623 @@
+//! Access to the systemd manager via D-Bus.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, format_err, Error};
+use futures_lite::StreamExt;
+use zbus::blocking::{Connection, Proxy};
+use zbus::proxy::SignalStream;
+use zbus::zvariant::{OwnedObjectPath, OwnedValue};
+
+/// systemd's private socket, only accessible to root. It does not require a running bus daemon.
+pub const SYSTEMD_PRIVATE_SOCKET: &str = "/run/systemd/private";
+
+const SYSTEMD_DESTINATION: &str = "org.freedesktop.systemd1";
+const MANAGER_PATH: &str = "/org/freedesktop/systemd1";
+const MANAGER_INTERFACE: &str = "org.freedesktop.systemd1.Manager";
+const UNIT_INTERFACE: &str = "org.freedesktop.systemd1.Unit";
+const PROPERTIES_INTERFACE: &str = "org.freedesktop.DBus.Properties";
+
+/// How to handle conflicting jobs when queuing a new one, see `systemctl --job-mode`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum JobMode {
+    #[default]
+    Replace,
+    Fail,
+    Isolate,
+    IgnoreDependencies,
+    IgnoreRequirements,
+}
+
+impl JobMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            JobMode::Replace => "replace",
+            JobMode::Fail => "fail",
+            JobMode::Isolate => "isolate",
+            JobMode::IgnoreDependencies => "ignore-dependencies",
+            JobMode::IgnoreRequirements => "ignore-requirements",
+        }
+    }
+}
+
+/// The result of a finished job, as reported by the `JobRemoved` signal.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum JobResult {
+    /// The job completed successfully.
+    Done,
+    /// The job was canceled before it finished.
+    Canceled,
+    /// The job timed out.
+    Timeout,
+    /// The job failed.
+    Failed,
+    /// A job this job depended on did not complete successfully.
+    Dependency,
+    /// The job was skipped because it did not apply to the unit's current state.
+    Skipped,
+    /// A result not known to this library.
+    Other(String),
+}
+
+impl JobResult {
+    fn from_str(result: &str) -> Self {
+        match result {
+            "done" => JobResult::Done,
+            "canceled" => JobResult::Canceled,
+            "timeout" => JobResult::Timeout,
+            "failed" => JobResult::Failed,
+            "dependency" => JobResult::Dependency,
+            "skipped" => JobResult::Skipped,
+            other => JobResult::Other(other.to_string()),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            JobResult::Done => "done",
+            JobResult::Canceled => "canceled",
+            JobResult::Timeout => "timeout",
+            JobResult::Failed => "failed",
+            JobResult::Dependency => "dependency",
+            JobResult::Skipped => "skipped",
+            JobResult::Other(other) => other,
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        *self == JobResult::Done
+    }
+}
+
+/// The state of a unit.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UnitState {
+    pub name: String,
+    pub description: String,
+    /// E.g. `loaded`, `not-found` or `masked`.
+    pub load_state: String,
+    /// E.g. `active`, `inactive`, `failed` or `activating`.
+    pub active_state: String,
+    /// The unit type specific state, e.g. `running` or `exited` for services.
+    pub sub_state: String,
+    /// E.g. `enabled`, `disabled` or `static`. Not available when listing units.
+    pub unit_file_state: Option<String>,
+}
+
+impl UnitState {
+    pub fn is_active(&self) -> bool {
+        self.active_state == "active" || self.active_state == "reloading"
+    }
+
+    pub fn is_failed(&self) -> bool {
+        self.active_state == "failed"
+    }
+}
+
+/// A change done to the file system by enabling or disabling unit files.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UnitFileChange {
+    /// `symlink` or `unlink`.
+    pub change_type: String,
+    pub file: String,
+    pub destination: String,
+}
+
+fn unit_file_changes(changes: Vec<(String, String, String)>) -> Vec<UnitFileChange> {
+    changes
+        .into_iter()
+        .map(|(change_type, file, destination)| UnitFileChange {
+            change_type,
+            file,
+            destination,
+        })
+        .collect()
+}
+
+fn string_property(props: &HashMap<String, OwnedValue>, name: &str) -> Option<String> {
+    props
+        .get(name)
+        .and_then(|value| <&str>::try_from(&**value).ok())
+        .map(str::to_string)
+}
+
+fn proxy<'a>(conn: &Connection, path: &'a str, interface: &'a str) -> Result<Proxy<'a>, Error> {
+    Ok(zbus::blocking::proxy::Builder::new(conn)
+        .destination(SYSTEMD_DESTINATION)?
+        .path(path)?
+        .interface(interface)?
+        .cache_properties(zbus::CacheProperties::No)
+        .build()?)
+}
+
+/// A connection to the systemd manager.
+///
+/// ```no_run
+/// # use proxmox_sys::systemd::{JobMode, SystemdManager};
+/// # fn test() -> Result<(), anyhow::Error> {
+/// let mut systemd = SystemdManager::connect()?;
+/// systemd.enable_unit_files(&["proxmox-backup-daily-update.timer"], false, false)?;
+/// systemd.daemon_reload()?;
+/// let job = systemd.restart_unit("proxmox-backup-proxy.service", JobMode::Replace)?;
+/// let result = systemd.wait_for_job(&job, std::time::Duration::from_secs(30))?;
+/// assert!(result.is_done());
+/// # Ok(())
+/// # }
+/// ```
+pub struct SystemdManager {
+    manager: Proxy<'static>,
+    job_removed: SignalStream<'static>,
+    /// Results of jobs which were removed while waiting for another job.
+    finished_jobs: HashMap<String, JobResult>,
+}
+
+impl SystemdManager {
+    /// Connect via the private socket if accessible (as root), otherwise via the system bus.
+    pub fn connect() -> Result<Self, Error> {
+        if nix::unistd::geteuid().is_root() && Path::new(SYSTEMD_PRIVATE_SOCKET).exists() {
+            if let Ok(manager) = Self::private() {
+                return Ok(manager);
+            }
+        }
+        Self::system_bus()
+    }
+
+    /// Connect via the system bus.
+    pub fn system_bus() -> Result<Self, Error> {
+        Self::with_connection(Connection::system()?)
+    }
+
+    /// Connect via systemd's private socket.
+    pub fn private() -> Result<Self, Error> {
+        Self::with_connection_to(SYSTEMD_PRIVATE_SOCKET, false)
+    }
+
+    /// Connect to an arbitrary socket, either a bus (`bus == true`) or a direct connection to the
+    /// manager.
+    pub fn with_connection_to<P: AsRef<Path>>(path: P, bus: bool) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let address = format!(
+            "unix:path={}",
+            path.to_str()
+                .ok_or_else(|| format_err!("non UTF-8 socket path {path:?}"))?,
+        );
+        let mut builder = zbus::blocking::connection::Builder::address(address.as_str())?;
+        if !bus {
+            builder = builder.p2p();
+        }
+        Self::with_connection(builder.build()?)
+    }
+
+    /// Use an existing connection, either to a bus or directly to the manager.
+    ///
+    /// This subscribes to the manager's signals, so that jobs can be waited for.
+    pub fn with_connection(conn: Connection) -> Result<Self, Error> {
+        let manager = proxy(&conn, MANAGER_PATH, MANAGER_INTERFACE)?;
+
+        // Listen for removed jobs before anything is queued, so no result can be missed.
+        let job_removed = async_io::block_on(manager.inner().receive_signal("JobRemoved"))?;
+        manager
+            .call::<_, _, ()>("Subscribe", &())
+            .map_err(|err| format_err!("failed to subscribe to systemd signals - {err}"))?;
+
+        Ok(Self {
+            manager,
+            job_removed,
+            finished_jobs: HashMap::new(),
+        })
+    }
+
+    fn job_call(&mut self, member: &str, unit: &str, mode: JobMode) -> Result<String, Error> {
+        let job: OwnedObjectPath = self
+            .manager
+            .call(member, &(unit, mode.as_str()))
+            .map_err(|err| format_err!("{member} of {unit} failed - {err}"))?;
+        Ok(job.as_str().to_string())
+    }
+
+    /// Queue a start job for a unit, returns the job's object path.
+    pub fn start_unit(&mut self, unit: &str, mode: JobMode) -> Result<String, Error> {
+        self.job_call("StartUnit", unit, mode)
+    }
+
+    /// Queue a stop job for a unit, returns the job's object path.
+    pub fn stop_unit(&mut self, unit: &str, mode: JobMode) -> Result<String, Error> {
+        self.job_call("StopUnit", unit, mode)
+    }
+
+    /// Queue a restart job for a unit, returns the job's object path.
+    pub fn restart_unit(&mut self, unit: &str, mode: JobMode) -> Result<String, Error> {
+        self.job_call("RestartUnit", unit, mode)
+    }
+
+    /// Queue a reload job for a unit, returns the job's object path.
+    pub fn reload_unit(&mut self, unit: &str, mode: JobMode) -> Result<String, Error> {
+        self.job_call("ReloadUnit", unit, mode)
+    }
+
+    /// Restart a unit only if it is running, returns the job's object path.
+    pub fn try_restart_unit(&mut self, unit: &str, mode: JobMode) -> Result<String, Error> {
+        self.job_call("TryRestartUnit", unit, mode)
+    }
+
+    /// Reload a unit if supported, otherwise restart it. Returns the job's object path.
+    pub fn reload_or_restart_unit(&mut self, unit: &str, mode: JobMode) -> Result<String, Error> {
+        self.job_call("ReloadOrRestartUnit", unit, mode)
+    }
+
+    /// Wait until a job has finished and return its result.
+    ///
+    /// A job which did not complete successfully is not an error, check the result for that. The
+    /// job must have been queued after this manager was connected, as its completion is only
+    /// noticed via the `JobRemoved` signal.
+    pub fn wait_for_job(&mut self, job: &str, timeout: Duration) -> Result<JobResult, Error> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            if let Some(result) = self.finished_jobs.remove(job) {
+                return Ok(result);
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                bail!("timeout waiting for job {job}");
+            }
+
+            let signal = async_io::block_on(futures_lite::future::or(
+                async { Some(self.job_removed.next().await) },
+                async {
+                    async_io::Timer::after(remaining).await;
+                    None
+                },
+            ));
+
+            let message = match signal {
+                Some(Some(message)) => message,
+                Some(None) => bail!("connection closed while waiting for job {job}"),
+                None => continue,
+            };
+
+            let (_id, removed_job, _unit, result): (u32, OwnedObjectPath, String, String) =
+                message.body().deserialize()?;
+            self.finished_jobs
+                .insert(removed_job.to_string(), JobResult::from_str(&result));
+        }
+    }
+
+    /// Get the state of a unit, loading it if necessary.
+    pub fn unit_state(&mut self, unit: &str) -> Result<UnitState, Error> {
+        let path: OwnedObjectPath = self.manager.call("LoadUnit", &(unit,))?;
+
+        let props: HashMap<String, OwnedValue> =
+            proxy(self.manager.connection(), path.as_str(), PROPERTIES_INTERFACE)?
+                .call("GetAll", &(UNIT_INTERFACE,))?;
+
+        Ok(UnitState {
+            name: string_property(&props, "Id").unwrap_or_else(|| unit.to_string()),
+            description: string_property(&props, "Description").unwrap_or_default(),
+            load_state: string_property(&props, "LoadState").unwrap_or_default(),
+            active_state: string_property(&props, "ActiveState").unwrap_or_default(),
+            sub_state: string_property(&props, "SubState").unwrap_or_default(),
+            unit_file_state: string_property(&props, "UnitFileState"),
+        })
+    }
+
+    /// Check whether a unit is active.
+    pub fn is_active(&mut self, unit: &str) -> Result<bool, Error> {
+        Ok(self.unit_state(unit)?.is_active())
+    }
+
+    /// List all currently loaded units.
+    pub fn list_units(&mut self) -> Result<Vec<UnitState>, Error> {
+        #[allow(clippy::type_complexity)]
+        let units: Vec<(
+            String,
+            String,
+            String,
+            String,
+            String,
+            String,
+            OwnedObjectPath,
+            u32,
+            String,
+            OwnedObjectPath,
+        )> = self.manager.call("ListUnits", &())?;
+
+        Ok(units
+            .into_iter()
+            .map(
+                |(name, description, load_state, active_state, sub_state, ..)| UnitState {
+                    name,
+                    description,
+                    load_state,
+                    active_state,
+                    sub_state,
+                    unit_file_state: None,
+                },
+            )
+            .collect())
+    }
+
+    /// Enable unit files, like `systemctl enable`. With `runtime` the change is done below
+    /// `/run` and does not persist across reboots.
+    pub fn enable_unit_files(
+        &mut self,
+        units: &[&str],
+        runtime: bool,
+        force: bool,
+    ) -> Result<Vec<UnitFileChange>, Error> {
+        let (_carries_install_info, changes): (bool, Vec<(String, String, String)>) = self
+            .manager
+            .call("EnableUnitFiles", &(units, runtime, force))?;
+        Ok(unit_file_changes(changes))
+    }
+
+    /// Disable unit files, like `systemctl disable`.
+    pub fn disable_unit_files(
+        &mut self,
+        units: &[&str],
+        runtime: bool,
+    ) -> Result<Vec<UnitFileChange>, Error> {
+        let changes: Vec<(String, String, String)> =
+            self.manager.call("DisableUnitFiles", &(units, runtime))?;
+        Ok(unit_file_changes(changes))
+    }
+
+    /// Reload the unit files, like `systemctl daemon-reload`.
+    pub fn daemon_reload(&mut self) -> Result<(), Error> {
+        self.manager.call::<_, _, ()>("Reload", &())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::os::unix::net::UnixStream;
+    use std::sync::mpsc;
+
+    use zbus::object_server::SignalContext;
+    use zbus::zvariant::ObjectPath;
+
+    use super::*;
+
+    const JOB_PATH: &str = "/org/freedesktop/systemd1/job/7";
+    const UNIT_PATH: &str = "/org/freedesktop/systemd1/unit/foo_2eservice";
+
+    #[derive(Debug, zbus::DBusError)]
+    #[zbus(prefix = "org.freedesktop.systemd1")]
+    enum StandInError {
+        #[zbus(error)]
+        ZBus(zbus::Error),
+        NoSuchUnit(String),
+    }
+
+    /// A minimal stand-in for the systemd manager.
+    struct StandInManager {
+        subscribed: bool,
+    }
+
+    impl StandInManager {
+        async fn queue_job(
+            &self,
+            ctxt: &SignalContext<'_>,
+            unit: &str,
+            mode: &str,
+            result: Option<&str>,
+        ) -> Result<OwnedObjectPath, StandInError> {
+            if unit == "missing.service" {
+                return Err(StandInError::NoSuchUnit(format!("Unit {unit} not found.")));
+            }
+            assert_eq!(mode, "replace");
+            assert!(self.subscribed);
+
+            let job = ObjectPath::try_from(JOB_PATH).unwrap();
+            if let Some(result) = result {
+                Self::job_removed(ctxt, 7, job.clone(), unit, result).await?;
+            }
+            Ok(job.into())
+        }
+    }
+
+    #[zbus::interface(name = "org.freedesktop.systemd1.Manager")]
+    impl StandInManager {
+        fn subscribe(&mut self) {
+            self.subscribed = true;
+        }
+
+        async fn start_unit(
+            &self,
+            unit: &str,
+            mode: &str,
+            #[zbus(signal_context)] ctxt: SignalContext<'_>,
+        ) -> Result<OwnedObjectPath, StandInError> {
+            self.queue_job(&ctxt, unit, mode, Some("done")).await
+        }
+
+        async fn restart_unit(
+            &self,
+            unit: &str,
+            mode: &str,
+            #[zbus(signal_context)] ctxt: SignalContext<'_>,
+        ) -> Result<OwnedObjectPath, StandInError> {
+            self.queue_job(&ctxt, unit, mode, Some("failed")).await
+        }
+
+        /// Never finishes.
+        async fn stop_unit(
+            &self,
+            unit: &str,
+            mode: &str,
+            #[zbus(signal_context)] ctxt: SignalContext<'_>,
+        ) -> Result<OwnedObjectPath, StandInError> {
+            self.queue_job(&ctxt, unit, mode, None).await
+        }
+
+        fn load_unit(&self, unit: &str) -> OwnedObjectPath {
+            assert_eq!(unit, "foo.service");
+            ObjectPath::try_from(UNIT_PATH).unwrap().into()
+        }
+
+        fn enable_unit_files(
+            &self,
+            units: Vec<String>,
+            _runtime: bool,
+            _force: bool,
+        ) -> (bool, Vec<(String, String, String)>) {
+            assert_eq!(units, ["foo.timer"]);
+            (
+                true,
+                vec![(
+                    "symlink".into(),
+                    "/etc/systemd/system/timers.target.wants/foo.timer".into(),
+                    "/lib/systemd/system/foo.timer".into(),
+                )],
+            )
+        }
+
+        fn reload(&self) {}
+
+        #[zbus(signal)]
+        async fn job_removed(
+            ctxt: &SignalContext<'_>,
+            id: u32,
+            job: ObjectPath<'_>,
+            unit: &str,
+            result: &str,
+        ) -> zbus::Result<()>;
+    }
+
+    struct StandInUnit;
+
+    #[zbus::interface(name = "org.freedesktop.systemd1.Unit")]
+    impl StandInUnit {
+        #[zbus(property)]
+        fn id(&self) -> &str {
+            "foo.service"
+        }
+
+        #[zbus(property)]
+        fn description(&self) -> &str {
+            "Foo Daemon"
+        }
+
+        #[zbus(property)]
+        fn load_state(&self) -> &str {
+            "loaded"
+        }
+
+        #[zbus(property)]
+        fn active_state(&self) -> &str {
+            "active"
+        }
+
+        #[zbus(property)]
+        fn sub_state(&self) -> &str {
+            "running"
+        }
+
+        #[zbus(property)]
+        fn unit_file_state(&self) -> &str {
+            "enabled"
+        }
+    }
+
+    #[test]
+    fn test_manager_stand_in() {
+        let (client, server) = UnixStream::pair().unwrap();
+        let (stop_tx, stop_rx) = mpsc::channel::<()>();
+
+        // the server side handshake only finishes once the client connected
+        let server = std::thread::spawn(move || {
+            let conn = zbus::blocking::connection::Builder::unix_stream(server)
+                .server(zbus::Guid::generate())
+                .unwrap()
+                .p2p()
+                .serve_at(MANAGER_PATH, StandInManager { subscribed: false })
+                .unwrap()
+                .serve_at(UNIT_PATH, StandInUnit)
+                .unwrap()
+                .build()
+                .unwrap();
+            let _ = stop_rx.recv();
+            drop(conn);
+        });
+
+        let conn = zbus::blocking::connection::Builder::unix_stream(client)
+            .p2p()
+            .build()
+            .unwrap();
+        let mut systemd = SystemdManager::with_connection(conn).unwrap();
+
+        let job = systemd.start_unit("foo.service", JobMode::Replace).unwrap();
+        assert_eq!(job, JOB_PATH);
+        assert_eq!(
+            systemd.wait_for_job(&job, Duration::from_secs(10)).unwrap(),
+            JobResult::Done,
+        );
+
+        let job = systemd.restart_unit("foo.service", JobMode::Replace).unwrap();
+        let result = systemd.wait_for_job(&job, Duration::from_secs(10)).unwrap();
+        assert_eq!(result, JobResult::Failed);
+        assert!(!result.is_done());
+
+        let job = systemd.stop_unit("foo.service", JobMode::Replace).unwrap();
+        let err = systemd
+            .wait_for_job(&job, Duration::from_millis(100))
+            .unwrap_err();
+        assert!(err.to_string().starts_with("timeout waiting for job"));
+
+        let err = systemd
+            .start_unit("missing.service", JobMode::Replace)
+            .unwrap_err();
+        assert!(err.to_string().contains("NoSuchUnit"), "{err}");
+
+        let state = systemd.unit_state("foo.service").unwrap();
+        assert_eq!(
+            state,
+            UnitState {
+                name: "foo.service".into(),
+                description: "Foo Daemon".into(),
+                load_state: "loaded".into(),
+                active_state: "active".into(),
+                sub_state: "running".into(),
+                unit_file_state: Some("enabled".into()),
+            }
+        );
+        assert!(state.is_active());
+
+        let changes = systemd
+            .enable_unit_files(&["foo.timer"], false, false)
+            .unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].change_type, "symlink");
+        assert_eq!(changes[0].destination, "/lib/systemd/system/foo.timer");
+
+        systemd.daemon_reload().unwrap();
+
+        drop(systemd);
+        stop_tx.send(()).unwrap();
+        server.join().unwrap();
+    }
+}
@@ -4,6 +4,14 @@ use std::path::PathBuf;
 
 use anyhow::{bail, Error};
 
+#[cfg(feature = "systemd-manager")]
+mod manager;
+#[cfg(feature = "systemd-manager")]
+pub use manager::*;
+
+mod unit_file;
+pub use unit_file::*;
+
 #[allow(clippy::manual_range_contains)]
 
 fn parse_hex_digit(d: u8) -> Result<u8, Error> {
@@ -0,0 +1,567 @@
+//! Generating, parsing and validating systemd unit files and drop-ins.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, format_err, Error};
+
+use crate::fs::{create_path, replace_file, CreateOptions};
+
+/// Directory for unit files and drop-ins of the local administrator.
+pub const SYSTEMD_UNIT_DIR: &str = "/etc/systemd/system";
+
+const UNIT_SUFFIXES: &[&str] = &[
+    "service",
+    "socket",
+    "device",
+    "mount",
+    "automount",
+    "swap",
+    "target",
+    "path",
+    "timer",
+    "slice",
+    "scope",
+];
+
+/// Check whether `name` is a valid unit name, like `foo.service` or `getty@tty1.service`.
+pub fn validate_unit_name(name: &str) -> Result<(), Error> {
+    if name.is_empty() || name.len() > 255 {
+        bail!("invalid unit name length");
+    }
+
+    let (prefix, suffix) = name
+        .rsplit_once('.')
+        .ok_or_else(|| format_err!("unit name '{name}' has no type suffix"))?;
+
+    if !UNIT_SUFFIXES.contains(&suffix) {
+        bail!("unit name '{name}' has unknown type suffix '{suffix}'");
+    }
+
+    if prefix.is_empty() || prefix.starts_with('@') {
+        bail!("unit name '{name}' has an empty prefix");
+    }
+
+    if prefix.matches('@').count() > 1 {
+        bail!("unit name '{name}' contains more than one '@'");
+    }
+
+    if let Some(c) = prefix
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || ":-_.\\@".contains(*c)))
+    {
+        bail!("unit name '{name}' contains invalid character {c:?}");
+    }
+
+    Ok(())
+}
+
+fn validate_section_name(name: &str) -> Result<(), Error> {
+    if name.is_empty()
+        || name
+            .chars()
+            .any(|c| c.is_ascii_control() || c == '[' || c == ']')
+    {
+        bail!("invalid section name '{name}'");
+    }
+    Ok(())
+}
+
+fn validate_key(key: &str) -> Result<(), Error> {
+    if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        bail!("invalid key '{key}'");
+    }
+    Ok(())
+}
+
+fn validate_value(key: &str, value: &str) -> Result<(), Error> {
+    if value.contains(['\n', '\r']) {
+        bail!("value of '{key}' contains a newline");
+    }
+    Ok(())
+}
+
+/// A section of a unit file, like `[Service]`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UnitSection {
+    pub name: String,
+    /// Entries in file order. Keys may occur more than once, an empty value resets the list of
+    /// previous values for list settings.
+    pub entries: Vec<(String, String)>,
+}
+
+impl UnitSection {
+    /// The last value of a key.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// All values of a key.
+    pub fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .filter(move |(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// A unit file or drop-in, keeping the order of sections and entries.
+///
+/// ```
+/// # use proxmox_sys::systemd::UnitFile;
+/// let mut unit = UnitFile::new();
+/// unit.set("Unit", "Description", "Daily update job");
+/// unit.set("Service", "Type", "oneshot");
+/// unit.add("Service", "ExecStart", "/usr/lib/foo/daily-update");
+/// unit.validate("foo-daily-update.service").unwrap();
+/// assert!(unit.to_string().starts_with("[Unit]\nDescription=Daily update job\n"));
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UnitFile {
+    sections: Vec<UnitSection>,
+}
+
+impl UnitFile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse a unit file. Comments are dropped and continuation lines joined.
+    pub fn parse(data: &str) -> Result<Self, Error> {
+        let mut unit = Self::new();
+        let mut current = None;
+        let mut lines = data.lines().enumerate();
+
+        while let Some((lineno, line)) = lines.next() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            if let Some(name) = line.strip_prefix('[') {
+                let name = name
+                    .strip_suffix(']')
+                    .ok_or_else(|| format_err!("line {}: unterminated section", lineno + 1))?;
+                validate_section_name(name)
+                    .map_err(|err| format_err!("line {}: {err}", lineno + 1))?;
+                current = Some(unit.section_index(name));
+                continue;
+            }
+
+            let mut line = line.to_string();
+            while line.ends_with('\\') {
+                line.pop();
+                match lines.next() {
+                    // comments within continued values are ignored
+                    Some((_, next)) if next.trim_start().starts_with(['#', ';']) => line.push('\\'),
+                    Some((_, next)) => {
+                        line.truncate(line.trim_end().len());
+                        line.push(' ');
+                        line.push_str(next.trim());
+                    }
+                    None => break,
+                }
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| format_err!("line {}: missing '='", lineno + 1))?;
+            let key = key.trim();
+            validate_key(key).map_err(|err| format_err!("line {}: {err}", lineno + 1))?;
+
+            let index = current
+                .ok_or_else(|| format_err!("line {}: entry outside of section", lineno + 1))?;
+            unit.sections[index]
+                .entries
+                .push((key.to_string(), value.trim().to_string()));
+        }
+
+        Ok(unit)
+    }
+
+    pub fn sections(&self) -> &[UnitSection] {
+        &self.sections
+    }
+
+    /// Get a section by name.
+    pub fn section(&self, name: &str) -> Option<&UnitSection> {
+        self.sections.iter().find(|s| s.name == name)
+    }
+
+    /// The index of a section, which is appended if it does not exist yet.
+    fn section_index(&mut self, name: &str) -> usize {
+        match self.sections.iter().position(|s| s.name == name) {
+            Some(pos) => pos,
+            None => {
+                self.sections.push(UnitSection {
+                    name: name.to_string(),
+                    entries: Vec::new(),
+                });
+                self.sections.len() - 1
+            }
+        }
+    }
+
+    fn section_mut(&mut self, name: &str) -> &mut UnitSection {
+        let index = self.section_index(name);
+        &mut self.sections[index]
+    }
+
+    /// The last value of a key in a section.
+    pub fn get(&self, section: &str, key: &str) -> Option<&str> {
+        self.section(section)?.get(key)
+    }
+
+    /// Set a key, replacing all previous values.
+    pub fn set<V: Into<String>>(&mut self, section: &str, key: &str, value: V) {
+        let section = self.section_mut(section);
+        let value = value.into();
+        match section.entries.iter().position(|(k, _)| k == key) {
+            Some(pos) => {
+                section.entries[pos].1 = value;
+                let mut index = 0;
+                section.entries.retain(|(k, _)| {
+                    index += 1;
+                    index - 1 <= pos || k != key
+                });
+            }
+            None => section.entries.push((key.to_string(), value)),
+        }
+    }
+
+    /// Add a value to a key, for settings which can be given multiple times like `ExecStartPre`.
+    pub fn add<V: Into<String>>(&mut self, section: &str, key: &str, value: V) {
+        self.section_mut(section)
+            .entries
+            .push((key.to_string(), value.into()));
+    }
+
+    /// Remove all values of a key.
+    pub fn remove(&mut self, section: &str, key: &str) {
+        if let Some(section) = self.sections.iter_mut().find(|s| s.name == section) {
+            section.entries.retain(|(k, _)| k != key);
+        }
+    }
+
+    /// Check the syntax of all sections and entries.
+    pub fn validate_syntax(&self) -> Result<(), Error> {
+        for section in self.sections.iter() {
+            validate_section_name(&section.name)?;
+            for (key, value) in section.entries.iter() {
+                validate_key(key)?;
+                validate_value(key, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Validate this as the complete unit file `unit_name`, including type specific required
+    /// settings.
+    pub fn validate(&self, unit_name: &str) -> Result<(), Error> {
+        validate_unit_name(unit_name)?;
+        self.validate_syntax()?;
+
+        let unit_type = unit_name
+            .rsplit_once('.')
+            .map(|(_, t)| t)
+            .unwrap_or_default();
+        let has = |section: &str, key: &str| {
+            self.get(section, key)
+                .map(|v| !v.is_empty())
+                .unwrap_or(false)
+        };
+
+        match unit_type {
+            "service" => {
+                let is_oneshot = self.get("Service", "Type") == Some("oneshot");
+                // oneshot services may consist of ExecStop= only
+                let has_exec =
+                    has("Service", "ExecStart") || is_oneshot && has("Service", "ExecStop");
+                if !has_exec {
+                    bail!("{unit_name}: service has no ExecStart setting");
+                }
+                if !is_oneshot
+                    && self
+                        .section("Service")
+                        .map(|s| s.get_all("ExecStart").filter(|v| !v.is_empty()).count())
+                        .unwrap_or(0)
+                        > 1
+                {
+                    bail!("{unit_name}: multiple ExecStart settings are only allowed for Type=oneshot");
+                }
+            }
+            "timer" => {
+                let section = self
+                    .section("Timer")
+                    .ok_or_else(|| format_err!("{unit_name}: missing [Timer] section"))?;
+                if !section
+                    .entries
+                    .iter()
+                    .any(|(k, v)| k.starts_with("On") && !v.is_empty())
+                {
+                    bail!("{unit_name}: timer has no trigger setting (OnCalendar, OnBootSec, ...)");
+                }
+            }
+            "mount" if !(has("Mount", "What") && has("Mount", "Where")) => {
+                bail!("{unit_name}: mount unit needs What and Where settings");
+            }
+            "socket" => {
+                let section = self
+                    .section("Socket")
+                    .ok_or_else(|| format_err!("{unit_name}: missing [Socket] section"))?;
+                if !section.entries.iter().any(|(k, _)| k.starts_with("Listen")) {
+                    bail!("{unit_name}: socket unit has no Listen setting");
+                }
+            }
+            "path" => {
+                let section = self
+                    .section("Path")
+                    .ok_or_else(|| format_err!("{unit_name}: missing [Path] section"))?;
+                if !section.entries.iter().any(|(k, _)| k.starts_with("Path")) {
+                    bail!("{unit_name}: path unit has no Path setting");
+                }
+            }
+            _ => (),
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for UnitFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, section) in self.sections.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            writeln!(f, "[{}]", section.name)?;
+            for (key, value) in section.entries.iter() {
+                writeln!(f, "{key}={value}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn unit_file_options() -> CreateOptions {
+    CreateOptions::new().perm(nix::sys::stat::Mode::from_bits_truncate(0o644))
+}
+
+/// Validate and write a unit file to `dir`. Returns the path of the written file.
+///
+/// A `daemon-reload` is required for systemd to pick up the change.
+pub fn write_unit_file_in<P: AsRef<Path>>(
+    dir: P,
+    name: &str,
+    unit: &UnitFile,
+) -> Result<PathBuf, Error> {
+    unit.validate(name)?;
+    let path = dir.as_ref().join(name);
+    replace_file(
+        &path,
+        unit.to_string().as_bytes(),
+        unit_file_options(),
+        true,
+    )
+    .map_err(|err| format_err!("failed to write unit file {path:?} - {err}"))?;
+    Ok(path)
+}
+
+/// Write a unit file to [`SYSTEMD_UNIT_DIR`].
+pub fn write_unit_file(name: &str, unit: &UnitFile) -> Result<PathBuf, Error> {
+    write_unit_file_in(SYSTEMD_UNIT_DIR, name, unit)
+}
+
+/// Validate and write a drop-in `<dir>/<unit>.d/<name>.conf`. Returns the path of the written
+/// file.
+///
+/// Drop-ins only need to contain the overridden settings, so only the syntax is checked.
+pub fn write_drop_in_in<P: AsRef<Path>>(
+    dir: P,
+    unit_name: &str,
+    name: &str,
+    drop_in: &UnitFile,
+) -> Result<PathBuf, Error> {
+    validate_unit_name(unit_name)?;
+    if name.is_empty() || name.contains('/') || name.starts_with('.') {
+        bail!("invalid drop-in name '{name}'");
+    }
+    drop_in.validate_syntax()?;
+
+    let dir = dir.as_ref().join(format!("{unit_name}.d"));
+    create_path(&dir, None, None)
+        .map_err(|err| format_err!("failed to create drop-in directory {dir:?} - {err}"))?;
+
+    let path = dir.join(format!("{name}.conf"));
+    replace_file(
+        &path,
+        drop_in.to_string().as_bytes(),
+        unit_file_options(),
+        true,
+    )
+    .map_err(|err| format_err!("failed to write drop-in {path:?} - {err}"))?;
+    Ok(path)
+}
+
+/// Write a drop-in for `unit_name` below [`SYSTEMD_UNIT_DIR`].
+pub fn write_drop_in(unit_name: &str, name: &str, drop_in: &UnitFile) -> Result<PathBuf, Error> {
+    write_drop_in_in(SYSTEMD_UNIT_DIR, unit_name, name, drop_in)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_unit_names() {
+        for name in [
+            "foo.service",
+            "getty@tty1.service",
+            "getty@.service",
+            "mnt-data\\x2dstore.mount",
+            "proxmox-backup-daily-update.timer",
+        ] {
+            validate_unit_name(name).unwrap_or_else(|err| panic!("{name}: {err}"));
+        }
+
+        for name in [
+            "",
+            "foo",
+            "foo.conf",
+            ".service",
+            "@foo.service",
+            "a@b@c.service",
+            "foo bar.service",
+            "foo/bar.service",
+        ] {
+            assert!(validate_unit_name(name).is_err(), "{name} accepted");
+        }
+    }
+
+    #[test]
+    fn test_parse_and_format() {
+        let data = "\
+# comment
+[Unit]
+Description=Foo
+After=network.target
+
+[Service]
+ExecStart=/usr/bin/foo \\
+    --bar \\
+# ignored
+    --baz
+Environment=A=1
+Environment=B=2
+";
+        let mut unit = UnitFile::parse(data).unwrap();
+        assert_eq!(unit.get("Unit", "Description"), Some("Foo"));
+        assert_eq!(
+            unit.get("Service", "ExecStart"),
+            Some("/usr/bin/foo --bar --baz")
+        );
+        assert_eq!(
+            unit.section("Service")
+                .unwrap()
+                .get_all("Environment")
+                .collect::<Vec<_>>(),
+            ["A=1", "B=2"]
+        );
+        unit.validate("foo.service").unwrap();
+
+        unit.set("Service", "Environment", "C=3");
+        unit.add("Install", "WantedBy", "multi-user.target");
+        assert_eq!(
+            unit.to_string(),
+            "\
+[Unit]
+Description=Foo
+After=network.target
+
+[Service]
+ExecStart=/usr/bin/foo --bar --baz
+Environment=C=3
+
+[Install]
+WantedBy=multi-user.target
+"
+        );
+        assert_eq!(UnitFile::parse(&unit.to_string()).unwrap(), unit);
+
+        assert!(UnitFile::parse("Description=Foo\n").is_err());
+        assert!(UnitFile::parse("[Unit\n").is_err());
+        assert!(UnitFile::parse("[Unit]\nDescription\n").is_err());
+        assert!(UnitFile::parse("[Unit]\nDesc ription=x\n").is_err());
+
+        // repeated sections are merged into the first one
+        let unit = UnitFile::parse("[A]\nKey=a\n[B]\nOther=b\n[A]\nKey=x\n").unwrap();
+        assert_eq!(unit.sections().len(), 2);
+        assert_eq!(
+            unit.section("A")
+                .unwrap()
+                .get_all("Key")
+                .collect::<Vec<_>>(),
+            ["a", "x"]
+        );
+        assert_eq!(unit.get("B", "Key"), None);
+        assert_eq!(unit.get("B", "Other"), Some("b"));
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut unit = UnitFile::new();
+        unit.set("Unit", "Description", "Foo");
+        assert!(unit.validate("foo.service").is_err());
+        assert!(unit.validate("foo.timer").is_err());
+        assert!(unit.validate("foo.mount").is_err());
+        unit.validate("foo.target").unwrap();
+
+        unit.set("Timer", "OnCalendar", "daily");
+        unit.validate("foo.timer").unwrap();
+
+        unit.set("Mount", "What", "/dev/sdb1");
+        assert!(unit.validate("mnt-data.mount").is_err());
+        unit.set("Mount", "Where", "/mnt/data");
+        unit.validate("mnt-data.mount").unwrap();
+
+        unit.add("Service", "ExecStart", "/bin/true");
+        unit.validate("foo.service").unwrap();
+        unit.add("Service", "ExecStart", "/bin/false");
+        assert!(unit.validate("foo.service").is_err());
+        unit.set("Service", "Type", "oneshot");
+        unit.validate("foo.service").unwrap();
+
+        unit.set("Unit", "Description", "multi\nline");
+        assert!(unit.validate("foo.service").is_err());
+        assert!(unit.validate_syntax().is_err());
+    }
+
+    #[test]
+    fn test_write_files() {
+        let dir =
+            std::env::temp_dir().join(format!("proxmox-sys-unit-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut unit = UnitFile::new();
+        unit.set("Service", "ExecStart", "/bin/true");
+        let path = write_unit_file_in(&dir, "foo.service", &unit).unwrap();
+        assert_eq!(std::fs::read_to_string(path).unwrap(), unit.to_string());
+        assert!(write_unit_file_in(&dir, "foo.timer", &unit).is_err());
+
+        let mut drop_in = UnitFile::new();
+        drop_in.set("Service", "Nice", "10");
+        let path = write_drop_in_in(&dir, "foo.service", "override", &drop_in).unwrap();
+        assert_eq!(path, dir.join("foo.service.d/override.conf"));
+        assert_eq!(
+            std::fs::read_to_string(path).unwrap(),
+            "[Service]\nNice=10\n"
+        );
+        assert!(write_drop_in_in(&dir, "foo.service", "../x", &drop_in).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
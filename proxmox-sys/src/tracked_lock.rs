@@ -0,0 +1,643 @@
+//! Inter-process reader-writer locks which record their holders and waiters.
+//!
+//! Next to each lock file `<path>`, a `<path>.lockinfo` file records which processes (and worker
+//! tasks) currently hold the lock and which wait for it, in the order they started waiting.
+//! Waiters are granted the lock in that order, so a steady stream of shared lockers cannot starve
+//! an exclusive one. Entries of processes which vanished are purged on every access.
+//!
+//! The lock file itself is additionally locked with `flock`, so code still using
+//! [`lock_file`](crate::fs::lock_file) on the same path is excluded as before.
+//!
+//! ```no_run
+//! # use std::time::Duration;
+//! # use proxmox_sys::tracked_lock::{inspect_lock, TrackedLock};
+//! # fn test() -> Result<(), anyhow::Error> {
+//! let lock = TrackedLock::new("/etc/proxmox-backup/.datastore.lck")
+//!     .upid("UPID:pbs:000012D4:0000B9E3:00000000:6553F1E0:verify:store1:root@pam:");
+//! let _guard = lock.lock(true, Some(Duration::from_secs(10)))?;
+//!
+//! // elsewhere: who is blocking the datastore config?
+//! for holder in inspect_lock("/etc/proxmox-backup/.datastore.lck")?.holders {
+//!     println!("{holder}");
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, format_err, Error};
+use nix::fcntl::{flock, FlockArg, OFlag};
+use nix::unistd::Pid;
+use serde::{Deserialize, Serialize};
+
+use crate::fs::{atomic_open_or_create_file, CreateOptions};
+use crate::linux::procfs::PidStat;
+use crate::WorkerTaskContext;
+
+/// Suffix of the file recording holders and waiters next to the lock file.
+pub const LOCK_INFO_SUFFIX: &str = ".lockinfo";
+
+const MAX_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A holder of, or waiter for, a lock.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct LockEntry {
+    /// Increasing number defining the queue order.
+    pub ticket: u64,
+    pub pid: u32,
+    /// The process start time in clock ticks since boot, to detect reused pids.
+    pub starttime: u64,
+    /// The worker task holding or waiting for the lock.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upid: Option<String>,
+    pub exclusive: bool,
+    /// When the lock was acquired, or for waiters, when they started waiting (epoch).
+    pub since: i64,
+}
+
+impl fmt::Display for LockEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mode = if self.exclusive {
+            "exclusive"
+        } else {
+            "shared"
+        };
+        write!(f, "pid {}", self.pid)?;
+        if let Some(upid) = &self.upid {
+            write!(f, " ({upid})")?;
+        }
+        match proxmox_time::epoch_to_rfc3339(self.since) {
+            Ok(since) => write!(f, ", {mode} since {since}"),
+            Err(_) => write!(f, ", {mode} since {}", self.since),
+        }
+    }
+}
+
+/// The holders of and waiters for a lock.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct LockState {
+    #[serde(default)]
+    next_ticket: u64,
+    #[serde(default)]
+    pub holders: Vec<LockEntry>,
+    /// Waiters in queue order.
+    #[serde(default)]
+    pub waiters: Vec<LockEntry>,
+}
+
+impl LockState {
+    fn remove(&mut self, ticket: u64) {
+        self.holders.retain(|entry| entry.ticket != ticket);
+        self.waiters.retain(|entry| entry.ticket != ticket);
+    }
+
+    /// Drop entries of processes which do not exist anymore.
+    fn purge(&mut self) {
+        let mut alive = |entry: &LockEntry| process_alive(entry.pid, entry.starttime);
+        self.holders.retain(&mut alive);
+        self.waiters.retain(&mut alive);
+    }
+
+    /// Move the waiter to the holders if it is next in line and compatible with the holders.
+    fn try_grant(&mut self, ticket: u64) -> Result<bool, Error> {
+        let pos = self
+            .waiters
+            .iter()
+            .position(|entry| entry.ticket == ticket)
+            .ok_or_else(|| format_err!("lock waiter entry vanished"))?;
+
+        let exclusive = self.waiters[pos].exclusive;
+        let grantable = if exclusive {
+            pos == 0 && self.holders.is_empty()
+        } else {
+            self.holders.iter().all(|entry| !entry.exclusive)
+                && self.waiters[..pos].iter().all(|entry| !entry.exclusive)
+        };
+
+        if grantable {
+            let mut entry = self.waiters.remove(pos);
+            entry.since = proxmox_time::epoch_i64();
+            self.holders.push(entry);
+        }
+        Ok(grantable)
+    }
+}
+
+fn process_alive(pid: u32, starttime: u64) -> bool {
+    match PidStat::read_from_pid(Pid::from_raw(pid as i32)) {
+        Ok(stat) => stat.starttime == starttime,
+        Err(_) => false,
+    }
+}
+
+fn own_starttime() -> Result<u64, Error> {
+    Ok(PidStat::read_from_pid(nix::unistd::getpid())?.starttime)
+}
+
+fn lock_info_path(path: &Path) -> PathBuf {
+    let mut info = path.as_os_str().to_owned();
+    info.push(LOCK_INFO_SUFFIX);
+    info.into()
+}
+
+/// Read, modify and write back the lock state while holding the info file lock.
+fn update_state<R>(
+    path: &Path,
+    options: &CreateOptions,
+    func: impl FnOnce(&mut LockState) -> Result<R, Error>,
+) -> Result<R, Error> {
+    let info_path = lock_info_path(path);
+    let mut file = atomic_open_or_create_file(
+        &info_path,
+        OFlag::O_RDWR | OFlag::O_CLOEXEC,
+        b"",
+        options.clone(),
+        false,
+    )?;
+    // only held for the short read-modify-write cycle
+    flock(file.as_raw_fd(), FlockArg::LockExclusive)?;
+
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)?;
+    let mut state: LockState = if data.is_empty() {
+        LockState::default()
+    } else {
+        serde_json::from_slice(&data).unwrap_or_else(|err| {
+            log::warn!("resetting corrupt lock info {info_path:?} - {err}");
+            LockState::default()
+        })
+    };
+
+    let original = state.clone();
+    state.purge();
+    let result = func(&mut state);
+
+    if state != original {
+        let data = serde_json::to_vec(&state)?;
+        file.seek(SeekFrom::Start(0))?;
+        file.set_len(0)?;
+        file.write_all(&data)?;
+    }
+
+    result
+}
+
+/// Read the holders of and waiters for the lock at `path`.
+pub fn inspect_lock<P: AsRef<Path>>(path: P) -> Result<LockState, Error> {
+    let path = path.as_ref();
+    let info_path = lock_info_path(path);
+    let mut file = match File::open(&info_path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(LockState::default()),
+        Err(err) => bail!("unable to open lock info {info_path:?} - {err}"),
+    };
+    flock(file.as_raw_fd(), FlockArg::LockShared)?;
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)
+        .map_err(|err| format_err!("unable to read lock info {info_path:?} - {err}"))?;
+    if data.is_empty() {
+        return Ok(LockState::default());
+    }
+    let mut state: LockState = serde_json::from_slice(&data)
+        .map_err(|err| format_err!("unable to parse lock info {info_path:?} - {err}"))?;
+    state.purge();
+    Ok(state)
+}
+
+/// List all locks in `dir` which are currently held or waited for, with their lock file path.
+pub fn list_locks<P: AsRef<Path>>(dir: P) -> Result<Vec<(PathBuf, LockState)>, Error> {
+    let dir = dir.as_ref();
+    let mut locks = Vec::new();
+    for entry in std::fs::read_dir(dir)
+        .map_err(|err| format_err!("unable to read directory {dir:?} - {err}"))?
+    {
+        let entry = entry?;
+        let name = entry.file_name();
+        let lock_name = match name.to_str().and_then(|n| n.strip_suffix(LOCK_INFO_SUFFIX)) {
+            Some(lock_name) if !lock_name.is_empty() => lock_name.to_string(),
+            _ => continue,
+        };
+        let path = dir.join(lock_name);
+        let state = inspect_lock(&path)?;
+        if !state.holders.is_empty() || !state.waiters.is_empty() {
+            locks.push((path, state));
+        }
+    }
+    locks.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(locks)
+}
+
+/// A lock recording its holders and waiters, see the [module documentation](self).
+#[derive(Clone)]
+pub struct TrackedLock {
+    path: PathBuf,
+    upid: Option<String>,
+    options: CreateOptions,
+}
+
+impl TrackedLock {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            upid: None,
+            options: CreateOptions::new(),
+        }
+    }
+
+    /// Record this worker task UPID with the lock entries.
+    pub fn upid<S: Into<String>>(mut self, upid: S) -> Self {
+        self.upid = Some(upid.into());
+        self
+    }
+
+    /// Options used when creating the lock and lock info files.
+    pub fn create_options(mut self, options: CreateOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The current holders and waiters of this lock.
+    pub fn state(&self) -> Result<LockState, Error> {
+        inspect_lock(&self.path)
+    }
+
+    fn enqueue(&self, exclusive: bool) -> Result<Waiter, Error> {
+        let entry_pid = std::process::id();
+        let starttime = own_starttime()?;
+        let ticket = update_state(&self.path, &self.options, |state| {
+            let ticket = state.next_ticket;
+            state.next_ticket += 1;
+            state.waiters.push(LockEntry {
+                ticket,
+                pid: entry_pid,
+                starttime,
+                upid: self.upid.clone(),
+                exclusive,
+                since: proxmox_time::epoch_i64(),
+            });
+            Ok(ticket)
+        })?;
+
+        Ok(Waiter {
+            lock: self.clone(),
+            ticket,
+            exclusive,
+            granted: false,
+            file: None,
+            done: false,
+        })
+    }
+
+    fn timeout_error(&self, exclusive: bool) -> Error {
+        let mode = if exclusive { "exclusive" } else { "shared" };
+        let holders = match self.state() {
+            Ok(state) if !state.holders.is_empty() => state
+                .holders
+                .iter()
+                .map(|holder| holder.to_string())
+                .collect::<Vec<_>>()
+                .join("; "),
+            Ok(_) => "unknown holder".to_string(),
+            Err(err) => format!("unknown holder ({err})"),
+        };
+        format_err!(
+            "unable to acquire {mode} lock {:?} - got timeout, held by {holders}",
+            self.path
+        )
+    }
+
+    /// Acquire the lock, waiting up to `timeout`, or forever if `None`.
+    ///
+    /// Waiting is done by polling the lock state with an exponential backoff of up to 100ms, so
+    /// it can take that long until a released lock is noticed.
+    pub fn lock(
+        &self,
+        exclusive: bool,
+        timeout: Option<Duration>,
+    ) -> Result<TrackedLockGuard, Error> {
+        self.lock_do(exclusive, timeout, None)
+    }
+
+    /// Like [`lock`](Self::lock), but gives up when the worker task gets aborted.
+    pub fn lock_with_worker(
+        &self,
+        exclusive: bool,
+        timeout: Option<Duration>,
+        worker: &dyn WorkerTaskContext,
+    ) -> Result<TrackedLockGuard, Error> {
+        self.lock_do(exclusive, timeout, Some(worker))
+    }
+
+    fn lock_do(
+        &self,
+        exclusive: bool,
+        timeout: Option<Duration>,
+        worker: Option<&dyn WorkerTaskContext>,
+    ) -> Result<TrackedLockGuard, Error> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut waiter = self.enqueue(exclusive)?;
+        let mut interval = Duration::from_millis(1);
+        loop {
+            if let Some(worker) = worker {
+                worker.check_abort()?;
+            }
+            if waiter.poll()? {
+                return Ok(waiter.into_guard());
+            }
+            let now = Instant::now();
+            if matches!(deadline, Some(deadline) if now >= deadline) {
+                return Err(self.timeout_error(exclusive));
+            }
+            if let Some(deadline) = deadline {
+                interval = interval.min(deadline - now);
+            }
+            std::thread::sleep(interval);
+            interval = (interval * 2).min(MAX_POLL_INTERVAL);
+        }
+    }
+
+    /// Acquire the lock asynchronously, waiting up to `timeout`, or forever if `None`.
+    ///
+    /// Like [`lock`](Self::lock), this polls with a backoff of up to 100ms. The lock state is
+    /// accessed via [`spawn_blocking`](tokio::task::spawn_blocking), as this involves file locks
+    /// and I/O.
+    ///
+    /// Dropping the future cancels the acquisition and leaves the queue, once a currently running
+    /// check of the lock state has finished.
+    #[cfg(feature = "async-lock")]
+    pub async fn lock_async(
+        &self,
+        exclusive: bool,
+        timeout: Option<Duration>,
+    ) -> Result<TrackedLockGuard, Error> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let lock = self.clone();
+        let mut waiter = tokio::task::spawn_blocking(move || lock.enqueue(exclusive)).await??;
+        let mut interval = Duration::from_millis(1);
+        loop {
+            let (acquired, returned) = tokio::task::spawn_blocking(move || {
+                let acquired = waiter.poll();
+                (acquired, waiter)
+            })
+            .await?;
+            waiter = returned;
+            if acquired? {
+                return Ok(waiter.into_guard());
+            }
+            let now = Instant::now();
+            if matches!(deadline, Some(deadline) if now >= deadline) {
+                return Err(self.timeout_error(exclusive));
+            }
+            if let Some(deadline) = deadline {
+                interval = interval.min(deadline - now);
+            }
+            tokio::time::sleep(interval).await;
+            interval = (interval * 2).min(MAX_POLL_INTERVAL);
+        }
+    }
+}
+
+/// A queue entry, removed again on drop unless turned into a guard.
+struct Waiter {
+    lock: TrackedLock,
+    ticket: u64,
+    exclusive: bool,
+    granted: bool,
+    file: Option<File>,
+    /// turned into a guard, which takes over the entry
+    done: bool,
+}
+
+impl Waiter {
+    /// Try to make progress, returns true once both the queue and the file lock are acquired.
+    fn poll(&mut self) -> Result<bool, Error> {
+        if !self.granted {
+            let ticket = self.ticket;
+            self.granted = update_state(&self.lock.path, &self.lock.options, |state| {
+                state.try_grant(ticket)
+            })?;
+            if !self.granted {
+                return Ok(false);
+            }
+        }
+
+        if self.file.is_none() {
+            self.file = Some(atomic_open_or_create_file(
+                &self.lock.path,
+                OFlag::O_RDWR | OFlag::O_CLOEXEC | OFlag::O_APPEND,
+                b"",
+                self.lock.options.clone(),
+                false,
+            )?);
+        }
+        let file = self.file.as_ref().unwrap();
+
+        let arg = if self.exclusive {
+            FlockArg::LockExclusiveNonblock
+        } else {
+            FlockArg::LockSharedNonblock
+        };
+        match flock(file.as_raw_fd(), arg) {
+            Ok(()) => Ok(true),
+            // held by a process not using the lock info
+            Err(nix::errno::Errno::EWOULDBLOCK) => Ok(false),
+            Err(err) => bail!("unable to lock {:?} - {err}", self.lock.path),
+        }
+    }
+
+    fn into_guard(mut self) -> TrackedLockGuard {
+        self.done = true;
+        TrackedLockGuard {
+            path: self.lock.path.clone(),
+            options: self.lock.options.clone(),
+            ticket: self.ticket,
+            _file: self.file.take().unwrap(),
+        }
+    }
+}
+
+impl Drop for Waiter {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        if let Err(err) = remove_entry(&self.lock.path, &self.lock.options, self.ticket) {
+            log::error!("unable to leave lock queue of {:?} - {err}", self.lock.path);
+        }
+    }
+}
+
+fn remove_entry(path: &Path, options: &CreateOptions, ticket: u64) -> Result<(), Error> {
+    update_state(path, options, |state| {
+        state.remove(ticket);
+        Ok(())
+    })
+}
+
+/// A held [`TrackedLock`], released when dropped.
+pub struct TrackedLockGuard {
+    path: PathBuf,
+    options: CreateOptions,
+    ticket: u64,
+    // dropped after the entry was removed, releasing the file lock
+    _file: File,
+}
+
+impl Drop for TrackedLockGuard {
+    fn drop(&mut self) {
+        if let Err(err) = remove_entry(&self.path, &self.options, self.ticket) {
+            log::error!(
+                "unable to remove lock holder entry of {:?} - {err}",
+                self.path
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn test_lock(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "proxmox-sys-tracked-lock-{name}-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join(".test.lck")
+    }
+
+    #[test]
+    fn test_holder_info() {
+        let path = test_lock("holder");
+        let lock = TrackedLock::new(&path).upid("UPID:test");
+
+        let guard = lock.lock(true, Some(Duration::from_secs(1))).unwrap();
+        let state = lock.state().unwrap();
+        assert_eq!(state.holders.len(), 1);
+        assert!(state.waiters.is_empty());
+        assert_eq!(state.holders[0].pid, std::process::id());
+        assert_eq!(state.holders[0].upid.as_deref(), Some("UPID:test"));
+
+        let err = match TrackedLock::new(&path).lock(false, Some(Duration::from_millis(50))) {
+            Ok(_) => panic!("got lock held exclusively elsewhere"),
+            Err(err) => err.to_string(),
+        };
+        assert!(err.contains("got timeout"), "{err}");
+        assert!(err.contains("(UPID:test), exclusive since"), "{err}");
+        // the timed out waiter left the queue
+        assert!(lock.state().unwrap().waiters.is_empty());
+
+        let locks = list_locks(path.parent().unwrap()).unwrap();
+        assert_eq!(locks.len(), 1);
+        assert_eq!(locks[0].0, path);
+
+        drop(guard);
+        let state = lock.state().unwrap();
+        assert!(state.holders.is_empty() && state.waiters.is_empty());
+        assert!(list_locks(path.parent().unwrap()).unwrap().is_empty());
+
+        // shared locks are compatible
+        let _a = lock.lock(false, Some(Duration::ZERO)).unwrap();
+        let _b = lock.lock(false, Some(Duration::ZERO)).unwrap();
+        assert_eq!(lock.state().unwrap().holders.len(), 2);
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_fair_queuing() {
+        let path = test_lock("fair");
+        let lock = TrackedLock::new(&path);
+
+        let shared = lock.lock(false, None).unwrap();
+
+        let writer = {
+            let lock = lock.clone();
+            std::thread::spawn(move || {
+                let _guard = lock.lock(true, Some(Duration::from_secs(10))).unwrap();
+            })
+        };
+        while lock.state().unwrap().waiters.is_empty() {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        // compatible with the holder, but queued behind the exclusive waiter
+        assert!(lock.lock(false, Some(Duration::from_millis(50))).is_err());
+
+        drop(shared);
+        writer.join().unwrap();
+        assert!(lock.state().unwrap().holders.is_empty());
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_stale_entries() {
+        let path = test_lock("stale");
+        let stale = LockEntry {
+            ticket: 0,
+            pid: std::process::id(),
+            starttime: own_starttime().unwrap() + 1,
+            upid: None,
+            exclusive: true,
+            since: 0,
+        };
+        let state = LockState {
+            next_ticket: 1,
+            holders: vec![stale],
+            waiters: Vec::new(),
+        };
+        std::fs::write(lock_info_path(&path), serde_json::to_vec(&state).unwrap()).unwrap();
+
+        assert!(inspect_lock(&path).unwrap().holders.is_empty());
+        let _guard = TrackedLock::new(&path)
+            .lock(true, Some(Duration::ZERO))
+            .unwrap();
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[cfg(feature = "async-lock")]
+    #[test]
+    fn test_async_cancel() {
+        let path = test_lock("async");
+        let lock = TrackedLock::new(&path);
+        let guard = lock.lock(true, None).unwrap();
+
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let waiting =
+                tokio::time::timeout(Duration::from_millis(50), lock.lock_async(true, None)).await;
+            assert!(waiting.is_err());
+            // the waiter may still be in a blocking task, which leaves the queue once it's done
+            let mut tries = 0;
+            while !lock.state().unwrap().waiters.is_empty() {
+                tries += 1;
+                assert!(tries < 100, "cancelled waiter did not leave the queue");
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+
+            drop(guard);
+            let _guard = lock.lock_async(true, None).await.unwrap();
+        });
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
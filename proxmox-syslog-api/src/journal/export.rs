@@ -0,0 +1,294 @@
+//! Parser for the journal export format, as produced by `journalctl -o export`.
+//!
+//! See <https://systemd.io/JOURNAL_EXPORT_FORMATS/> for the format description.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::io::{BufRead, Read};
+
+use anyhow::{bail, Error};
+use serde::ser::{Serialize, SerializeMap, Serializer};
+
+/// The maximum size of a single binary field, the same limit journald uses for its data objects.
+const MAX_FIELD_SIZE: u64 = 768 * 1024 * 1024;
+
+/// A single journal entry with all its fields, including the `__`-prefixed address fields.
+///
+/// Fields may occur more than once, and their values may contain binary data.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct JournalEntry {
+    fields: Vec<(String, Vec<u8>)>,
+}
+
+impl JournalEntry {
+    /// Iterate over all fields in the order they were exported.
+    pub fn fields(&self) -> impl Iterator<Item = (&str, &[u8])> {
+        self.fields
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_slice()))
+    }
+
+    /// Get the first value of a field.
+    pub fn field(&self, name: &str) -> Option<&[u8]> {
+        self.fields
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_slice())
+    }
+
+    /// Get all values of a field.
+    pub fn field_values<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a [u8]> + 'a {
+        self.fields
+            .iter()
+            .filter(move |(n, _)| n == name)
+            .map(|(_, value)| value.as_slice())
+    }
+
+    /// Get the first value of a field as text, replacing invalid UTF-8.
+    pub fn field_str(&self, name: &str) -> Option<Cow<'_, str>> {
+        self.field(name).map(String::from_utf8_lossy)
+    }
+
+    fn field_num<T: std::str::FromStr>(&self, name: &str) -> Option<T> {
+        std::str::from_utf8(self.field(name)?).ok()?.parse().ok()
+    }
+
+    /// The cursor of this entry, usable to continue reading after it.
+    pub fn cursor(&self) -> Option<Cow<'_, str>> {
+        self.field_str("__CURSOR")
+    }
+
+    /// The wallclock time of the entry in microseconds since the epoch.
+    pub fn realtime_usec(&self) -> Option<u64> {
+        self.field_num("__REALTIME_TIMESTAMP")
+    }
+
+    /// The monotonic time of the entry in microseconds since boot.
+    pub fn monotonic_usec(&self) -> Option<u64> {
+        self.field_num("__MONOTONIC_TIMESTAMP")
+    }
+
+    /// The boot id the entry was logged in.
+    pub fn boot_id(&self) -> Option<Cow<'_, str>> {
+        self.field_str("_BOOT_ID")
+    }
+
+    /// The log message.
+    pub fn message(&self) -> Option<Cow<'_, str>> {
+        self.field_str("MESSAGE")
+    }
+
+    /// The syslog priority, from 0 (emerg) to 7 (debug).
+    pub fn priority(&self) -> Option<u8> {
+        self.field_num("PRIORITY")
+    }
+
+    /// The pid of the logging process.
+    pub fn pid(&self) -> Option<u32> {
+        self.field_num("_PID")
+    }
+
+    /// The systemd unit of the logging process.
+    pub fn unit(&self) -> Option<Cow<'_, str>> {
+        self.field_str("_SYSTEMD_UNIT")
+    }
+
+    /// The syslog identifier, usually the program name.
+    pub fn identifier(&self) -> Option<Cow<'_, str>> {
+        self.field_str("SYSLOG_IDENTIFIER")
+    }
+
+    /// The host name the entry was logged on.
+    pub fn hostname(&self) -> Option<Cow<'_, str>> {
+        self.field_str("_HOSTNAME")
+    }
+}
+
+/// Serializes as a map of field names to strings, or arrays of strings for fields occurring more
+/// than once. Invalid UTF-8 is replaced.
+impl Serialize for JournalEntry {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map: BTreeMap<&str, Vec<Cow<str>>> = BTreeMap::new();
+        for (name, value) in self.fields.iter() {
+            map.entry(name)
+                .or_default()
+                .push(String::from_utf8_lossy(value));
+        }
+
+        let mut ser = serializer.serialize_map(Some(map.len()))?;
+        for (name, values) in map.iter() {
+            if values.len() == 1 {
+                ser.serialize_entry(name, &values[0])?;
+            } else {
+                ser.serialize_entry(name, values)?;
+            }
+        }
+        ser.end()
+    }
+}
+
+/// Reads [`JournalEntry`]s from a stream in the journal export format.
+pub struct ExportReader<R> {
+    reader: R,
+    line: Vec<u8>,
+}
+
+impl<R: BufRead> ExportReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            line: Vec::new(),
+        }
+    }
+
+    /// Read the next entry, returns `None` at the end of the stream.
+    pub fn read_entry(&mut self) -> Result<Option<JournalEntry>, Error> {
+        let mut entry = JournalEntry::default();
+        loop {
+            self.line.clear();
+            if self.reader.read_until(b'\n', &mut self.line)? == 0 {
+                // EOF, the final entry may lack the terminating empty line
+                return Ok(if entry.fields.is_empty() {
+                    None
+                } else {
+                    Some(entry)
+                });
+            }
+
+            let line = match self.line.strip_suffix(b"\n") {
+                Some(line) => line,
+                None => bail!("unexpected end of journal export stream"),
+            };
+
+            if line.is_empty() {
+                if entry.fields.is_empty() {
+                    continue;
+                }
+                return Ok(Some(entry));
+            }
+
+            match line.iter().position(|b| *b == b'=') {
+                Some(pos) => {
+                    let name = field_name(&line[..pos])?;
+                    entry.fields.push((name, line[(pos + 1)..].to_vec()));
+                }
+                None => {
+                    // binary field: name, newline, little endian 64 bit size, data, newline
+                    let name = field_name(line)?;
+                    let mut size = [0u8; 8];
+                    self.reader.read_exact(&mut size)?;
+                    let size = u64::from_le_bytes(size);
+                    if size > MAX_FIELD_SIZE {
+                        bail!("field '{}' too large ({} bytes)", name, size);
+                    }
+                    // don't trust the size for the allocation, the stream may be truncated
+                    let mut value = Vec::new();
+                    (&mut self.reader).take(size).read_to_end(&mut value)?;
+                    if value.len() as u64 != size {
+                        bail!(
+                            "unexpected end of journal export stream in field '{}'",
+                            name
+                        );
+                    }
+                    let mut newline = [0u8; 1];
+                    self.reader.read_exact(&mut newline)?;
+                    if newline[0] != b'\n' {
+                        bail!("missing newline after binary field '{}'", name);
+                    }
+                    entry.fields.push((name, value));
+                }
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for ExportReader<R> {
+    type Item = Result<JournalEntry, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_entry().transpose()
+    }
+}
+
+fn field_name(name: &[u8]) -> Result<String, Error> {
+    match std::str::from_utf8(name) {
+        Ok(name) if !name.is_empty() => Ok(name.to_string()),
+        _ => bail!("invalid field name in journal export stream"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_export_format() {
+        let mut data = b"__CURSOR=s=abc;i=1\n\
+__REALTIME_TIMESTAMP=1700000000123456\n\
+__MONOTONIC_TIMESTAMP=42\n\
+_BOOT_ID=0123456789abcdef\n\
+_PID=1234\n\
+PRIORITY=3\n\
+_SYSTEMD_UNIT=proxmox-backup.service\n\
+SYSLOG_IDENTIFIER=proxmox-backup-api\n\
+TAG=one\n\
+TAG=two\n\
+MESSAGE\n"
+            .to_vec();
+        let message = b"multi\nline";
+        data.extend_from_slice(&(message.len() as u64).to_le_bytes());
+        data.extend_from_slice(message);
+        data.extend_from_slice(b"\n\n__CURSOR=s=abc;i=2\nMESSAGE=last\n");
+
+        let entries: Vec<JournalEntry> = ExportReader::new(&data[..])
+            .collect::<Result<_, _>>()
+            .expect("failed to parse export format");
+        assert_eq!(entries.len(), 2);
+
+        let entry = &entries[0];
+        assert_eq!(entry.cursor().as_deref(), Some("s=abc;i=1"));
+        assert_eq!(entry.realtime_usec(), Some(1700000000123456));
+        assert_eq!(entry.monotonic_usec(), Some(42));
+        assert_eq!(entry.boot_id().as_deref(), Some("0123456789abcdef"));
+        assert_eq!(entry.pid(), Some(1234));
+        assert_eq!(entry.priority(), Some(3));
+        assert_eq!(entry.unit().as_deref(), Some("proxmox-backup.service"));
+        assert_eq!(entry.identifier().as_deref(), Some("proxmox-backup-api"));
+        assert_eq!(entry.message().as_deref(), Some("multi\nline"));
+        assert_eq!(entry.hostname(), None);
+        assert_eq!(
+            entry.field_values("TAG").collect::<Vec<_>>(),
+            [&b"one"[..], &b"two"[..]]
+        );
+        assert_eq!(entry.fields().count(), 11);
+
+        // the last entry does not need to be followed by an empty line
+        assert_eq!(entries[1].message().as_deref(), Some("last"));
+
+        // but a truncated line is an error
+        let truncated = &data[..(data.len() - 1)];
+        assert!(ExportReader::new(truncated).nth(1).unwrap().is_err());
+    }
+
+    #[test]
+    fn test_export_format_errors() {
+        let mut reader = ExportReader::new(&b"MESSAGE\n\x05\0\0\0\0\0\0\0abc"[..]);
+        assert!(reader.read_entry().is_err());
+
+        let mut reader = ExportReader::new(&b"MESSAGE\n\x01\0\0\0\0\0\0\0ab\n"[..]);
+        assert!(reader.read_entry().is_err());
+
+        let mut reader = ExportReader::new(&b"=value\n\n"[..]);
+        assert!(reader.read_entry().is_err());
+
+        // huge sizes must neither be allocated up front nor accepted
+        let mut reader = ExportReader::new(&b"MESSAGE\n\xff\xff\xff\xff\xff\xff\xff\x7fabc\n"[..]);
+        assert!(reader.read_entry().is_err());
+
+        let mut data = b"MESSAGE\n".to_vec();
+        data.extend_from_slice(&(MAX_FIELD_SIZE - 1).to_le_bytes());
+        data.extend_from_slice(b"abc\n");
+        let mut reader = ExportReader::new(&data[..]);
+        assert!(reader.read_entry().is_err());
+    }
+}
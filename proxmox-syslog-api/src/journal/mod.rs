@@ -4,6 +4,12 @@ use anyhow::Error;
 
 use super::{SyslogFilter, SyslogLine};
 
+mod export;
+pub use export::{ExportReader, JournalEntry};
+
+mod reader;
+pub use reader::{JournalEntries, JournalFilter, JournalReader, JournalSource};
+
 pub fn dump_journal(filter: SyslogFilter) -> Result<(u64, Vec<SyslogLine>), Error> {
     let mut args = vec!["-o", "short", "--no-pager"];
 
@@ -54,9 +60,10 @@ pub fn dump_journal(filter: SyslogFilter) -> Result<(u64, Vec<SyslogLine>), Erro
         }
     }
 
-    let status = child.wait().unwrap();
-    if !status.success() {
-        log::error!("journalctl failed with {}", status);
+    match child.wait() {
+        Ok(status) if !status.success() => log::error!("journalctl failed with {}", status),
+        Ok(_) => (),
+        Err(err) => log::error!("failed to wait for journalctl: {}", err),
     }
 
     // HACK: ExtJS store.guaranteeRange() does not like empty array
@@ -0,0 +1,423 @@
+//! Structured access to the systemd journal.
+
+use std::borrow::Cow;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+
+use anyhow::{bail, format_err, Error};
+
+use super::export::{ExportReader, JournalEntry};
+
+/// Where to read journal entries from.
+#[derive(Clone, Debug)]
+pub enum JournalSource {
+    /// The system journal.
+    System,
+    /// All journal files in a directory, e.g. one copied from another host.
+    Directory(PathBuf),
+    /// Explicit journal files.
+    Files(Vec<PathBuf>),
+}
+
+/// Journal entry filter. All set conditions need to match.
+#[derive(Clone, Debug, Default)]
+pub struct JournalFilter {
+    /// Only show entries of this systemd unit. A missing suffix defaults to `.service`.
+    pub unit: Option<String>,
+    /// Only show entries up to this priority, from 0 (emerg) to 7 (debug).
+    pub priority: Option<u8>,
+    /// Only show entries logged by this process.
+    pub pid: Option<u32>,
+    /// Only show entries of this boot.
+    pub boot_id: Option<String>,
+    /// Only show entries logged at or after this time (epoch).
+    pub since: Option<i64>,
+    /// Only show entries logged at or before this time (epoch).
+    pub until: Option<i64>,
+}
+
+fn unit_name(unit: &str) -> Cow<'_, str> {
+    if unit.contains('.') {
+        Cow::Borrowed(unit)
+    } else {
+        Cow::Owned(format!("{unit}.service"))
+    }
+}
+
+impl JournalFilter {
+    /// Check whether an entry matches this filter.
+    ///
+    /// Unit matching follows `journalctl --unit` and also includes messages systemd logged about
+    /// the unit.
+    pub fn matches(&self, entry: &JournalEntry) -> bool {
+        if let Some(unit) = &self.unit {
+            let unit = unit_name(unit);
+            let found = [
+                "_SYSTEMD_UNIT",
+                "UNIT",
+                "OBJECT_SYSTEMD_UNIT",
+                "COREDUMP_UNIT",
+            ]
+            .iter()
+            .any(|field| entry.field(field) == Some(unit.as_bytes()));
+            if !found {
+                return false;
+            }
+        }
+
+        if let Some(priority) = self.priority {
+            match entry.priority() {
+                Some(prio) if prio <= priority => (),
+                _ => return false,
+            }
+        }
+
+        if let Some(pid) = self.pid {
+            if entry.pid() != Some(pid) {
+                return false;
+            }
+        }
+
+        if let Some(boot_id) = &self.boot_id {
+            if entry.boot_id().as_deref() != Some(boot_id.as_str()) {
+                return false;
+            }
+        }
+
+        if self.since.is_some() || self.until.is_some() {
+            let time = match entry.realtime_usec() {
+                Some(usec) => (usec / 1_000_000) as i64,
+                None => return false,
+            };
+            if matches!(self.since, Some(since) if time < since) {
+                return false;
+            }
+            if matches!(self.until, Some(until) if time > until) {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    fn journalctl_args(&self, args: &mut Vec<String>) {
+        if let Some(unit) = &self.unit {
+            args.push(format!("--unit={unit}"));
+        }
+        if let Some(priority) = self.priority {
+            args.push(format!("--priority={priority}"));
+        }
+        if let Some(since) = self.since {
+            args.push(format!("--since=@{since}"));
+        }
+        if let Some(until) = self.until {
+            args.push(format!("--until=@{until}"));
+        }
+        // field matches go last, as they are positional arguments
+        if let Some(pid) = self.pid {
+            args.push(format!("_PID={pid}"));
+        }
+        if let Some(boot_id) = &self.boot_id {
+            args.push(format!("_BOOT_ID={boot_id}"));
+        }
+    }
+}
+
+/// Builder for reading structured journal entries.
+///
+/// ```no_run
+/// # use proxmox_syslog_api::{JournalFilter, JournalReader, JournalSource};
+/// # fn test() -> Result<(), anyhow::Error> {
+/// let entries = JournalReader::new(JournalSource::System)
+///     .filter(JournalFilter {
+///         unit: Some("proxmox-backup-proxy".to_string()),
+///         priority: Some(4),
+///         ..Default::default()
+///     })
+///     .open()?;
+///
+/// for entry in entries.take(100) {
+///     let entry = entry?;
+///     println!("{}: {}", entry.cursor().unwrap_or_default(), entry.message().unwrap_or_default());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct JournalReader {
+    source: JournalSource,
+    filter: JournalFilter,
+    after_cursor: Option<String>,
+    follow: bool,
+}
+
+impl JournalReader {
+    pub fn new(source: JournalSource) -> Self {
+        Self {
+            source,
+            filter: JournalFilter::default(),
+            after_cursor: None,
+            follow: false,
+        }
+    }
+
+    /// Only return entries matching `filter`.
+    pub fn filter(mut self, filter: JournalFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Start after the entry with this cursor, as returned by [`JournalEntry::cursor`].
+    pub fn after_cursor<S: Into<String>>(mut self, cursor: S) -> Self {
+        self.after_cursor = Some(cursor.into());
+        self
+    }
+
+    /// Keep waiting for new entries instead of stopping at the end of the journal.
+    ///
+    /// Without a cursor, only entries logged after opening the journal are returned.
+    pub fn follow(mut self, follow: bool) -> Self {
+        self.follow = follow;
+        self
+    }
+
+    /// Start reading the journal.
+    pub fn open(self) -> Result<JournalEntries, Error> {
+        let mut args = vec![
+            "--output=export".to_string(),
+            "--no-pager".to_string(),
+            "--quiet".to_string(),
+        ];
+
+        match &self.source {
+            JournalSource::System => (),
+            JournalSource::Directory(dir) => {
+                args.push(format!("--directory={}", path_arg(dir)?));
+            }
+            JournalSource::Files(files) => {
+                if files.is_empty() {
+                    bail!("no journal files given");
+                }
+                for file in files {
+                    args.push(format!("--file={}", path_arg(file)?));
+                }
+            }
+        }
+
+        if let Some(cursor) = &self.after_cursor {
+            args.push(format!("--after-cursor={cursor}"));
+        }
+
+        if self.follow {
+            args.push("--follow".to_string());
+            if self.after_cursor.is_none() {
+                args.push("--lines=0".to_string());
+            }
+        }
+
+        self.filter.journalctl_args(&mut args);
+
+        let mut child = Command::new("journalctl")
+            .args(&args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|err| format_err!("failed to execute journalctl - {err}"))?;
+
+        let stdout = child.stdout.take().unwrap();
+
+        Ok(JournalEntries {
+            reader: ExportReader::new(Box::new(BufReader::new(stdout))),
+            child: Some(child),
+            local: None,
+        })
+    }
+
+    /// Read entries from a stream in the journal export format instead of the journal itself.
+    ///
+    /// Filters and the cursor are applied while reading, following is not supported.
+    pub fn open_export<R>(self, reader: R) -> Result<JournalEntries, Error>
+    where
+        R: BufRead + Send + 'static,
+    {
+        if self.follow {
+            bail!("cannot follow a journal export stream");
+        }
+
+        Ok(JournalEntries {
+            reader: ExportReader::new(Box::new(reader)),
+            child: None,
+            local: Some((self.filter, self.after_cursor)),
+        })
+    }
+}
+
+fn path_arg(path: &std::path::Path) -> Result<&str, Error> {
+    path.to_str()
+        .ok_or_else(|| format_err!("non-utf8 journal path {path:?}"))
+}
+
+/// An iterator over journal entries, see [`JournalReader`].
+///
+/// Dropping it stops the underlying `journalctl` process.
+pub struct JournalEntries {
+    reader: ExportReader<Box<dyn BufRead + Send>>,
+    child: Option<Child>,
+    /// filter and pending cursor when reading export data directly
+    local: Option<(JournalFilter, Option<String>)>,
+}
+
+impl JournalEntries {
+    fn finish(&mut self) -> Result<(), Error> {
+        if let Some(mut child) = self.child.take() {
+            let status = child
+                .wait()
+                .map_err(|err| format_err!("failed to wait for journalctl - {err}"))?;
+            if !status.success() {
+                bail!("journalctl failed with {status}");
+            }
+        }
+        Ok(())
+    }
+
+    fn next_entry(&mut self) -> Result<Option<JournalEntry>, Error> {
+        loop {
+            let entry = match self.reader.read_entry()? {
+                Some(entry) => entry,
+                None => {
+                    self.finish()?;
+                    return Ok(None);
+                }
+            };
+
+            let (filter, cursor) = match &mut self.local {
+                Some(local) => local,
+                None => return Ok(Some(entry)),
+            };
+
+            if let Some(pending) = cursor {
+                if entry.cursor().as_deref() == Some(pending.as_str()) {
+                    *cursor = None;
+                }
+                continue;
+            }
+
+            if filter.matches(&entry) {
+                return Ok(Some(entry));
+            }
+        }
+    }
+}
+
+impl Iterator for JournalEntries {
+    type Item = Result<JournalEntry, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_entry().transpose()
+    }
+}
+
+impl Drop for JournalEntries {
+    fn drop(&mut self) {
+        if let Some(mut child) = self.child.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const EXPORT: &[u8] = b"\
+__CURSOR=s=1;i=1\n\
+__REALTIME_TIMESTAMP=1700000000000000\n\
+_BOOT_ID=aaaa\n\
+_PID=100\n\
+PRIORITY=6\n\
+_SYSTEMD_UNIT=foo.service\n\
+MESSAGE=started\n\
+\n\
+__CURSOR=s=1;i=2\n\
+__REALTIME_TIMESTAMP=1700000060000000\n\
+_BOOT_ID=aaaa\n\
+_PID=1\n\
+PRIORITY=3\n\
+_SYSTEMD_UNIT=init.scope\n\
+UNIT=foo.service\n\
+MESSAGE=foo.service: Failed\n\
+\n\
+__CURSOR=s=1;i=3\n\
+__REALTIME_TIMESTAMP=1700000120000000\n\
+_BOOT_ID=bbbb\n\
+_PID=200\n\
+PRIORITY=4\n\
+_SYSTEMD_UNIT=bar.service\n\
+MESSAGE=warning\n\
+\n";
+
+    fn messages(reader: JournalReader) -> Vec<String> {
+        reader
+            .open_export(EXPORT)
+            .unwrap()
+            .map(|entry| entry.unwrap().message().unwrap().into_owned())
+            .collect()
+    }
+
+    fn filtered(filter: JournalFilter) -> Vec<String> {
+        messages(JournalReader::new(JournalSource::System).filter(filter))
+    }
+
+    #[test]
+    fn test_filters() {
+        assert_eq!(filtered(JournalFilter::default()).len(), 3);
+
+        let unit = filtered(JournalFilter {
+            unit: Some("foo".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(unit, ["started", "foo.service: Failed"]);
+
+        let priority = filtered(JournalFilter {
+            priority: Some(4),
+            ..Default::default()
+        });
+        assert_eq!(priority, ["foo.service: Failed", "warning"]);
+
+        let pid = filtered(JournalFilter {
+            pid: Some(200),
+            ..Default::default()
+        });
+        assert_eq!(pid, ["warning"]);
+
+        let boot = filtered(JournalFilter {
+            boot_id: Some("aaaa".to_string()),
+            priority: Some(5),
+            ..Default::default()
+        });
+        assert_eq!(boot, ["foo.service: Failed"]);
+
+        let time = filtered(JournalFilter {
+            since: Some(1700000060),
+            until: Some(1700000060),
+            ..Default::default()
+        });
+        assert_eq!(time, ["foo.service: Failed"]);
+    }
+
+    #[test]
+    fn test_after_cursor() {
+        let after = messages(JournalReader::new(JournalSource::System).after_cursor("s=1;i=2"));
+        assert_eq!(after, ["warning"]);
+
+        let unknown = messages(JournalReader::new(JournalSource::System).after_cursor("s=1;i=9"));
+        assert!(unknown.is_empty());
+
+        assert!(JournalReader::new(JournalSource::System)
+            .follow(true)
+            .open_export(EXPORT)
+            .is_err());
+    }
+}
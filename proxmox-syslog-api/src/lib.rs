@@ -4,4 +4,7 @@ pub use api_types::*;
 #[cfg(feature = "impl")]
 mod journal;
 #[cfg(feature = "impl")]
-pub use journal::dump_journal;
+pub use journal::{
+    dump_journal, ExportReader, JournalEntries, JournalEntry, JournalFilter, JournalReader,
+    JournalSource,
+};